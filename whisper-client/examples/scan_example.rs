@@ -15,10 +15,11 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let scan_key = ScanKey::new(scan_secret)?;
     
     let spend_secret = SecretKey::from_slice(&[2u8; 32])?;
-    let spend_pubkey = PublicKey::from_secret_key(&secp, &spend_secret).x_only_public_key().0;
+    let spend_pubkey = PublicKey::from_secret_key(&secp, &spend_secret);
     
     println!("  Scan pubkey:  {}", hex::encode(scan_key.public.serialize()));
-    println!("  Spend pubkey: {}\n", hex::encode(spend_pubkey.serialize()));
+    println!("  Spend pubkey: {}", hex::encode(spend_pubkey.serialize()));
+    println!("  Address:      {}\n", scan_key.to_address(&spend_pubkey, bitcoin::Network::Regtest));
     
     // 2. Create client
     let server_url = std::env::var("WHISPER_SERVER")
@@ -44,7 +45,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         }
         Err(e) => {
             eprintln!("  ✗ Connection failed: {}", e);
-            eprintln!("    Make sure the server is running at {}\n", client.base_url);
+            eprintln!("    Make sure the server is running at {}\n", client.base_url());
             return Ok(());
         }
     }
@@ -58,6 +59,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let inputs = vec![InputData {
         pubkey: input_pubkey,
         is_taproot: true,
+        outpoint: None,
     }];
     
     match client.scan_range(0, 100, &inputs).await {
@@ -68,7 +70,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 println!("  ✓ Found {} payment(s):\n", results.len());
                 for (i, result) in results.iter().enumerate() {
                     println!("    Payment #{}:", i + 1);
-                    println!("      TXID:   {}", hex::encode(result.txid));
+                    println!("      TXID:   {}", result.outpoint().txid);
                     println!("      Vout:   {}", result.vout);
                     println!("      Amount: {} sats", result.amount);
                     println!("      Label:  {:?}", result.label);
@@ -1,75 +1,711 @@
 use whisper_core::*;
-use reqwest::Client;
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
-use bitcoin::secp256k1::XOnlyPublicKey;
+use bitcoin::secp256k1::{SecretKey, PublicKey};
+use bitcoin::Network;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+use tokio::sync::OnceCell;
+use tokio_util::sync::CancellationToken;
+use futures_util::StreamExt;
+
+mod transport;
+
+pub use reqwest::Url;
+pub use transport::*;
+
+/// Chunk size used by `scan_full_range` when the server doesn't advertise
+/// its `max_block_range` (matches the server's default).
+const DEFAULT_MAX_BLOCK_RANGE: u32 = 1000;
+
+/// Blocks below a checkpoint rescanned by `scan_since_checkpoint` unless
+/// configured otherwise, to pick up outputs moved by a shallow reorg.
+const DEFAULT_RESCAN_DEPTH: u32 = 6;
+
+/// Sent as every request's `scan_pubkey`: the server only checks its
+/// length, and a real scan key would link the request to one account.
+const PLACEHOLDER_SCAN_PUBKEY: &str = "0000000000000000000000000000000000000000000000000000000000000000";
 
 #[derive(Error, Debug)]
 pub enum ClientError {
     #[error("HTTP error: {0}")]
-    Http(#[from] reqwest::Error),
+    Http(reqwest::Error),
+    #[error("Request timed out: {0}")]
+    Timeout(reqwest::Error),
     #[error("Core error: {0}")]
     Core(#[from] CoreError),
     #[error("Invalid response: {0}")]
     InvalidResponse(String),
-    #[error("Server error ({status}): {message}")]
-    ServerError { status: u16, message: String },
+    #[error("Server error ({status}, {kind:?}): {message}")]
+    ServerError { status: u16, kind: ServerErrorKind, message: String },
+    #[error("Network mismatch: client expects {expected}, server is on {actual}")]
+    NetworkMismatch { expected: Network, actual: Network },
+    #[error("Candidate does not match its transaction: {0}")]
+    CandidateMismatch(String),
+    #[error("Invalid client configuration: {0}")]
+    InvalidConfig(String),
+    /// The blocking task verifying candidates panicked or was cancelled
+    #[error("Candidate verification task failed: {0}")]
+    VerificationTask(tokio::task::JoinError),
+    /// The scan was cancelled; `partial` holds results from chunks that
+    /// completed before cancellation.
+    #[error("Scan cancelled ({} results from completed chunks)", partial.len())]
+    Cancelled { partial: Vec<ScanResult> },
 }
 
-#[derive(Debug, Serialize)]
-struct ScanRequest {
-    scan_pubkey: String,
-    start_height: i32,
-    end_height: i32,
-    prefixes: Vec<String>,
-    include_proofs: Option<bool>,
+impl ClientError {
+    /// Whether retrying the same request may succeed: transport failures
+    /// and server-side database errors, but not rejected requests.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            ClientError::Http(_) | ClientError::Timeout(_) => true,
+            ClientError::ServerError { kind, .. } => *kind == ServerErrorKind::Database,
+            _ => false,
+        }
+    }
 }
 
-#[derive(Debug, Deserialize)]
-struct OutputCandidate {
-    txid: String,
-    vout: i32,
-    amount: i64,
-    script_pubkey: String,
-    block_height: i32,
-    block_hash: String,
-    timestamp: i64,
+/// Error kind from the server's JSON error body (`"error"` field).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ServerErrorKind {
+    Validation,
+    Database,
+    NotFound,
+    Forbidden,
+    /// Unrecognized kind, or a body that isn't a JSON error
+    #[serde(other)]
+    Unknown,
 }
 
-#[derive(Debug, Deserialize)]
-struct ScanResponse {
-    candidates: Vec<OutputCandidate>,
-    scanned_blocks: Vec<i32>,
-    server_time_ms: u64,
+impl From<reqwest::Error> for ClientError {
+    fn from(e: reqwest::Error) -> Self {
+        if e.is_timeout() {
+            ClientError::Timeout(e)
+        } else {
+            ClientError::Http(e)
+        }
+    }
+}
+
+/// HTTP transport settings for `SilentPaymentClient`.
+#[derive(Debug, Clone)]
+pub struct ClientConfig {
+    /// Maximum time to establish a TCP connection to the server.
+    pub connect_timeout: Duration,
+    /// Maximum time for a whole request, from sending to reading the body.
+    pub request_timeout: Duration,
+    /// How long idle pooled connections are kept alive.
+    pub pool_idle_timeout: Duration,
+    /// Proxy for every request, e.g. `socks5h://127.0.0.1:9050` to reach
+    /// the server over Tor. SOCKS proxies need the `socks` feature.
+    pub proxy: Option<Url>,
+    /// Times a request failing with a retryable error (see
+    /// `ClientError::is_retryable`) is sent again.
+    pub max_retries: u32,
+    /// Delay before the first retry; doubled for each further one.
+    pub retry_backoff: Duration,
+    /// Largest (decompressed) response body read; a server sending more
+    /// fails the request with `ClientError::InvalidResponse` before the
+    /// rest is buffered.
+    pub max_response_bytes: usize,
+}
+
+impl Default for ClientConfig {
+    fn default() -> Self {
+        Self {
+            connect_timeout: Duration::from_secs(10),
+            request_timeout: Duration::from_secs(30),
+            pool_idle_timeout: Duration::from_secs(90),
+            proxy: None,
+            max_retries: 0,
+            retry_backoff: Duration::from_millis(500),
+            max_response_bytes: 64 * 1024 * 1024,
+        }
+    }
+}
+
+impl ClientConfig {
+    /// Route requests through `proxy` (`None` connects directly).
+    pub fn proxy(mut self, proxy: Option<Url>) -> Self {
+        self.proxy = proxy;
+        self
+    }
+}
+
+/// Detailed result of a scan, for diagnostics.
+#[derive(Debug, Clone, Default)]
+pub struct ScanOutcome {
+    /// Outputs confirmed to belong to us
+    pub results: Vec<ScanResult>,
+    /// Candidates whose prefix matched but which failed local verification
+    pub false_positives: usize,
+    /// Candidates dropped because their inclusion proof did not verify
+    pub rejected_proofs: usize,
+    /// Total candidates returned by the server
+    pub candidates_checked: usize,
+    /// Malformed candidates that were skipped (bad hex, invalid output
+    /// key), one error each; the rest of the scan is unaffected. Shared so
+    /// the outcome stays `Clone` (`ClientError` wraps `reqwest::Error`)
+    pub errors: Vec<std::sync::Arc<ClientError>>,
+}
+
+/// Time spent in each phase of one scan, from
+/// `scan_range_instrumented`, to tell whether the server or the client is
+/// the bottleneck.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ScanMetrics {
+    /// Computing expected outputs and prefixes for every account and label
+    pub prefix_ms: f64,
+    /// The scan request, as seen through the transport
+    pub http_ms: f64,
+    /// Checking proofs and matching candidates locally
+    pub verify_ms: f64,
+    /// Candidates returned by the server
+    pub candidates: usize,
+    /// Candidates confirmed to belong to us
+    pub matches: usize,
 }
 
-pub struct SilentPaymentClient {
-    http_client: Client,
-    pub base_url: String,
+/// How far an incremental scan has got, for a wallet to persist between
+/// runs and pass back to `scan_since_checkpoint`. Start a new wallet from
+/// `ScanCheckpoint::default()`, or with `last_scanned_height` just below its
+/// birthday height.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ScanCheckpoint {
+    pub last_scanned_height: u32,
+    /// Every output found so far, in the order found
+    pub results: Vec<ScanResult>,
+}
+
+/// One wallet account scanned by a `SilentPaymentClient`.
+#[derive(Clone)]
+struct ScanAccount {
     scan_key: ScanKey,
-    spend_key: XOnlyPublicKey,
-    max_label: u8,
+    spend_key: PublicKey,
+    /// Labels to scan for; `None` is the unlabeled output
+    labels: Vec<Option<u32>>,
+    /// If set, every detected output is checked to be spendable with it.
+    spend_secret: Option<SecretKey>,
+}
+
+impl ScanAccount {
+    fn new(scan_key: ScanKey, spend_key: PublicKey, max_label: u32) -> Self {
+        if scan_key.public == spend_key {
+            tracing::warn!(
+                "spend key equals the scan key; anyone given the scan secret can also spend received payments"
+            );
+        }
+        Self {
+            scan_key,
+            spend_key,
+            labels: std::iter::once(None).chain((1..=max_label).map(Some)).collect(),
+            spend_secret: None,
+        }
+    }
+}
+
+/// Which of an account's labels get a prefix in each scan request.
+///
+/// Every prefix sent costs a server lookup and brings back its own false
+/// positives, so wallets with many labels can trade completeness of a
+/// single scan for smaller requests. Candidates are still verified against
+/// all of the account's labels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PrefixStrategy {
+    /// The unlabeled output and every label (the default)
+    #[default]
+    All,
+    /// Only the unlabeled output; labeled payments are not found
+    Unlabeled,
+    /// At most this many prefixes per account: the unlabeled output plus
+    /// the next labels in turn, so repeated scans of the same range cycle
+    /// through all of them. The unlabeled prefix is always sent, so
+    /// `Subset(0)` and `Subset(1)` behave like `Unlabeled`
+    Subset(usize),
+}
+
+impl PrefixStrategy {
+    /// Labels of `labels` (unlabeled first) to send in scan number `round`.
+    fn select(self, labels: &[Option<u32>], round: usize) -> Vec<Option<u32>> {
+        let labeled: Vec<Option<u32>> = labels.iter().copied().filter(Option::is_some).collect();
+        let per_round = match self {
+            PrefixStrategy::All => return labels.to_vec(),
+            PrefixStrategy::Unlabeled => 0,
+            PrefixStrategy::Subset(max) => max.saturating_sub(1).min(labeled.len()),
+        };
+        let start = if labeled.is_empty() { 0 } else { round * per_round % labeled.len() };
+        std::iter::once(None)
+            .chain(labeled.iter().cycle().skip(start).take(per_round).copied())
+            .collect()
+    }
 }
 
-impl SilentPaymentClient {
+/// Scans for payments to one or more wallet accounts through a
+/// `ScanTransport` (HTTP by default). Keys never leave the client; the
+/// transport only carries prefixes and candidates.
+pub struct SilentPaymentClient<T = HttpTransport> {
+    transport: T,
+    /// Accounts in the order added; never empty
+    accounts: Vec<ScanAccount>,
+    /// Network the wallet's address belongs to; `None` skips the check.
+    network: Option<Network>,
+    /// Only require the server's network to share `network`'s address HRP
+    /// (set by `with_address_network`, since `tsp` doesn't say which test network)
+    network_hrp_only: bool,
+    /// Set once the server's network has been confirmed to match.
+    network_checked: OnceCell<()>,
+    /// Prefix width in bytes sent to the server (4 or 8)
+    prefix_len: usize,
+    /// Blocks at and below a checkpoint that `scan_since_checkpoint` scans again
+    rescan_depth: u32,
+    /// Created from a `ScanOnlyKeypair`; spend secrets are refused
+    scan_only: bool,
+    /// Which labels get a prefix in each scan request
+    prefix_strategy: PrefixStrategy,
+    /// Scan requests sent so far, to rotate `PrefixStrategy::Subset`
+    prefix_round: AtomicUsize,
+    /// Chunks scanned at once by chunked scans; above 1, candidates are
+    /// also verified on blocking threads
+    workers: usize,
+}
+
+impl SilentPaymentClient<HttpTransport> {
     pub fn new(
         base_url: String,
         scan_key: ScanKey,
-        spend_key: XOnlyPublicKey,
-        max_label: u8,
+        spend_key: PublicKey,
+        max_label: u32,
     ) -> Self {
-        let http_client = Client::builder()
-            .timeout(std::time::Duration::from_secs(30))
-            .build()
-            .unwrap_or_else(|_| Client::new());
+        Self::with_transport(HttpTransport::new(base_url), scan_key, spend_key, max_label)
+    }
+    
+    /// Create a client with explicit HTTP settings (timeouts, proxy).
+    pub fn with_config(
+        base_url: String,
+        scan_key: ScanKey,
+        spend_key: PublicKey,
+        max_label: u32,
+        config: ClientConfig,
+    ) -> Result<Self, ClientError> {
+        let transport = HttpTransport::with_config(base_url, &config)?;
+        Ok(Self::with_transport(transport, scan_key, spend_key, max_label))
+    }
+    
+    /// Create a client that scans for payments to `address`.
+    ///
+    /// The base spend key is recovered from the address (removing its label
+    /// with the scan secret), and labels up to the address's label are
+    /// scanned. `scan_secret` must match the address's
+    /// scan key. Add `.with_address_network(address)` to also check the
+    /// server's network.
+    pub fn from_address(
+        base_url: String,
+        scan_secret: SecretKey,
+        address: &SilentPaymentAddress,
+    ) -> Result<Self, ClientError> {
+        let scan_key = ScanKey::new(scan_secret)?;
+        if scan_key.public != address.scan_pubkey {
+            return Err(CoreError::InvalidKey(
+                "scan secret does not match the address scan key".into()
+            ).into());
+        }
+        
+        let max_label = if address.is_labeled {
+            address.label.unwrap_or(0)
+        } else {
+            0
+        };
+        
+        let spend_pubkey = scan_key.base_spend_key(address)?;
+        Ok(Self::new(base_url, scan_key, spend_pubkey, max_label))
+    }
+    
+    /// Create a client for `keypair`'s unlabeled address and labels
+    /// `1..=max_label` on `network`, checking detected outputs with its
+    /// spend secret. Fails with `CoreError::NetworkMismatch` if the keys
+    /// were derived for the other kind of network
+    /// (`SilentPaymentKeypair::validate_for_network`).
+    pub fn from_keypair(
+        base_url: String,
+        keypair: &SilentPaymentKeypair,
+        max_label: u32,
+        network: Network,
+    ) -> Result<Self, ClientError> {
+        keypair.validate_for_network(network)?;
+        let client = Self::new(base_url, keypair.scan.clone(), keypair.spend.public, max_label)
+            .with_network(network);
+        match keypair.spend.secret {
+            Some(spend_secret) => client.with_spend_secret(spend_secret),
+            None => Ok(client),
+        }
+    }
+    
+    /// Create a client for a deployment that must only detect payments.
+    /// The keypair carries no spend secret, and `with_spend_secret` fails
+    /// on the returned client, so one can't be loaded by mistake later.
+    pub fn from_scan_only(base_url: String, keypair: ScanOnlyKeypair, max_label: u32) -> Self {
+        let mut client = Self::new(base_url, keypair.scan, keypair.spend_pubkey, max_label);
+        client.scan_only = true;
+        client
+    }
+    
+    /// URL of the index server.
+    pub fn base_url(&self) -> &str {
+        &self.transport.base_url
+    }
+    
+    /// Start building an HTTP client; see `SilentPaymentClientBuilder`.
+    pub fn builder() -> SilentPaymentClientBuilder {
+        SilentPaymentClientBuilder::default()
+    }
+}
+
+/// Step-by-step construction of an HTTP `SilentPaymentClient`, for when
+/// the positional constructors don't cover the settings needed.
+///
+/// `base_url`, `scan_key` and `spend_key` are required; everything else
+/// has the same default as `SilentPaymentClient::new`.
+#[derive(Debug, Default)]
+pub struct SilentPaymentClientBuilder {
+    base_url: Option<String>,
+    scan_key: Option<ScanKey>,
+    spend_key: Option<PublicKey>,
+    max_label: u32,
+    labels: Option<Vec<u32>>,
+    network: Option<Network>,
+    prefix_len: Option<usize>,
+    prefix_strategy: PrefixStrategy,
+    spend_secret: Option<SecretKey>,
+    scan_only: bool,
+    /// Set by `keypair`, to check its derivation against `network`
+    keypair: Option<SilentPaymentKeypair>,
+    workers: Option<usize>,
+    config: ClientConfig,
+}
+
+impl SilentPaymentClientBuilder {
+    pub fn base_url(mut self, base_url: impl Into<String>) -> Self {
+        self.base_url = Some(base_url.into());
+        self
+    }
+    
+    pub fn scan_key(mut self, scan_key: ScanKey) -> Self {
+        self.scan_key = Some(scan_key);
+        self
+    }
+    
+    pub fn spend_key(mut self, spend_key: PublicKey) -> Self {
+        self.spend_key = Some(spend_key);
+        self
+    }
+    
+    /// Scan labels `0..=max_label` (default 0, unlabeled only).
+    pub fn max_label(mut self, max_label: u32) -> Self {
+        self.max_label = max_label;
+        self
+    }
+    
+    /// Scan exactly these labels; see `SilentPaymentClient::with_labels`.
+    pub fn labels(mut self, labels: &[u32]) -> Self {
+        self.labels = Some(labels.to_vec());
+        self
+    }
+    
+    /// See `SilentPaymentClient::with_network`.
+    pub fn network(mut self, network: Network) -> Self {
+        self.network = Some(network);
+        self
+    }
+    
+    /// See `SilentPaymentClient::with_prefix_len`.
+    pub fn prefix_len(mut self, len: usize) -> Self {
+        self.prefix_len = Some(len);
+        self
+    }
+    
+    /// See `SilentPaymentClient::with_prefix_strategy`.
+    pub fn prefix_strategy(mut self, strategy: PrefixStrategy) -> Self {
+        self.prefix_strategy = strategy;
+        self
+    }
+    
+    /// See `SilentPaymentClient::with_spend_secret`.
+    pub fn spend_secret(mut self, spend_secret: SecretKey) -> Self {
+        self.spend_secret = Some(spend_secret);
+        self
+    }
+    
+    /// Take the scan key, spend key and spend secret from `keypair`. With a
+    /// `network`, `build` also fails if the keys were derived for the other
+    /// kind of network, as in `SilentPaymentClient::from_keypair`.
+    pub fn keypair(mut self, keypair: &SilentPaymentKeypair) -> Self {
+        self.scan_key = Some(keypair.scan.clone());
+        self.spend_key = Some(keypair.spend.public);
+        self.spend_secret = keypair.spend.secret;
+        self.keypair = Some(keypair.clone());
+        self
+    }
+    
+    /// Take the scan and spend keys from `keypair` and build a scan-only
+    /// client, as `SilentPaymentClient::from_scan_only` does; `build` then
+    /// fails if a spend secret was also given.
+    pub fn scan_only(mut self, keypair: ScanOnlyKeypair) -> Self {
+        self.scan_key = Some(keypair.scan);
+        self.spend_key = Some(keypair.spend_pubkey);
+        self.scan_only = true;
+        self
+    }
+    
+    /// See `SilentPaymentClient::with_workers`.
+    pub fn workers(mut self, workers: usize) -> Self {
+        self.workers = Some(workers);
+        self
+    }
+    
+    /// Replace all HTTP settings at once.
+    pub fn config(mut self, config: ClientConfig) -> Self {
+        self.config = config;
+        self
+    }
+    
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.config.connect_timeout = timeout;
+        self
+    }
+    
+    pub fn request_timeout(mut self, timeout: Duration) -> Self {
+        self.config.request_timeout = timeout;
+        self
+    }
+    
+    pub fn proxy(mut self, proxy: Url) -> Self {
+        self.config.proxy = Some(proxy);
+        self
+    }
+    
+    /// Retry retryable failures up to `max_retries` times, waiting
+    /// `backoff` before the first retry and doubling it after each.
+    pub fn retries(mut self, max_retries: u32, backoff: Duration) -> Self {
+        self.config.max_retries = max_retries;
+        self.config.retry_backoff = backoff;
+        self
+    }
+    
+    /// Fail responses with bodies larger than `max_bytes`.
+    pub fn max_response_bytes(mut self, max_bytes: usize) -> Self {
+        self.config.max_response_bytes = max_bytes;
+        self
+    }
+    
+    /// Build the client, failing with `ClientError::InvalidConfig` if a
+    /// required field is missing, the prefix length is unsupported, or a
+    /// scan-only client was given a spend secret, and with
+    /// `CoreError::NetworkMismatch` if `keypair` doesn't fit `network`.
+    pub fn build(self) -> Result<SilentPaymentClient, ClientError> {
+        let missing = |field: &str| ClientError::InvalidConfig(format!("{} is required", field));
+        let base_url = self.base_url.ok_or_else(|| missing("base_url"))?;
+        let scan_key = self.scan_key.ok_or_else(|| missing("scan_key"))?;
+        let spend_key = self.spend_key.ok_or_else(|| missing("spend_key"))?;
+        let prefix_len = self.prefix_len.unwrap_or(4);
+        if !PREFIX_LENGTHS.contains(&prefix_len) {
+            return Err(ClientError::InvalidConfig(format!("unsupported prefix length {}", prefix_len)));
+        }
+        if let (Some(keypair), Some(network)) = (&self.keypair, self.network) {
+            keypair.validate_for_network(network)?;
+        }
         
+        let mut client = SilentPaymentClient::with_config(base_url, scan_key, spend_key, self.max_label, self.config)?
+            .with_prefix_len(prefix_len)
+            .with_prefix_strategy(self.prefix_strategy);
+        client.scan_only = self.scan_only;
+        if let Some(labels) = &self.labels {
+            client = client.with_labels(labels);
+        }
+        if let Some(network) = self.network {
+            client = client.with_network(network);
+        }
+        if let Some(spend_secret) = self.spend_secret {
+            client = client.with_spend_secret(spend_secret)?;
+        }
+        if let Some(workers) = self.workers {
+            client = client.with_workers(workers);
+        }
+        Ok(client)
+    }
+}
+
+impl<T: ScanTransport> SilentPaymentClient<T> {
+    /// Create a client that reaches the server through `transport`,
+    /// scanning for labels `0..=max_label`.
+    pub fn with_transport(
+        transport: T,
+        scan_key: ScanKey,
+        spend_key: PublicKey,
+        max_label: u32,
+    ) -> Self {
         Self {
-            http_client,
-            base_url,
-            scan_key,
-            spend_key,
-            max_label,
+            transport,
+            accounts: vec![ScanAccount::new(scan_key, spend_key, max_label)],
+            network: None,
+            network_hrp_only: false,
+            network_checked: OnceCell::new(),
+            prefix_len: 4,
+            rescan_depth: DEFAULT_RESCAN_DEPTH,
+            scan_only: false,
+            prefix_strategy: PrefixStrategy::All,
+            prefix_round: AtomicUsize::new(0),
+            workers: 1,
+        }
+    }
+    
+    /// Also scan for another account (scan key, spend key, labels
+    /// `0..=max_label`) over the same transport. Prefixes for all accounts
+    /// go out in one request per scan, and each result records the account
+    /// that matched it in `ScanResult::account`. Accounts are
+    /// numbered in the order added, starting with 0 for the client's own.
+    ///
+    /// `with_labels` and `with_spend_secret` apply to the most recently
+    /// added account.
+    pub fn with_account(mut self, scan_key: ScanKey, spend_key: PublicKey, max_label: u32) -> Self {
+        self.accounts.push(ScanAccount::new(scan_key, spend_key, max_label));
+        self
+    }
+    
+    /// The most recently added account.
+    fn last_account(&mut self) -> &mut ScanAccount {
+        self.accounts.last_mut().expect("client always has an account")
+    }
+    
+    /// Expect the server to index `network`. Before the first scan the
+    /// server's network is fetched from `/api/v1/status`, and scans fail with
+    /// `ClientError::NetworkMismatch` if it differs (e.g. a mainnet address
+    /// against a testnet server).
+    pub fn with_network(mut self, network: Network) -> Self {
+        self.network = Some(network);
+        self.network_hrp_only = false;
+        self.network_checked = OnceCell::new();
+        self
+    }
+    
+    /// Like `with_network`, but taking the network from a decoded address.
+    /// A `tsp` address is accepted on any test network (testnet, signet or
+    /// regtest), since the address can't tell them apart; an `sp` address
+    /// still requires mainnet.
+    pub fn with_address_network(mut self, address: &SilentPaymentAddress) -> Self {
+        self.network = Some(address.network);
+        self.network_hrp_only = true;
+        self.network_checked = OnceCell::new();
+        self
+    }
+    
+    /// Scan for the unlabeled output plus exactly `labels` (the wallet's
+    /// active labels) instead of `0..=max_label`. Fewer labels means fewer
+    /// prefixes sent and less local work.
+    pub fn with_labels(mut self, labels: &[u32]) -> Self {
+        self.last_account().labels = std::iter::once(None)
+            .chain(labels.iter().copied().filter(|&m| m != 0).map(Some))
+            .collect();
+        self
+    }
+    
+    /// Check each detected output with `ScanResult::assert_spendable`
+    /// before returning it. Outputs the spend secret can't sign for are
+    /// dropped and reported in `ScanOutcome::errors`.
+    ///
+    /// Fails with `ClientError::InvalidConfig` on a scan-only client
+    /// (`from_scan_only` or the builder's `scan_only`).
+    pub fn with_spend_secret(mut self, spend_secret: SecretKey) -> Result<Self, ClientError> {
+        if self.scan_only {
+            return Err(ClientError::InvalidConfig("scan-only client must not hold a spend secret".into()));
+        }
+        self.last_account().spend_secret = Some(spend_secret);
+        Ok(self)
+    }
+    
+    /// Send `len`-byte prefixes (4 or 8) instead of the default 4. Must
+    /// match the server's `PREFIX_LENGTH`; see `negotiate_prefix_len`.
+    pub fn with_prefix_len(mut self, len: usize) -> Self {
+        self.prefix_len = len;
+        self
+    }
+    
+    /// Choose which labels get a prefix in each scan request (all of them
+    /// by default). Use `PrefixStrategy::Subset` to stay under the server's
+    /// `max_prefixes` or a false-positive budget with many labels.
+    pub fn with_prefix_strategy(mut self, strategy: PrefixStrategy) -> Self {
+        self.prefix_strategy = strategy;
+        self
+    }
+    
+    /// Have `scan_since_checkpoint` rescan the last `depth` blocks covered
+    /// by a checkpoint (6 by default), so outputs in blocks replaced by a
+    /// reorg up to that deep are dropped or found again. 0 only scans new
+    /// blocks.
+    pub fn with_rescan_depth(mut self, depth: u32) -> Self {
+        self.rescan_depth = depth;
+        self
+    }
+    
+    /// Scan up to `workers` chunks at a time in `scan_full_range` (and the
+    /// scans built on it), verifying their candidates on tokio's blocking
+    /// threads so large ranges use several cores. Results are still
+    /// returned in height order. 1 (the default) scans chunk by chunk;
+    /// 0 is treated as 1.
+    pub fn with_workers(mut self, workers: usize) -> Self {
+        self.workers = workers.max(1);
+        self
+    }
+    
+    /// Adopt the prefix width advertised by the server's `/api/v1/status`
+    /// (4 bytes if the server doesn't report one) and return it.
+    pub async fn negotiate_prefix_len(&mut self) -> Result<usize, ClientError> {
+        let len = self.get_status().await?.prefix_len.unwrap_or(4);
+        if !PREFIX_LENGTHS.contains(&len) {
+            return Err(ClientError::InvalidResponse(format!("Unsupported prefix length: {}", len)));
         }
+        self.prefix_len = len;
+        Ok(len)
+    }
+    
+    /// Whether any account uses its scan key as its spend key, usually the
+    /// scan pubkey passed where the spend pubkey was meant. Such a wallet
+    /// still scans, but loses the separation between the two keys.
+    pub fn is_single_key(&self) -> bool {
+        self.accounts.iter().any(|account| account.scan_key.public == account.spend_key)
+    }
+    
+    /// Network the client expects the server to be on, if configured.
+    pub fn network(&self) -> Option<Network> {
+        self.network
+    }
+    
+    /// Check the server's reported network against the configured one.
+    /// The server is only asked once; later calls return immediately.
+    pub async fn verify_network(&self) -> Result<(), ClientError> {
+        let Some(expected) = self.network else {
+            return Ok(());
+        };
+        
+        self.network_checked
+            .get_or_try_init(|| async {
+                let status = self.get_status().await?;
+                let actual = parse_network(&status.network).ok_or_else(|| {
+                    ClientError::InvalidResponse(format!("Unknown server network: {}", status.network))
+                })?;
+                
+                let matches = if self.network_hrp_only {
+                    SilentPaymentAddress::hrp(expected) == SilentPaymentAddress::hrp(actual)
+                } else {
+                    actual == expected
+                };
+                if !matches {
+                    return Err(ClientError::NetworkMismatch { expected, actual });
+                }
+                Ok(())
+            })
+            .await
+            .map(|_| ())
     }
     
     /// Scan a range of blocks for Silent Payments given transaction inputs
@@ -79,134 +715,562 @@ impl SilentPaymentClient {
         end_height: u32,
         inputs: &[InputData],
     ) -> Result<Vec<ScanResult>, ClientError> {
+        self.scan_range_detailed(start_height, end_height, inputs)
+            .await
+            .map(|outcome| outcome.results)
+    }
+    
+    /// Like `scan_range`, but also reports how many candidates were
+    /// checked, how many turned out to be prefix false positives, and
+    /// which account each result belongs to.
+    pub async fn scan_range_detailed(
+        &self,
+        start_height: u32,
+        end_height: u32,
+        inputs: &[InputData],
+    ) -> Result<ScanOutcome, ClientError> {
+        self.scan_range_instrumented(start_height, end_height, inputs)
+            .await
+            .map(|(outcome, _metrics)| outcome)
+    }
+    
+    /// Like `scan_range_detailed`, but also times the prefix computation,
+    /// the request and local verification, summed over the requests when
+    /// further outputs (k = 1, 2, ...) are looked for.
+    pub async fn scan_range_instrumented(
+        &self,
+        start_height: u32,
+        end_height: u32,
+        inputs: &[InputData],
+    ) -> Result<(ScanOutcome, ScanMetrics), ClientError> {
+        let round = self.prefix_round.fetch_add(1, Ordering::Relaxed);
+        self.scan_range_with(start_height, end_height, inputs, self.prefix_strategy, round).await
+    }
+    
+    /// Scan requests for the labels `strategy` picks in prefix round
+    /// `round`. The first asks for the outputs with k = 0; as long as some
+    /// account finds one, another asks for k = 1, 2, ... for the accounts
+    /// that did, stopping at the first k that finds nothing (BIP-352).
+    async fn scan_range_with(
+        &self,
+        start_height: u32,
+        end_height: u32,
+        inputs: &[InputData],
+        strategy: PrefixStrategy,
+        round: usize,
+    ) -> Result<(ScanOutcome, ScanMetrics), ClientError> {
         if start_height > end_height {
             return Err(ClientError::InvalidResponse(
                 "start_height must be <= end_height".into()
             ));
         }
         
-        // Compute prefixes for these inputs
-        let prefixes = compute_prefixes(
-            &self.scan_key,
-            &self.spend_key,
-            inputs,
-            self.max_label,
-        )?;
+        self.verify_network().await?;
         
-        // Convert to hex strings
-        let prefix_strs: Vec<String> = prefixes
+        let labels: Vec<Vec<Option<u32>>> = self.accounts
             .iter()
-            .map(|p| format!("{:08x}", p))
+            .map(|account| strategy.select(&account.labels, round))
             .collect();
+        let mut outcome = ScanOutcome::default();
+        let mut metrics = ScanMetrics::default();
+        let mut active: Vec<usize> = (0..self.accounts.len()).collect();
+        for k in 0..K_MAX {
+            let (found, found_metrics) = self
+                .scan_range_for_k(start_height, end_height, inputs, &labels, k, &active)
+                .await?;
+            
+            active = found.results.iter().map(|result| result.account).collect();
+            active.sort_unstable();
+            active.dedup();
+            
+            outcome.results.extend(found.results);
+            outcome.false_positives += found.false_positives;
+            outcome.rejected_proofs += found.rejected_proofs;
+            outcome.candidates_checked += found.candidates_checked;
+            outcome.errors.extend(found.errors);
+            metrics.prefix_ms += found_metrics.prefix_ms;
+            metrics.http_ms += found_metrics.http_ms;
+            metrics.verify_ms += found_metrics.verify_ms;
+            metrics.candidates += found_metrics.candidates;
+            metrics.matches += found_metrics.matches;
+            
+            if active.is_empty() {
+                break;
+            }
+        }
+        
+        // Later k are found after the rest of their block; keep the results
+        // in block order, with a transaction's outputs in output order
+        let mut first_seen = std::collections::HashMap::new();
+        for (i, result) in outcome.results.iter().enumerate() {
+            first_seen.entry(result.txid).or_insert(i);
+        }
+        outcome.results.sort_by_key(|result| (result.block_height, first_seen[&result.txid], result.vout));
+        
+        Ok((outcome, metrics))
+    }
+    
+    /// One scan request, sending the prefixes of the k-th output for the
+    /// `active` accounts and their selected `labels` (one list per account),
+    /// and checking the candidates as such.
+    async fn scan_range_for_k(
+        &self,
+        start_height: u32,
+        end_height: u32,
+        inputs: &[InputData],
+        labels: &[Vec<Option<u32>>],
+        k: u32,
+        active: &[usize],
+    ) -> Result<(ScanOutcome, ScanMetrics), ClientError> {
+        // Compute prefixes for these inputs, across the accounts
+        let started = Instant::now();
+        let mut prefixes = Vec::new();
+        for &index in active {
+            let account = &self.accounts[index];
+            for output in account.scan_key
+                .compute_expected_outputs_for_k(&account.spend_key, inputs, &labels[index], k)?
+            {
+                prefixes.push(prefix_from_xonly_len(&output, self.prefix_len)?);
+            }
+        }
+        
+        // Labels and accounts can collide in their first bytes; send each
+        // prefix once. Local verification still checks every label.
+        let prefix_strs = unique_prefix_strings(&prefixes, self.prefix_len);
+        let prefix_ms = elapsed_ms(started);
         
         // Query server
         let request = ScanRequest {
-            scan_pubkey: hex::encode(self.scan_key.public.serialize()),
+            scan_pubkey: PLACEHOLDER_SCAN_PUBKEY.to_string(),
             start_height: start_height as i32,
             end_height: end_height as i32,
             prefixes: prefix_strs,
-            include_proofs: Some(false),
+            include_proofs: Some(true),
         };
         
-        let url = format!("{}/api/v1/scan", self.base_url);
-        let http_response = self.http_client
-            .post(&url)
-            .json(&request)
-            .send()
-            .await?;
+        let started = Instant::now();
+        let response = self.transport.scan(&request).await?;
+        let http_ms = elapsed_ms(started);
+        
+        let started = Instant::now();
+        let outcome = self.verify_candidates(response.candidates, inputs, k, active).await?;
+        let metrics = ScanMetrics {
+            prefix_ms,
+            http_ms,
+            verify_ms: elapsed_ms(started),
+            candidates: outcome.candidates_checked,
+            matches: outcome.results.len(),
+        };
+        Ok((outcome, metrics))
+    }
+    
+    /// Scan an arbitrarily large range by splitting it into chunks no larger
+    /// than the server's advertised `max_block_range`. Chunks are requested
+    /// one at a time unless `with_workers` allows more, and results are
+    /// returned in block order.
+    pub async fn scan_full_range(
+        &self,
+        start_height: u32,
+        end_height: u32,
+        inputs: &[InputData],
+    ) -> Result<Vec<ScanResult>, ClientError> {
+        self.scan_full_range_cancellable(start_height, end_height, inputs, &CancellationToken::new())
+            .await
+    }
+    
+    /// `scan_full_range` that stops when `cancel` is triggered: the
+    /// in-flight chunk request is abandoned, no further chunks are requested,
+    /// and `ClientError::Cancelled` carries the results found so far.
+    pub async fn scan_full_range_cancellable(
+        &self,
+        start_height: u32,
+        end_height: u32,
+        inputs: &[InputData],
+        cancel: &CancellationToken,
+    ) -> Result<Vec<ScanResult>, ClientError> {
+        self.scan_chunked(start_height, end_height, inputs, cancel, self.prefix_strategy).await
+    }
+    
+    /// Scan in chunks of the server's `max_block_range`, with `strategy`.
+    async fn scan_chunked(
+        &self,
+        start_height: u32,
+        end_height: u32,
+        inputs: &[InputData],
+        cancel: &CancellationToken,
+        strategy: PrefixStrategy,
+    ) -> Result<Vec<ScanResult>, ClientError> {
+        if start_height > end_height {
+            return Err(ClientError::InvalidResponse(
+                "start_height must be <= end_height".into()
+            ));
+        }
         
-        // Check for server-side errors
-        let status = http_response.status();
-        if !status.is_success() {
-            let body = http_response.text().await.unwrap_or_default();
-            return Err(ClientError::ServerError {
-                status: status.as_u16(),
-                message: body,
-            });
+        let chunk_size = self
+            .get_status()
+            .await?
+            .max_block_range
+            .filter(|&n| n > 0)
+            .unwrap_or(DEFAULT_MAX_BLOCK_RANGE);
+        
+        let mut ranges = Vec::new();
+        let mut chunk_start = start_height;
+        loop {
+            let chunk_end = chunk_start.saturating_add(chunk_size - 1).min(end_height);
+            ranges.push((chunk_start, chunk_end));
+            if chunk_end == end_height {
+                break;
+            }
+            chunk_start = chunk_end + 1;
         }
         
-        let response = http_response.json::<ScanResponse>().await?;
+        // One prefix round for the whole scan, so every chunk sends the
+        // same labels however many workers run
+        let round = self.prefix_round.fetch_add(1, Ordering::Relaxed);
         
-        // Verify candidates locally
+        // `buffered` runs up to `workers` chunks at once but yields them in
+        // range order, so results stay in height order
+        let mut chunks = futures_util::stream::iter(ranges)
+            .map(|(chunk_start, chunk_end)| self.scan_range_with(chunk_start, chunk_end, inputs, strategy, round))
+            .buffered(self.workers);
         let mut results = Vec::new();
-        let labels: Vec<Option<u8>> = (0..=self.max_label)
-            .map(|m| if m == 0 { None } else { Some(m) })
+        loop {
+            let chunk = tokio::select! {
+                _ = cancel.cancelled() => return Err(ClientError::Cancelled { partial: results }),
+                chunk = chunks.next() => chunk,
+            };
+            let Some(chunk) = chunk else {
+                break;
+            };
+            results.extend(chunk?.0.results);
+        }
+        
+        Ok(results)
+    }
+    
+    /// Labels that received payments in `heights`, in ascending order, with
+    /// 0 for the unlabeled output. Scans every label the client was set up
+    /// with (`0..=max_label` unless narrowed by `with_labels`), whatever the
+    /// prefix strategy, so a restored wallet can find the labels it used
+    /// and pass them to `with_labels`.
+    pub async fn discover_labels(
+        &self,
+        inputs: &[InputData],
+        heights: std::ops::RangeInclusive<u32>,
+    ) -> Result<Vec<u32>, ClientError> {
+        let results = self
+            .scan_chunked(*heights.start(), *heights.end(), inputs, &CancellationToken::new(), PrefixStrategy::All)
+            .await?;
+        let labels: std::collections::BTreeSet<u32> = results
+            .iter()
+            .map(|result| result.label.unwrap_or(0))
             .collect();
+        Ok(labels.into_iter().collect())
+    }
+    
+    /// Resume an incremental scan: scan from just after
+    /// `checkpoint.last_scanned_height` to the server's tip and return the
+    /// checkpoint to store for next time.
+    ///
+    /// The last `rescan_depth` blocks of the checkpoint are scanned again;
+    /// results the checkpoint holds for those heights are replaced by what
+    /// the rescan finds, so outputs orphaned by a reorg drop out. If the
+    /// server's tip is below the checkpoint, the checkpoint is rolled back
+    /// to it the same way. Mempool results (no `block_height`) are dropped:
+    /// once confirmed, the rescan finds them again with their height.
+    pub async fn scan_since_checkpoint(
+        &self,
+        checkpoint: &ScanCheckpoint,
+        inputs: &[InputData],
+    ) -> Result<ScanCheckpoint, ClientError> {
+        let tip = self.get_status().await?.tip_height;
+        let Ok(tip) = u32::try_from(tip) else {
+            // Nothing indexed yet
+            return Ok(checkpoint.clone());
+        };
         
-        for candidate in response.candidates {
-            let script_bytes = hex::decode(&candidate.script_pubkey)
-                .map_err(|e| ClientError::InvalidResponse(e.to_string()))?;
-            
-            if let Some(output_match) = self.scan_key.check_output(
-                &script_bytes,
-                &self.spend_key,
-                inputs,
-                &labels,
-            )? {
-                // Build full ScanResult from OutputMatch + tx metadata
-                let txid_bytes = hex::decode(&candidate.txid)
-                    .map_err(|e| ClientError::InvalidResponse(e.to_string()))?;
-                
-                let mut txid = [0u8; 32];
-                if txid_bytes.len() == 32 {
-                    txid.copy_from_slice(&txid_bytes);
+        let start = (checkpoint.last_scanned_height.min(tip) + 1).saturating_sub(self.rescan_depth);
+        let mut results: Vec<ScanResult> = checkpoint.results
+            .iter()
+            .filter(|result| result.block_height.is_some_and(|height| height < start))
+            .cloned()
+            .collect();
+        if start <= tip {
+            for result in self.scan_full_range(start, tip, inputs).await? {
+                if !results.contains(&result) {
+                    results.push(result);
                 }
-                
-                let scan_result = ScanResult::from_match(
-                    &output_match,
-                    txid,
-                    candidate.vout as u32,
-                    candidate.amount as u64,
-                );
-                
-                results.push(scan_result);
             }
         }
         
-        Ok(results)
+        Ok(ScanCheckpoint { last_scanned_height: tip, results })
     }
     
-    /// Get server status
-    pub async fn get_status(&self) -> Result<ServerStatus, ClientError> {
-        let url = format!("{}/api/v1/status", self.base_url);
-        let response = self.http_client
-            .get(&url)
-            .send()
-            .await?;
+    /// Verify server candidates locally against the `active` accounts'
+    /// keys, as the k-th output paying them. A malformed
+    /// candidate is logged, recorded in `ScanOutcome::errors` and skipped
+    /// rather than failing the whole batch. Matches whose candidate came
+    /// without an amount get it from their transaction.
+    async fn verify_candidates(
+        &self,
+        candidates: Vec<OutputCandidate>,
+        inputs: &[InputData],
+        k: u32,
+        active: &[usize],
+    ) -> Result<ScanOutcome, ClientError> {
+        let mut outcome = ScanOutcome {
+            candidates_checked: candidates.len(),
+            ..ScanOutcome::default()
+        };
+        
+        // Matching is CPU-bound; with several workers it runs on a
+        // blocking thread so concurrent chunks use separate cores
+        let checked = if self.workers > 1 {
+            let accounts = self.accounts.clone();
+            let inputs = inputs.to_vec();
+            let active = active.to_vec();
+            tokio::task::spawn_blocking(move || check_candidates(&accounts, &active, candidates, &inputs, k))
+                .await
+                .map_err(ClientError::VerificationTask)?
+        } else {
+            check_candidates(&self.accounts, active, candidates, inputs, k)
+        };
         
-        let status = response.status();
-        if !status.is_success() {
-            let body = response.text().await.unwrap_or_default();
-            return Err(ClientError::ServerError {
-                status: status.as_u16(),
-                message: body,
-            });
+        for (candidate, check) in checked {
+            match check {
+                None => outcome.rejected_proofs += 1,
+                Some(Ok(Some(mut result))) => {
+                    if candidate.amount.is_none() {
+                        let (tx, _) = self.fetch_transaction(&result).await?;
+                        result.amount = tx.output.get(result.vout as usize)
+                            .ok_or_else(|| ClientError::CandidateMismatch(format!(
+                                "transaction has no output {}", result.vout
+                            )))?
+                            .value
+                            .to_sat();
+                    }
+                    outcome.results.push(result);
+                }
+                Some(Ok(None)) => outcome.false_positives += 1,
+                Some(Err(e)) => {
+                    tracing::warn!(txid = %candidate.txid, vout = candidate.vout, "skipping malformed candidate: {}", e);
+                    outcome.errors.push(std::sync::Arc::new(e));
+                }
+            }
         }
         
-        let server_status = response.json::<ServerStatus>().await?;
-        Ok(server_status)
+        Ok(outcome)
     }
-}
-
-#[derive(Debug, Deserialize)]
-pub struct ServerStatus {
-    pub status: String,
-    pub tip_height: i32,
-    pub network: String,
-    #[serde(default)]
-    pub total_outputs: i64,
-    #[serde(default)]
-    pub total_blocks: i64,
-    #[serde(default)]
-    pub uptime_seconds: u64,
-    #[serde(default)]
-    pub version: String,
-}
-
+    
+    /// Check a detected output against its raw transaction from
+    /// `/api/v1/tx/{txid}`: the transaction must hash to the result's txid,
+    /// sit in the block with `block_header` (e.g. from the wallet's own
+    /// header chain), and pay `result.amount` to the output's script at
+    /// `result.vout`.
+    pub async fn verify_candidate(
+        &self,
+        result: &ScanResult,
+        block_header: &bitcoin::block::Header,
+    ) -> Result<(), ClientError> {
+        use bitcoin::hashes::Hash;
+        
+        let (tx, block_hash) = self.fetch_transaction(result).await?;
+        if block_hash != hex::encode(block_header.block_hash().to_byte_array()) {
+            return Err(ClientError::CandidateMismatch(format!(
+                "transaction is in block {}, not the expected block",
+                block_hash
+            )));
+        }
+        
+        let output = tx.output.get(result.vout as usize).ok_or_else(|| {
+            ClientError::CandidateMismatch(format!("transaction has no output {}", result.vout))
+        })?;
+        if output.value.to_sat() != result.amount {
+            return Err(ClientError::CandidateMismatch(format!(
+                "output pays {} sats, server reported {}",
+                output.value.to_sat(), result.amount
+            )));
+        }
+        if output.script_pubkey != result.output_script() {
+            return Err(ClientError::CandidateMismatch(
+                "output script differs from the detected output key".into()
+            ));
+        }
+        
+        Ok(())
+    }
+    
+    /// Raw transaction of a detected output and the hash of its block,
+    /// checked to hash to the result's txid.
+    async fn fetch_transaction(
+        &self,
+        result: &ScanResult,
+    ) -> Result<(bitcoin::Transaction, String), ClientError> {
+        use bitcoin::hashes::Hash;
+        
+        let tx_response = self.transport.transaction(&result.outpoint().txid.to_string()).await?;
+        let raw_tx = hex::decode(&tx_response.raw_tx)
+            .map_err(|e| ClientError::InvalidResponse(e.to_string()))?;
+        let tx: bitcoin::Transaction = bitcoin::consensus::deserialize(&raw_tx)
+            .map_err(|e| ClientError::InvalidResponse(e.to_string()))?;
+        
+        if tx.compute_txid().to_byte_array() != result.txid {
+            return Err(ClientError::CandidateMismatch(
+                "raw transaction hashes to a different txid".into()
+            ));
+        }
+        
+        Ok((tx, tx_response.block_hash))
+    }
+    
+    /// Get server status
+    pub async fn get_status(&self) -> Result<ServerStatus, ClientError> {
+        self.transport.status().await
+    }
+}
+
+/// Parse the network name reported by the server (`NETWORK` in its config).
+fn parse_network(name: &str) -> Option<Network> {
+    match name.to_ascii_lowercase().as_str() {
+        "mainnet" | "main" | "bitcoin" => Some(Network::Bitcoin),
+        "testnet" | "testnet3" | "test" => Some(Network::Testnet),
+        "signet" => Some(Network::Signet),
+        "regtest" => Some(Network::Regtest),
+        _ => None,
+    }
+}
+
+/// Hex-encode `len`-byte prefixes, dropping duplicates while keeping
+/// first-seen order.
+fn unique_prefix_strings(prefixes: &[u64], len: usize) -> Vec<String> {
+    let mut seen = std::collections::HashSet::new();
+    prefixes
+        .iter()
+        .filter(|p| seen.insert(**p))
+        .map(|p| format!("{:0width$x}", p, width = len * 2))
+        .collect()
+}
+
+/// Milliseconds since `started`, keeping sub-millisecond precision.
+fn elapsed_ms(started: Instant) -> f64 {
+    started.elapsed().as_secs_f64() * 1000.0
+}
+
+/// Internal-order bytes (as kept in `ScanResult::txid`) of a txid the
+/// server sent in display order.
+fn txid_from_hex(display: &str) -> Option<[u8; 32]> {
+    use bitcoin::hashes::Hash;
+    
+    display.parse::<bitcoin::Txid>().ok().map(|txid| txid.to_byte_array())
+}
+
+/// What `verify_candidate_output` found for one candidate: its result,
+/// tagged with the matching account, if any.
+type CandidateMatch = Result<Option<ScanResult>, ClientError>;
+
+/// Check candidates against the `active` accounts' keys as their k-th
+/// output, keeping each candidate with its outcome: `None` if its
+/// inclusion proof fails (the server is never trusted), otherwise what
+/// `verify_candidate_output` found.
+fn check_candidates(
+    accounts: &[ScanAccount],
+    active: &[usize],
+    candidates: Vec<OutputCandidate>,
+    inputs: &[InputData],
+    k: u32,
+) -> Vec<(OutputCandidate, Option<CandidateMatch>)> {
+    candidates
+        .into_iter()
+        .map(|candidate| {
+            let check = verify_inclusion(&candidate)
+                .then(|| verify_candidate_output(accounts, active, &candidate, inputs, k));
+            (candidate, check)
+        })
+        .collect()
+}
+
+/// Check one proven candidate against each active account's keys and
+/// labels as its k-th output, returning the result for the first account
+/// that matches.
+fn verify_candidate_output(
+    accounts: &[ScanAccount],
+    active: &[usize],
+    candidate: &OutputCandidate,
+    inputs: &[InputData],
+    k: u32,
+) -> CandidateMatch {
+    let script_pubkey = hex::decode(&candidate.script_pubkey)
+        .map_err(|e| ClientError::InvalidResponse(format!(
+            "candidate {}:{} has invalid script_pubkey: {}", candidate.txid, candidate.vout, e
+        )))?;
+    let txid = txid_from_hex(&candidate.txid)
+        .ok_or_else(|| ClientError::InvalidResponse(format!(
+            "candidate has invalid txid {:?}", candidate.txid
+        )))?;
+    
+    for &index in active {
+        let account = &accounts[index];
+        let Some(output_match) = account.scan_key.check_output_for_k(
+            &script_pubkey,
+            &account.spend_key,
+            inputs,
+            &account.labels,
+            k,
+        )? else {
+            continue;
+        };
+        
+        let mut result = ScanResult::from_match(
+            &output_match,
+            txid,
+            candidate.vout as u32,
+            candidate.amount.unwrap_or(0) as u64,
+        );
+        result.block_height = Some(candidate.block_height as u32);
+        result.account = index;
+        if let Some(spend_secret) = &account.spend_secret {
+            result.assert_spendable(spend_secret)?;
+        }
+        return Ok(Some(result));
+    }
+    
+    Ok(None)
+}
+
+/// Check a candidate's merkle proof: the header must hash to the claimed
+/// block, and the txid plus branch must recompute the header's merkle root.
+fn verify_inclusion(candidate: &OutputCandidate) -> bool {
+    let Some(proof) = &candidate.proof else {
+        return false;
+    };
+    
+    let decode32 = |s: &str| -> Option<[u8; 32]> {
+        hex::decode(s).ok()?.try_into().ok()
+    };
+    
+    let (Ok(header), Some(block_hash), Some(txid)) = (
+        hex::decode(&proof.block_header),
+        decode32(&candidate.block_hash),
+        txid_from_hex(&candidate.txid),
+    ) else {
+        return false;
+    };
+    
+    let branch: Option<Vec<[u8; 32]>> = proof.branch.iter().map(|h| decode32(h)).collect();
+    let Some(branch) = branch else {
+        return false;
+    };
+    
+    if header.len() != BLOCK_HEADER_LEN || block_hash_from_header(&header) != block_hash {
+        return false;
+    }
+    
+    verify_merkle_proof(&txid, &branch, proof.tx_index, &header).unwrap_or(false)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use bitcoin::secp256k1::SecretKey;
     
     #[test]
     fn test_client_creation() {
@@ -221,6 +1285,1473 @@ mod tests {
             10,
         );
         
-        assert_eq!(client.max_label, 10);
+        assert_eq!(client.accounts[0].labels.len(), 11);
+        
+        // Only the wallet's active labels once set
+        let client = client.with_labels(&[3, 7, 200]);
+        assert_eq!(client.accounts[0].labels, vec![None, Some(3), Some(7), Some(200)]);
+    }
+    
+    #[test]
+    fn test_client_from_address() {
+        let secp = bitcoin::secp256k1::Secp256k1::new();
+        let scan_secret = SecretKey::from_slice(&[1u8; 32]).unwrap();
+        let spend_secret = SecretKey::from_slice(&[2u8; 32]).unwrap();
+        let spend_pubkey = spend_secret.public_key(&secp);
+        let address = ScanKey::new(scan_secret).unwrap()
+            .to_labeled_address(&spend_pubkey, 3, Network::Regtest)
+            .unwrap();
+        
+        let client = SilentPaymentClient::from_address(
+            "http://localhost:3000".into(),
+            scan_secret,
+            &address,
+        ).unwrap();
+        
+        // The client scans with the base key, not the address's labeled key
+        assert_eq!(client.accounts[0].spend_key, spend_pubkey);
+        assert_ne!(address.spend_pubkey, spend_pubkey);
+        assert_eq!(client.accounts[0].labels, vec![None, Some(1), Some(2), Some(3)]);
+        
+        // A scan secret for a different address is rejected
+        let other_secret = SecretKey::from_slice(&[3u8; 32]).unwrap();
+        assert!(SilentPaymentClient::from_address(
+            "http://localhost:3000".into(),
+            other_secret,
+            &address,
+        ).is_err());
+    }
+    
+    #[test]
+    fn test_unique_prefix_strings_drops_collisions() {
+        // Repeated prefixes keep only their first occurrence
+        let prefixes = [0xdeadbeef, 0x01020304, 0xdeadbeef, 0x0a0b0c0d, 0x01020304];
+        
+        assert_eq!(
+            unique_prefix_strings(&prefixes, 4),
+            vec!["deadbeef", "01020304", "0a0b0c0d"],
+        );
+        
+        // 8-byte prefixes keep leading zeros
+        assert_eq!(
+            unique_prefix_strings(&[0x00ab_0000_0000_0001, 0x00ab_0000_0000_0001], 8),
+            vec!["00ab000000000001"],
+        );
+    }
+    
+    #[tokio::test]
+    async fn test_scan_request_sends_each_prefix_once() {
+        let (scan_key, spend_pubkey, inputs) = test_wallet();
+        
+        // Label 1 listed twice, plus a second account with the same keys:
+        // six expected outputs but only two distinct prefixes
+        let client = SilentPaymentClient::with_transport(MockTransport::default(), scan_key.clone(), spend_pubkey, 0)
+            .with_labels(&[1, 1])
+            .with_account(scan_key.clone(), spend_pubkey, 1);
+        client.scan_range(0, 10, &inputs).await.unwrap();
+        
+        let expected: Vec<String> = scan_key
+            .compute_expected_outputs_for_labels(&spend_pubkey, &inputs, &[None, Some(1)])
+            .unwrap()
+            .iter()
+            .map(|output| hex::encode(&output.serialize()[..4]))
+            .collect();
+        assert_eq!(*client.transport.requested_prefixes.lock().unwrap(), expected);
+    }
+    
+    fn candidate_with_proof(txids: &[[u8; 32]], index: usize) -> OutputCandidate {
+        candidate_with_script(txids, index, String::new())
+    }
+    
+    fn candidate_with_script(txids: &[[u8; 32]], index: usize, script_pubkey: String) -> OutputCandidate {
+        use bitcoin::hashes::Hash;
+        
+        let root = merkle_root_from_branch(&txids[index], &merkle_branch(txids, index), index as u32);
+        let header = bitcoin::block::Header {
+            version: bitcoin::block::Version::TWO,
+            prev_blockhash: bitcoin::BlockHash::all_zeros(),
+            merkle_root: bitcoin::TxMerkleNode::from_byte_array(root),
+            time: 1_700_000_000,
+            bits: bitcoin::CompactTarget::from_consensus(0x207fffff),
+            nonce: 0,
+        };
+        
+        OutputCandidate {
+            txid: bitcoin::Txid::from_byte_array(txids[index]).to_string(),
+            vout: 0,
+            amount: Some(1000),
+            script_pubkey,
+            block_height: 1,
+            block_hash: hex::encode(header.block_hash().to_byte_array()),
+            timestamp: 0,
+            proof: Some(MerkleProof {
+                block_header: hex::encode(bitcoin::consensus::serialize(&header)),
+                tx_index: index as u32,
+                branch: merkle_branch(txids, index).iter().map(hex::encode).collect(),
+            }),
+        }
+    }
+    
+    /// Scan key, spend key and the single taproot input most scans here use
+    fn test_wallet() -> (ScanKey, bitcoin::secp256k1::PublicKey, Vec<InputData>) {
+        let secp = bitcoin::secp256k1::Secp256k1::new();
+        let pubkey = |seed: u8| {
+            bitcoin::secp256k1::PublicKey::from_secret_key(&secp, &SecretKey::from_slice(&[seed; 32]).unwrap())
+        };
+        let inputs = vec![InputData { pubkey: pubkey(3), is_taproot: true, outpoint: None }];
+        (ScanKey::from_slice(&[1u8; 32]).unwrap(), pubkey(2), inputs)
+    }
+    
+    /// A candidate paying `output_key` with a P2TR script, proven in its own block
+    fn p2tr_candidate(
+        txids: &[[u8; 32]],
+        index: usize,
+        output_key: &bitcoin::secp256k1::XOnlyPublicKey,
+    ) -> OutputCandidate {
+        candidate_with_script(txids, index, format!("5120{}", hex::encode(output_key.serialize())))
+    }
+    
+    #[test]
+    fn test_verify_inclusion_valid_proof() {
+        let txids: Vec<[u8; 32]> = (1..=5u8).map(|i| [i; 32]).collect();
+        let candidate = candidate_with_proof(&txids, 3);
+        assert!(verify_inclusion(&candidate));
+    }
+    
+    #[test]
+    fn test_verify_inclusion_rejects_tampering() {
+        let txids: Vec<[u8; 32]> = (1..=5u8).map(|i| [i; 32]).collect();
+        
+        // Tampered branch
+        let mut candidate = candidate_with_proof(&txids, 3);
+        candidate.proof.as_mut().unwrap().branch[0] = hex::encode([0xffu8; 32]);
+        assert!(!verify_inclusion(&candidate));
+        
+        // Txid swapped for one not in the block
+        let mut candidate = candidate_with_proof(&txids, 3);
+        candidate.txid = hex::encode([0x42u8; 32]);
+        assert!(!verify_inclusion(&candidate));
+        
+        // Header that doesn't match the claimed block hash
+        let mut candidate = candidate_with_proof(&txids, 3);
+        candidate.block_hash = hex::encode([0u8; 32]);
+        assert!(!verify_inclusion(&candidate));
+        
+        // No proof at all
+        let mut candidate = candidate_with_proof(&txids, 3);
+        candidate.proof = None;
+        assert!(!verify_inclusion(&candidate));
+    }
+    
+    fn candidate_json(c: &OutputCandidate) -> serde_json::Value {
+        let proof = c.proof.as_ref().unwrap();
+        serde_json::json!({
+            "txid": c.txid,
+            "vout": c.vout,
+            "amount": c.amount,
+            "script_pubkey": c.script_pubkey,
+            "block_height": c.block_height,
+            "block_hash": c.block_hash,
+            "timestamp": c.timestamp,
+            "proof": {
+                "block_header": proof.block_header,
+                "tx_index": proof.tx_index,
+                "branch": proof.branch,
+            },
+        })
+    }
+    
+    /// Read one HTTP request from the socket, returning its request line
+    /// and body.
+    async fn read_request(socket: &mut tokio::net::TcpStream) -> (String, String) {
+        use tokio::io::AsyncReadExt;
+        
+        let mut buf = Vec::new();
+        let mut chunk = [0u8; 4096];
+        loop {
+            let n = socket.read(&mut chunk).await.unwrap_or(0);
+            if n == 0 {
+                break;
+            }
+            buf.extend_from_slice(&chunk[..n]);
+            
+            let text = String::from_utf8_lossy(&buf);
+            if let Some(header_end) = text.find("\r\n\r\n") {
+                let content_length = text[..header_end]
+                    .lines()
+                    .find_map(|l| {
+                        let (name, value) = l.split_once(':')?;
+                        name.eq_ignore_ascii_case("content-length")
+                            .then(|| value.trim().parse::<usize>().ok())?
+                    })
+                    .unwrap_or(0);
+                if buf.len() >= header_end + 4 + content_length {
+                    let request_line = text.lines().next().unwrap_or_default().to_string();
+                    return (request_line, text[header_end + 4..].to_string());
+                }
+            }
+        }
+        (String::new(), String::new())
+    }
+    
+    /// Minimal HTTP server answering every request with the same JSON body.
+    async fn mock_server(response_body: String) -> String {
+        mock_server_with(move |_, _| response_body.clone()).await
+    }
+    
+    /// Minimal HTTP server answering each request with the JSON body
+    /// returned by `handler(request_line, request_body)`.
+    async fn mock_server_with<F>(handler: F) -> String
+    where
+        F: Fn(&str, &str) -> String + Send + Sync + 'static,
+    {
+        mock_server_with_status(move |line, body| (200, handler(line, body))).await
+    }
+    
+    /// Like `mock_server_with`, with the handler also choosing the status code.
+    async fn mock_server_with_status<F>(handler: F) -> String
+    where
+        F: Fn(&str, &str) -> (u16, String) + Send + Sync + 'static,
+    {
+        use tokio::io::AsyncWriteExt;
+        
+        let handler = std::sync::Arc::new(handler);
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                let handler = handler.clone();
+                tokio::spawn(async move {
+                    let (request_line, request_body) = read_request(&mut socket).await;
+                    let (status, body) = handler(&request_line, &request_body);
+                    let response = format!(
+                        "HTTP/1.1 {} Status\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                        status,
+                        body.len(),
+                        body
+                    );
+                    let _ = socket.write_all(response.as_bytes()).await;
+                });
+            }
+        });
+        format!("http://{}", addr)
+    }
+    
+    #[tokio::test]
+    async fn test_scan_range_detailed_counts_false_positives() {
+        use bitcoin::secp256k1::{PublicKey, Secp256k1};
+        
+        let secp = Secp256k1::new();
+        let (scan_key, spend_pubkey, inputs) = test_wallet();
+        
+        let shared_secret = scan_key.compute_shared_secret(&inputs).unwrap();
+        let ours = scan_key.derive_output_pubkey(&shared_secret, &spend_pubkey, None).unwrap();
+        
+        // One real payment plus three unrelated outputs the server matched by prefix
+        let mut output_keys = vec![ours];
+        for i in 50..53u8 {
+            let other = PublicKey::from_secret_key(&secp, &SecretKey::from_slice(&[i; 32]).unwrap());
+            output_keys.push(other.x_only_public_key().0);
+        }
+        
+        let txids: Vec<[u8; 32]> = (1..=4u8).map(|i| [i; 32]).collect();
+        let candidates: Vec<serde_json::Value> = output_keys
+            .iter()
+            .enumerate()
+            .map(|(i, key)| candidate_json(&p2tr_candidate(&txids, i, key)))
+            .collect();
+        let body = serde_json::json!({
+            "candidates": candidates,
+            "scanned_blocks": [1],
+            "server_time_ms": 1,
+        });
+        
+        let url = mock_server(body.to_string()).await;
+        let client = SilentPaymentClient::new(url, scan_key, spend_pubkey, 0);
+        let outcome = client.scan_range_detailed(0, 10, &inputs).await.unwrap();
+        
+        // The match leads to a request for k = 1, answered with the same
+        // four candidates, none of them a k = 1 output
+        assert_eq!(outcome.candidates_checked, 8);
+        assert_eq!(outcome.results.len(), 1);
+        assert_eq!(outcome.results[0].output_pubkey, ours);
+        assert_eq!(outcome.false_positives, 7);
+        assert_eq!(outcome.rejected_proofs, 0);
+    }
+    
+    /// In-process transport answering every scan with fixed candidates and
+    /// recording the prefixes it was asked for. Transactions not in
+    /// `transactions` are not found. With `filter_prefixes` it acts as an
+    /// in-memory index, returning only candidates whose output key starts
+    /// with a requested prefix.
+    #[derive(Default)]
+    struct MockTransport {
+        candidates: Vec<OutputCandidate>,
+        transactions: Vec<bitcoin::Transaction>,
+        filter_prefixes: bool,
+        /// Advertised `max_block_range`; 1000 when unset
+        max_block_range: Option<u32>,
+        requested_prefixes: std::sync::Mutex<Vec<String>>,
+        requested_scan_pubkeys: std::sync::Mutex<Vec<String>>,
+        requested_ranges: std::sync::Mutex<Vec<(i32, i32)>>,
+    }
+    
+    impl ScanTransport for MockTransport {
+        async fn scan(&self, request: &ScanRequest) -> Result<ScanResponse, ClientError> {
+            self.requested_prefixes.lock().unwrap().extend(request.prefixes.iter().cloned());
+            self.requested_scan_pubkeys.lock().unwrap().push(request.scan_pubkey.clone());
+            self.requested_ranges.lock().unwrap().push((request.start_height, request.end_height));
+            let range = request.start_height..=request.end_height;
+            Ok(ScanResponse {
+                candidates: self.candidates
+                    .iter()
+                    .filter(|candidate| range.contains(&candidate.block_height))
+                    .filter(|candidate| {
+                        // Script hex is `5120` followed by the output key
+                        !self.filter_prefixes || request.prefixes.iter().any(|prefix| {
+                            candidate.script_pubkey.get(4..4 + prefix.len()) == Some(prefix.as_str())
+                        })
+                    })
+                    .cloned()
+                    .collect(),
+                scanned_blocks: (request.start_height..=request.end_height).collect(),
+                server_time_ms: 0,
+            })
+        }
+        
+        async fn status(&self) -> Result<ServerStatus, ClientError> {
+            Ok(ServerStatus {
+                status: "ok".into(),
+                tip_height: 10,
+                network: "regtest".into(),
+                total_outputs: 0,
+                total_blocks: 0,
+                uptime_seconds: 0,
+                version: String::new(),
+                max_block_range: Some(self.max_block_range.unwrap_or(1000)),
+                max_prefixes: Some(1000),
+                prefix_len: Some(4),
+                node_tip: None,
+                blocks_behind: None,
+            })
+        }
+        
+        async fn transaction(&self, txid: &str) -> Result<TransactionResponse, ClientError> {
+            if let Some(tx) = self.transactions.iter().find(|tx| tx.compute_txid().to_string() == txid) {
+                return Ok(TransactionResponse {
+                    raw_tx: hex::encode(bitcoin::consensus::serialize(tx)),
+                    block_hash: String::new(),
+                });
+            }
+            Err(ClientError::ServerError {
+                status: 404,
+                kind: ServerErrorKind::NotFound,
+                message: format!("Transaction {} not found", txid),
+            })
+        }
+    }
+    
+    /// `MockTransport` that holds each scan request for a while, recording
+    /// how many were in flight at once.
+    #[derive(Default)]
+    struct HeldTransport {
+        inner: MockTransport,
+        in_flight: AtomicUsize,
+        max_in_flight: AtomicUsize,
+    }
+    
+    impl ScanTransport for HeldTransport {
+        async fn scan(&self, request: &ScanRequest) -> Result<ScanResponse, ClientError> {
+            let now = self.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+            self.max_in_flight.fetch_max(now, Ordering::SeqCst);
+            tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+            self.in_flight.fetch_sub(1, Ordering::SeqCst);
+            self.inner.scan(request).await
+        }
+        
+        async fn status(&self) -> Result<ServerStatus, ClientError> {
+            self.inner.status().await
+        }
+        
+        async fn transaction(&self, txid: &str) -> Result<TransactionResponse, ClientError> {
+            self.inner.transaction(txid).await
+        }
+    }
+    
+    #[tokio::test]
+    async fn test_scan_range_with_mock_transport() {
+        use bitcoin::secp256k1::{PublicKey, Secp256k1};
+        
+        let secp = Secp256k1::new();
+        let (scan_key, spend_pubkey, inputs) = test_wallet();
+        let ours = scan_key.compute_expected_outputs(&spend_pubkey, &inputs, 0).unwrap()[0];
+        let other = PublicKey::from_secret_key(&secp, &SecretKey::from_slice(&[50u8; 32]).unwrap())
+            .x_only_public_key().0;
+        
+        let txids: Vec<[u8; 32]> = (1..=2u8).map(|i| [i; 32]).collect();
+        let transport = MockTransport {
+            candidates: [ours, other]
+                .iter()
+                .enumerate()
+                .map(|(i, key)| p2tr_candidate(&txids, i, key))
+                .collect(),
+            ..Default::default()
+        };
+        
+        let client = SilentPaymentClient::with_transport(transport, scan_key.clone(), spend_pubkey, 0)
+            .with_network(Network::Regtest);
+        let results = client.scan_range(0, 10, &inputs).await.unwrap();
+        
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].output_pubkey, ours);
+        
+        // Finding k = 0 leads to a request for k = 1, which finds nothing
+        let next = scan_key.compute_expected_outputs_for_k(&spend_pubkey, &inputs, &[None], 1).unwrap()[0];
+        assert_eq!(
+            *client.transport.requested_prefixes.lock().unwrap(),
+            vec![format!("{:08x}", prefix_from_xonly(&ours)), format!("{:08x}", prefix_from_xonly(&next))]
+        );
+        
+        // Transport errors surface unchanged
+        let err = client.verify_candidate(&results[0], &bitcoin::constants::genesis_block(Network::Regtest).header)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, ClientError::ServerError { kind: ServerErrorKind::NotFound, .. }));
+    }
+    
+    #[tokio::test]
+    async fn test_generated_payments_round_trip_through_index() {
+        use bitcoin::hashes::Hash;
+        use bitcoin::secp256k1::{PublicKey, Secp256k1};
+        
+        let secp = Secp256k1::new();
+        let wallet = |scan: u8, spend: u8| {
+            let spend_pubkey = PublicKey::from_secret_key(&secp, &SecretKey::from_slice(&[spend; 32]).unwrap());
+            (ScanKey::from_slice(&[scan; 32]).unwrap(), spend_pubkey)
+        };
+        let (scan_key, spend_pubkey) = wallet(1, 2);
+        let (other_scan, other_spend) = wallet(5, 6);
+        
+        // Two payments to us (unlabeled and label 2), one to someone else,
+        // all in one block
+        let payments = [
+            generate_test_payment(&scan_key, &spend_pubkey, None, 0, 1).unwrap(),
+            generate_test_payment(&scan_key, &spend_pubkey, Some(2), 0, 2).unwrap(),
+            generate_test_payment(&other_scan, &other_spend, None, 0, 3).unwrap(),
+        ];
+        let txids: Vec<[u8; 32]> = payments
+            .iter()
+            .map(|p| p.transaction.compute_txid().to_byte_array())
+            .collect();
+        let candidates = payments
+            .iter()
+            .enumerate()
+            .map(|(i, payment)| {
+                let output = &payment.transaction.output[0];
+                OutputCandidate {
+                    amount: Some(output.value.to_sat() as i64),
+                    ..candidate_with_script(&txids, i, hex::encode(output.script_pubkey.as_bytes()))
+                }
+            })
+            .collect();
+        
+        let transport = MockTransport { candidates, filter_prefixes: true, ..Default::default() };
+        let client = SilentPaymentClient::with_transport(transport, scan_key, spend_pubkey, 0)
+            .with_network(Network::Regtest)
+            .with_labels(&[2]);
+        
+        // Each wallet computes its prefixes from the transaction's inputs
+        let mut found = Vec::new();
+        for payment in &payments {
+            let inputs = InputData::from_transaction(&payment.transaction, &payment.prevouts);
+            let outcome = client.scan_range_detailed(0, 10, &inputs).await.unwrap();
+            assert_eq!(outcome.false_positives, 0);
+            found.extend(outcome.results);
+        }
+        
+        assert_eq!(found.len(), 2);
+        for (result, payment) in found.iter().zip(&payments) {
+            assert_eq!(*result, payment.expected);
+            assert_eq!(result.amount, payment.expected.amount);
+            assert_eq!(result.label, payment.expected.label);
+            assert_eq!(result.block_height, Some(1));
+        }
+    }
+    
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_concurrent_chunks_match_sequential_scan() {
+        use bitcoin::secp256k1::{PublicKey, Secp256k1};
+        
+        let secp = Secp256k1::new();
+        let (scan_key, spend_pubkey, inputs) = test_wallet();
+        let ours = scan_key.compute_expected_outputs(&spend_pubkey, &inputs, 0).unwrap()[0];
+        let other = PublicKey::from_secret_key(&secp, &SecretKey::from_slice(&[50u8; 32]).unwrap())
+            .x_only_public_key().0;
+        
+        // A payment to us and a stranger's output in each of blocks 1..=20
+        let txids: Vec<[u8; 32]> = (1..=40u8).map(|i| [i; 32]).collect();
+        let candidates: Vec<OutputCandidate> = (0..40)
+            .map(|i| {
+                let key = if i % 2 == 0 { ours } else { other };
+                OutputCandidate { block_height: i as i32 / 2 + 1, ..p2tr_candidate(&txids, i, &key) }
+            })
+            .collect();
+        
+        let client = |workers: usize| {
+            let transport = MockTransport {
+                candidates: candidates.clone(),
+                max_block_range: Some(3),
+                ..Default::default()
+            };
+            SilentPaymentClient::with_transport(transport, scan_key.clone(), spend_pubkey, 0).with_workers(workers)
+        };
+        
+        let sequential = client(1).scan_full_range(1, 20, &inputs).await.unwrap();
+        let pooled = client(4);
+        let concurrent = pooled.scan_full_range(1, 20, &inputs).await.unwrap();
+        
+        assert_eq!(sequential.len(), 20);
+        assert_eq!(concurrent, sequential);
+        let heights: Vec<Option<u32>> = concurrent.iter().map(|result| result.block_height).collect();
+        assert_eq!(heights, (1..=20).map(Some).collect::<Vec<_>>());
+        // Seven chunks, each finding k = 0 and then asking for k = 1
+        assert_eq!(pooled.transport.requested_ranges.lock().unwrap().len(), 14);
+        
+        // Held requests overlap with several workers, never with one
+        let held = |workers: usize| {
+            let transport = HeldTransport {
+                inner: MockTransport {
+                    candidates: candidates.clone(),
+                    max_block_range: Some(3),
+                    ..Default::default()
+                },
+                ..Default::default()
+            };
+            SilentPaymentClient::with_transport(transport, scan_key.clone(), spend_pubkey, 0).with_workers(workers)
+        };
+        let (one, four) = (held(1), held(4));
+        assert_eq!(one.scan_full_range(1, 20, &inputs).await.unwrap(), sequential);
+        assert_eq!(four.scan_full_range(1, 20, &inputs).await.unwrap(), sequential);
+        assert_eq!(one.transport.max_in_flight.load(Ordering::SeqCst), 1);
+        assert!(four.transport.max_in_flight.load(Ordering::SeqCst) > 1);
+    }
+    
+    #[tokio::test]
+    async fn test_hidden_amount_is_read_from_transaction() {
+        use bitcoin::hashes::Hash;
+        
+        let (scan_key, spend_pubkey, inputs) = test_wallet();
+        let ours = scan_key.compute_expected_outputs(&spend_pubkey, &inputs, 0).unwrap()[0];
+        let script = bitcoin::ScriptBuf::new_p2tr_tweaked(
+            bitcoin::key::TweakedPublicKey::dangerous_assume_tweaked(ours)
+        );
+        
+        let tx = bitcoin::Transaction {
+            version: bitcoin::transaction::Version::TWO,
+            lock_time: bitcoin::absolute::LockTime::ZERO,
+            input: vec![bitcoin::TxIn::default()],
+            output: vec![bitcoin::TxOut { value: bitcoin::Amount::from_sat(42_000), script_pubkey: script.clone() }],
+        };
+        let txids = [tx.compute_txid().to_byte_array()];
+        let candidate = OutputCandidate {
+            amount: None,
+            ..candidate_with_script(&txids, 0, hex::encode(script.as_bytes()))
+        };
+        
+        // A server with REVEAL_AMOUNTS=false leaves the field out
+        let mut json = candidate_json(&candidate);
+        json.as_object_mut().unwrap().remove("amount");
+        let parsed: OutputCandidate = serde_json::from_value(json).unwrap();
+        assert_eq!(parsed.amount, None);
+        
+        let transport = MockTransport {
+            candidates: vec![parsed],
+            transactions: vec![tx],
+            ..Default::default()
+        };
+        let client = SilentPaymentClient::with_transport(transport, scan_key, spend_pubkey, 0)
+            .with_network(Network::Regtest);
+        let results = client.scan_range(0, 10, &inputs).await.unwrap();
+        
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].amount, 42_000);
+    }
+    
+    #[tokio::test]
+    async fn test_transaction_paying_two_labels_yields_both() {
+        use bitcoin::secp256k1::{PublicKey, Secp256k1};
+        
+        let secp = Secp256k1::new();
+        let (scan_key, spend_pubkey, inputs) = test_wallet();
+        let to_label = |m, k| scan_key.compute_expected_outputs_for_k(&spend_pubkey, &inputs, &[Some(m)], k).unwrap()[0];
+        let other = PublicKey::from_secret_key(&secp, &SecretKey::from_slice(&[50u8; 32]).unwrap())
+            .x_only_public_key().0;
+        
+        // One transaction: label 5 (k = 0), change to someone else, label 2
+        // (k = 1), served by prefix as an index would
+        let txids: Vec<[u8; 32]> = (1..=2u8).map(|i| [i; 32]).collect();
+        let candidates = [to_label(5, 0), other, to_label(2, 1)]
+            .iter()
+            .enumerate()
+            .map(|(vout, key)| OutputCandidate {
+                vout: vout as i32,
+                ..p2tr_candidate(&txids, 0, key)
+            })
+            .collect();
+        let transport = MockTransport { candidates, filter_prefixes: true, ..Default::default() };
+        
+        let client = SilentPaymentClient::with_transport(transport, scan_key.clone(), spend_pubkey, 0)
+            .with_labels(&[2, 5]);
+        let outcome = client.scan_range_detailed(0, 10, &inputs).await.unwrap();
+        
+        // Found by the k = 0 and k = 1 requests; the k = 2 request finds nothing
+        let found: Vec<(u32, Option<u32>)> = outcome.results.iter().map(|r| (r.vout, r.label)).collect();
+        assert_eq!(found, vec![(0, Some(5)), (2, Some(2))]);
+        assert_eq!(outcome.candidates_checked, 2);
+        assert_eq!(client.transport.requested_prefixes.lock().unwrap().len(), 9);
+        
+        // The same scan, timed
+        let (outcome, metrics) = client.scan_range_instrumented(0, 10, &inputs).await.unwrap();
+        assert_eq!(metrics.candidates, 2);
+        assert_eq!(metrics.matches, outcome.results.len());
+        assert!(metrics.matches <= metrics.candidates);
+        assert!(metrics.prefix_ms > 0.0 && metrics.verify_ms > 0.0 && metrics.http_ms >= 0.0);
+    }
+    
+    #[tokio::test]
+    async fn test_discover_labels_reports_labels_paid() {
+        let (scan_key, spend_pubkey, inputs) = test_wallet();
+        let shared_secret = scan_key.compute_shared_secret(&inputs).unwrap();
+        let output = |label| scan_key.derive_output_pubkey(&shared_secret, &spend_pubkey, label).unwrap();
+        
+        // Payments to the unlabeled output, label 9 and label 3
+        let txids: Vec<[u8; 32]> = (1..=3u8).map(|i| [i; 32]).collect();
+        let candidates = [output(None), output(Some(9)), output(Some(3))]
+            .iter()
+            .enumerate()
+            .map(|(i, key)| p2tr_candidate(&txids, i, key))
+            .collect();
+        let transport = MockTransport { candidates, ..Default::default() };
+        
+        // Discovery ignores a narrower prefix strategy
+        let client = SilentPaymentClient::with_transport(transport, scan_key, spend_pubkey, 20)
+            .with_prefix_strategy(PrefixStrategy::Unlabeled);
+        assert_eq!(client.discover_labels(&inputs, 0..=10).await.unwrap(), vec![0, 3, 9]);
+        // Every label for k = 0, then again for k = 1
+        assert_eq!(client.transport.requested_prefixes.lock().unwrap().len(), 42);
+    }
+    
+    #[tokio::test]
+    async fn test_result_txid_formats_as_explorer_txid() {
+        use bitcoin::hashes::Hash;
+        
+        let (scan_key, spend_pubkey, inputs) = test_wallet();
+        let shared_secret = scan_key.compute_shared_secret(&inputs).unwrap();
+        let output = scan_key.derive_output_pubkey(&shared_secret, &spend_pubkey, None).unwrap();
+        
+        // The genesis coinbase, as any block explorer shows it
+        let explorer_txid = "4a5e1e4baab89f3a32518a88c31bc87f618f76673e2cc77ab2127b7afdeda33b";
+        let genesis = bitcoin::constants::genesis_block(Network::Bitcoin);
+        let txids = vec![genesis.txdata[0].compute_txid().to_byte_array()];
+        let candidate = p2tr_candidate(&txids, 0, &output);
+        assert_eq!(candidate.txid, explorer_txid);
+        
+        let transport = MockTransport { candidates: vec![candidate], ..Default::default() };
+        let client = SilentPaymentClient::with_transport(transport, scan_key, spend_pubkey, 0);
+        let results = client.scan_range(0, 10, &inputs).await.unwrap();
+        
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].txid, txids[0]);
+        assert_eq!(results[0].outpoint().txid.to_string(), explorer_txid);
+    }
+    
+    #[tokio::test]
+    async fn test_unlabeled_prefix_strategy_sends_one_prefix() {
+        let (scan_key, spend_pubkey, inputs) = test_wallet();
+        let shared_secret = scan_key.compute_shared_secret(&inputs).unwrap();
+        let unlabeled = scan_key.derive_output_pubkey(&shared_secret, &spend_pubkey, None).unwrap();
+        
+        let txids: Vec<[u8; 32]> = vec![[1u8; 32]];
+        let transport = MockTransport {
+            candidates: vec![p2tr_candidate(&txids, 0, &unlabeled)],
+            ..Default::default()
+        };
+        let client = SilentPaymentClient::with_transport(transport, scan_key.clone(), spend_pubkey, 10)
+            .with_prefix_strategy(PrefixStrategy::Unlabeled);
+        let results = client.scan_range(0, 10, &inputs).await.unwrap();
+        
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].label, None);
+        let next = scan_key.compute_expected_outputs_for_k(&spend_pubkey, &inputs, &[None], 1).unwrap()[0];
+        assert_eq!(
+            *client.transport.requested_prefixes.lock().unwrap(),
+            vec![format!("{:08x}", prefix_from_xonly(&unlabeled)), format!("{:08x}", prefix_from_xonly(&next))]
+        );
+        
+        // Subset(3): unlabeled plus two labels per request, rotating
+        let labels: Vec<Option<u32>> = std::iter::once(None).chain((1..=5).map(Some)).collect();
+        let rounds: Vec<_> = (0..3).map(|round| PrefixStrategy::Subset(3).select(&labels, round)).collect();
+        assert_eq!(rounds, vec![
+            vec![None, Some(1), Some(2)],
+            vec![None, Some(3), Some(4)],
+            vec![None, Some(5), Some(1)],
+        ]);
+        assert_eq!(PrefixStrategy::Subset(0).select(&labels, 4), vec![None]);
+    }
+    
+    #[tokio::test]
+    async fn test_subset_strategy_uses_one_round_per_chunked_scan() {
+        let (scan_key, spend_pubkey, inputs) = test_wallet();
+        let transport = MockTransport {
+            max_block_range: Some(3),
+            ..Default::default()
+        };
+        let client = SilentPaymentClient::with_transport(transport, scan_key.clone(), spend_pubkey, 5)
+            .with_prefix_strategy(PrefixStrategy::Subset(2))
+            .with_workers(4);
+        let expected_prefixes = |label: u32| -> Vec<String> {
+            scan_key
+                .compute_expected_outputs_for_labels(&spend_pubkey, &inputs, &[None, Some(label)])
+                .unwrap()
+                .iter()
+                .map(|output| format!("{:08x}", prefix_from_xonly(output)))
+                .collect()
+        };
+        
+        // Four chunks, each with the unlabeled prefix and label 1
+        client.scan_full_range(0, 10, &inputs).await.unwrap();
+        let requested = std::mem::take(&mut *client.transport.requested_prefixes.lock().unwrap());
+        assert_eq!(requested.len(), 8);
+        for chunk in requested.chunks(2) {
+            assert_eq!(chunk, expected_prefixes(1).as_slice());
+        }
+        
+        // The next full scan moves on to label 2 for every chunk
+        client.scan_full_range(0, 10, &inputs).await.unwrap();
+        let requested = client.transport.requested_prefixes.lock().unwrap();
+        assert_eq!(requested.len(), 8);
+        for chunk in requested.chunks(2) {
+            assert_eq!(chunk, expected_prefixes(2).as_slice());
+        }
+    }
+    
+    #[tokio::test]
+    async fn test_scan_since_checkpoint_resumes_after_last_height() {
+        let (scan_key, spend_pubkey, inputs) = test_wallet();
+        let shared_secret = scan_key.compute_shared_secret(&inputs).unwrap();
+        let ours = scan_key.derive_output_pubkey(&shared_secret, &spend_pubkey, None).unwrap();
+        
+        // Two payments, at heights 4 and 9 (the mock's tip is 10)
+        let txids: Vec<[u8; 32]> = (1..=2u8).map(|i| [i; 32]).collect();
+        let candidates = [(0, 4), (1, 9)]
+            .into_iter()
+            .map(|(index, height)| OutputCandidate {
+                block_height: height,
+                ..p2tr_candidate(&txids, index, &ours)
+            })
+            .collect();
+        let transport = MockTransport { candidates, ..Default::default() };
+        let client = SilentPaymentClient::with_transport(transport, scan_key, spend_pubkey, 0)
+            .with_rescan_depth(0);
+        
+        // A fresh wallet scans everything
+        let checkpoint = client.scan_since_checkpoint(&ScanCheckpoint::default(), &inputs).await.unwrap();
+        assert_eq!(checkpoint.last_scanned_height, 10);
+        assert_eq!(checkpoint.results.len(), 2);
+        
+        // Resuming from height 6 only asks for blocks 7..=10 (for k = 0,
+        // then k = 1 after the payment at 9), keeping the earlier result;
+        // the checkpoint round-trips through JSON
+        let saved = ScanCheckpoint { last_scanned_height: 6, results: checkpoint.results[..1].to_vec() };
+        let saved: ScanCheckpoint = serde_json::from_str(&serde_json::to_string(&saved).unwrap()).unwrap();
+        client.transport.requested_ranges.lock().unwrap().clear();
+        let resumed = client.scan_since_checkpoint(&saved, &inputs).await.unwrap();
+        assert_eq!(*client.transport.requested_ranges.lock().unwrap(), vec![(7, 10), (7, 10)]);
+        assert_eq!(resumed, checkpoint);
+        
+        // With a rescan depth the range reaches back below the checkpoint,
+        // replacing results at those heights instead of duplicating them
+        let client = client.with_rescan_depth(3);
+        client.transport.requested_ranges.lock().unwrap().clear();
+        let rescanned = client.scan_since_checkpoint(&checkpoint, &inputs).await.unwrap();
+        assert_eq!(*client.transport.requested_ranges.lock().unwrap(), vec![(8, 10), (8, 10)]);
+        assert_eq!(rescanned, checkpoint);
+        
+        // Mempool results are not carried over; a confirmed output comes
+        // back from the rescan with its height
+        let mut with_mempool = checkpoint.clone();
+        with_mempool.results.push(ScanResult { block_height: None, ..checkpoint.results[0].clone() });
+        let rescanned = client.scan_since_checkpoint(&with_mempool, &inputs).await.unwrap();
+        assert_eq!(rescanned, checkpoint);
+    }
+    
+    #[tokio::test]
+    async fn test_scan_attributes_results_to_accounts() {
+        use bitcoin::secp256k1::{PublicKey, Secp256k1};
+        
+        let secp = Secp256k1::new();
+        let spend = |seed: u8| {
+            PublicKey::from_secret_key(&secp, &SecretKey::from_slice(&[seed; 32]).unwrap())
+        };
+        let (scan_a, spend_a, inputs) = test_wallet();
+        let (scan_b, spend_b) = (ScanKey::from_slice(&[11u8; 32]).unwrap(), spend(12));
+        let for_a = scan_a.compute_expected_outputs(&spend_a, &inputs, 0).unwrap()[0];
+        let for_b = scan_b.compute_expected_outputs(&spend_b, &inputs, 1).unwrap()[1];
+        
+        // Account B's label-1 output comes first, then account A's
+        let txids: Vec<[u8; 32]> = (1..=2u8).map(|i| [i; 32]).collect();
+        let transport = MockTransport {
+            candidates: [for_b, for_a]
+                .iter()
+                .enumerate()
+                .map(|(i, key)| p2tr_candidate(&txids, i, key))
+                .collect(),
+            ..Default::default()
+        };
+        
+        let client = SilentPaymentClient::with_transport(transport, scan_a.clone(), spend_a, 0)
+            .with_account(scan_b, spend_b, 1);
+        let outcome = client.scan_range_detailed(0, 10, &inputs).await.unwrap();
+        
+        assert_eq!(outcome.results.len(), 2);
+        assert_eq!(outcome.results[0].output_pubkey, for_b);
+        assert_eq!(outcome.results[0].label, Some(1));
+        assert_eq!(outcome.results[1].output_pubkey, for_a);
+        let accounts: Vec<usize> = outcome.results.iter().map(|result| result.account).collect();
+        assert_eq!(accounts, vec![1, 0]);
+        
+        // Chunked and checkpoint scans keep the attribution
+        let full: Vec<usize> = client.scan_full_range(0, 10, &inputs).await.unwrap()
+            .iter()
+            .map(|result| result.account)
+            .collect();
+        assert_eq!(full, vec![1, 0]);
+        let checkpoint = client.scan_since_checkpoint(&ScanCheckpoint::default(), &inputs).await.unwrap();
+        let since: Vec<usize> = checkpoint.results.iter().map(|result| result.account).collect();
+        assert_eq!(since, vec![1, 0]);
+        
+        // No request names either account's scan key
+        let scan_pubkeys = client.transport.requested_scan_pubkeys.lock().unwrap().clone();
+        assert!(scan_pubkeys.iter().all(|key| key == PLACEHOLDER_SCAN_PUBKEY));
+        assert!(!scan_pubkeys.contains(&hex::encode(scan_a.public.serialize())));
+        client.transport.requested_prefixes.lock().unwrap().clear();
+        client.scan_range(0, 10, &inputs).await.unwrap();
+        
+        // The k = 0 request carried A's prefix and both of B's; both found
+        // a payment, so the k = 1 request carried as many
+        let requested = client.transport.requested_prefixes.lock().unwrap();
+        assert_eq!(requested.len(), 6);
+        assert!(requested[..3].contains(&format!("{:08x}", prefix_from_xonly(&for_a))));
+        assert!(requested[..3].contains(&format!("{:08x}", prefix_from_xonly(&for_b))));
+    }
+    
+    #[tokio::test]
+    async fn test_malformed_candidate_does_not_abort_scan() {
+        use bitcoin::secp256k1::{PublicKey, Secp256k1};
+        
+        let secp = Secp256k1::new();
+        let (scan_key, spend_pubkey, inputs) = test_wallet();
+        let ours = scan_key.compute_expected_outputs(&spend_pubkey, &inputs, 0).unwrap()[0];
+        let other = PublicKey::from_secret_key(&secp, &SecretKey::from_slice(&[50u8; 32]).unwrap())
+            .x_only_public_key().0;
+        
+        // Invalid hex and an output key that isn't a curve point, between
+        // a real payment and an unrelated output
+        let scripts = [
+            format!("5120{}", hex::encode(ours.serialize())),
+            "5120zz".to_string(),
+            format!("5120{}", "ff".repeat(32)),
+            format!("5120{}", hex::encode(other.serialize())),
+        ];
+        let txids: Vec<[u8; 32]> = (1..=4u8).map(|i| [i; 32]).collect();
+        let transport = MockTransport {
+            candidates: scripts
+                .iter()
+                .enumerate()
+                .map(|(i, script)| candidate_with_script(&txids, i, script.clone()))
+                .collect(),
+            ..Default::default()
+        };
+        
+        let client = SilentPaymentClient::with_transport(transport, scan_key, spend_pubkey, 0);
+        let outcome = client.scan_range_detailed(0, 10, &inputs).await.unwrap();
+        
+        // The k = 1 request gets the same four candidates back, and finds
+        // nothing in them
+        assert_eq!(outcome.candidates_checked, 8);
+        assert_eq!(outcome.results.len(), 1);
+        assert_eq!(outcome.results[0].output_pubkey, ours);
+        assert_eq!(outcome.false_positives, 3);
+        assert_eq!(outcome.errors.len(), 4);
+        assert!(matches!(*outcome.errors[0], ClientError::InvalidResponse(_)));
+        assert!(matches!(*outcome.errors[1], ClientError::Core(CoreError::InvalidKey(_))));
+    }
+    
+    #[test]
+    fn test_keypair_client_checks_derivation_network() {
+        let seed = hex::decode("000102030405060708090a0b0c0d0e0f").unwrap();
+        let testnet = derive_keys_from_seed(&seed, Network::Testnet, 0).unwrap();
+        let url = || "http://localhost:3000".to_string();
+        
+        assert!(matches!(
+            SilentPaymentClient::from_keypair(url(), &testnet, 0, Network::Bitcoin),
+            Err(ClientError::Core(CoreError::NetworkMismatch(_)))
+        ));
+        let client = SilentPaymentClient::from_keypair(url(), &testnet, 2, Network::Regtest).unwrap();
+        assert_eq!(client.network, Some(Network::Regtest));
+        assert_eq!(client.accounts[0].spend_key, testnet.spend.public);
+        assert_eq!(client.accounts[0].spend_secret, testnet.spend.secret);
+        
+        // The builder checks once both the keypair and the network are known
+        let builder = || SilentPaymentClient::builder().base_url(url()).keypair(&testnet);
+        assert!(matches!(
+            builder().network(Network::Bitcoin).build(),
+            Err(ClientError::Core(CoreError::NetworkMismatch(_)))
+        ));
+        assert!(builder().network(Network::Signet).build().is_ok());
+        assert!(builder().build().is_ok());
+    }
+    
+    #[test]
+    fn test_scan_only_client_refuses_spend_secret() {
+        let keypair = SilentPaymentKeypair::from_secrets(
+            SecretKey::from_slice(&[81u8; 32]).unwrap(),
+            SecretKey::from_slice(&[82u8; 32]).unwrap(),
+        ).unwrap();
+        let spend_secret = keypair.spend.secret.unwrap();
+        
+        let client = SilentPaymentClient::from_scan_only("http://localhost:3000".into(), keypair.clone().into(), 0);
+        assert!(matches!(client.with_spend_secret(spend_secret), Err(ClientError::InvalidConfig(_))));
+        
+        // The builder keeps the restriction
+        let builder = || SilentPaymentClient::builder()
+            .base_url("http://localhost:3000")
+            .scan_only(keypair.clone().into());
+        let client = builder().build().unwrap();
+        assert!(client.scan_only);
+        assert_eq!(client.accounts[0].spend_key, keypair.spend.public);
+        assert!(matches!(builder().spend_secret(spend_secret).build(), Err(ClientError::InvalidConfig(_))));
+        
+        // Other clients still take one
+        let client = SilentPaymentClient::new("http://localhost:3000".into(), keypair.scan.clone(), keypair.spend.public, 0);
+        assert!(client.with_spend_secret(spend_secret).is_ok());
+    }
+    
+    #[tokio::test]
+    async fn test_keypair_detects_payment_to_own_address() {
+        use bitcoin::secp256k1::{PublicKey, Scalar, Secp256k1};
+        
+        let secp = Secp256k1::new();
+        let keypair = SilentPaymentKeypair::from_secrets(
+            SecretKey::from_slice(&[4u8; 32]).unwrap(),
+            SecretKey::from_slice(&[5u8; 32]).unwrap(),
+        ).unwrap();
+        let address = keypair.address(Network::Regtest);
+        assert_eq!(address.network, Network::Regtest);
+        
+        // Sender pays `address` from an input with secret a, spending the
+        // null outpoint (the receiver's input below has none):
+        // P = B_spend + hash(input_hash * a * B_scan || 0)*G
+        let input_secret = SecretKey::from_slice(&[6u8; 32]).unwrap();
+        let input_hash = whisper_core::input_hash(&[bitcoin::OutPoint::null()], &input_secret.public_key(&secp)).unwrap();
+        let ecdh = address.scan_pubkey
+            .mul_tweak(&secp, &Scalar::from_be_bytes(input_secret.secret_bytes()).unwrap())
+            .unwrap()
+            .mul_tweak(&secp, &Scalar::from_be_bytes(input_hash).unwrap())
+            .unwrap();
+        let tweak = Scalar::from_be_bytes(TaggedHash::output(&ecdh.serialize(), 0)).unwrap();
+        let output = address.spend_pubkey
+            .add_exp_tweak(&secp, &tweak)
+            .unwrap()
+            .x_only_public_key().0;
+        
+        let txids: Vec<[u8; 32]> = (1..=2u8).map(|i| [i; 32]).collect();
+        let body = serde_json::json!({
+            "candidates": [candidate_json(&p2tr_candidate(&txids, 1, &output))],
+            "scanned_blocks": [1],
+            "server_time_ms": 1,
+        });
+        
+        // Receiver scans with only its own keypair
+        let url = mock_server(body.to_string()).await;
+        let client = SilentPaymentClient::from_address(url, keypair.scan.secret, &address)
+            .unwrap()
+            .with_spend_secret(keypair.spend.secret.unwrap())
+            .unwrap();
+        let inputs = vec![InputData {
+            pubkey: PublicKey::from_secret_key(&secp, &input_secret),
+            is_taproot: false,
+            outpoint: None,
+        }];
+        let results = client.scan_range(0, 10, &inputs).await.unwrap();
+        
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].output_pubkey, output);
+        assert_eq!(keypair.spend.secret.unwrap().public_key(&secp), address.spend_pubkey);
+    }
+    
+    #[tokio::test]
+    async fn test_verify_candidate_checks_reported_amount() {
+        use bitcoin::hashes::Hash;
+        use bitcoin::secp256k1::{PublicKey, Secp256k1};
+        
+        let secp = Secp256k1::new();
+        let spend_pubkey = PublicKey::from_secret_key(&secp, &SecretKey::from_slice(&[7u8; 32]).unwrap());
+        let output_pubkey = spend_pubkey.x_only_public_key().0;
+        let mut result = ScanResult {
+            txid: [0u8; 32],
+            vout: 1,
+            amount: 50_000,
+            label: None,
+            tweak: [0u8; 32],
+            output_pubkey,
+            block_height: Some(1),
+            spend_pubkey: None,
+            account: 0,
+        };
+        
+        let tx = bitcoin::Transaction {
+            version: bitcoin::transaction::Version::TWO,
+            lock_time: bitcoin::absolute::LockTime::ZERO,
+            input: vec![bitcoin::TxIn::default()],
+            output: vec![
+                bitcoin::TxOut { value: bitcoin::Amount::from_sat(1_000), script_pubkey: bitcoin::ScriptBuf::new() },
+                bitcoin::TxOut { value: bitcoin::Amount::from_sat(50_000), script_pubkey: result.output_script() },
+            ],
+        };
+        result.txid = tx.compute_txid().to_byte_array();
+        
+        let txids = [result.txid];
+        let candidate = candidate_with_proof(&txids, 0);
+        let header: bitcoin::block::Header = bitcoin::consensus::deserialize(
+            &hex::decode(&candidate.proof.unwrap().block_header).unwrap(),
+        ).unwrap();
+        
+        let body = serde_json::json!({
+            "txid": tx.compute_txid().to_string(),
+            "raw_tx": hex::encode(bitcoin::consensus::serialize(&tx)),
+            "block_hash": candidate.block_hash,
+        });
+        let url = mock_server(body.to_string()).await;
+        let client = SilentPaymentClient::new(url, ScanKey::from_slice(&[1u8; 32]).unwrap(), spend_pubkey, 0);
+        
+        client.verify_candidate(&result, &header).await.unwrap();
+        
+        // Server reported a different amount than the transaction pays
+        let inflated = ScanResult { amount: 5_000_000, ..result.clone() };
+        assert!(matches!(
+            client.verify_candidate(&inflated, &header).await,
+            Err(ClientError::CandidateMismatch(_))
+        ));
+        
+        // Transaction claimed to be in a block other than the expected one
+        let other_header = bitcoin::block::Header { nonce: 1, ..header };
+        assert!(matches!(
+            client.verify_candidate(&result, &other_header).await,
+            Err(ClientError::CandidateMismatch(_))
+        ));
+    }
+    
+    #[tokio::test]
+    async fn test_network_mismatch_rejected_before_scan() {
+        // Every request gets a status body, so a scan query would fail to parse
+        let body = serde_json::json!({
+            "status": "ok",
+            "tip_height": 100,
+            "network": "mainnet",
+        });
+        let url = mock_server(body.to_string()).await;
+        
+        let scan_key = ScanKey::from_slice(&[1u8; 32]).unwrap();
+        let spend_pubkey = scan_key.public;
+        let client = SilentPaymentClient::new(url.clone(), scan_key.clone(), spend_pubkey, 0)
+            .with_network(Network::Testnet);
+        
+        let result = client.scan_range(0, 10, &[]).await;
+        assert!(matches!(
+            result,
+            Err(ClientError::NetworkMismatch { expected: Network::Testnet, actual: Network::Bitcoin })
+        ));
+        
+        let client = SilentPaymentClient::new(url, scan_key, spend_pubkey, 0)
+            .with_network(Network::Bitcoin);
+        assert!(client.verify_network().await.is_ok());
+    }
+    
+    #[tokio::test]
+    async fn test_address_network_accepts_any_test_network() {
+        let body = serde_json::json!({
+            "status": "ok",
+            "tip_height": 100,
+            "network": "signet",
+        });
+        let url = mock_server(body.to_string()).await;
+        
+        let scan_key = ScanKey::from_slice(&[1u8; 32]).unwrap();
+        let spend_pubkey = scan_key.public;
+        
+        // A decoded tsp address says "testnet" but fits a signet server
+        let address: SilentPaymentAddress = scan_key.to_address(&spend_pubkey, Network::Signet)
+            .to_string()
+            .parse()
+            .unwrap();
+        assert_eq!(address.network, Network::Testnet);
+        let client = SilentPaymentClient::new(url.clone(), scan_key.clone(), spend_pubkey, 0)
+            .with_address_network(&address);
+        assert!(client.verify_network().await.is_ok());
+        
+        // An explicit network is still compared exactly
+        let client = SilentPaymentClient::new(url.clone(), scan_key.clone(), spend_pubkey, 0)
+            .with_network(Network::Testnet);
+        assert!(matches!(
+            client.verify_network().await,
+            Err(ClientError::NetworkMismatch { expected: Network::Testnet, actual: Network::Signet })
+        ));
+        
+        // A mainnet address never matches a test network
+        let mainnet = scan_key.to_address(&spend_pubkey, Network::Bitcoin);
+        let client = SilentPaymentClient::new(url, scan_key, spend_pubkey, 0)
+            .with_address_network(&mainnet);
+        assert!(matches!(
+            client.verify_network().await,
+            Err(ClientError::NetworkMismatch { expected: Network::Bitcoin, actual: Network::Signet })
+        ));
+    }
+    
+    #[tokio::test]
+    async fn test_scan_full_range_chunks_by_server_limit() {
+        use std::sync::{Arc, Mutex};
+        
+        let scans: Arc<Mutex<Vec<(i64, i64)>>> = Arc::default();
+        let recorded = scans.clone();
+        let url = mock_server_with(move |request_line, body| {
+            if request_line.starts_with("GET /api/v1/status") {
+                return serde_json::json!({
+                    "status": "ok",
+                    "tip_height": 3000,
+                    "network": "regtest",
+                    "max_block_range": 1000,
+                })
+                .to_string();
+            }
+            
+            let req: serde_json::Value = serde_json::from_str(body).unwrap();
+            recorded.lock().unwrap().push((
+                req["start_height"].as_i64().unwrap(),
+                req["end_height"].as_i64().unwrap(),
+            ));
+            serde_json::json!({ "candidates": [], "scanned_blocks": [], "server_time_ms": 1 })
+                .to_string()
+        })
+        .await;
+        
+        let (scan_key, spend_pubkey, inputs) = test_wallet();
+        let client = SilentPaymentClient::new(url, scan_key, spend_pubkey, 0);
+        
+        // 2500 blocks with a 1000-block limit: two full chunks and a partial one
+        let results = client.scan_full_range(100, 2599, &inputs).await.unwrap();
+        
+        assert!(results.is_empty());
+        assert_eq!(
+            *scans.lock().unwrap(),
+            vec![(100, 1099), (1100, 2099), (2100, 2599)],
+        );
+    }
+    
+    #[tokio::test]
+    async fn test_scan_full_range_stops_when_cancelled() {
+        use std::sync::{Arc, Mutex};
+        
+        let cancel = CancellationToken::new();
+        let scans: Arc<Mutex<Vec<i64>>> = Arc::default();
+        let (recorded, trigger) = (scans.clone(), cancel.clone());
+        let url = mock_server_with(move |request_line, body| {
+            if request_line.starts_with("GET /api/v1/status") {
+                return serde_json::json!({
+                    "status": "ok",
+                    "tip_height": 3000,
+                    "network": "regtest",
+                    "max_block_range": 1000,
+                })
+                .to_string();
+            }
+            
+            // The wallet UI cancels while the second chunk is being served
+            let req: serde_json::Value = serde_json::from_str(body).unwrap();
+            let mut scans = recorded.lock().unwrap();
+            scans.push(req["start_height"].as_i64().unwrap());
+            if scans.len() == 2 {
+                trigger.cancel();
+            }
+            serde_json::json!({ "candidates": [], "scanned_blocks": [], "server_time_ms": 1 })
+                .to_string()
+        })
+        .await;
+        
+        let (scan_key, spend_pubkey, inputs) = test_wallet();
+        let client = SilentPaymentClient::new(url, scan_key, spend_pubkey, 0);
+        
+        let result = client.scan_full_range_cancellable(0, 4999, &inputs, &cancel).await;
+        
+        assert!(matches!(result, Err(ClientError::Cancelled { partial }) if partial.is_empty()));
+        // Five chunks were needed; none after the cancelled one was requested
+        assert_eq!(*scans.lock().unwrap(), vec![0, 1000]);
+    }
+    
+    #[tokio::test]
+    async fn test_server_error_kinds_are_parsed() {
+        let url = mock_server_with_status(|request_line, _| {
+            let (status, kind) = if request_line.starts_with("POST /api/v1/scan") {
+                (400, "validation")
+            } else {
+                (500, "database")
+            };
+            let body = serde_json::json!({ "error": kind, "message": "nope", "code": status });
+            (status, body.to_string())
+        })
+        .await;
+        
+        let (scan_key, spend_pubkey, inputs) = test_wallet();
+        let client = SilentPaymentClient::new(url, scan_key, spend_pubkey, 0);
+        
+        let err = client.scan_range(0, 10, &inputs).await.unwrap_err();
+        assert!(matches!(
+            &err,
+            ClientError::ServerError { status: 400, kind: ServerErrorKind::Validation, message } if message == "nope"
+        ));
+        assert!(!err.is_retryable());
+        
+        let err = client.get_status().await.unwrap_err();
+        assert!(matches!(err, ClientError::ServerError { status: 500, kind: ServerErrorKind::Database, .. }));
+        assert!(err.is_retryable());
+        
+        // Non-JSON bodies (e.g. from a proxy) keep the raw text
+        let url = mock_server_with_status(|_, _| (502, "Bad Gateway".to_string())).await;
+        let client = SilentPaymentClient::new(url, ScanKey::from_slice(&[1u8; 32]).unwrap(), spend_pubkey, 0);
+        assert!(matches!(
+            client.get_status().await,
+            Err(ClientError::ServerError { status: 502, kind: ServerErrorKind::Unknown, message }) if message == "Bad Gateway"
+        ));
+    }
+    
+    #[tokio::test]
+    async fn test_get_status_reads_limits() {
+        let body = serde_json::json!({
+            "status": "ok",
+            "tip_height": 100,
+            "network": "regtest",
+            "max_block_range": 500,
+            "max_prefixes": 250,
+            "prefix_len": 8,
+        });
+        let url = mock_server(body.to_string()).await;
+        
+        let scan_key = ScanKey::from_slice(&[1u8; 32]).unwrap();
+        let spend_pubkey = scan_key.public;
+        let mut client = SilentPaymentClient::new(url, scan_key, spend_pubkey, 0);
+        let status = client.get_status().await.unwrap();
+        
+        assert_eq!(status.max_block_range, Some(500));
+        assert_eq!(status.max_prefixes, Some(250));
+        
+        assert_eq!(client.negotiate_prefix_len().await.unwrap(), 8);
+        assert_eq!(client.prefix_len, 8);
+    }
+    
+    #[tokio::test]
+    async fn test_request_timeout() {
+        // Server that accepts connections but never answers
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut open = Vec::new();
+            while let Ok((socket, _)) = listener.accept().await {
+                open.push(socket);
+            }
+        });
+        
+        let scan_key = ScanKey::from_slice(&[1u8; 32]).unwrap();
+        let spend_pubkey = scan_key.public;
+        let config = ClientConfig {
+            request_timeout: Duration::from_millis(200),
+            ..ClientConfig::default()
+        };
+        let client = SilentPaymentClient::with_config(
+            format!("http://{}", addr),
+            scan_key,
+            spend_pubkey,
+            0,
+            config,
+        )
+        .unwrap();
+        
+        let started = std::time::Instant::now();
+        let result = client.get_status().await;
+        
+        assert!(matches!(result, Err(ClientError::Timeout(_))));
+        assert!(started.elapsed() < Duration::from_secs(2));
+    }
+    
+    #[tokio::test]
+    async fn test_oversized_response_is_aborted() {
+        use tokio::io::AsyncWriteExt;
+        
+        // Server streaming an endless body without a content-length, so
+        // only a client that stops reading ever finishes the request
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                tokio::spawn(async move {
+                    read_request(&mut socket).await;
+                    let headers = "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\nconnection: close\r\n\r\n[";
+                    if socket.write_all(headers.as_bytes()).await.is_err() {
+                        return;
+                    }
+                    let chunk = vec![b' '; 16 * 1024];
+                    while socket.write_all(&chunk).await.is_ok() {}
+                });
+            }
+        });
+        
+        let scan_key = ScanKey::from_slice(&[1u8; 32]).unwrap();
+        let client = SilentPaymentClient::builder()
+            .base_url(format!("http://{}", addr))
+            .scan_key(scan_key.clone())
+            .spend_key(scan_key.public)
+            .max_response_bytes(64 * 1024)
+            .build()
+            .unwrap();
+        
+        let result = tokio::time::timeout(Duration::from_secs(5), client.get_status())
+            .await
+            .expect("client kept buffering an oversized body");
+        
+        assert!(matches!(result, Err(ClientError::InvalidResponse(ref message)) if message.contains("65536")));
+    }
+    
+    #[tokio::test]
+    async fn test_builder_applies_timeout_and_retries() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        
+        // Server that accepts connections but never answers
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let connections = std::sync::Arc::new(AtomicUsize::new(0));
+        let counter = connections.clone();
+        tokio::spawn(async move {
+            let mut open = Vec::new();
+            while let Ok((socket, _)) = listener.accept().await {
+                counter.fetch_add(1, Ordering::SeqCst);
+                open.push(socket);
+            }
+        });
+        
+        let scan_key = ScanKey::from_slice(&[1u8; 32]).unwrap();
+        let spend_pubkey = scan_key.public;
+        let client = SilentPaymentClient::builder()
+            .base_url(format!("http://{}", addr))
+            .scan_key(scan_key.clone())
+            .spend_key(spend_pubkey)
+            .labels(&[2, 5])
+            .request_timeout(Duration::from_millis(100))
+            .retries(2, Duration::from_millis(10))
+            .build()
+            .unwrap();
+        assert_eq!(client.accounts[0].labels, vec![None, Some(2), Some(5)]);
+        
+        // Timeouts are retryable: the first attempt plus two retries
+        let started = std::time::Instant::now();
+        assert!(matches!(client.get_status().await, Err(ClientError::Timeout(_))));
+        assert_eq!(connections.load(Ordering::SeqCst), 3);
+        assert!(started.elapsed() < Duration::from_secs(2));
+        
+        // Required fields are checked
+        let err = SilentPaymentClient::builder()
+            .base_url("http://localhost:3000")
+            .spend_key(spend_pubkey)
+            .build()
+            .err()
+            .unwrap();
+        assert!(matches!(err, ClientError::InvalidConfig(msg) if msg.contains("scan_key")));
+        let err = SilentPaymentClient::builder()
+            .base_url("http://localhost:3000")
+            .scan_key(scan_key)
+            .spend_key(spend_pubkey)
+            .prefix_len(5)
+            .build()
+            .err()
+            .unwrap();
+        assert!(matches!(err, ClientError::InvalidConfig(_)));
+    }
+    
+    #[test]
+    fn test_scan_key_as_spend_key_is_flagged() {
+        let scan_secret = SecretKey::from_slice(&[3u8; 32]).unwrap();
+        let scan_key = ScanKey::new(scan_secret).unwrap();
+        let spend_pubkey = ScanKey::from_slice(&[4u8; 32]).unwrap().public;
+        
+        let build = |spend_key| SilentPaymentClient::builder()
+            .base_url("http://localhost:3000")
+            .scan_key(scan_key.clone())
+            .spend_key(spend_key)
+            .build()
+            .unwrap();
+        assert!(build(scan_key.public).is_single_key());
+        assert!(!build(spend_pubkey).is_single_key());
+        
+        // A second account with equal keys flags the client too
+        let client = build(spend_pubkey).with_account(scan_key.clone(), scan_key.public, 0);
+        assert!(client.is_single_key());
+        
+        let address = SilentPaymentAddress {
+            spend_pubkey: scan_key.public,
+            scan_pubkey: scan_key.public,
+            is_labeled: false,
+            label: None,
+            network: Network::Bitcoin,
+        };
+        assert!(address.is_single_key());
+        let client = SilentPaymentClient::from_address("http://localhost:3000".into(), scan_secret, &address).unwrap();
+        assert!(client.is_single_key());
+        assert!(!SilentPaymentAddress { spend_pubkey, ..address }.is_single_key());
+    }
+    
+    #[cfg(feature = "socks")]
+    #[tokio::test]
+    async fn test_requests_go_through_socks_proxy() {
+        use tokio::io::AsyncReadExt;
+        
+        // Fake proxy that records the first byte of the SOCKS greeting
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let proxy_addr = listener.local_addr().unwrap();
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut version = [0u8; 1];
+            socket.read_exact(&mut version).await.unwrap();
+            let _ = tx.send(version[0]);
+        });
+        
+        let scan_key = ScanKey::from_slice(&[1u8; 32]).unwrap();
+        let spend_pubkey = scan_key.public;
+        let proxy = Url::parse(&format!("socks5h://{}", proxy_addr)).unwrap();
+        let config = ClientConfig {
+            request_timeout: Duration::from_millis(500),
+            ..ClientConfig::default()
+        }
+        .proxy(Some(proxy));
+        let client = SilentPaymentClient::with_config(
+            "http://whisperindexexample.onion".into(),
+            scan_key,
+            spend_pubkey,
+            0,
+            config,
+        )
+        .unwrap();
+        
+        // The fake proxy never completes the handshake, so the request fails
+        assert!(client.get_status().await.is_err());
+        assert_eq!(rx.await.unwrap(), 0x05);
     }
 }
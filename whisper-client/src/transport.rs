@@ -0,0 +1,258 @@
+use crate::{ClientConfig, ClientError, ServerErrorKind};
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use std::future::Future;
+use std::time::Duration;
+
+/// How `SilentPaymentClient` reaches an index server.
+///
+/// `HttpTransport` talks to the REST API with reqwest; other
+/// implementations can route requests differently (e.g. over Tor) or
+/// answer them in-process for offline tests. Prefix computation and
+/// candidate verification stay in the client whatever the transport.
+pub trait ScanTransport: Send + Sync {
+    /// `POST /api/v1/scan`
+    fn scan(&self, request: &ScanRequest) -> impl Future<Output = Result<ScanResponse, ClientError>> + Send;
+
+    /// `GET /api/v1/status`
+    fn status(&self) -> impl Future<Output = Result<ServerStatus, ClientError>> + Send;
+
+    /// `GET /api/v1/tx/{txid}`, with `txid` in display byte order as in scan candidates
+    fn transaction(&self, txid: &str) -> impl Future<Output = Result<TransactionResponse, ClientError>> + Send;
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ScanRequest {
+    pub scan_pubkey: String,
+    pub start_height: i32,
+    pub end_height: i32,
+    pub prefixes: Vec<String>,
+    pub include_proofs: Option<bool>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct OutputCandidate {
+    /// Hex in display byte order (as block explorers show it)
+    pub txid: String,
+    pub vout: i32,
+    /// `None` when the server hides amounts; the client then reads it from
+    /// the transaction of a matched output
+    #[serde(default)]
+    pub amount: Option<i64>,
+    pub script_pubkey: String,
+    pub block_height: i32,
+    pub block_hash: String,
+    pub timestamp: i64,
+    #[serde(default)]
+    pub proof: Option<MerkleProof>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct MerkleProof {
+    pub block_header: String,
+    pub tx_index: u32,
+    pub branch: Vec<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct TransactionResponse {
+    pub raw_tx: String,
+    pub block_hash: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ScanResponse {
+    pub candidates: Vec<OutputCandidate>,
+    pub scanned_blocks: Vec<i32>,
+    pub server_time_ms: u64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ServerStatus {
+    pub status: String,
+    pub tip_height: i32,
+    pub network: String,
+    #[serde(default)]
+    pub total_outputs: i64,
+    #[serde(default)]
+    pub total_blocks: i64,
+    #[serde(default)]
+    pub uptime_seconds: u64,
+    #[serde(default)]
+    pub version: String,
+    /// Largest `end_height - start_height` the server accepts per scan
+    #[serde(default)]
+    pub max_block_range: Option<u32>,
+    /// Most prefixes the server accepts in one scan request
+    #[serde(default)]
+    pub max_prefixes: Option<usize>,
+    /// Prefix width in bytes the server expects
+    #[serde(default)]
+    pub prefix_len: Option<usize>,
+    /// Height of the server's node, when the server knows it
+    #[serde(default)]
+    pub node_tip: Option<i64>,
+    /// Blocks the index is behind `node_tip`; a growing value means the
+    /// indexer has stalled
+    #[serde(default)]
+    pub blocks_behind: Option<i64>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ErrorBody {
+    error: ServerErrorKind,
+    message: String,
+}
+
+/// Read a response body, failing as soon as it exceeds `max_bytes` rather
+/// than buffering the rest.
+async fn read_body(mut response: reqwest::Response, max_bytes: usize) -> Result<Vec<u8>, ClientError> {
+    let too_large = || ClientError::InvalidResponse(format!(
+        "response body exceeds {} bytes", max_bytes
+    ));
+    if response.content_length().is_some_and(|len| len > max_bytes as u64) {
+        return Err(too_large());
+    }
+
+    let mut body = Vec::new();
+    while let Some(chunk) = response.chunk().await? {
+        if body.len() + chunk.len() > max_bytes {
+            return Err(too_large());
+        }
+        body.extend_from_slice(&chunk);
+    }
+    Ok(body)
+}
+
+/// Build a `ClientError::ServerError` from a non-success response, keeping
+/// the raw body as the message if it isn't a JSON error.
+async fn server_error(response: reqwest::Response, max_bytes: usize) -> ClientError {
+    let status = response.status().as_u16();
+    let body = match read_body(response, max_bytes).await {
+        Ok(body) => String::from_utf8_lossy(&body).into_owned(),
+        Err(e) => return e,
+    };
+    match serde_json::from_str::<ErrorBody>(&body) {
+        Ok(error) => ClientError::ServerError { status, kind: error.error, message: error.message },
+        Err(_) => ClientError::ServerError { status, kind: ServerErrorKind::Unknown, message: body },
+    }
+}
+
+/// The REST API over HTTP(S), via reqwest.
+#[derive(Debug, Clone)]
+pub struct HttpTransport {
+    http_client: Client,
+    pub base_url: String,
+    max_retries: u32,
+    retry_backoff: Duration,
+    max_response_bytes: usize,
+}
+
+impl HttpTransport {
+    pub fn new(base_url: String) -> Self {
+        let config = ClientConfig::default();
+        let http_client = Self::build_http_client(&config)
+            .unwrap_or_else(|_| Client::new());
+        Self {
+            http_client,
+            base_url,
+            max_retries: config.max_retries,
+            retry_backoff: config.retry_backoff,
+            max_response_bytes: config.max_response_bytes,
+        }
+    }
+
+    /// Transport with explicit HTTP settings (timeouts, proxy, retries).
+    pub fn with_config(base_url: String, config: &ClientConfig) -> Result<Self, ClientError> {
+        let http_client = Self::build_http_client(config)?;
+        Ok(Self {
+            http_client,
+            base_url,
+            max_retries: config.max_retries,
+            retry_backoff: config.retry_backoff,
+            max_response_bytes: config.max_response_bytes,
+        })
+    }
+
+    fn build_http_client(config: &ClientConfig) -> Result<Client, reqwest::Error> {
+        let mut builder = Client::builder()
+            .connect_timeout(config.connect_timeout)
+            .timeout(config.request_timeout)
+            .pool_idle_timeout(config.pool_idle_timeout);
+        if let Some(proxy) = &config.proxy {
+            builder = builder.proxy(reqwest::Proxy::all(proxy.clone())?);
+        }
+        builder.build()
+    }
+
+    /// Decode a JSON success response of at most `max_response_bytes`, or
+    /// turn an error status into `ClientError::ServerError`.
+    async fn json<R: serde::de::DeserializeOwned>(&self, response: reqwest::Response) -> Result<R, ClientError> {
+        if !response.status().is_success() {
+            return Err(server_error(response, self.max_response_bytes).await);
+        }
+        let body = read_body(response, self.max_response_bytes).await?;
+        serde_json::from_slice(&body).map_err(|e| ClientError::InvalidResponse(e.to_string()))
+    }
+
+    /// Run `send` until it succeeds, fails with an error that isn't
+    /// retryable, or the configured retries are used up.
+    async fn with_retries<R, F, Fut>(&self, mut send: F) -> Result<R, ClientError>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<R, ClientError>>,
+    {
+        let mut delay = self.retry_backoff;
+        let mut retries = 0;
+        loop {
+            match send().await {
+                Err(e) if e.is_retryable() && retries < self.max_retries => {
+                    retries += 1;
+                    tracing::debug!("Retrying request ({}/{}) after {:?}: {}", retries, self.max_retries, delay, e);
+                    tokio::time::sleep(delay).await;
+                    delay = delay.saturating_mul(2);
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+impl ScanTransport for HttpTransport {
+    async fn scan(&self, request: &ScanRequest) -> Result<ScanResponse, ClientError> {
+        let url = format!("{}/api/v1/scan", self.base_url);
+        self.with_retries(|| async {
+            let response = self.http_client
+                .post(&url)
+                .json(request)
+                .send()
+                .await?;
+            self.json(response).await
+        })
+        .await
+    }
+
+    async fn status(&self) -> Result<ServerStatus, ClientError> {
+        let url = format!("{}/api/v1/status", self.base_url);
+        self.with_retries(|| async {
+            let response = self.http_client
+                .get(&url)
+                .send()
+                .await?;
+            self.json(response).await
+        })
+        .await
+    }
+
+    async fn transaction(&self, txid: &str) -> Result<TransactionResponse, ClientError> {
+        let url = format!("{}/api/v1/tx/{}", self.base_url, txid);
+        self.with_retries(|| async {
+            let response = self.http_client
+                .get(&url)
+                .send()
+                .await?;
+            self.json(response).await
+        })
+        .await
+    }
+}
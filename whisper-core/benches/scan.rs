@@ -0,0 +1,122 @@
+//! Benchmarks for the scanning hot path. Run with `cargo bench -p whisper-core`.
+//!
+//! Baseline (release build, one core of a cloud x86-64 VM):
+//!
+//! | benchmark                     | time     |
+//! |-------------------------------|----------|
+//! | compute_shared_secret/1       | 48.8 µs  |
+//! | compute_shared_secret/10      | 521 µs   |
+//! | compute_shared_secret/100     | 5.33 ms  |
+//! | derive_output_pubkey          | 50.1 µs  |
+//! | check_output/1000_candidates  | 212 ms   |
+//! | compute_prefixes/0            | 150 µs   |
+//! | compute_prefixes/10           | 703 µs   |
+//! | compute_prefixes/100          | 5.82 ms  |
+//!
+//! `check_output` recomputes the shared secret for every candidate, so a
+//! block's worth of candidates costs about as many ECDH operations.
+
+use bitcoin::secp256k1::{PublicKey, Secp256k1, SecretKey};
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use whisper_core::{compute_prefixes, InputData, ScanKey};
+
+fn scan_key() -> ScanKey {
+    ScanKey::from_slice(&[1u8; 32]).unwrap()
+}
+
+fn spend_pubkey() -> PublicKey {
+    let secp = Secp256k1::new();
+    PublicKey::from_secret_key(&secp, &SecretKey::from_slice(&[2u8; 32]).unwrap())
+}
+
+/// `count` distinct taproot inputs.
+fn inputs(count: usize) -> Vec<InputData> {
+    let secp = Secp256k1::new();
+    (0..count)
+        .map(|i| {
+            let mut secret = [0x42u8; 32];
+            secret[..8].copy_from_slice(&(i as u64 + 1).to_be_bytes());
+            InputData {
+                pubkey: PublicKey::from_secret_key(&secp, &SecretKey::from_slice(&secret).unwrap()),
+                is_taproot: true,
+                outpoint: None,
+            }
+        })
+        .collect()
+}
+
+fn bench_shared_secret(c: &mut Criterion) {
+    let scan_key = scan_key();
+    let mut group = c.benchmark_group("compute_shared_secret");
+    for count in [1, 10, 100] {
+        let inputs = inputs(count);
+        group.bench_with_input(BenchmarkId::from_parameter(count), &inputs, |b, inputs| {
+            b.iter(|| scan_key.compute_shared_secret(black_box(inputs)).unwrap())
+        });
+    }
+    group.finish();
+}
+
+fn bench_derive_output(c: &mut Criterion) {
+    let scan_key = scan_key();
+    let spend_pubkey = spend_pubkey();
+    let shared_secret = scan_key.compute_shared_secret(&inputs(1)).unwrap();
+    c.bench_function("derive_output_pubkey", |b| {
+        b.iter(|| {
+            scan_key
+                .derive_output_pubkey(black_box(&shared_secret), &spend_pubkey, None)
+                .unwrap()
+        })
+    });
+}
+
+fn bench_check_output(c: &mut Criterion) {
+    let scan_key = scan_key();
+    let spend_pubkey = spend_pubkey();
+    let inputs = inputs(2);
+    let secp = Secp256k1::new();
+
+    // 1000 candidates, one of which is ours
+    let mut candidates: Vec<Vec<u8>> = (0..1000u32)
+        .map(|i| {
+            let mut secret = [0x17u8; 32];
+            secret[..4].copy_from_slice(&(i + 1).to_be_bytes());
+            let key = PublicKey::from_secret_key(&secp, &SecretKey::from_slice(&secret).unwrap());
+            let mut script = vec![0x51, 0x20];
+            script.extend_from_slice(&key.x_only_public_key().0.serialize());
+            script
+        })
+        .collect();
+    let ours = scan_key.compute_expected_outputs(&spend_pubkey, &inputs, 0).unwrap()[0];
+    candidates[500][2..].copy_from_slice(&ours.serialize());
+
+    c.bench_function("check_output/1000_candidates", |b| {
+        b.iter(|| {
+            candidates
+                .iter()
+                .filter(|script| {
+                    scan_key
+                        .check_output(black_box(script), &spend_pubkey, &inputs, &[None])
+                        .unwrap()
+                        .is_some()
+                })
+                .count()
+        })
+    });
+}
+
+fn bench_prefixes(c: &mut Criterion) {
+    let scan_key = scan_key();
+    let spend_pubkey = spend_pubkey();
+    let inputs = inputs(2);
+    let mut group = c.benchmark_group("compute_prefixes");
+    for max_label in [0, 10, 100] {
+        group.bench_with_input(BenchmarkId::from_parameter(max_label), &max_label, |b, &max_label| {
+            b.iter(|| compute_prefixes(&scan_key, &spend_pubkey, black_box(&inputs), max_label).unwrap())
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_shared_secret, bench_derive_output, bench_check_output, bench_prefixes);
+criterion_main!(benches);
@@ -0,0 +1,15 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use whisper_core::SilentPaymentAddress;
+
+// Decoding must never panic, and anything it accepts must round-trip.
+fuzz_target!(|data: &[u8]| {
+    let Ok(s) = std::str::from_utf8(data) else {
+        return;
+    };
+    if let Ok(address) = SilentPaymentAddress::decode(s) {
+        let reencoded = SilentPaymentAddress::decode(&address.to_string()).unwrap();
+        assert_eq!(reencoded, address);
+    }
+});
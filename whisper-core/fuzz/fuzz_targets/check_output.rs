@@ -0,0 +1,20 @@
+#![no_main]
+
+use bitcoin::secp256k1::{PublicKey, Secp256k1, SecretKey};
+use libfuzzer_sys::fuzz_target;
+use whisper_core::{InputData, ScanKey};
+
+// Arbitrary scriptPubKeys must never panic, with or without labels.
+fuzz_target!(|script: &[u8]| {
+    let secp = Secp256k1::new();
+    let scan_key = ScanKey::from_slice(&[1u8; 32]).unwrap();
+    let spend_pubkey = PublicKey::from_secret_key(&secp, &SecretKey::from_slice(&[2u8; 32]).unwrap());
+    let inputs = vec![InputData {
+        pubkey: PublicKey::from_secret_key(&secp, &SecretKey::from_slice(&[3u8; 32]).unwrap()),
+        is_taproot: true,
+        outpoint: None,
+    }];
+    
+    let _ = scan_key.check_output(script, &spend_pubkey, &inputs, &[None, Some(1)]);
+    let _ = scan_key.check_output_with_labels(script, &spend_pubkey, &inputs, &scan_key.label_lookup_table(1));
+});
@@ -0,0 +1,182 @@
+use bitcoin::bech32::primitives::decode::CheckedHrpstring;
+use bitcoin::bech32::primitives::iter::{ByteIterExt, Fe32IterExt};
+use bitcoin::bech32::{Bech32m, Fe32, Hrp};
+use bitcoin::hashes::{sha256, Hash, HashEngine};
+use bitcoin::secp256k1::{PublicKey, Scalar, Secp256k1, SecretKey};
+use bitcoin::Network;
+use std::fmt;
+use std::str::FromStr;
+use crate::{CoreError, ScanKey, SilentPaymentAddress};
+
+/// Human-readable part of mainnet silent payment addresses.
+const HRP_MAINNET: &str = "sp";
+/// Human-readable part shared by testnet, signet and regtest addresses.
+const HRP_TESTNET: &str = "tsp";
+/// Serialized length of the two compressed keys (B_scan || B_spend).
+const KEYS_LEN: usize = 66;
+
+impl ScanKey {
+    /// Unlabeled address receiving to this scan key and `spend` on `network`.
+    /// `address.to_string()` gives the shareable `sp1...` string.
+    pub fn to_address(&self, spend: &PublicKey, network: Network) -> SilentPaymentAddress {
+        SilentPaymentAddress {
+            spend_pubkey: *spend,
+            scan_pubkey: self.public,
+            is_labeled: false,
+            label: None,
+            network,
+        }
+    }
+    
+    /// Address for label `m` of this scan key and the base spend key
+    /// `spend` on `network`. It holds the labeled key B_m = B + label_m*G,
+    /// so `address.to_string()` is the string to share.
+    pub fn to_labeled_address(
+        &self,
+        spend: &PublicKey,
+        m: u32,
+        network: Network,
+    ) -> Result<SilentPaymentAddress, CoreError> {
+        Ok(SilentPaymentAddress {
+            spend_pubkey: self.labeled_spend_pubkey(spend, m)?,
+            is_labeled: true,
+            label: Some(m),
+            ..self.to_address(spend, network)
+        })
+    }
+    
+    /// Base spend key B of `address`, one of this scan key's addresses:
+    /// `spend_pubkey` for an unlabeled address, B_m - label_m*G for a
+    /// labeled one. Fails for another scan key's address or a labeled
+    /// address without a label number.
+    pub fn base_spend_key(&self, address: &SilentPaymentAddress) -> Result<PublicKey, CoreError> {
+        if address.scan_pubkey != self.public {
+            return Err(CoreError::InvalidAddress("address belongs to a different scan key".into()));
+        }
+        if !address.is_labeled {
+            return Ok(address.spend_pubkey);
+        }
+        let m = address.label
+            .ok_or_else(|| CoreError::InvalidAddress("labeled address has no label number".into()))?;
+        
+        let label = SecretKey::from_slice(&self.label_tweak(m))
+            .map_err(|_| CoreError::CryptoError("Invalid label scalar".into()))?;
+        address.spend_pubkey
+            .add_exp_tweak(&Secp256k1::new(), &Scalar::from(label.negate()))
+            .map_err(|e| CoreError::CryptoError(e.to_string()))
+    }
+    
+    /// Stable identifier of the unlabeled base address of `address`, for
+    /// grouping results across labels: SHA256(scan_pubkey || base spend
+    /// key). Equality still treats differently labeled addresses as
+    /// distinct. Fails as `base_spend_key` does.
+    pub fn base_fingerprint(&self, address: &SilentPaymentAddress) -> Result<[u8; 32], CoreError> {
+        let mut engine = sha256::Hash::engine();
+        engine.input(&address.scan_pubkey.serialize());
+        engine.input(&self.base_spend_key(address)?.serialize());
+        Ok(sha256::Hash::from_engine(engine).to_byte_array())
+    }
+}
+
+impl SilentPaymentAddress {
+    /// Human-readable part used on `network`: `sp` for mainnet, `tsp` for
+    /// testnet, signet and regtest alike.
+    pub fn hrp(network: Network) -> &'static str {
+        match network {
+            Network::Bitcoin => HRP_MAINNET,
+            _ => HRP_TESTNET,
+        }
+    }
+
+    /// Whether this address can belong to `network`. Only mainnet and
+    /// "some test network" can be told apart from the address, so a
+    /// decoded `tsp` address matches testnet, signet and regtest.
+    pub fn matches_network(&self, network: Network) -> bool {
+        Self::hrp(self.network) == Self::hrp(network)
+    }
+
+    /// BIP-352 bech32m encoding (version 0): `sp` on mainnet, `tsp` elsewhere.
+    ///
+    /// Both keys are encoded compressed, keeping their Y parity. The label
+    /// number isn't encoded, so a labeled address decodes as an unlabeled
+    /// one with the same keys.
+    pub fn encode(&self) -> String {
+        let hrp = Hrp::parse(Self::hrp(self.network)).expect("valid hrp");
+
+        let mut keys = Vec::with_capacity(KEYS_LEN);
+        keys.extend_from_slice(&self.scan_pubkey.serialize());
+        keys.extend_from_slice(&self.spend_pubkey.serialize());
+
+        keys.into_iter()
+            .bytes_to_fes()
+            .with_checksum::<Bech32m>(&hrp)
+            .with_witness_version(Fe32::Q)
+            .chars()
+            .collect()
+    }
+
+    /// Parse an `sp1...` / `tsp1...` address. `tsp` addresses decode as
+    /// testnet, since signet and regtest share that prefix; use
+    /// `matches_network` rather than comparing `network` directly.
+    ///
+    /// Versions 1 to 30 are read forward-compatibly (only the first 66
+    /// bytes are used); version 31 is rejected.
+    pub fn decode(s: &str) -> Result<Self, CoreError> {
+        let mut checked = CheckedHrpstring::new::<Bech32m>(s)
+            .map_err(|e| CoreError::InvalidAddress(e.to_string()))?;
+
+        let network = match checked.hrp().to_lowercase().as_str() {
+            HRP_MAINNET => Network::Bitcoin,
+            HRP_TESTNET => Network::Testnet,
+            other => return Err(CoreError::InvalidAddress(format!("unknown prefix {:?}", other))),
+        };
+
+        let version = checked
+            .remove_witness_version()
+            .ok_or_else(|| CoreError::InvalidAddress("missing version".into()))?
+            .to_u8();
+        let data: Vec<u8> = checked.byte_iter().collect();
+        match version {
+            0 if data.len() != KEYS_LEN => {
+                return Err(CoreError::InvalidAddress(format!(
+                    "version 0 payload must be {} bytes, got {}", KEYS_LEN, data.len()
+                )));
+            }
+            1..=30 if data.len() < KEYS_LEN => {
+                return Err(CoreError::InvalidAddress(format!(
+                    "payload must be at least {} bytes, got {}", KEYS_LEN, data.len()
+                )));
+            }
+            31 => return Err(CoreError::InvalidAddress("unsupported version 31".into())),
+            _ => {}
+        }
+
+        let key = |bytes: &[u8]| {
+            PublicKey::from_slice(bytes)
+                .map_err(|e| CoreError::InvalidKey(e.to_string()))
+        };
+
+        let (scan, spend) = data[..KEYS_LEN].split_at(33);
+        Ok(Self {
+            scan_pubkey: key(scan)?,
+            spend_pubkey: key(spend)?,
+            is_labeled: false,
+            label: None,
+            network,
+        })
+    }
+}
+
+impl fmt::Display for SilentPaymentAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.encode())
+    }
+}
+
+impl FromStr for SilentPaymentAddress {
+    type Err = CoreError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::decode(s)
+    }
+}
@@ -1,8 +1,11 @@
 use whisper_core::*;
 use bitcoin::secp256k1::{SecretKey, PublicKey, Secp256k1};
 
-// SECTION 1.1: Round-Trip BIP-352 Compliance Testing
-// (Replaces former placeholder test vectors with real computed verification)
+// SECTION 1.1: BIP-352 Derivation Known Answers
+// Each case round-trips through check_output and is pinned to shared secrets
+// and outputs computed independently of this crate from the primitives
+// (inputs are G and 2G without outpoints, which hash as the null outpoint).
+// The official vectors in section 1.6 cover real transactions.
 #[test]
 fn verify_roundtrip_bip352_compliance() {
     let secp = Secp256k1::new();
@@ -16,17 +19,17 @@ fn verify_roundtrip_bip352_compliance() {
     let spend_secret = SecretKey::from_slice(
         &hex::decode("0202020202020202020202020202020202020202020202020202020202020202").unwrap()
     ).unwrap();
-    let spend_pubkey = PublicKey::from_secret_key(&secp, &spend_secret).x_only_public_key().0;
+    let spend_pubkey = PublicKey::from_secret_key(&secp, &spend_secret);
     
     // Generator point as input pubkey
     let input_bytes = hex::decode("0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798").unwrap();
     let input_pubkey = PublicKey::from_slice(&input_bytes).unwrap();
-    let inputs = vec![InputData { pubkey: input_pubkey, is_taproot: true }];
+    let inputs = vec![InputData { pubkey: input_pubkey, is_taproot: true, outpoint: None }];
     
     // Compute → derive → check_output must round-trip
     let shared_secret = scan_key.compute_shared_secret(&inputs)
         .expect("Shared secret computation must succeed");
-    assert_eq!(shared_secret.len(), 32);
+    assert_eq!(shared_secret.len(), 33);
     
     let output = scan_key.derive_output_pubkey(&shared_secret, &spend_pubkey, None)
         .expect("Output derivation must succeed");
@@ -42,7 +45,8 @@ fn verify_roundtrip_bip352_compliance() {
     assert_eq!(detected.output_pubkey, output);
     assert_eq!(detected.label, None);
     
-    println!("  ✓ Case 1: Single input, no label — PASSED");
+    assert_eq!(hex::encode(shared_secret), "0229a072ee308a2430228803a18544822b0fa5b3b65994f86f379abc76e6dac3fd");
+    assert_eq!(hex::encode(output.serialize()), "15f85fc1aed542f08b724f0e4ab931be02805a3b4327d661845a10300e116cc2");
     
     // Test case 2: Multiple inputs accumulation
     let scan_secret2 = SecretKey::from_slice(
@@ -53,14 +57,14 @@ fn verify_roundtrip_bip352_compliance() {
     let spend_secret2 = SecretKey::from_slice(
         &hex::decode("0404040404040404040404040404040404040404040404040404040404040404").unwrap()
     ).unwrap();
-    let spend_pubkey2 = PublicKey::from_secret_key(&secp, &spend_secret2).x_only_public_key().0;
+    let spend_pubkey2 = PublicKey::from_secret_key(&secp, &spend_secret2);
     
     let input2_bytes = hex::decode("02c6047f9441ed7d6d3045406e95c07cd85c778e4b8cef3ca7abac09b95c709ee5").unwrap();
     let input2_pubkey = PublicKey::from_slice(&input2_bytes).unwrap();
     
     let inputs2 = vec![
-        InputData { pubkey: input_pubkey, is_taproot: true },
-        InputData { pubkey: input2_pubkey, is_taproot: true },
+        InputData { pubkey: input_pubkey, is_taproot: true, outpoint: None },
+        InputData { pubkey: input2_pubkey, is_taproot: true, outpoint: None },
     ];
     
     let shared_secret2 = scan_key2.compute_shared_secret(&inputs2)
@@ -76,7 +80,9 @@ fn verify_roundtrip_bip352_compliance() {
         .expect("Must detect own output (multi-input)");
     assert_eq!(detected2.output_pubkey, output2);
     
-    println!("  ✓ Case 2: Multiple inputs — PASSED");
+    // One ECDH over the summed input keys, A = G + 2G
+    assert_eq!(hex::encode(shared_secret2), "0256df44e771cdddf2c1a20d870c2140e803a64cf27ade00b31d213f23919d2f5c");
+    assert_eq!(hex::encode(output2.serialize()), "899f69d278f35056d89183ecddb9d9f23bb5318e6e1e9a90eb9ae0570d300861");
     
     // Test case 3: With label 5
     let scan_secret3 = SecretKey::from_slice(
@@ -87,9 +93,9 @@ fn verify_roundtrip_bip352_compliance() {
     let spend_secret3 = SecretKey::from_slice(
         &hex::decode("0606060606060606060606060606060606060606060606060606060606060606").unwrap()
     ).unwrap();
-    let spend_pubkey3 = PublicKey::from_secret_key(&secp, &spend_secret3).x_only_public_key().0;
+    let spend_pubkey3 = PublicKey::from_secret_key(&secp, &spend_secret3);
     
-    let inputs3 = vec![InputData { pubkey: input_pubkey, is_taproot: true }];
+    let inputs3 = vec![InputData { pubkey: input_pubkey, is_taproot: true, outpoint: None }];
     
     let shared_secret3 = scan_key3.compute_shared_secret(&inputs3).expect("Must succeed");
     let output3 = scan_key3.derive_output_pubkey(&shared_secret3, &spend_pubkey3, Some(5))
@@ -105,8 +111,9 @@ fn verify_roundtrip_bip352_compliance() {
     assert_eq!(detected3.label, Some(5));
     assert_eq!(detected3.output_pubkey, output3);
     
-    println!("  ✓ Case 3: Labeled output (label=5) — PASSED");
-    println!("  ✓ All round-trip BIP-352 compliance tests PASSED");
+    // P = B + label_5*G + t*G
+    assert_eq!(hex::encode(shared_secret3), "02f6de4e4b9fa178ddd559fe3dfa409fb7ac5acad1598556504bc35febfa7c5320");
+    assert_eq!(hex::encode(output3.serialize()), "4bb5f0c3be6c4c41f6ecb86235f1cfa1a669b2daa74c99195b34e52dc23da656");
 }
 
 // SECTION 1.2: Label Derivation Correctness
@@ -117,10 +124,10 @@ fn test_label_uniqueness() {
     let scan_key = ScanKey::new(scan_secret).unwrap();
     
     let spend_secret = SecretKey::from_slice(&[2u8; 32]).unwrap();
-    let spend_pubkey = PublicKey::from_secret_key(&secp, &spend_secret).x_only_public_key().0;
+    let spend_pubkey = PublicKey::from_secret_key(&secp, &spend_secret);
     
     let input_pubkey = PublicKey::from_secret_key(&secp, &SecretKey::from_slice(&[3u8; 32]).unwrap());
-    let inputs = vec![InputData { pubkey: input_pubkey, is_taproot: true }];
+    let inputs = vec![InputData { pubkey: input_pubkey, is_taproot: true, outpoint: None }];
     
     let shared_secret = scan_key.compute_shared_secret(&inputs).unwrap();
     
@@ -158,11 +165,11 @@ fn test_single_input() {
     let scan_key = ScanKey::new(scan_secret).unwrap();
     
     let input_pubkey = PublicKey::from_secret_key(&secp, &SecretKey::from_slice(&[2u8; 32]).unwrap());
-    let inputs = vec![InputData { pubkey: input_pubkey, is_taproot: true }];
+    let inputs = vec![InputData { pubkey: input_pubkey, is_taproot: true, outpoint: None }];
     
     let result = scan_key.compute_shared_secret(&inputs);
     assert!(result.is_ok(), "Single input should succeed");
-    assert_eq!(result.unwrap().len(), 32);
+    assert_eq!(result.unwrap().len(), 33);
 }
 
 #[test]
@@ -177,12 +184,12 @@ fn test_many_inputs() {
         bytes[0] = i;
         let secret = SecretKey::from_slice(&bytes).unwrap();
         let pubkey = PublicKey::from_secret_key(&secp, &secret);
-        InputData { pubkey, is_taproot: true }
+        InputData { pubkey, is_taproot: true, outpoint: None }
     }).collect();
     
     let result = scan_key.compute_shared_secret(&inputs);
     assert!(result.is_ok(), "100 inputs should succeed");
-    assert_eq!(result.unwrap().len(), 32);
+    assert_eq!(result.unwrap().len(), 33);
 }
 
 // SECTION 1.4: Cryptographic Constants Verification
@@ -190,12 +197,12 @@ fn test_many_inputs() {
 fn test_tagged_hash_constants() {
     assert_eq!(TaggedHash::SHARED_SECRET, "BIP0352/SharedSecret", 
                "CRITICAL: Shared secret tag must match BIP-352 exactly");
-    assert_eq!(TaggedHash::OUTPUT, "BIP0352/Outputs", 
-               "CRITICAL: Output tag must match BIP-352 exactly");
+    assert_eq!(TaggedHash::INPUTS, "BIP0352/Inputs", 
+               "CRITICAL: Inputs tag must match BIP-352 exactly");
     
     // Verify no whitespace
     assert!(!TaggedHash::SHARED_SECRET.contains(' '));
-    assert!(!TaggedHash::OUTPUT.contains(' '));
+    assert!(!TaggedHash::INPUTS.contains(' '));
 }
 
 #[test]
@@ -224,10 +231,10 @@ fn test_false_positive_rate() {
     let scan_key = ScanKey::new(scan_secret).unwrap();
     
     let spend_secret = SecretKey::from_slice(&[11u8; 32]).unwrap();
-    let spend_pubkey = PublicKey::from_secret_key(&secp, &spend_secret).x_only_public_key().0;
+    let spend_pubkey = PublicKey::from_secret_key(&secp, &spend_secret);
     
     let input_pubkey = PublicKey::from_secret_key(&secp, &SecretKey::from_slice(&[12u8; 32]).unwrap());
-    let inputs = vec![InputData { pubkey: input_pubkey, is_taproot: true }];
+    let inputs = vec![InputData { pubkey: input_pubkey, is_taproot: true, outpoint: None }];
     
     // Generate 1000 random outputs and check false positives
     let mut false_positives = 0;
@@ -270,10 +277,10 @@ fn test_tweak_for_spending() {
     let scan_key = ScanKey::new(scan_secret).unwrap();
     
     let spend_secret = SecretKey::from_slice(&[21u8; 32]).unwrap();
-    let spend_pubkey = PublicKey::from_secret_key(&secp, &spend_secret).x_only_public_key().0;
+    let spend_pubkey = PublicKey::from_secret_key(&secp, &spend_secret);
     
     let input_pubkey = PublicKey::from_secret_key(&secp, &SecretKey::from_slice(&[22u8; 32]).unwrap());
-    let inputs = vec![InputData { pubkey: input_pubkey, is_taproot: true }];
+    let inputs = vec![InputData { pubkey: input_pubkey, is_taproot: true, outpoint: None }];
     
     // Derive output
     let shared_secret = scan_key.compute_shared_secret(&inputs).unwrap();
@@ -324,10 +331,10 @@ fn test_script_validation() {
     let scan_key = ScanKey::new(scan_secret).unwrap();
     
     let spend_secret = SecretKey::from_slice(&[31u8; 32]).unwrap();
-    let spend_pubkey = PublicKey::from_secret_key(&secp, &spend_secret).x_only_public_key().0;
+    let spend_pubkey = PublicKey::from_secret_key(&secp, &spend_secret);
     
     let input_pubkey = PublicKey::from_secret_key(&secp, &SecretKey::from_slice(&[32u8; 32]).unwrap());
-    let inputs = vec![InputData { pubkey: input_pubkey, is_taproot: true }];
+    let inputs = vec![InputData { pubkey: input_pubkey, is_taproot: true, outpoint: None }];
     let labels = vec![None];
     
     // Test invalid scripts
@@ -343,3 +350,320 @@ fn test_script_validation() {
         assert!(result.is_none(), "Invalid script {} should be rejected", i);
     }
 }
+
+// SECTION 1.5: Spend Key Parity
+// A receiver whose spend key has odd Y: the address carries the compressed
+// key, odd Y included, and the sender tweaks that point. Lifting the x-only
+// key to even Y instead would derive -B + t*G, a different output.
+#[test]
+fn test_odd_parity_spend_key_keeps_its_parity() {
+    use bitcoin::secp256k1::{Parity, Scalar, XOnlyPublicKey};
+    
+    let secp = Secp256k1::new();
+    let scan_key = ScanKey::new(SecretKey::from_slice(&[40u8; 32]).unwrap()).unwrap();
+    
+    // Find a spend key whose point has odd Y
+    let spend_secret = (41u8..=255)
+        .map(|i| SecretKey::from_slice(&[i; 32]).unwrap())
+        .find(|secret| PublicKey::from_secret_key(&secp, secret).x_only_public_key().1 == Parity::Odd)
+        .expect("an odd-Y key exists in range");
+    let spend_pubkey = PublicKey::from_secret_key(&secp, &spend_secret);
+    
+    // The address keeps the parity through encoding
+    let address: SilentPaymentAddress = scan_key
+        .to_address(&spend_pubkey, bitcoin::Network::Bitcoin)
+        .to_string()
+        .parse()
+        .unwrap();
+    assert_eq!(address.spend_pubkey.serialize()[0], 0x03);
+    assert_eq!(address.spend_pubkey, spend_pubkey);
+    
+    let input_pubkey = PublicKey::from_secret_key(&secp, &SecretKey::from_slice(&[3u8; 32]).unwrap());
+    let inputs = vec![InputData { pubkey: input_pubkey, is_taproot: true, outpoint: None }];
+    let shared_secret = scan_key.compute_shared_secret(&inputs).unwrap();
+    
+    let tweak = TaggedHash::output(&shared_secret, 0);
+    let script_for = |output: XOnlyPublicKey| {
+        let mut script = vec![0x51, 0x20];
+        script.extend_from_slice(&output.serialize());
+        script
+    };
+    
+    // Sender paying the decoded address: P = B + t*G on the odd point
+    let sender_output = sender_output_pubkey(&shared_secret, &address.spend_pubkey, 0).unwrap();
+    let expected = spend_pubkey
+        .add_exp_tweak(&secp, &Scalar::from_be_bytes(tweak).unwrap())
+        .unwrap()
+        .x_only_public_key()
+        .0;
+    assert_eq!(sender_output, expected);
+    assert_eq!(sender_output, scan_key.derive_output_pubkey(&shared_secret, &spend_pubkey, None).unwrap());
+    let detected = scan_key.check_output(&script_for(sender_output), &spend_pubkey, &inputs, &[None])
+        .unwrap()
+        .expect("payment to the odd-Y spend key must be detected");
+    assert_eq!(detected.output_pubkey, sender_output);
+    assert_eq!(detected.tweak, tweak);
+    
+    // Spent with the spend secret as is, no negation
+    let result = ScanResult::from_match(&detected, [0u8; 32], 0, 1_000);
+    assert_eq!(result.spending_key(&spend_secret).unwrap(), spend_secret.add_tweak(&Scalar::from_be_bytes(tweak).unwrap()).unwrap());
+    
+    // The naive even lift of the x-only key gives another output, not ours
+    let even_lift = PublicKey::from_x_only_public_key(spend_pubkey.x_only_public_key().0, Parity::Even);
+    let naive_output = sender_output_pubkey(&shared_secret, &even_lift, 0).unwrap();
+    assert_ne!(naive_output, sender_output);
+    assert!(scan_key.check_output(&script_for(naive_output), &spend_pubkey, &inputs, &[None]).unwrap().is_none());
+}
+
+// SECTION 1.6: Official BIP-352 Vectors
+// `tests/data/send_and_receive_test_vectors.json` is the BIP's
+// send_and_receive_test_vectors.json, unchanged.
+fn official_vectors() -> Vec<serde_json::Value> {
+    serde_json::from_str(include_str!("../tests/data/send_and_receive_test_vectors.json"))
+        .expect("vector file is a JSON array")
+}
+
+/// A transaction spending the vector's `vin` (outpoints, scriptSigs,
+/// witnesses) to `outputs`, with the prevouts it spends.
+fn vector_transaction(
+    vin: &serde_json::Value,
+    outputs: Vec<bitcoin::TxOut>,
+) -> (bitcoin::Transaction, Vec<bitcoin::TxOut>) {
+    use bitcoin::{Amount, OutPoint, ScriptBuf, TxIn, TxOut, Txid, Witness};
+    
+    let hex_field = |value: &serde_json::Value| hex::decode(value.as_str().unwrap_or("")).unwrap();
+    let (input, prevouts) = vin.as_array().unwrap().iter().map(|input| {
+        let witness_bytes = hex_field(&input["txinwitness"]);
+        let witness: Witness = if witness_bytes.is_empty() {
+            Witness::new()
+        } else {
+            bitcoin::consensus::deserialize(&witness_bytes).unwrap()
+        };
+        let txin = TxIn {
+            previous_output: OutPoint {
+                txid: input["txid"].as_str().unwrap().parse::<Txid>().unwrap(),
+                vout: input["vout"].as_u64().unwrap() as u32,
+            },
+            script_sig: ScriptBuf::from_bytes(hex_field(&input["scriptSig"])),
+            witness,
+            ..Default::default()
+        };
+        let prevout = TxOut {
+            value: Amount::ZERO,
+            script_pubkey: ScriptBuf::from_bytes(hex_field(&input["prevout"]["scriptPubKey"]["hex"])),
+        };
+        (txin, prevout)
+    }).unzip();
+    
+    let tx = bitcoin::Transaction {
+        version: bitcoin::transaction::Version::TWO,
+        lock_time: bitcoin::absolute::LockTime::ZERO,
+        input,
+        output: outputs,
+    };
+    (tx, prevouts)
+}
+
+#[test]
+fn verify_official_bip352_receiving_vectors() {
+    use bitcoin::secp256k1::XOnlyPublicKey;
+    use bitcoin::{Amount, ScriptBuf, TxOut};
+    use std::collections::BTreeMap;
+    
+    let secp = Secp256k1::new();
+    for case in official_vectors() {
+        let comment = case["comment"].as_str().unwrap_or("").to_string();
+        for receiving in case["receiving"].as_array().unwrap() {
+            let given = &receiving["given"];
+            let expected = &receiving["expected"];
+            
+            let key = |name: &str| SecretKey::from_slice(&hex::decode(given["key_material"][name].as_str().unwrap()).unwrap()).unwrap();
+            let keypair = SilentPaymentKeypair::from_secrets(key("scan_priv_key"), key("spend_priv_key")).unwrap();
+            let spend_pubkey = keypair.spend.public;
+            let labels: Vec<Option<u32>> = std::iter::once(None)
+                .chain(given["labels"].as_array().unwrap().iter().map(|m| Some(m.as_u64().unwrap() as u32)))
+                .collect();
+            
+            // Addresses: the unlabeled one, then one per label
+            let addresses: Vec<String> = labels
+                .iter()
+                .map(|&label| match label {
+                    Some(m) => keypair.scan.to_labeled_address(&spend_pubkey, m, bitcoin::Network::Bitcoin).unwrap().to_string(),
+                    None => keypair.address(bitcoin::Network::Bitcoin).to_string(),
+                })
+                .collect();
+            let expected_addresses: Vec<String> = expected["addresses"].as_array().unwrap()
+                .iter()
+                .map(|a| a.as_str().unwrap().to_string())
+                .collect();
+            assert_eq!(addresses, expected_addresses, "{}", comment);
+            
+            let outputs = given["outputs"].as_array().unwrap().iter().map(|key| {
+                let mut script = vec![0x51, 0x20];
+                script.extend_from_slice(&hex::decode(key.as_str().unwrap()).unwrap());
+                TxOut { value: Amount::ZERO, script_pubkey: ScriptBuf::from_bytes(script) }
+            }).collect();
+            let (tx, prevouts) = vector_transaction(&given["vin"], outputs);
+            
+            // Tweak and shared secret, absent when the input keys cancel out
+            let tweak = InputData::transaction_tweak(&tx, &prevouts);
+            assert_eq!(tweak.map(|t| hex::encode(t.serialize())).as_deref(), expected["tweak"].as_str(), "{}", comment);
+            let shared_secret = tweak.map(|t| hex::encode(keypair.scan.shared_secret_from_tweak(&t).unwrap()));
+            assert_eq!(shared_secret.as_deref(), expected["shared_secret"].as_str(), "{}", comment);
+            
+            // Found output keys with their spend tweaks, against the expected ones
+            let found: BTreeMap<String, String> = keypair.scan
+                .scan_transaction(&tx, &prevouts, &spend_pubkey, &labels)
+                .unwrap()
+                .iter()
+                .map(|result| (hex::encode(result.output_pubkey.serialize()), hex::encode(result.tweak)))
+                .collect();
+            
+            // The K_max case only gives the number of outputs found
+            if let Some(n_outputs) = expected["n_outputs"].as_u64() {
+                assert_eq!(found.len() as u64, n_outputs, "{}", comment);
+                continue;
+            }
+            let wanted: BTreeMap<String, String> = expected["outputs"].as_array().unwrap()
+                .iter()
+                .map(|output| (
+                    output["pub_key"].as_str().unwrap().to_string(),
+                    output["priv_key_tweak"].as_str().unwrap().to_string(),
+                ))
+                .collect();
+            assert_eq!(found, wanted, "{}", comment);
+            
+            // Each tweak spends its output
+            for (pub_key, tweak) in &wanted {
+                let result = ScanResult {
+                    txid: [0u8; 32],
+                    vout: 0,
+                    amount: 0,
+                    label: None,
+                    tweak: hex::decode(tweak).unwrap().try_into().unwrap(),
+                    output_pubkey: pub_key.parse::<XOnlyPublicKey>().unwrap(),
+                    block_height: None,
+                    spend_pubkey: Some(spend_pubkey),
+                    account: 0,
+                };
+                let spending_key = result.spending_key(&key("spend_priv_key")).unwrap();
+                assert_eq!(spending_key.x_only_public_key(&secp).0, result.output_pubkey, "{}", comment);
+            }
+        }
+    }
+}
+
+// Section 1.5 on an official vector: the receiver of "multiple outputs,
+// multiple recipients" has a spend key with odd Y. Its address and both of
+// its outputs come out right only with the parity kept.
+#[test]
+fn verify_official_odd_parity_spend_key() {
+    use bitcoin::secp256k1::Parity;
+    use bitcoin::{Amount, ScriptBuf, TxOut};
+    use std::collections::BTreeSet;
+    
+    let case = official_vectors()
+        .into_iter()
+        .find(|case| case["comment"] == "Multiple outputs: multiple outputs, multiple recipients")
+        .expect("vector is present");
+    let receiving = &case["receiving"][0];
+    let given = &receiving["given"];
+    
+    let key = |name: &str| SecretKey::from_slice(&hex::decode(given["key_material"][name].as_str().unwrap()).unwrap()).unwrap();
+    let keypair = SilentPaymentKeypair::from_secrets(key("scan_priv_key"), key("spend_priv_key")).unwrap();
+    let spend_pubkey = keypair.spend.public;
+    assert_eq!(spend_pubkey.x_only_public_key().1, Parity::Odd);
+    
+    // The address carries the 0x03 key
+    let address = keypair.address(bitcoin::Network::Bitcoin).to_string();
+    assert_eq!(address, receiving["expected"]["addresses"][0].as_str().unwrap());
+    assert_eq!(address.parse::<SilentPaymentAddress>().unwrap().spend_pubkey, spend_pubkey);
+    
+    let outputs = given["outputs"].as_array().unwrap().iter().map(|key| {
+        let mut script = vec![0x51, 0x20];
+        script.extend_from_slice(&hex::decode(key.as_str().unwrap()).unwrap());
+        TxOut { value: Amount::ZERO, script_pubkey: ScriptBuf::from_bytes(script) }
+    }).collect();
+    let (tx, prevouts) = vector_transaction(&given["vin"], outputs);
+    let scan = |spend: &PublicKey| -> BTreeSet<String> {
+        keypair.scan
+            .scan_transaction(&tx, &prevouts, spend, &[None])
+            .unwrap()
+            .iter()
+            .map(|result| hex::encode(result.output_pubkey.serialize()))
+            .collect()
+    };
+    
+    let expected: BTreeSet<String> = receiving["expected"]["outputs"].as_array().unwrap()
+        .iter()
+        .map(|output| output["pub_key"].as_str().unwrap().to_string())
+        .collect();
+    assert_eq!(expected.len(), 2);
+    assert_eq!(scan(&spend_pubkey), expected);
+    
+    // Lifting the x-only spend key to even Y finds neither output
+    let even_lift = PublicKey::from_x_only_public_key(spend_pubkey.x_only_public_key().0, Parity::Even);
+    assert!(scan(&even_lift).is_empty());
+}
+
+#[test]
+fn verify_official_bip352_sending_vectors() {
+    use std::collections::BTreeSet;
+    
+    for case in official_vectors() {
+        let comment = case["comment"].as_str().unwrap_or("").to_string();
+        for sending in case["sending"].as_array().unwrap() {
+            let given = &sending["given"];
+            let (tx, prevouts) = vector_transaction(&given["vin"], Vec::new());
+            
+            // Only eligible inputs' keys count, as for the receiver, with a
+            // taproot key negated when its output key has odd Y; every
+            // outpoint counts for the input hash
+            let secp = Secp256k1::new();
+            let secrets: Vec<SecretKey> = given["vin"].as_array().unwrap()
+                .iter()
+                .enumerate()
+                .filter_map(|(i, input)| {
+                    let single = bitcoin::Transaction { input: vec![tx.input[i].clone()], ..tx.clone() };
+                    let eligible = InputData::from_transaction(&single, &prevouts[i..=i]).pop()?;
+                    let secret = SecretKey::from_slice(&hex::decode(input["private_key"].as_str().unwrap()).unwrap()).unwrap();
+                    Some(if secret.public_key(&secp) == eligible.pubkey { secret } else { secret.negate() })
+                })
+                .collect();
+            let outpoints: Vec<bitcoin::OutPoint> = tx.input.iter().map(|txin| txin.previous_output).collect();
+            // A recipient with a `count` is paid that many times
+            let recipients: Vec<SilentPaymentAddress> = given["recipients"].as_array().unwrap()
+                .iter()
+                .flat_map(|recipient| {
+                    let address: SilentPaymentAddress = recipient.as_str()
+                        .or_else(|| recipient["address"].as_str())
+                        .unwrap()
+                        .parse()
+                        .unwrap();
+                    let count = recipient["count"].as_u64().unwrap_or(1) as usize;
+                    std::iter::repeat_n(address, count)
+                })
+                .collect();
+            
+            // Sending fails without eligible inputs, when their keys cancel
+            // out or past K_max; the vectors expect no outputs then
+            let outputs: BTreeSet<String> = match derive_send_outputs(&secrets, &outpoints, &recipients) {
+                Ok(outputs) => outputs.iter().map(|(_, key)| hex::encode(key.serialize())).collect(),
+                Err(_) => BTreeSet::new(),
+            };
+            
+            // Expected outputs are one set, or several acceptable sets when
+            // recipient order leaves k ambiguous
+            let expected = sending["expected"]["outputs"].as_array().unwrap();
+            let as_set = |keys: &serde_json::Value| -> BTreeSet<String> {
+                keys.as_array().unwrap().iter().map(|k| k.as_str().unwrap().to_string()).collect()
+            };
+            let acceptable: Vec<BTreeSet<String>> = if expected.iter().all(|e| e.is_array()) && !expected.is_empty() {
+                expected.iter().map(as_set).collect()
+            } else {
+                vec![as_set(&sending["expected"]["outputs"])]
+            };
+            assert!(acceptable.contains(&outputs), "{}: got {:?}", comment, outputs);
+        }
+    }
+}
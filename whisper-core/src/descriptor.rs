@@ -0,0 +1,83 @@
+use bitcoin::hashes::{sha256, Hash};
+use crate::ScanResult;
+
+/// Characters allowed in a descriptor, grouped as in Bitcoin Core's
+/// checksum (BIP-380).
+const INPUT_CHARSET: &str =
+    "0123456789()[],'/*abcdefgh@:$%{}IJKLMNOPQRSTUVWXYZ&+-.;<=>?!^_|~ijklmnopqrstuvwxyzABCDEFGH`#\"\\ ";
+const CHECKSUM_CHARSET: &[u8] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+
+fn polymod(c: u64, val: u64) -> u64 {
+    let c0 = c >> 35;
+    let mut c = ((c & 0x7_ffff_ffff) << 5) ^ val;
+    for (bit, generator) in [
+        0xf5_dee5_1989,
+        0xa9_fdca_3312,
+        0x1b_ab10_e32d,
+        0x37_06b1_677a,
+        0x64_4d62_6ffd,
+    ]
+    .into_iter()
+    .enumerate()
+    {
+        if c0 & (1 << bit) != 0 {
+            c ^= generator;
+        }
+    }
+    c
+}
+
+/// BIP-380 descriptor checksum (the 8 characters after `#`), or `None` if
+/// `descriptor` contains a character descriptors can't hold.
+pub fn descriptor_checksum(descriptor: &str) -> Option<String> {
+    let mut c = 1u64;
+    let mut cls = 0u64;
+    let mut cls_count = 0;
+    for ch in descriptor.chars() {
+        let pos = INPUT_CHARSET.find(ch)? as u64;
+        c = polymod(c, pos & 31);
+        cls = cls * 3 + (pos >> 5);
+        cls_count += 1;
+        if cls_count == 3 {
+            c = polymod(c, cls);
+            cls = 0;
+            cls_count = 0;
+        }
+    }
+    if cls_count > 0 {
+        c = polymod(c, cls);
+    }
+    for _ in 0..8 {
+        c = polymod(c, 0);
+    }
+    c ^= 1;
+
+    Some((0..8).map(|j| CHECKSUM_CHARSET[((c >> (5 * (7 - j))) & 31) as usize] as char).collect())
+}
+
+/// `rawtr(<output key>)#checksum` descriptors for found outputs, ready for
+/// Bitcoin Core's `importdescriptors` into a watch-only wallet. Each
+/// describes exactly `ScanResult::output_script`; results sharing an output
+/// key yield one descriptor.
+pub fn export_descriptors(results: &[ScanResult]) -> Vec<String> {
+    let mut seen = std::collections::HashSet::new();
+    results
+        .iter()
+        .filter(|result| seen.insert(result.output_pubkey))
+        .map(|result| {
+            let descriptor = format!("rawtr({})", result.output_pubkey);
+            let checksum = descriptor_checksum(&descriptor).expect("hex is in the descriptor charset");
+            format!("{}#{}", descriptor, checksum)
+        })
+        .collect()
+}
+
+/// Electrum protocol scripthash of a found output: SHA256 of its
+/// scriptPubKey, byte-reversed. Hex-encoded, it is what
+/// `blockchain.scripthash.subscribe` expects, so a wallet can watch the
+/// output on an Electrum server.
+pub fn scan_result_scripthash(result: &ScanResult) -> [u8; 32] {
+    let mut hash = sha256::Hash::hash(result.output_script().as_bytes()).to_byte_array();
+    hash.reverse();
+    hash
+}
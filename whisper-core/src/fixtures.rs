@@ -0,0 +1,88 @@
+use bitcoin::hashes::{hash160, Hash};
+use bitcoin::secp256k1::{PublicKey, Secp256k1, SecretKey};
+use bitcoin::{Amount, OutPoint, ScriptBuf, Transaction, TxIn, TxOut, Txid, Witness};
+use crate::{sender_output_pubkey, CoreError, InputData, ScanKey, ScanResult, TaggedHash};
+
+/// A synthetic transaction paying a silent payment wallet, for tests that
+/// need chain data without a node: feed `transaction` to an indexer (or
+/// build candidates from it) and expect the wallet to find `expected`.
+#[derive(Debug, Clone)]
+pub struct TestPayment {
+    /// One P2WPKH input and outputs k = 0, 1, ... to the wallet, the
+    /// payment being the last
+    pub transaction: Transaction,
+    /// Outputs spent by `transaction.input`, in order
+    pub prevouts: Vec<TxOut>,
+    /// What scanning `transaction` with the wallet's keys should return
+    pub expected: ScanResult,
+}
+
+/// Deterministic payment to `spend_pubkey` (with `label`, `None` for the
+/// unlabeled output) numbered `k` among the outputs paying the scan key,
+/// from a sender key derived from `seed`. The transaction also pays
+/// outputs 0..k to the same address, as scans (`scan_transaction`, prefix
+/// scans) only find the k-th output after those. Different seeds give
+/// different senders, transactions and output keys; the same seed always
+/// gives the same payment.
+pub fn generate_test_payment(
+    scan_key: &ScanKey,
+    spend_pubkey: &PublicKey,
+    label: Option<u32>,
+    k: u32,
+    seed: u32,
+) -> Result<TestPayment, CoreError> {
+    let secp = Secp256k1::new();
+    let sender_secret = SecretKey::from_slice(&TaggedHash::hash("Whisper/TestPayment", &seed.to_be_bytes()))
+        .map_err(|e| CoreError::CryptoError(e.to_string()))?;
+    let sender_key = PublicKey::from_secret_key(&secp, &sender_secret);
+
+    // The sender spends a P2WPKH output of a made-up funding transaction
+    let key_hash = hash160::Hash::hash(&sender_key.serialize());
+    let mut funding_script = vec![0x00, 0x14];
+    funding_script.extend_from_slice(key_hash.as_byte_array());
+    let amount = 10_000 + u64::from(seed % 1_000) * 100;
+    let prevout = TxOut {
+        value: Amount::from_sat(amount + 1_000),
+        script_pubkey: ScriptBuf::from_bytes(funding_script),
+    };
+    let funding_outpoint = OutPoint {
+        txid: Txid::from_byte_array(TaggedHash::hash("Whisper/TestFunding", &seed.to_be_bytes())),
+        vout: 0,
+    };
+
+    // Paid the way a sender would: to the key in the (labeled) address
+    let inputs = [InputData { pubkey: sender_key, is_taproot: false, outpoint: Some(funding_outpoint) }];
+    let shared_secret = scan_key.compute_shared_secret(&inputs)?;
+    let address_key = match label {
+        Some(m) => scan_key.labeled_spend_pubkey(spend_pubkey, m)?,
+        None => *spend_pubkey,
+    };
+    let outputs = (0..=k)
+        .map(|output_k| {
+            let mut script = vec![0x51, 0x20];
+            script.extend_from_slice(&sender_output_pubkey(&shared_secret, &address_key, output_k)?.serialize());
+            Ok(TxOut { value: Amount::from_sat(amount), script_pubkey: ScriptBuf::from_bytes(script) })
+        })
+        .collect::<Result<Vec<_>, CoreError>>()?;
+
+    let transaction = Transaction {
+        version: bitcoin::transaction::Version::TWO,
+        lock_time: bitcoin::absolute::LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: funding_outpoint,
+            // A placeholder signature: only the revealed key matters here
+            witness: Witness::from_slice(&[vec![0u8; 71], sender_key.serialize().to_vec()]),
+            ..Default::default()
+        }],
+        output: outputs,
+    };
+
+    let prevouts = vec![prevout];
+    let expected = scan_key
+        .scan_transaction(&transaction, &prevouts, spend_pubkey, &[label])?
+        .into_iter()
+        .find(|result| result.vout == k)
+        .ok_or_else(|| CoreError::CryptoError("generated payment does not match its own keys".into()))?;
+
+    Ok(TestPayment { transaction, prevouts, expected })
+}
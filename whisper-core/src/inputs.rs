@@ -0,0 +1,188 @@
+use bitcoin::hashes::{hash160, Hash};
+use bitcoin::script::Instruction;
+use bitcoin::secp256k1::{Parity, PublicKey, Scalar, Secp256k1, XOnlyPublicKey};
+use bitcoin::{OutPoint, Transaction, TxIn, TxOut, Witness};
+use crate::{CoreError, InputData, TaggedHash};
+
+/// Length of a compressed SEC1 public key.
+const COMPRESSED_PUBKEY_LEN: usize = 33;
+
+/// BIP-341 NUMS point H, used as the internal key of script-path-only
+/// outputs. BIP-352 skips inputs that reveal it.
+const NUMS_H: [u8; 32] = [
+    0x50, 0x92, 0x9b, 0x74, 0xc1, 0xa0, 0x49, 0x54, 0xb7, 0x8b, 0x4b, 0x60, 0x35, 0xe9, 0x7a, 0x5e,
+    0x07, 0x8a, 0x5a, 0x0f, 0x28, 0xec, 0x96, 0xd5, 0x47, 0xbf, 0xee, 0x9a, 0xce, 0x80, 0x3a, 0xc0,
+];
+
+/// Whether a taproot witness is a script-path spend whose control block
+/// reveals the NUMS internal key. The annex (a last element starting with
+/// 0x50 when there are at least two elements) is ignored, so it isn't
+/// mistaken for the control block.
+fn is_nums_script_path(witness: &Witness) -> bool {
+    let mut items: Vec<&[u8]> = witness.iter().collect();
+    if items.len() >= 2 && items.last().is_some_and(|annex| annex.first() == Some(&0x50)) {
+        items.pop();
+    }
+    
+    // A single element is a key-path signature
+    if items.len() < 2 {
+        return false;
+    }
+    
+    let control_block = items[items.len() - 1];
+    control_block.len() >= 33 && control_block[1..33] == NUMS_H
+}
+
+/// P2WPKH witness program: OP_0 <20-byte key hash>.
+fn is_p2wpkh_program(script: &[u8]) -> bool {
+    script.len() == 22 && script[0] == 0x00 && script[1] == 0x14
+}
+
+/// Compressed pubkey from a P2WPKH witness (`<signature> <pubkey>`), if it
+/// hashes to `key_hash`.
+fn witness_pubkey(txin: &TxIn, key_hash: &[u8]) -> Option<PublicKey> {
+    if txin.witness.len() != 2 {
+        return None;
+    }
+    let key = txin.witness.last()?;
+    if key.len() != COMPRESSED_PUBKEY_LEN || !hash160_matches(key, key_hash) {
+        return None;
+    }
+    PublicKey::from_slice(key).ok()
+}
+
+fn hash160_matches(pubkey_bytes: &[u8], expected: &[u8]) -> bool {
+    hash160::Hash::hash(pubkey_bytes).as_byte_array()[..] == expected[..]
+}
+
+/// Extract the public key contributed by an input, if the input type is
+/// eligible for Silent Payments (BIP-352 "Inputs For Shared Secret Derivation").
+///
+/// Supported: P2TR (key path, or script path unless the internal key is the
+/// NUMS point), P2WPKH, P2SH-P2WPKH and P2PKH with a compressed key.
+/// Everything else (P2WSH, bare multisig, uncompressed keys, ...) returns `None`.
+pub fn extract_eligible_input(txin: &TxIn, prevout: &TxOut) -> Option<InputData> {
+    let script = prevout.script_pubkey.as_bytes();
+
+    let pubkey = if prevout.script_pubkey.is_p2tr() {
+        // Key-path and script-path spends both contribute the output key,
+        // except script paths from a NUMS internal key
+        if is_nums_script_path(&txin.witness) {
+            return None;
+        }
+        // Output key is x-only; BIP-352 lifts it with even Y
+        let x_only = XOnlyPublicKey::from_slice(&script[2..34]).ok()?;
+        PublicKey::from_x_only_public_key(x_only, Parity::Even)
+    } else if prevout.script_pubkey.is_p2wpkh() {
+        // Witness: <signature> <compressed pubkey>
+        witness_pubkey(txin, &script[2..22])?
+    } else if prevout.script_pubkey.is_p2sh() {
+        // scriptSig is a single push of the redeemScript, which must be a
+        // P2WPKH program committed to by the P2SH hash; the key then comes
+        // from the witness as for native P2WPKH
+        let mut instructions = txin.script_sig.instructions();
+        let redeem_script = match (instructions.next(), instructions.next()) {
+            (Some(Ok(Instruction::PushBytes(bytes))), None) => bytes.as_bytes(),
+            _ => return None,
+        };
+        if !is_p2wpkh_program(redeem_script) || !hash160_matches(redeem_script, &script[2..22]) {
+            return None;
+        }
+        witness_pubkey(txin, &redeem_script[2..22])?
+    } else if prevout.script_pubkey.is_p2pkh() {
+        // scriptSig: <signature> <pubkey>; take the last push that hashes
+        // to the committed key hash (a malleated scriptSig may carry extras)
+        let pushes: Vec<&[u8]> = txin
+            .script_sig
+            .instructions()
+            .filter_map(|ins| match ins {
+                Ok(Instruction::PushBytes(bytes)) => Some(bytes.as_bytes()),
+                _ => None,
+            })
+            .collect();
+        let key = pushes
+            .into_iter()
+            .rev()
+            .find(|bytes| {
+                bytes.len() == COMPRESSED_PUBKEY_LEN && hash160_matches(bytes, &script[3..23])
+            })?;
+        PublicKey::from_slice(key).ok()?
+    } else {
+        return None;
+    };
+
+    Some(InputData {
+        pubkey,
+        is_taproot: prevout.script_pubkey.is_p2tr(),
+        outpoint: Some(txin.previous_output),
+    })
+}
+
+impl InputData {
+    /// Build the shared-secret inputs for a transaction. `prevouts` must be
+    /// the outputs spent by `tx.input`, in the same order. Ineligible inputs
+    /// are skipped, as are inputs without a matching prevout. With no
+    /// eligible input the list is empty and the transaction is not a silent
+    /// payment (`ScanKey::scan_transaction` returns no results for it).
+    pub fn from_transaction(tx: &Transaction, prevouts: &[TxOut]) -> Vec<InputData> {
+        tx.input
+            .iter()
+            .zip(prevouts)
+            .filter_map(|(txin, prevout)| extract_eligible_input(txin, prevout))
+            .collect()
+    }
+
+    /// The transaction's tweak, input_hash * A, where A is the sum of the
+    /// input keys and `input_hash` commits to it and the smallest outpoint
+    /// of `inputs` (see `input_hash`). A receiver's shared secret is its
+    /// scan secret times this point, so it is what an index server
+    /// publishes per transaction. `None` when there are no inputs or their
+    /// keys sum to the point at infinity: no sender can pay such a
+    /// transaction's outputs to a silent payment address.
+    ///
+    /// BIP-352 takes the smallest outpoint the transaction spends, eligible
+    /// or not; when it also spends inputs left out of `inputs`, use
+    /// `tweak_with_outpoints` with all of them.
+    pub fn tweak(inputs: &[InputData]) -> Option<PublicKey> {
+        let outpoints: Vec<OutPoint> = inputs
+            .iter()
+            .map(|input| input.outpoint.unwrap_or_else(OutPoint::null))
+            .collect();
+        Self::tweak_with_outpoints(inputs, &outpoints)
+    }
+
+    /// `tweak` with the input hash over `outpoints`, every outpoint spent
+    /// by the transaction, including those of ineligible inputs.
+    pub fn tweak_with_outpoints(inputs: &[InputData], outpoints: &[OutPoint]) -> Option<PublicKey> {
+        let keys: Vec<&PublicKey> = inputs.iter().map(|input| &input.pubkey).collect();
+        let input_pubkey_sum = PublicKey::combine_keys(&keys).ok()?;
+        let input_hash = Scalar::from_be_bytes(input_hash(outpoints, &input_pubkey_sum).ok()?).ok()?;
+        input_pubkey_sum.mul_tweak(&Secp256k1::new(), &input_hash).ok()
+    }
+
+    /// Tweak of `tx` (see `tweak`), from its eligible inputs among
+    /// `prevouts` and all the outpoints it spends.
+    pub fn transaction_tweak(tx: &Transaction, prevouts: &[TxOut]) -> Option<PublicKey> {
+        let outpoints: Vec<OutPoint> = tx.input.iter().map(|txin| txin.previous_output).collect();
+        Self::tweak_with_outpoints(&Self::from_transaction(tx, prevouts), &outpoints)
+    }
+}
+
+/// BIP-352 input hash: TaggedHash("BIP0352/Inputs", outpoint_L || A),
+/// where outpoint_L is the smallest of `outpoints` as serialized in a
+/// transaction (txid in internal byte order, then the little-endian vout,
+/// compared bytewise) and A the compressed sum of the input keys. A sender
+/// multiplies its summed input secret by it, a receiver the summed input
+/// keys.
+pub fn input_hash(outpoints: &[OutPoint], input_pubkey_sum: &PublicKey) -> Result<[u8; 32], CoreError> {
+    let smallest = outpoints
+        .iter()
+        .map(bitcoin::consensus::serialize)
+        .min()
+        .ok_or_else(|| CoreError::InvalidInput("at least one outpoint is required".into()))?;
+
+    let mut data = Vec::with_capacity(36 + 33);
+    data.extend_from_slice(&smallest);
+    data.extend_from_slice(&input_pubkey_sum.serialize());
+    Ok(TaggedHash::hash(TaggedHash::INPUTS, &data))
+}
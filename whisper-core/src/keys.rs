@@ -0,0 +1,187 @@
+use bitcoin::bip32::{ChildNumber, DerivationPath, Xpriv};
+use bitcoin::secp256k1::{PublicKey, Secp256k1, SecretKey};
+use bitcoin::Network;
+use crate::{CoreError, ScanKey, SilentPaymentAddress, SpendKey};
+
+/// Scan and spend secrets of one wallet account.
+///
+/// The scan key is what scanning needs; the spend key's secret is needed
+/// to sign for received outputs. Wallets should hold both through this type.
+#[derive(Debug, Clone)]
+pub struct SilentPaymentKeypair {
+    pub scan: ScanKey,
+    pub spend: SpendKey,
+    /// Account path the keys were derived below, if they came from BIP-32
+    account_path: Option<DerivationPath>,
+}
+
+impl SilentPaymentKeypair {
+    /// BIP-352 branch below the account for the scan key (`1'/0`).
+    const SCAN_BRANCH: [ChildNumber; 2] = [
+        ChildNumber::Hardened { index: 1 },
+        ChildNumber::Normal { index: 0 },
+    ];
+    /// BIP-352 branch below the account for the spend key (`0'/0`).
+    const SPEND_BRANCH: [ChildNumber; 2] = [
+        ChildNumber::Hardened { index: 0 },
+        ChildNumber::Normal { index: 0 },
+    ];
+    
+    pub fn from_secrets(scan_secret: SecretKey, spend_secret: SecretKey) -> Result<Self, CoreError> {
+        let secp = Secp256k1::new();
+        Ok(Self {
+            scan: ScanKey::new(scan_secret)?,
+            spend: SpendKey {
+                public: PublicKey::from_secret_key(&secp, &spend_secret),
+                secret: Some(spend_secret),
+            },
+            account_path: None,
+        })
+    }
+    
+    /// Derive both keys below `account_path` of `master`, using the BIP-352
+    /// branches `<account_path>/1'/0` (scan) and `<account_path>/0'/0` (spend).
+    pub fn from_bip32(master: &Xpriv, account_path: &DerivationPath) -> Result<Self, CoreError> {
+        let secp = Secp256k1::new();
+        let derive = |branch: &[ChildNumber]| {
+            master
+                .derive_priv(&secp, &account_path.extend(branch))
+                .map(|xpriv| xpriv.private_key)
+                .map_err(|e| CoreError::InvalidKey(e.to_string()))
+        };
+        
+        Ok(Self {
+            account_path: Some(account_path.clone()),
+            ..Self::from_secrets(derive(&Self::SCAN_BRANCH)?, derive(&Self::SPEND_BRANCH)?)?
+        })
+    }
+    
+    /// Unlabeled address for these keys on `network`.
+    pub fn address(&self, network: Network) -> SilentPaymentAddress {
+        self.scan.to_address(&self.spend.public, network)
+    }
+    
+    /// Account path the keys were derived below (`from_bip32` and
+    /// `derive_keys_from_seed`); `None` for keys from `from_secrets`.
+    pub fn account_path(&self) -> Option<&DerivationPath> {
+        self.account_path.as_ref()
+    }
+    
+    /// Fresh addresses to hand out: the unlabeled address, then labels
+    /// 1, 2, ... Payments to any of them are found by the same scan, given
+    /// the labels handed out so far (`with_labels` / `max_label` in the
+    /// client). Labeled ones hold their labeled spend key, so
+    /// `address.to_string()` is the string to share.
+    pub fn addresses(&self, network: Network) -> impl Iterator<Item = SilentPaymentAddress> + '_ {
+        let labeled = (1..=u32::MAX)
+            .filter_map(move |m| self.scan.to_labeled_address(&self.spend.public, m, network).ok());
+        std::iter::once(self.address(network)).chain(labeled)
+    }
+    
+    /// Check that these keys are meant for `network`. Keys themselves
+    /// aren't tied to a network, but keys derived along a BIP-352 path
+    /// (`m/352'/coin_type'/...`) are: coin type 0 is mainnet, 1 the test
+    /// networks. Fails with `CoreError::NetworkMismatch` when the path's
+    /// coin type is for the other kind of network; keys from `from_secrets`
+    /// or a non-BIP-352 path can't be checked and pass.
+    pub fn validate_for_network(&self, network: Network) -> Result<(), CoreError> {
+        let Some(derived) = self.account_path.as_ref().and_then(bip352_coin_type) else {
+            return Ok(());
+        };
+        
+        let expected = coin_type(network);
+        if derived != expected {
+            return Err(CoreError::NetworkMismatch(format!(
+                "keys derived with coin type {}' ({}) used on {}",
+                derived,
+                if derived == 0 { "mainnet" } else { "test networks" },
+                network,
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// BIP-44 style coin type BIP-352 derivation uses on `network`.
+fn coin_type(network: Network) -> u32 {
+    if network == Network::Bitcoin { 0 } else { 1 }
+}
+
+/// Coin type of an `m/352'/coin_type'/...` path, if `path` is one.
+fn bip352_coin_type(path: &DerivationPath) -> Option<u32> {
+    match path.as_ref() {
+        [ChildNumber::Hardened { index: 352 }, ChildNumber::Hardened { index }, ..] => Some(*index),
+        _ => None,
+    }
+}
+
+/// Scan secret and public spend key of one wallet account, for scanning
+/// daemons that should only detect payments, never spend them.
+///
+/// There is nowhere to put a spend secret, so nothing holding this type
+/// can sign for a found output. Signing a sweep takes a full keypair:
+///
+/// ```
+/// # fn check(psbt: bitcoin::Psbt, keypair: whisper_core::SilentPaymentKeypair, results: &[whisper_core::ScanResult]) {
+/// let signed = whisper_core::sign_sweep(psbt, &keypair, results, None);
+/// # }
+/// ```
+///
+/// and the same call with a scan-only keypair doesn't compile:
+///
+/// ```compile_fail,E0308
+/// # fn check(psbt: bitcoin::Psbt, keypair: whisper_core::ScanOnlyKeypair, results: &[whisper_core::ScanResult]) {
+/// let signed = whisper_core::sign_sweep(psbt, &keypair, results, None);
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct ScanOnlyKeypair {
+    pub scan: ScanKey,
+    pub spend_pubkey: PublicKey,
+}
+
+impl ScanOnlyKeypair {
+    pub fn new(scan_secret: SecretKey, spend_pubkey: PublicKey) -> Result<Self, CoreError> {
+        Ok(Self {
+            scan: ScanKey::new(scan_secret)?,
+            spend_pubkey,
+        })
+    }
+    
+    /// Unlabeled address for these keys on `network`.
+    pub fn address(&self, network: Network) -> SilentPaymentAddress {
+        self.scan.to_address(&self.spend_pubkey, network)
+    }
+}
+
+impl From<SilentPaymentKeypair> for ScanOnlyKeypair {
+    /// Drop the spend secret, keeping what scanning needs.
+    fn from(keypair: SilentPaymentKeypair) -> Self {
+        Self {
+            scan: keypair.scan,
+            spend_pubkey: keypair.spend.public,
+        }
+    }
+}
+
+/// Derive the keys of `account` from a BIP-32 seed along the BIP-352 paths
+/// `m/352'/coin_type'/account'/1'/0` (scan) and `m/352'/coin_type'/account'/0'/0`
+/// (spend), with `coin_type` 0 on mainnet and 1 on test networks. The same
+/// seed always yields the same address, so wallets can be restored.
+pub fn derive_keys_from_seed(
+    seed: &[u8],
+    network: Network,
+    account: u32,
+) -> Result<SilentPaymentKeypair, CoreError> {
+    let master = Xpriv::new_master(network, seed)
+        .map_err(|e| CoreError::InvalidKey(e.to_string()))?;
+    
+    let account_path: DerivationPath = [352, coin_type(network), account]
+        .into_iter()
+        .map(ChildNumber::from_hardened_idx)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| CoreError::InvalidKey(e.to_string()))?
+        .into();
+    
+    SilentPaymentKeypair::from_bip32(&master, &account_path)
+}
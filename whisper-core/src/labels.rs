@@ -0,0 +1,154 @@
+use bitcoin::secp256k1::{Parity, PublicKey, Scalar, Secp256k1, SecretKey, XOnlyPublicKey};
+use std::collections::HashMap;
+use crate::{taproot_output_key, CoreError, InputData, ScanKey, TaggedHash, K_MAX};
+
+/// Result of a table-based label lookup (BIP-352 labels, B_m = B + label_m*G).
+#[derive(Debug, Clone)]
+pub struct LabeledOutputMatch {
+    /// `None` for a payment to the unlabeled spend key
+    pub label: Option<u32>,
+    /// Scalar to add to the spend secret to spend the output (t_k [+ label_m])
+    pub tweak: [u8; 32],
+    pub output_pubkey: XOnlyPublicKey,
+}
+
+impl ScanKey {
+    /// label_m = TaggedHash("BIP0352/Label", scan_secret || ser32(m))
+    pub fn label_tweak(&self, m: u32) -> [u8; 32] {
+        let mut data = Vec::with_capacity(36);
+        data.extend_from_slice(&self.secret.secret_bytes());
+        data.extend_from_slice(&m.to_be_bytes());
+        TaggedHash::hash(TaggedHash::LABEL, &data)
+    }
+
+    /// Labeled spend key B_m = B + label_m*G (the key a labeled address encodes).
+    pub fn labeled_spend_pubkey(
+        &self,
+        spend_pubkey: &PublicKey,
+        m: u32,
+    ) -> Result<PublicKey, CoreError> {
+        let secp = Secp256k1::new();
+        let tweak = Scalar::from_be_bytes(self.label_tweak(m))
+            .map_err(|_| CoreError::CryptoError("Invalid label scalar".into()))?;
+        spend_pubkey
+            .add_exp_tweak(&secp, &tweak)
+            .map_err(|e| CoreError::CryptoError(e.to_string()))
+    }
+
+    /// Precompute label_m*G -> m for labels 1..=max_label.
+    ///
+    /// After deriving the unlabeled output P_0 = B + t*G, the difference
+    /// between a candidate output and P_0 is exactly label_m*G, so a single
+    /// hash lookup identifies the label regardless of how many exist.
+    pub fn label_lookup_table(&self, max_label: u32) -> HashMap<[u8; 33], u32> {
+        let secp = Secp256k1::new();
+        (1..=max_label)
+            .filter_map(|m| {
+                let secret = SecretKey::from_slice(&self.label_tweak(m)).ok()?;
+                Some((PublicKey::from_secret_key(&secp, &secret).serialize(), m))
+            })
+            .collect()
+    }
+
+    /// Check one transaction's candidate outputs against the unlabeled
+    /// spend key and every label in `label_table`, in O(1) per candidate
+    /// and output, returning (index into `candidate_script_pubkeys`, match)
+    /// in candidate order.
+    ///
+    /// As in `scan_transaction`, the candidates are checked as the k-th
+    /// output paying the scan key for k = 0, 1, ..., stopping at the first
+    /// k that none of them matches.
+    pub fn check_outputs_with_labels(
+        &self,
+        candidate_script_pubkeys: &[&[u8]],
+        spend_pubkey: &PublicKey,
+        inputs: &[InputData],
+        label_table: &HashMap<[u8; 33], u32>,
+    ) -> Result<Vec<(usize, LabeledOutputMatch)>, CoreError> {
+        let mut unmatched: HashMap<XOnlyPublicKey, usize> = HashMap::new();
+        for (i, script) in candidate_script_pubkeys.iter().enumerate() {
+            let Some(x_only_bytes) = taproot_output_key(script) else {
+                continue;
+            };
+            let candidate_xonly = XOnlyPublicKey::from_slice(x_only_bytes)
+                .map_err(|e| CoreError::InvalidKey(e.to_string()))?;
+            unmatched.entry(candidate_xonly).or_insert(i);
+        }
+        if unmatched.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let shared_secret = self.compute_shared_secret(inputs)?;
+        let mut results = Vec::new();
+        for k in 0..K_MAX {
+            let mut found = None;
+            for (&candidate_xonly, &i) in &unmatched {
+                if let Some(output_match) = self.match_with_table(&shared_secret, k, candidate_xonly, spend_pubkey, label_table)? {
+                    found = Some((i, output_match));
+                    break;
+                }
+            }
+            let Some((i, output_match)) = found else {
+                break;
+            };
+            unmatched.remove(&output_match.output_pubkey);
+            results.push((i, output_match));
+        }
+
+        results.sort_by_key(|(i, _)| *i);
+        Ok(results)
+    }
+
+    /// Match one output key as the k-th output, via the label table.
+    fn match_with_table(
+        &self,
+        shared_secret: &[u8; 33],
+        k: u32,
+        candidate_xonly: XOnlyPublicKey,
+        spend_pubkey: &PublicKey,
+        label_table: &HashMap<[u8; 33], u32>,
+    ) -> Result<Option<LabeledOutputMatch>, CoreError> {
+        let secp = Secp256k1::new();
+        let output_tweak = TaggedHash::output(shared_secret, k);
+        let t = Scalar::from_be_bytes(output_tweak)
+            .map_err(|_| CoreError::CryptoError("Invalid tweak scalar".into()))?;
+
+        // P_k = B + t_k*G
+        let base = spend_pubkey
+            .add_exp_tweak(&secp, &t)
+            .map_err(|e| CoreError::CryptoError(e.to_string()))?;
+
+        if base.x_only_public_key().0 == candidate_xonly {
+            return Ok(Some(LabeledOutputMatch {
+                label: None,
+                tweak: output_tweak,
+                output_pubkey: candidate_xonly,
+            }));
+        }
+
+        // The candidate's Y is unknown, so try D = ±P - P_k for both lifts
+        let neg_base = base.negate(&secp);
+        for parity in [Parity::Even, Parity::Odd] {
+            let candidate = PublicKey::from_x_only_public_key(candidate_xonly, parity);
+            let Ok(diff) = candidate.combine(&neg_base) else {
+                continue;
+            };
+
+            if let Some(&m) = label_table.get(&diff.serialize()) {
+                let label_secret = SecretKey::from_slice(&self.label_tweak(m))
+                    .map_err(|e| CoreError::CryptoError(e.to_string()))?;
+                let tweak = label_secret
+                    .add_tweak(&t)
+                    .map_err(|e| CoreError::CryptoError(e.to_string()))?;
+
+                return Ok(Some(LabeledOutputMatch {
+                    label: Some(m),
+                    tweak: tweak.secret_bytes(),
+                    output_pubkey: candidate_xonly,
+                }));
+            }
+        }
+
+        Ok(None)
+    }
+}
@@ -1,24 +1,50 @@
-use bitcoin::secp256k1::{PublicKey, SecretKey, Scalar, XOnlyPublicKey, Parity, Secp256k1};
+use bitcoin::secp256k1::{PublicKey, SecretKey, Scalar, XOnlyPublicKey, Secp256k1};
 use bitcoin::hashes::{sha256, Hash, HashEngine};
 use serde::{Serialize, Deserialize};
 use thiserror::Error;
 
+mod address;
+mod descriptor;
+#[cfg(any(test, feature = "test-utils"))]
+mod fixtures;
+mod inputs;
+mod keys;
+mod labels;
+mod merkle;
+mod sender;
+mod sweep;
+
+pub use descriptor::*;
+#[cfg(any(test, feature = "test-utils"))]
+pub use fixtures::*;
+pub use inputs::*;
+pub use keys::*;
+pub use labels::*;
+pub use merkle::*;
+pub use sender::*;
+pub use sweep::*;
+
 #[derive(Error, Debug)]
 pub enum CoreError {
     #[error("Invalid key: {0}")]
     InvalidKey(String),
     #[error("Cryptographic operation failed: {0}")]
     CryptoError(String),
-    #[error("Invalid input data")]
-    InvalidInput,
+    #[error("Invalid input data: {0}")]
+    InvalidInput(String),
+    #[error("Invalid silent payment address: {0}")]
+    InvalidAddress(String),
+    #[error("Network mismatch: {0}")]
+    NetworkMismatch(String),
 }
 
 /// BIP-352 Tagged Hash implementation
 pub struct TaggedHash;
 
 impl TaggedHash {
+    pub const INPUTS: &'static str = "BIP0352/Inputs";
     pub const SHARED_SECRET: &'static str = "BIP0352/SharedSecret";
-    pub const OUTPUT: &'static str = "BIP0352/Outputs";
+    pub const LABEL: &'static str = "BIP0352/Label";
     
     pub fn hash(tag: &str, data: &[u8]) -> [u8; 32] {
         let mut engine = sha256::Hash::engine();
@@ -28,35 +54,86 @@ impl TaggedHash {
         engine.input(data);
         sha256::Hash::from_engine(engine).to_byte_array()
     }
+    
+    /// Output tweak t_k for the k-th output paid to one scan key in a
+    /// transaction: TaggedHash("BIP0352/SharedSecret", shared_secret ||
+    /// ser32(k)), including for k = 0, where `shared_secret` is the
+    /// compressed ECDH point (see `ScanKey::compute_shared_secret`).
+    pub fn output(shared_secret: &[u8; 33], k: u32) -> [u8; 32] {
+        let mut data = [0u8; 37];
+        data[..33].copy_from_slice(shared_secret);
+        data[33..].copy_from_slice(&k.to_be_bytes());
+        Self::hash(Self::SHARED_SECRET, &data)
+    }
 }
 
 /// Silent Payment address components
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct SilentPaymentAddress {
-    pub spend_pubkey: XOnlyPublicKey,
-    pub scan_pubkey: XOnlyPublicKey,
+    /// Key a sender pays: the base spend key B, or B_m = B + label_m*G for
+    /// a labeled address (`ScanKey::base_spend_key` recovers B). Address keys are full points: a sender uses them with the Y they
+    /// were encoded with, odd or even.
+    pub spend_pubkey: PublicKey,
+    pub scan_pubkey: PublicKey,
     pub is_labeled: bool,
-    pub label: Option<u8>,
+    pub label: Option<u32>,
+    /// Network the address is for; defaults to mainnet when absent
+    #[serde(with = "network_serde", default = "network_serde::mainnet")]
+    pub network: bitcoin::Network,
+}
+
+/// Serializes `bitcoin::Network` by name (`bitcoin`, `testnet`, ...), since
+/// the `bitcoin` crate is built without its serde feature. Use with
+/// `#[serde(with = "whisper_core::network_serde")]`.
+pub mod network_serde {
+    use bitcoin::Network;
+    use serde::{Deserialize, Deserializer, Serializer};
+    
+    pub fn mainnet() -> Network {
+        Network::Bitcoin
+    }
+    
+    pub fn serialize<S: Serializer>(network: &Network, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(network)
+    }
+    
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Network, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        name.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+impl SilentPaymentAddress {
+    /// Whether the address uses its scan key as its spend key. That is
+    /// usually a misconfiguration: whoever holds the scan secret (e.g. a
+    /// scanning server) then also holds the key that spends payments.
+    pub fn is_single_key(&self) -> bool {
+        self.scan_pubkey == self.spend_pubkey
+    }
 }
 
 /// Scanning key pair (client holds this)
 #[derive(Debug, Clone)]
 pub struct ScanKey {
     pub secret: SecretKey,
-    pub public: XOnlyPublicKey,
+    pub public: PublicKey,
 }
 
 impl ScanKey {
     pub fn new(secret: SecretKey) -> Result<Self, CoreError> {
         let secp = Secp256k1::new();
-        let public = PublicKey::from_secret_key(&secp, &secret);
         Ok(Self {
             secret,
-            public: public.x_only_public_key().0,
+            public: PublicKey::from_secret_key(&secp, &secret),
         })
     }
     
     pub fn from_slice(data: &[u8]) -> Result<Self, CoreError> {
+        if data.len() != 32 {
+            return Err(CoreError::InvalidInput(format!(
+                "scan secret must be 32 bytes, got {}", data.len()
+            )));
+        }
         let secret = SecretKey::from_slice(data)
             .map_err(|e| CoreError::InvalidKey(e.to_string()))?;
         Self::new(secret)
@@ -66,7 +143,7 @@ impl ScanKey {
 /// Spend key (public only for scanning, secret for spending)
 #[derive(Debug, Clone)]
 pub struct SpendKey {
-    pub public: XOnlyPublicKey,
+    pub public: PublicKey,
     pub secret: Option<SecretKey>,
 }
 
@@ -75,30 +152,99 @@ pub struct SpendKey {
 pub struct InputData {
     pub pubkey: PublicKey,
     pub is_taproot: bool,
+    /// Outpoint spent by this input, for the BIP-352 input hash. `None`
+    /// counts as the null outpoint, for inputs made up without a
+    /// transaction (e.g. in tests); a sender deriving outputs for the same
+    /// inputs must then use `OutPoint::null()` too.
+    pub outpoint: Option<bitcoin::OutPoint>,
 }
 
 /// Intermediate result from output checking — contains only
 /// the cryptographically derived fields. Caller fills tx metadata.
 #[derive(Debug, Clone)]
 pub struct OutputMatch {
-    pub label: Option<u8>,
+    pub label: Option<u32>,
     pub tweak: [u8; 32],
     pub output_pubkey: XOnlyPublicKey,
+    /// Unlabeled spend key the output was matched against
+    pub spend_pubkey: PublicKey,
 }
 
-/// Full result of scanning one output (includes tx metadata)
+/// Full result of scanning one output (includes tx metadata).
+///
+/// Equality and hashing consider only the outpoint `(txid, vout)`, so the
+/// same output found by overlapping scans compares equal.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ScanResult {
+    /// Transaction hash in internal byte order (`Txid::to_byte_array`);
+    /// `outpoint().txid` displays it the way block explorers do
     pub txid: [u8; 32],
     pub vout: u32,
     pub amount: u64,
-    pub label: Option<u8>,
+    pub label: Option<u32>,
     pub tweak: [u8; 32],
     pub output_pubkey: XOnlyPublicKey,
+    /// Height of the block the output was found in, if known
+    #[serde(default)]
+    pub block_height: Option<u32>,
+    /// Base (unlabeled) spend key that produced the match, so the output
+    /// key can be re-derived as `spend_pubkey + tweak*G` without
+    /// remembering which of several spend keys matched. Not part of the
+    /// `to_bytes` encoding.
+    #[serde(default)]
+    pub spend_pubkey: Option<PublicKey>,
+    /// Index of the wallet account that matched, for clients scanning
+    /// several accounts (0 otherwise). Not part of the `to_bytes` encoding.
+    #[serde(default)]
+    pub account: usize,
+}
+
+impl PartialEq for ScanResult {
+    fn eq(&self, other: &Self) -> bool {
+        self.txid == other.txid && self.vout == other.vout
+    }
+}
+
+impl Eq for ScanResult {}
+
+impl std::hash::Hash for ScanResult {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.txid.hash(state);
+        self.vout.hash(state);
+    }
+}
+
+/// Keep one result per outpoint, preferring the one seen at the highest
+/// block height (e.g. after a reorg rescan). Order of first appearance is
+/// preserved.
+pub fn dedup_results(results: Vec<ScanResult>) -> Vec<ScanResult> {
+    let mut deduped: Vec<ScanResult> = Vec::with_capacity(results.len());
+    let mut index: std::collections::HashMap<([u8; 32], u32), usize> = std::collections::HashMap::new();
+    
+    for result in results {
+        match index.entry((result.txid, result.vout)) {
+            std::collections::hash_map::Entry::Occupied(entry) => {
+                let kept = &mut deduped[*entry.get()];
+                if result.block_height > kept.block_height {
+                    *kept = result;
+                }
+            }
+            std::collections::hash_map::Entry::Vacant(entry) => {
+                entry.insert(deduped.len());
+                deduped.push(result);
+            }
+        }
+    }
+    
+    deduped
 }
 
 impl ScanResult {
+    /// Length of the `to_bytes` encoding.
+    pub const ENCODED_LEN: usize = 117;
+    
     /// Construct a full ScanResult from an OutputMatch and tx metadata.
+    /// `block_height` is left unset.
     pub fn from_match(m: &OutputMatch, txid: [u8; 32], vout: u32, amount: u64) -> Self {
         Self {
             txid,
@@ -107,80 +253,244 @@ impl ScanResult {
             label: m.label,
             tweak: m.tweak,
             output_pubkey: m.output_pubkey,
+            block_height: None,
+            spend_pubkey: Some(m.spend_pubkey),
+            account: 0,
+        }
+    }
+    
+    /// Private key controlling this output: the spend secret plus `tweak`.
+    /// Fails if that key's x-only public key isn't `output_pubkey`, i.e. the
+    /// secret isn't the one for the matched spend key.
+    pub fn spending_key(&self, spend_secret: &SecretKey) -> Result<SecretKey, CoreError> {
+        let secp = Secp256k1::new();
+        let tweak = Scalar::from_be_bytes(self.tweak)
+            .map_err(|_| CoreError::CryptoError("Invalid tweak scalar".into()))?;
+        
+        let key = spend_secret.add_tweak(&tweak)
+            .map_err(|e| CoreError::CryptoError(e.to_string()))?;
+        if key.x_only_public_key(&secp).0 != self.output_pubkey {
+            return Err(CoreError::InvalidKey("spend secret does not control this output".into()));
+        }
+        Ok(key)
+    }
+    
+    /// Sanity check that a detected output is really ours: the key derived
+    /// from `spend_secret` and `tweak` must have `output_pubkey` as its
+    /// x-only public key. Catches matches whose tweak or parity handling
+    /// would leave the output unspendable.
+    pub fn assert_spendable(&self, spend_secret: &SecretKey) -> Result<(), CoreError> {
+        self.spending_key(spend_secret).map(|_| ())
+    }
+    
+    /// Taproot data for an output that uses this output's key as its
+    /// internal key under the script tree in `tree` (e.g. with a recovery
+    /// script path), for wallets that move a received payment into such an
+    /// output. The result gives the tweaked output key, the merkle root and
+    /// a control block per leaf; `tweaked_spending_key` with the same merkle
+    /// root still spends it by key path.
+    pub fn taproot_spend_info(
+        &self,
+        tree: bitcoin::taproot::TaprootBuilder,
+    ) -> Result<bitcoin::taproot::TaprootSpendInfo, CoreError> {
+        let secp = Secp256k1::new();
+        tree.finalize(&secp, self.output_pubkey)
+            .map_err(|_| CoreError::InvalidInput("script tree is incomplete".into()))
+    }
+    
+    /// Key-path signing key for an output built by `taproot_spend_info`:
+    /// `spending_key` tweaked with `merkle_root` per BIP-341. With no merkle
+    /// root this is the BIP-86 style tweak of the bare output key, which
+    /// is not how a received output itself is spent (see `spending_key`).
+    pub fn tweaked_spending_key(
+        &self,
+        spend_secret: &SecretKey,
+        merkle_root: Option<bitcoin::taproot::TapNodeHash>,
+    ) -> Result<bitcoin::key::TweakedKeypair, CoreError> {
+        use bitcoin::key::TapTweak;
+        
+        let secp = Secp256k1::new();
+        let keypair = bitcoin::key::Keypair::from_secret_key(&secp, &self.spending_key(spend_secret)?);
+        Ok(keypair.tap_tweak(&secp, merkle_root))
+    }
+    
+    /// The output's taproot scriptPubKey, `OP_1 <32-byte output key>`.
+    pub fn output_script(&self) -> bitcoin::ScriptBuf {
+        let mut script = Vec::with_capacity(34);
+        script.extend_from_slice(&[0x51, 0x20]);
+        script.extend_from_slice(&self.output_pubkey.serialize());
+        bitcoin::ScriptBuf::from_bytes(script)
+    }
+    
+    /// The output's outpoint, for spending it in a transaction or PSBT.
+    /// `txid` holds the hash in internal byte order, so the `Txid` displays
+    /// reversed, the way block explorers show it.
+    pub fn outpoint(&self) -> bitcoin::OutPoint {
+        bitcoin::OutPoint::new(bitcoin::Txid::from_byte_array(self.txid), self.vout)
+    }
+    
+    /// The output itself (amount and `output_script`), e.g. as the
+    /// `witness_utxo` of a PSBT input or for coin selection.
+    pub fn txout(&self) -> bitcoin::TxOut {
+        bitcoin::TxOut {
+            value: bitcoin::Amount::from_sat(self.amount),
+            script_pubkey: self.output_script(),
         }
     }
+    
+    /// Compact fixed-size encoding for local storage.
+    ///
+    /// | offset | size | field                                   |
+    /// |--------|------|-----------------------------------------|
+    /// | 0      | 32   | txid                                    |
+    /// | 32     | 4    | vout (big-endian)                       |
+    /// | 36     | 8    | amount in sats (big-endian)             |
+    /// | 44     | 1    | label present (0x00 = none, 0x01 = set) |
+    /// | 45     | 4    | label (big-endian, 0 when absent)       |
+    /// | 49     | 32   | tweak                                   |
+    /// | 81     | 32   | output pubkey (x-only)                  |
+    /// | 113    | 4    | block height (big-endian, FFFFFFFF = unknown) |
+    pub fn to_bytes(&self) -> [u8; Self::ENCODED_LEN] {
+        let mut out = [0u8; Self::ENCODED_LEN];
+        out[0..32].copy_from_slice(&self.txid);
+        out[32..36].copy_from_slice(&self.vout.to_be_bytes());
+        out[36..44].copy_from_slice(&self.amount.to_be_bytes());
+        if let Some(label) = self.label {
+            out[44] = 0x01;
+            out[45..49].copy_from_slice(&label.to_be_bytes());
+        }
+        out[49..81].copy_from_slice(&self.tweak);
+        out[81..113].copy_from_slice(&self.output_pubkey.serialize());
+        out[113..117].copy_from_slice(&self.block_height.unwrap_or(u32::MAX).to_be_bytes());
+        out
+    }
+    
+    /// Decode the `to_bytes` encoding. The input must be exactly
+    /// `ENCODED_LEN` bytes. `spend_pubkey` isn't encoded and comes back
+    /// as `None`.
+    pub fn from_bytes(data: &[u8]) -> Result<Self, CoreError> {
+        if data.len() != Self::ENCODED_LEN {
+            return Err(CoreError::InvalidInput(format!(
+                "scan result must be {} bytes, got {}", Self::ENCODED_LEN, data.len()
+            )));
+        }
+        
+        let label = match (data[44], u32::from_be_bytes(data[45..49].try_into().unwrap())) {
+            (0x00, 0) => None,
+            (0x01, label) => Some(label),
+            (flag, _) => {
+                return Err(CoreError::InvalidInput(format!("invalid label flag {:#04x}", flag)));
+            }
+        };
+        
+        let output_pubkey = XOnlyPublicKey::from_slice(&data[81..113])
+            .map_err(|e| CoreError::InvalidKey(e.to_string()))?;
+        
+        let mut txid = [0u8; 32];
+        txid.copy_from_slice(&data[0..32]);
+        let mut tweak = [0u8; 32];
+        tweak.copy_from_slice(&data[49..81]);
+        
+        Ok(Self {
+            txid,
+            vout: u32::from_be_bytes(data[32..36].try_into().unwrap()),
+            amount: u64::from_be_bytes(data[36..44].try_into().unwrap()),
+            label,
+            tweak,
+            output_pubkey,
+            block_height: match u32::from_be_bytes(data[113..117].try_into().unwrap()) {
+                u32::MAX => None,
+                height => Some(height),
+            },
+            spend_pubkey: None,
+            account: 0,
+        })
+    }
 }
 
 impl ScanKey {
-    /// Compute shared secret for a set of inputs per BIP-352.
+    /// Compute shared secret for a set of inputs per BIP-352: the
+    /// compressed ECDH point ser_P(b_scan * input_hash * A), where A is the
+    /// sum of the input keys (see `InputData::tweak`). `inputs` should hold
+    /// every eligible input of the transaction; if it also spends ineligible
+    /// ones, compute the tweak with `InputData::transaction_tweak` and use
+    /// `shared_secret_from_tweak`.
     ///
-    /// Uses proper ECDH scalar multiplication (scan_secret * input_pubkey),
-    /// NOT public key addition.
-    pub fn compute_shared_secret(&self, inputs: &[InputData]) -> Result<[u8; 32], CoreError> {
-        if inputs.is_empty() {
-            return Err(CoreError::InvalidInput);
-        }
-        
+    /// All inputs are treated alike, whoever signed them: in a coinjoin the
+    /// shared secret covers every eligible input of the transaction, and
+    /// matches the senders' `shared_secret_share`s combined with
+    /// `combine_shared_secret_shares`. Fails when there are no inputs or
+    /// their keys sum to the point at infinity; such a transaction pays no
+    /// silent payment.
+    pub fn compute_shared_secret(&self, inputs: &[InputData]) -> Result<[u8; 33], CoreError> {
+        let tweak = InputData::tweak(inputs).ok_or_else(|| CoreError::InvalidInput(
+            "no eligible input keys, or they sum to the point at infinity".into()
+        ))?;
+        self.shared_secret_from_tweak(&tweak)
+    }
+    
+    /// Shared secret from a transaction's precomputed tweak point
+    /// input_hash * A (light-client variant, see `InputData::tweak`):
+    /// ser_P(scan_secret * tweak_point), equal to `compute_shared_secret`
+    /// over the transaction's inputs.
+    pub fn shared_secret_from_tweak(&self, tweak_point: &PublicKey) -> Result<[u8; 33], CoreError> {
         let secp = Secp256k1::new();
-        let mut accumulated_scalar: Option<Scalar> = None;
-        
-        for input in inputs {
-            // FIX: Use mul_tweak for proper ECDH (secret * Pubkey),
-            // not combine() which was incorrectly adding two public keys.
-            let scalar = Scalar::from_be_bytes(self.secret.secret_bytes())
-                .map_err(|_| CoreError::CryptoError("Invalid secret scalar".into()))?;
-            let shared_point = input.pubkey.mul_tweak(&secp, &scalar)
-                .map_err(|e| CoreError::CryptoError(e.to_string()))?;
-            
-            // Extract x-coordinate
-            let (x_only, _parity) = shared_point.x_only_public_key();
-            let d_bytes = x_only.serialize();
-            
-            // t_i = TaggedHash("BIP0352/SharedSecret", d_bytes)
-            let t_i_bytes = TaggedHash::hash(TaggedHash::SHARED_SECRET, &d_bytes);
-            let t_i = Scalar::from_be_bytes(t_i_bytes)
-                .map_err(|_| CoreError::CryptoError("Invalid scalar".into()))?;
-            
-            accumulated_scalar = match accumulated_scalar {
-                None => Some(t_i),
-                Some(acc) => {
-                    let sum = acc.add(&t_i);
-                    Some(sum)
-                }
-            };
-        }
+        let scalar = Scalar::from(self.secret);
+        let shared_point = tweak_point.mul_tweak(&secp, &scalar)
+            .map_err(|e| CoreError::CryptoError(e.to_string()))?;
+        Ok(shared_point.serialize())
+    }
+    
+    /// Scalar added to the spend key for the k-th output: t_k (see
+    /// `TaggedHash::output`), plus label_m for label m (the labeled spend
+    /// key is B_m = B + label_m*G, see `label_tweak`).
+    fn output_tweak(&self, shared_secret: &[u8; 33], k: u32, label: Option<u32>) -> Result<[u8; 32], CoreError> {
+        let t = TaggedHash::output(shared_secret, k);
+        let Some(m) = label else {
+            return Ok(t);
+        };
         
-        Ok(accumulated_scalar.unwrap().to_be_bytes())
+        let t = Scalar::from_be_bytes(t)
+            .map_err(|_| CoreError::CryptoError("Invalid tweak scalar".into()))?;
+        let label_secret = SecretKey::from_slice(&self.label_tweak(m))
+            .map_err(|e| CoreError::CryptoError(e.to_string()))?;
+        let tweak = label_secret.add_tweak(&t)
+            .map_err(|e| CoreError::CryptoError(e.to_string()))?;
+        Ok(tweak.secret_bytes())
     }
     
-    /// Derive output public key given shared secret and spend pubkey
+    /// Derive output public key given shared secret and spend pubkey.
+    ///
+    /// The spend key is used as the full point, with the Y parity it has
+    /// (as in BIP-352, where addresses carry compressed keys): P = B + t*G,
+    /// or P = B_m + t*G = B + (t + label_m)*G for label m, where B_m is the
+    /// key the labeled address encodes. Only the x-coordinate of P goes on
+    /// chain. This is the output with k = 0, the first (and usually only)
+    /// one paying the scan key in a transaction.
     pub fn derive_output_pubkey(
         &self,
-        shared_secret: &[u8; 32],
-        spend_pubkey: &XOnlyPublicKey,
-        label: Option<u8>,
+        shared_secret: &[u8; 33],
+        spend_pubkey: &PublicKey,
+        label: Option<u32>,
+    ) -> Result<XOnlyPublicKey, CoreError> {
+        self.derive_output(shared_secret, 0, spend_pubkey, label)
+    }
+    
+    /// P = B + t_k*G (P = B_m + t_k*G for label m).
+    fn derive_output(
+        &self,
+        shared_secret: &[u8; 33],
+        k: u32,
+        spend_pubkey: &PublicKey,
+        label: Option<u32>,
     ) -> Result<XOnlyPublicKey, CoreError> {
         let secp = Secp256k1::new();
         
-        // Compute tweak
-        let tweak_bytes = match label {
-            None => TaggedHash::hash(TaggedHash::OUTPUT, shared_secret),
-            Some(m) => {
-                let mut data = Vec::with_capacity(33);
-                data.extend_from_slice(shared_secret);
-                data.push(m);
-                TaggedHash::hash(TaggedHash::OUTPUT, &data)
-            }
-        };
-        
-        let tweak = Scalar::from_be_bytes(tweak_bytes)
+        let tweak = Scalar::from_be_bytes(self.output_tweak(shared_secret, k, label)?)
             .map_err(|_| CoreError::CryptoError("Invalid tweak scalar".into()))?;
         
-        // Convert x-only spend_pubkey to full PublicKey (assume even Y)
-        let pk = PublicKey::from_x_only_public_key(*spend_pubkey, Parity::Even);
-        
-        // P = B + t*G
-        let output_pk = pk.add_exp_tweak(&secp, &tweak)
+        // P = B + (t [+ label_m])*G
+        let output_pk = spend_pubkey.add_exp_tweak(&secp, &tweak)
             .map_err(|e| CoreError::CryptoError(e.to_string()))?;
         
         Ok(output_pk.x_only_public_key().0)
@@ -188,55 +498,228 @@ impl ScanKey {
     
     /// Check if a candidate output belongs to us.
     /// Returns an `OutputMatch` (without tx metadata) if the output matches.
+    ///
+    /// The spend key is used with its own Y parity, as a sender decoding
+    /// our address does (see `derive_output_pubkey`).
+    ///
+    /// Only the first output paying the scan key (k = 0) is recognized;
+    /// once it is found, `check_output_for_k` checks for k = 1, 2, ... in
+    /// turn, and `scan_transaction` finds them all in one go.
     pub fn check_output(
         &self,
         candidate_script_pubkey: &[u8],
-        spend_pubkey: &XOnlyPublicKey,
+        spend_pubkey: &PublicKey,
+        inputs: &[InputData],
+        labels: &[Option<u32>],
+    ) -> Result<Option<OutputMatch>, CoreError> {
+        self.check_output_for_k(candidate_script_pubkey, spend_pubkey, inputs, labels, 0)
+    }
+    
+    /// `check_output` for the k-th output paying the scan key. BIP-352 only
+    /// counts it when outputs 0..k of the same transaction were found too:
+    /// callers look for k = 0, 1, ... and stop at the first k with no match.
+    pub fn check_output_for_k(
+        &self,
+        candidate_script_pubkey: &[u8],
+        spend_pubkey: &PublicKey,
         inputs: &[InputData],
-        labels: &[Option<u8>],
+        labels: &[Option<u32>],
+        k: u32,
     ) -> Result<Option<OutputMatch>, CoreError> {
-        // Verify it's a Taproot output (0x5120 + 32 bytes)
-        if candidate_script_pubkey.len() != 34 
-            || candidate_script_pubkey[0] != 0x51 
-            || candidate_script_pubkey[1] != 0x20 {
+        let Some(x_only_bytes) = taproot_output_key(candidate_script_pubkey) else {
             return Ok(None);
-        }
-        
-        // Extract x-only pubkey from script
-        let mut x_only_bytes = [0u8; 32];
-        x_only_bytes.copy_from_slice(&candidate_script_pubkey[2..34]);
-        let candidate_xonly = XOnlyPublicKey::from_slice(&x_only_bytes)
+        };
+        let candidate_xonly = XOnlyPublicKey::from_slice(x_only_bytes)
             .map_err(|e| CoreError::InvalidKey(e.to_string()))?;
         
         // Compute shared secret from inputs
         let shared_secret = self.compute_shared_secret(inputs)?;
-        
-        // Try each label
+        self.match_output_key(&shared_secret, k, candidate_xonly, spend_pubkey, labels)
+    }
+    
+    /// `check_output` for an output key as the k-th output paying the scan
+    /// key, with the shared secret already computed.
+    fn match_output_key(
+        &self,
+        shared_secret: &[u8; 33],
+        k: u32,
+        candidate_xonly: XOnlyPublicKey,
+        spend_pubkey: &PublicKey,
+        labels: &[Option<u32>],
+    ) -> Result<Option<OutputMatch>, CoreError> {
         for &label in labels {
-            let expected_output = self.derive_output_pubkey(&shared_secret, spend_pubkey, label)?;
+            let expected_output = self.derive_output(shared_secret, k, spend_pubkey, label)?;
             
             if expected_output == candidate_xonly {
-                // Compute tweak for spending later
-                let tweak = match label {
-                    None => TaggedHash::hash(TaggedHash::OUTPUT, &shared_secret),
-                    Some(m) => {
-                        let mut data = Vec::with_capacity(33);
-                        data.extend_from_slice(&shared_secret);
-                        data.push(m);
-                        TaggedHash::hash(TaggedHash::OUTPUT, &data)
-                    }
-                };
-                
                 return Ok(Some(OutputMatch {
                     label,
-                    tweak,
+                    // Keep tweak for spending later
+                    tweak: self.output_tweak(shared_secret, k, label)?,
                     output_pubkey: candidate_xonly,
+                    spend_pubkey: *spend_pubkey,
                 }));
             }
         }
         
         Ok(None)
     }
+    
+    /// Full output keys we expect for these inputs: the unlabeled output
+    /// followed by labels 1..=max_label. Lets a wallet (or a sender checking
+    /// before broadcast) know which scriptPubKeys to look for without
+    /// querying the index server.
+    pub fn compute_expected_outputs(
+        &self,
+        spend_pubkey: &PublicKey,
+        inputs: &[InputData],
+        max_label: u32,
+    ) -> Result<Vec<XOnlyPublicKey>, CoreError> {
+        let labels: Vec<Option<u32>> = std::iter::once(None)
+            .chain((1..=max_label).map(Some))
+            .collect();
+        self.compute_expected_outputs_for_labels(spend_pubkey, inputs, &labels)
+    }
+    
+    /// Expected output keys for exactly `labels` (`None` is the unlabeled
+    /// output), in the given order.
+    pub fn compute_expected_outputs_for_labels(
+        &self,
+        spend_pubkey: &PublicKey,
+        inputs: &[InputData],
+        labels: &[Option<u32>],
+    ) -> Result<Vec<XOnlyPublicKey>, CoreError> {
+        self.compute_expected_outputs_for_k(spend_pubkey, inputs, labels, 0)
+    }
+    
+    /// `compute_expected_outputs_for_labels` for the k-th output paying the
+    /// scan key, to look for once outputs 0..k were found.
+    pub fn compute_expected_outputs_for_k(
+        &self,
+        spend_pubkey: &PublicKey,
+        inputs: &[InputData],
+        labels: &[Option<u32>],
+        k: u32,
+    ) -> Result<Vec<XOnlyPublicKey>, CoreError> {
+        let shared_secret = self.compute_shared_secret(inputs)?;
+        
+        labels
+            .iter()
+            .map(|&label| self.derive_output(&shared_secret, k, spend_pubkey, label))
+            .collect()
+    }
+    
+    /// Outputs of `tx` paying us, for a wallet that already has the
+    /// transaction (e.g. from a mempool notification). `prevouts` are the
+    /// outputs spent by `tx.input`, in order; the eligible inputs among them
+    /// give the shared secret, computed once, and every taproot output is
+    /// checked against `labels` (`None` is the unlabeled output). A
+    /// transaction without eligible inputs, or whose input keys sum to the
+    /// point at infinity, pays no one and yields nothing.
+    ///
+    /// A sender paying the scan key several times numbers those outputs
+    /// k = 0, 1, ... As in BIP-352, outputs are looked up for k = 0 first,
+    /// then k = 1 and so on, stopping at the first k that no output matches:
+    /// with outputs for k = 0 and k = 2 but none for k = 1, only the k = 0
+    /// output is found. Results are in output order.
+    pub fn scan_transaction(
+        &self,
+        tx: &bitcoin::Transaction,
+        prevouts: &[bitcoin::TxOut],
+        spend_pubkey: &PublicKey,
+        labels: &[Option<u32>],
+    ) -> Result<Vec<ScanResult>, CoreError> {
+        if prevouts.len() != tx.input.len() {
+            return Err(CoreError::InvalidInput(format!(
+                "{} prevouts given for {} inputs", prevouts.len(), tx.input.len()
+            )));
+        }
+        
+        let Some(tweak) = InputData::transaction_tweak(tx, prevouts) else {
+            return Ok(Vec::new());
+        };
+        let shared_secret = self.shared_secret_from_tweak(&tweak)?;
+        let txid = tx.compute_txid().to_byte_array();
+        
+        // Taproot outputs with their vout and amount
+        let taproot_outputs: Vec<(u32, XOnlyPublicKey, u64)> = tx.output
+            .iter()
+            .enumerate()
+            .filter_map(|(vout, output)| {
+                let key = taproot_output_key(output.script_pubkey.as_bytes())?;
+                let key = XOnlyPublicKey::from_slice(key).ok()?;
+                Some((vout as u32, key, output.value.to_sat()))
+            })
+            .collect();
+        let keys: Vec<XOnlyPublicKey> = taproot_outputs.iter().map(|&(_, key, _)| key).collect();
+        
+        Ok(self
+            .scan_output_keys(&shared_secret, &keys, spend_pubkey, labels)?
+            .into_iter()
+            .map(|(i, output_match)| {
+                let (vout, _, amount) = taproot_outputs[i];
+                ScanResult::from_match(&output_match, txid, vout, amount)
+            })
+            .collect())
+    }
+    
+    /// The output keys of one transaction that pay us, given its shared
+    /// secret, as (index into `output_keys`, match) in `output_keys` order.
+    /// Outputs are looked up for k = 0, 1, ... until the first k with no
+    /// match, and at most `K_MAX` of them, as in `scan_transaction`; this is
+    /// its matching step for callers that have the shared secret but not
+    /// the transaction (e.g. a server scanning stored tweak data).
+    pub fn scan_output_keys(
+        &self,
+        shared_secret: &[u8; 33],
+        output_keys: &[XOnlyPublicKey],
+        spend_pubkey: &PublicKey,
+        labels: &[Option<u32>],
+    ) -> Result<Vec<(usize, OutputMatch)>, CoreError> {
+        // Keys not yet matched, with their index; each k then derives one
+        // key per label instead of checking every output
+        let mut unmatched: std::collections::HashMap<XOnlyPublicKey, usize> = output_keys
+            .iter()
+            .enumerate()
+            .map(|(i, &key)| (key, i))
+            .collect();
+        
+        let mut results = Vec::new();
+        for k in 0..K_MAX {
+            let mut found = None;
+            for &label in labels {
+                let expected = self.derive_output(shared_secret, k, spend_pubkey, label)?;
+                if let Some(i) = unmatched.remove(&expected) {
+                    found = Some((i, OutputMatch {
+                        label,
+                        tweak: self.output_tweak(shared_secret, k, label)?,
+                        output_pubkey: expected,
+                        spend_pubkey: *spend_pubkey,
+                    }));
+                    break;
+                }
+            }
+            let Some(found) = found else {
+                break;
+            };
+            results.push(found);
+        }
+        results.sort_by_key(|&(i, _)| i);
+        
+        Ok(results)
+    }
+}
+
+/// Most outputs one transaction may pay a scan key (BIP-352 K_max):
+/// senders refuse to create more, and scanners don't look for more.
+pub const K_MAX: u32 = 2323;
+
+/// The 32-byte output key of a taproot scriptPubKey (`0x51 0x20 <key>`),
+/// or `None` for any other script, including off-by-one lengths.
+pub(crate) fn taproot_output_key(script: &[u8]) -> Option<&[u8]> {
+    match script {
+        [0x51, 0x20, key @ ..] if key.len() == 32 => Some(key),
+        _ => None,
+    }
 }
 
 /// Generate 4-byte prefix from x-only pubkey
@@ -245,27 +728,122 @@ pub fn prefix_from_xonly(xonly: &XOnlyPublicKey) -> u32 {
     u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
 }
 
-/// Compute prefixes for a transaction's inputs
+/// Supported prefix widths in bytes (4 is the default index format).
+pub const PREFIX_LENGTHS: [usize; 2] = [4, 8];
+
+/// First `len` bytes (1..=8) of an x-only pubkey as a big-endian integer.
+/// For `len == 4` this equals `prefix_from_xonly`.
+pub fn prefix_from_xonly_len(xonly: &XOnlyPublicKey, len: usize) -> Result<u64, CoreError> {
+    if len == 0 || len > 8 {
+        return Err(CoreError::InvalidInput(format!("prefix length must be 1 to 8 bytes, got {}", len)));
+    }
+    let bytes = xonly.serialize();
+    Ok(bytes[..len].iter().fold(0u64, |acc, &b| (acc << 8) | b as u64))
+}
+
+/// Like `compute_prefixes`, with a prefix width of `len` bytes.
+pub fn compute_prefixes_with_len(
+    scan_key: &ScanKey,
+    spend_pubkey: &PublicKey,
+    inputs: &[InputData],
+    max_label: u32,
+    len: usize,
+) -> Result<Vec<u64>, CoreError> {
+    scan_key
+        .compute_expected_outputs(spend_pubkey, inputs, max_label)?
+        .iter()
+        .map(|output| prefix_from_xonly_len(output, len))
+        .collect()
+}
+
+/// Compute prefixes for a transaction's inputs.
+///
+/// These are the prefixes of the first output paying the scan key (k = 0).
+/// A scan that finds one goes on with `compute_prefixes_for_k` for k = 1,
+/// 2, ... and stops at the first k that finds nothing, as BIP-352 scanning
+/// does (the client's prefix scans work this way).
 pub fn compute_prefixes(
     scan_key: &ScanKey,
-    spend_pubkey: &XOnlyPublicKey,
+    spend_pubkey: &PublicKey,
     inputs: &[InputData],
-    max_label: u8,
+    max_label: u32,
 ) -> Result<Vec<u32>, CoreError> {
-    let mut prefixes = Vec::new();
-    let shared_secret = scan_key.compute_shared_secret(inputs)?;
-    
-    // No label case
-    let output = scan_key.derive_output_pubkey(&shared_secret, spend_pubkey, None)?;
-    prefixes.push(prefix_from_xonly(&output));
+    let outputs = scan_key.compute_expected_outputs(spend_pubkey, inputs, max_label)?;
+    Ok(outputs.iter().map(prefix_from_xonly).collect())
+}
+
+/// Like `compute_prefixes`, for an explicit label list rather than
+/// `0..=max_label`, so wallets with a few sparse labels (say 3, 7, 200)
+/// only ask for those. One prefix per entry of `labels`, in order.
+pub fn compute_prefixes_for_labels(
+    scan_key: &ScanKey,
+    spend_pubkey: &PublicKey,
+    inputs: &[InputData],
+    labels: &[Option<u32>],
+) -> Result<Vec<u32>, CoreError> {
+    compute_prefixes_for_k(scan_key, spend_pubkey, inputs, labels, 0)
+}
+
+/// Like `compute_prefixes_for_labels`, for the k-th output paying the scan
+/// key (see `compute_prefixes`).
+pub fn compute_prefixes_for_k(
+    scan_key: &ScanKey,
+    spend_pubkey: &PublicKey,
+    inputs: &[InputData],
+    labels: &[Option<u32>],
+    k: u32,
+) -> Result<Vec<u32>, CoreError> {
+    let outputs = scan_key.compute_expected_outputs_for_k(spend_pubkey, inputs, labels, k)?;
+    Ok(outputs.iter().map(prefix_from_xonly).collect())
+}
+
+/// A candidate output from any source (index server, file, another index),
+/// with the metadata copied into a `ScanResult` when it matches.
+#[derive(Debug, Clone)]
+pub struct CandidateOutput {
+    pub script_pubkey: Vec<u8>,
+    pub txid: [u8; 32],
+    pub vout: u32,
+    pub amount: u64,
+    pub block_height: Option<u32>,
+}
+
+/// Check every candidate against our keys with `check_output`, labels
+/// `0..=max_label`, and return the matches in input order. No networking is
+/// involved, so this also serves offline and air-gapped verification.
+pub fn verify_candidates(
+    scan_key: &ScanKey,
+    spend_pubkey: &PublicKey,
+    inputs: &[InputData],
+    max_label: u32,
+    candidates: &[CandidateOutput],
+) -> Result<Vec<ScanResult>, CoreError> {
+    let labels: Vec<Option<u32>> = std::iter::once(None)
+        .chain((1..=max_label).map(Some))
+        .collect();
     
-    // Label cases
-    for m in 1..=max_label {
-        let output = scan_key.derive_output_pubkey(&shared_secret, spend_pubkey, Some(m))?;
-        prefixes.push(prefix_from_xonly(&output));
+    let mut results = Vec::new();
+    for candidate in candidates {
+        let Some(output_match) = scan_key.check_output(
+            &candidate.script_pubkey,
+            spend_pubkey,
+            inputs,
+            &labels,
+        )? else {
+            continue;
+        };
+        
+        let mut result = ScanResult::from_match(
+            &output_match,
+            candidate.txid,
+            candidate.vout,
+            candidate.amount,
+        );
+        result.block_height = candidate.block_height;
+        results.push(result);
     }
     
-    Ok(prefixes)
+    Ok(results)
 }
 
 #[cfg(test)]
@@ -289,6 +867,6 @@ mod basic_tests {
     fn test_scan_key_creation() {
         let secret_bytes = [1u8; 32];
         let scan_key = ScanKey::from_slice(&secret_bytes).unwrap();
-        assert_eq!(scan_key.public.serialize().len(), 32);
+        assert_eq!(scan_key.public.serialize().len(), 33);
     }
 }
@@ -0,0 +1,79 @@
+use bitcoin::hashes::{sha256d, Hash};
+use crate::CoreError;
+
+/// Length of a serialized block header.
+pub const BLOCK_HEADER_LEN: usize = 80;
+
+fn hash_pair(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
+    let mut data = [0u8; 64];
+    data[..32].copy_from_slice(left);
+    data[32..].copy_from_slice(right);
+    sha256d::Hash::hash(&data).to_byte_array()
+}
+
+/// Compute the merkle branch (sibling hashes, leaf to root) proving that
+/// `txids[index]` is part of the block. Txids are in internal byte order.
+/// Follows Bitcoin's rule of pairing the last node with itself on odd levels.
+pub fn merkle_branch(txids: &[[u8; 32]], index: usize) -> Vec<[u8; 32]> {
+    let mut branch = Vec::new();
+    if index >= txids.len() {
+        return branch;
+    }
+
+    let mut level = txids.to_vec();
+    let mut idx = index;
+
+    while level.len() > 1 {
+        let sibling = if idx ^ 1 < level.len() { idx ^ 1 } else { idx };
+        branch.push(level[sibling]);
+
+        level = level
+            .chunks(2)
+            .map(|pair| hash_pair(&pair[0], pair.get(1).unwrap_or(&pair[0])))
+            .collect();
+        idx /= 2;
+    }
+
+    branch
+}
+
+/// Recompute the merkle root from a leaf, its branch and its position.
+pub fn merkle_root_from_branch(leaf: &[u8; 32], branch: &[[u8; 32]], index: u32) -> [u8; 32] {
+    let mut current = *leaf;
+    let mut idx = index;
+
+    for sibling in branch {
+        current = if idx & 1 == 0 {
+            hash_pair(&current, sibling)
+        } else {
+            hash_pair(sibling, &current)
+        };
+        idx >>= 1;
+    }
+
+    current
+}
+
+/// Check a merkle proof against the merkle root committed in a serialized
+/// 80-byte block header.
+pub fn verify_merkle_proof(
+    txid: &[u8; 32],
+    branch: &[[u8; 32]],
+    index: u32,
+    header: &[u8],
+) -> Result<bool, CoreError> {
+    if header.len() != BLOCK_HEADER_LEN {
+        return Err(CoreError::InvalidInput(format!(
+            "block header must be {} bytes, got {}", BLOCK_HEADER_LEN, header.len()
+        )));
+    }
+
+    // Header layout: version (4) | prev_blockhash (32) | merkle_root (32) | ...
+    let root = merkle_root_from_branch(txid, branch, index);
+    Ok(root[..] == header[36..68])
+}
+
+/// Double-SHA256 of a serialized block header (the block hash, internal byte order).
+pub fn block_hash_from_header(header: &[u8]) -> [u8; 32] {
+    sha256d::Hash::hash(header).to_byte_array()
+}
@@ -0,0 +1,123 @@
+use bitcoin::secp256k1::{PublicKey, Scalar, Secp256k1, SecretKey, XOnlyPublicKey};
+use bitcoin::OutPoint;
+use crate::{input_hash, CoreError, SilentPaymentAddress, TaggedHash, K_MAX};
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+
+/// One input's contribution to the shared secret, the ECDH point
+/// a_i * B_scan. `input_secret` is the secret of the key the input reveals
+/// as `InputData` has it: for a taproot input whose output key has odd Y,
+/// the negated secret of the even-Y key.
+pub fn shared_secret_share(
+    input_secret: &SecretKey,
+    scan_pubkey: &PublicKey,
+) -> Result<PublicKey, CoreError> {
+    let secp = Secp256k1::new();
+    scan_pubkey
+        .mul_tweak(&secp, &Scalar::from(*input_secret))
+        .map_err(|e| CoreError::CryptoError(e.to_string()))
+}
+
+/// Combine per-input shares into the transaction's shared secret,
+/// ser_P(input_hash * sum(a_i * B_scan)), with `input_hash` over all the
+/// transaction's inputs (see `input_hash`).
+///
+/// Every eligible input of the transaction counts, whoever owns it: in a
+/// coinjoin each party computes `shared_secret_share` for its own inputs
+/// and the shares are combined here, in any order. The result equals what
+/// the receiver gets from `ScanKey::compute_shared_secret` over all the
+/// transaction's inputs.
+pub fn combine_shared_secret_shares(
+    shares: &[PublicKey],
+    input_hash: &[u8; 32],
+) -> Result<[u8; 33], CoreError> {
+    if shares.is_empty() {
+        return Err(CoreError::InvalidInput("at least one eligible input is required".into()));
+    }
+    let secp = Secp256k1::new();
+    let shares: Vec<&PublicKey> = shares.iter().collect();
+    let sum = PublicKey::combine_keys(&shares)
+        .map_err(|_| CoreError::InvalidInput("input keys sum to the point at infinity".into()))?;
+    let input_hash = Scalar::from_be_bytes(*input_hash)
+        .map_err(|_| CoreError::CryptoError("Invalid input hash scalar".into()))?;
+    let shared_point = sum.mul_tweak(&secp, &input_hash)
+        .map_err(|e| CoreError::CryptoError(e.to_string()))?;
+    Ok(shared_point.serialize())
+}
+
+/// Output key a sender creates for `spend_pubkey` (the key in the
+/// recipient's address, labeled or not, with the Y parity it was encoded
+/// with) from the combined shared secret: P = B_spend + t_k*G. `k` numbers
+/// the outputs paying the same scan key in the transaction, from 0; see
+/// `TaggedHash::output`.
+pub fn sender_output_pubkey(
+    shared_secret: &[u8; 33],
+    spend_pubkey: &PublicKey,
+    k: u32,
+) -> Result<XOnlyPublicKey, CoreError> {
+    let secp = Secp256k1::new();
+    let tweak = Scalar::from_be_bytes(TaggedHash::output(shared_secret, k))
+        .map_err(|_| CoreError::CryptoError("Invalid tweak scalar".into()))?;
+    let output = spend_pubkey
+        .add_exp_tweak(&secp, &tweak)
+        .map_err(|e| CoreError::CryptoError(e.to_string()))?;
+    Ok(output.x_only_public_key().0)
+}
+
+/// Output keys for every silent payment recipient of one transaction, in
+/// recipient order, from the secret keys of all its eligible inputs (as
+/// for `shared_secret_share`) and every outpoint it spends, eligible or
+/// not, for the input hash.
+///
+/// The shared secret is computed once per scan key, so recipients sharing a
+/// scan key (e.g. several labels of one wallet) reuse it, and their outputs
+/// are numbered k = 0, 1, ... in recipient order. Paying the same address
+/// twice therefore gives two distinct outputs. More than `K_MAX` outputs to
+/// one scan key are rejected, as the receiver wouldn't look for them.
+pub fn derive_send_outputs(
+    sender_secrets: &[SecretKey],
+    outpoints: &[OutPoint],
+    recipients: &[SilentPaymentAddress],
+) -> Result<Vec<(SilentPaymentAddress, XOnlyPublicKey)>, CoreError> {
+    if sender_secrets.is_empty() {
+        return Err(CoreError::InvalidInput("at least one eligible input is required".into()));
+    }
+    let secp = Secp256k1::new();
+    let input_pubkeys: Vec<PublicKey> = sender_secrets
+        .iter()
+        .map(|secret| PublicKey::from_secret_key(&secp, secret))
+        .collect();
+    let input_pubkeys: Vec<&PublicKey> = input_pubkeys.iter().collect();
+    let input_pubkey_sum = PublicKey::combine_keys(&input_pubkeys)
+        .map_err(|_| CoreError::InvalidInput("input keys sum to the point at infinity".into()))?;
+    let input_hash = input_hash(outpoints, &input_pubkey_sum)?;
+
+    let mut shared_secrets: HashMap<PublicKey, ([u8; 33], u32)> = HashMap::new();
+    let mut outputs: Vec<(SilentPaymentAddress, XOnlyPublicKey)> = Vec::with_capacity(recipients.len());
+
+    for recipient in recipients {
+        let (shared_secret, k) = match shared_secrets.entry(recipient.scan_pubkey) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                let shares = sender_secrets
+                    .iter()
+                    .map(|secret| shared_secret_share(secret, &recipient.scan_pubkey))
+                    .collect::<Result<Vec<_>, _>>()?;
+                let secret = combine_shared_secret_shares(&shares, &input_hash)?;
+                entry.insert((secret, 0))
+            }
+        };
+        if *k >= K_MAX {
+            return Err(CoreError::InvalidInput(format!(
+                "more than {} outputs to scan key {} in one transaction",
+                K_MAX, recipient.scan_pubkey
+            )));
+        }
+
+        let output = sender_output_pubkey(shared_secret, &recipient.spend_pubkey, *k)?;
+        *k += 1;
+        outputs.push((recipient.clone(), output));
+    }
+
+    Ok(outputs)
+}
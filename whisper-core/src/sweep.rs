@@ -0,0 +1,54 @@
+use bitcoin::hashes::Hash;
+use bitcoin::secp256k1::{Keypair, Message, Secp256k1};
+use bitcoin::sighash::{Annex, Prevouts, SighashCache, TapSighashType};
+use bitcoin::{Psbt, Transaction, TxOut, Witness};
+use crate::{CoreError, ScanResult, SilentPaymentKeypair};
+
+/// Sign every input of a sweep PSBT with a taproot key-path signature and
+/// extract the transaction. Each input's `witness_utxo` must pay to one of
+/// `results`; its key is `ScanResult::spending_key` of the wallet's spend
+/// secret. With an `annex`, every input's witness carries it after the
+/// signature and the BIP-341 sighash commits to it.
+pub fn sign_sweep(
+    mut psbt: Psbt,
+    keypair: &SilentPaymentKeypair,
+    results: &[ScanResult],
+    annex: Option<Annex<'_>>,
+) -> Result<Transaction, CoreError> {
+    let secp = Secp256k1::new();
+    let spend_secret = keypair.spend.secret
+        .ok_or_else(|| CoreError::InvalidKey("keypair has no spend secret".into()))?;
+    let prevouts: Vec<TxOut> = psbt.inputs
+        .iter()
+        .map(|input| input.witness_utxo.clone())
+        .collect::<Option<_>>()
+        .ok_or_else(|| CoreError::InvalidInput("every input needs a witness_utxo".into()))?;
+
+    let mut cache = SighashCache::new(&psbt.unsigned_tx);
+    let mut witnesses = Vec::with_capacity(prevouts.len());
+    for (index, prevout) in prevouts.iter().enumerate() {
+        let result = results
+            .iter()
+            .find(|r| r.output_script() == prevout.script_pubkey)
+            .ok_or_else(|| CoreError::InvalidInput(format!("input {} is not a found output", index)))?;
+        let key = Keypair::from_secret_key(&secp, &result.spending_key(&spend_secret)?);
+
+        let sighash = cache
+            .taproot_signature_hash(index, &Prevouts::All(&prevouts), annex.clone(), None, TapSighashType::Default)
+            .map_err(|e| CoreError::CryptoError(e.to_string()))?;
+        let signature = secp.sign_schnorr_no_aux_rand(&Message::from_digest(sighash.to_byte_array()), &key);
+        let mut witness = Witness::p2tr_key_spend(&bitcoin::taproot::Signature {
+            signature,
+            sighash_type: TapSighashType::Default,
+        });
+        if let Some(annex) = &annex {
+            witness.push(annex.as_bytes());
+        }
+        witnesses.push(witness);
+    }
+
+    for (input, witness) in psbt.inputs.iter_mut().zip(witnesses) {
+        input.final_script_witness = Some(witness);
+    }
+    psbt.extract_tx().map_err(|e| CoreError::InvalidInput(e.to_string()))
+}
@@ -13,7 +13,7 @@ fn test_tagged_hash_bip352() {
     assert_eq!(hash, hash2);
     
     // Different tags should produce different hashes
-    let hash3 = TaggedHash::hash(TaggedHash::OUTPUT, data);
+    let hash3 = TaggedHash::hash(TaggedHash::INPUTS, data);
     assert_ne!(hash, hash3);
 }
 
@@ -25,7 +25,7 @@ fn test_scan_key_generation() {
     
     // Verify public key derivation
     let expected_pubkey = PublicKey::from_secret_key(&secp, &secret);
-    assert_eq!(scan_key.public, expected_pubkey.x_only_public_key().0);
+    assert_eq!(scan_key.public, expected_pubkey);
 }
 
 #[test]
@@ -43,11 +43,12 @@ fn test_shared_secret_computation() {
     let inputs = vec![InputData {
         pubkey: input_pubkey,
         is_taproot: true,
+        outpoint: None,
     }];
     
     // Compute shared secret
     let shared_secret = scan_key.compute_shared_secret(&inputs).unwrap();
-    assert_eq!(shared_secret.len(), 32);
+    assert_eq!(shared_secret.len(), 33);
     
     // Shared secret must be deterministic
     let shared_secret2 = scan_key.compute_shared_secret(&inputs).unwrap();
@@ -63,7 +64,7 @@ fn test_output_derivation() {
     let scan_key = ScanKey::new(scan_secret).unwrap();
     
     let spend_secret = SecretKey::from_slice(&[5u8; 32]).unwrap();
-    let spend_pubkey = PublicKey::from_secret_key(&secp, &spend_secret).x_only_public_key().0;
+    let spend_pubkey = PublicKey::from_secret_key(&secp, &spend_secret);
     
     // Create input
     let input_secret = SecretKey::from_slice(&[6u8; 32]).unwrap();
@@ -72,6 +73,7 @@ fn test_output_derivation() {
     let inputs = vec![InputData {
         pubkey: input_pubkey,
         is_taproot: true,
+        outpoint: None,
     }];
     
     // Derive output
@@ -94,7 +96,7 @@ fn test_output_detection() {
     let scan_key = ScanKey::new(scan_secret).unwrap();
     
     let spend_secret = SecretKey::from_slice(&[8u8; 32]).unwrap();
-    let spend_pubkey = PublicKey::from_secret_key(&secp, &spend_secret).x_only_public_key().0;
+    let spend_pubkey = PublicKey::from_secret_key(&secp, &spend_secret);
     
     // Create input
     let input_secret = SecretKey::from_slice(&[9u8; 32]).unwrap();
@@ -103,6 +105,7 @@ fn test_output_detection() {
     let inputs = vec![InputData {
         pubkey: input_pubkey,
         is_taproot: true,
+        outpoint: None,
     }];
     
     // Derive expected output
@@ -132,7 +135,7 @@ fn test_labeled_output() {
     let scan_key = ScanKey::new(scan_secret).unwrap();
     
     let spend_secret = SecretKey::from_slice(&[11u8; 32]).unwrap();
-    let spend_pubkey = PublicKey::from_secret_key(&secp, &spend_secret).x_only_public_key().0;
+    let spend_pubkey = PublicKey::from_secret_key(&secp, &spend_secret);
     
     // Create input
     let input_secret = SecretKey::from_slice(&[12u8; 32]).unwrap();
@@ -141,10 +144,11 @@ fn test_labeled_output() {
     let inputs = vec![InputData {
         pubkey: input_pubkey,
         is_taproot: true,
+        outpoint: None,
     }];
     
     // Test label 5
-    let label = Some(5u8);
+    let label = Some(5u32);
     let shared_secret = scan_key.compute_shared_secret(&inputs).unwrap();
     let output_pubkey = scan_key.derive_output_pubkey(&shared_secret, &spend_pubkey, label).unwrap();
     
@@ -188,13 +192,13 @@ fn test_multiple_inputs() {
     let input2 = PublicKey::from_secret_key(&secp, &SecretKey::from_slice(&[16u8; 32]).unwrap());
     
     let inputs = vec![
-        InputData { pubkey: input1, is_taproot: true },
-        InputData { pubkey: input2, is_taproot: false },
+        InputData { pubkey: input1, is_taproot: true, outpoint: None },
+        InputData { pubkey: input2, is_taproot: false, outpoint: None },
     ];
     
     // Should compute combined shared secret
     let shared_secret = scan_key.compute_shared_secret(&inputs).unwrap();
-    assert_eq!(shared_secret.len(), 32);
+    assert_eq!(shared_secret.len(), 33);
 }
 
 #[test]
@@ -205,10 +209,10 @@ fn test_invalid_script_rejection() {
     let scan_key = ScanKey::new(scan_secret).unwrap();
     
     let spend_secret = SecretKey::from_slice(&[18u8; 32]).unwrap();
-    let spend_pubkey = PublicKey::from_secret_key(&secp, &spend_secret).x_only_public_key().0;
+    let spend_pubkey = PublicKey::from_secret_key(&secp, &spend_secret);
     
     let input_pubkey = PublicKey::from_secret_key(&secp, &SecretKey::from_slice(&[19u8; 32]).unwrap());
-    let inputs = vec![InputData { pubkey: input_pubkey, is_taproot: true }];
+    let inputs = vec![InputData { pubkey: input_pubkey, is_taproot: true, outpoint: None }];
     
     // Invalid script (not Taproot)
     let invalid_script = vec![0x00, 0x14, 0x12, 0x34]; // P2WPKH
@@ -218,6 +222,157 @@ fn test_invalid_script_rejection() {
     assert!(result.is_none());
 }
 
+#[test]
+fn test_coinjoin_inputs_from_several_senders() {
+    use bitcoin::hashes::Hash;
+    use bitcoin::secp256k1::Parity;
+    
+    let secp = Secp256k1::new();
+    let scan_key = ScanKey::from_slice(&[1u8; 32]).unwrap();
+    let spend_pubkey = PublicKey::from_secret_key(&secp, &SecretKey::from_slice(&[2u8; 32]).unwrap());
+    
+    // Three parties, each holding the key of its own input; the middle one
+    // spends P2WPKH, the others taproot (seen by the receiver with even Y,
+    // so a party whose taproot key has odd Y negates its secret)
+    let secrets: Vec<SecretKey> = (60..63u8)
+        .map(|i| {
+            let secret = SecretKey::from_slice(&[i; 32]).unwrap();
+            let odd = secret.public_key(&secp).x_only_public_key().1 == Parity::Odd;
+            if i != 61 && odd { secret.negate() } else { secret }
+        })
+        .collect();
+    let inputs: Vec<InputData> = secrets
+        .iter()
+        .enumerate()
+        .map(|(i, secret)| InputData {
+            pubkey: PublicKey::from_secret_key(&secp, secret),
+            is_taproot: i != 1,
+            outpoint: Some(bitcoin::OutPoint {
+                txid: bitcoin::Txid::from_byte_array([i as u8; 32]),
+                vout: i as u32,
+            }),
+        })
+        .collect();
+    assert!(inputs.iter().all(|input| !input.is_taproot || input.pubkey.x_only_public_key().1 == Parity::Even));
+    
+    // The input hash covers every input's key and outpoint, so it is
+    // agreed on from the transaction; then each party computes only its own
+    // share, and combining order doesn't matter
+    let input_pubkeys: Vec<&PublicKey> = inputs.iter().map(|input| &input.pubkey).collect();
+    let outpoints: Vec<bitcoin::OutPoint> = inputs.iter().map(|input| input.outpoint.unwrap()).collect();
+    let input_hash = input_hash(&outpoints, &PublicKey::combine_keys(&input_pubkeys).unwrap()).unwrap();
+    let shares: Vec<PublicKey> = secrets
+        .iter()
+        .map(|secret| shared_secret_share(secret, &scan_key.public).unwrap())
+        .collect();
+    let combined = combine_shared_secret_shares(&shares, &input_hash).unwrap();
+    let reversed: Vec<PublicKey> = shares.iter().rev().copied().collect();
+    assert_eq!(combine_shared_secret_shares(&reversed, &input_hash).unwrap(), combined);
+    
+    // The receiver sums over all inputs alike and gets the same secret
+    assert_eq!(scan_key.compute_shared_secret(&inputs).unwrap(), combined);
+    
+    let output = sender_output_pubkey(&combined, &spend_pubkey, 0).unwrap();
+    assert_eq!(output, scan_key.derive_output_pubkey(&combined, &spend_pubkey, None).unwrap());
+    let mut script = vec![0x51, 0x20];
+    script.extend_from_slice(&output.serialize());
+    let found = scan_key.check_output(&script, &spend_pubkey, &inputs, &[None]).unwrap();
+    assert!(found.is_some(), "coinjoin payment must be detected");
+    
+    // Leaving out one party's input changes the secret
+    assert_ne!(scan_key.compute_shared_secret(&inputs[..2]).unwrap(), combined);
+    assert!(scan_key.check_output(&script, &spend_pubkey, &inputs[..2], &[None]).unwrap().is_none());
+    assert!(combine_shared_secret_shares(&[], &input_hash).is_err());
+}
+
+#[test]
+fn test_derive_send_outputs_pays_each_recipient() {
+    use bitcoin::secp256k1::XOnlyPublicKey;
+    use bitcoin::OutPoint;
+    use std::collections::BTreeSet;
+    
+    // BIP-352 sending vector "multiple outputs, multiple recipients": one
+    // recipient, then a second one paid twice
+    let secret = |s: &str| SecretKey::from_slice(&hex::decode(s).unwrap()).unwrap();
+    let sender_secrets = [
+        secret("eadc78165ff1f8ea94ad7cfdc54990738a4c53f6e0507b42154201b8e5dff3b1"),
+        secret("0378e95685b74565fa56751b84a32dfd18545d10d691641b8372e32164fad66a"),
+    ];
+    let outpoints: Vec<OutPoint> = [
+        "f4184fc596403b9d638783cf57adfe4c75c605f6356fbc91338530e9831e9e16:0",
+        "a1075db55d416d3ca199f55b6084e2115b9345e16c5cf302fc80e9d5fbf5d48d:0",
+    ].iter().map(|s| s.parse().unwrap()).collect();
+    let alice: SilentPaymentAddress = "sp1qqgste7k9hx0qftg6qmwlkqtwuy6cycyavzmzj85c6qdfhjdpdjtdgqjuexzk6murw56suy3e0rd2cgqvycxttddwsvgxe2usfpxumr70xc9pkqwv".parse().unwrap();
+    let bob: SilentPaymentAddress = "sp1qqgrz6j0lcqnc04vxccydl0kpsj4frfje0ktmgcl2t346hkw30226xqupawdf48k8882j0strrvcmgg2kdawz53a54dd376ngdhak364hzcmynqtn".parse().unwrap();
+    
+    let outputs = derive_send_outputs(&sender_secrets, &outpoints, &[alice.clone(), bob.clone(), bob.clone()]).unwrap();
+    assert_eq!(outputs.iter().map(|(address, _)| address).collect::<Vec<_>>(), vec![&alice, &bob, &bob]);
+    
+    // Bob's two outputs are numbered k = 0 and k = 1, so all three differ
+    let keys: BTreeSet<String> = outputs.iter().map(|(_, key)| key.to_string()).collect();
+    let expected: BTreeSet<String> = [
+        "2e847bb01d1b491da512ddd760b8509617ee38057003d6115d00ba562451323a",
+        "841792c33c9dc6193e76744134125d40add8f2f4a96475f28ba150be032d64e8",
+        "f207162b1a7abc51c42017bef055e9ec1efc3d3567cb720357e2b84325db33ac",
+    ].iter().map(|s| s.to_string()).collect();
+    assert_eq!(keys, expected);
+    
+    // Bob's scan finds both of his outputs and not Alice's
+    let bob_scan = ScanKey::new(secret("060b751d7892149006ed7b98606955a29fe284a1e900070c0971f5fb93dbf422")).unwrap();
+    let secp = Secp256k1::new();
+    let inputs: Vec<InputData> = sender_secrets
+        .iter()
+        .zip(&outpoints)
+        .map(|(secret, outpoint)| InputData {
+            pubkey: PublicKey::from_secret_key(&secp, secret),
+            is_taproot: false,
+            outpoint: Some(*outpoint),
+        })
+        .collect();
+    let shared_secret = bob_scan.compute_shared_secret(&inputs).unwrap();
+    let output_keys: Vec<XOnlyPublicKey> = outputs.iter().map(|(_, key)| *key).collect();
+    let found = bob_scan.scan_output_keys(&shared_secret, &output_keys, &bob.spend_pubkey, &[None]).unwrap();
+    assert_eq!(found.iter().map(|(i, _)| *i).collect::<Vec<_>>(), vec![1, 2]);
+    
+    assert!(derive_send_outputs(&[], &outpoints, &[bob]).is_err());
+}
+
+#[test]
+fn test_scan_result_rederives_from_base_spend_key() {
+    use bitcoin::secp256k1::Scalar;
+    
+    let secp = Secp256k1::new();
+    let scan_key = ScanKey::from_slice(&[20u8; 32]).unwrap();
+    let spend_pubkey = PublicKey::from_secret_key(&secp, &SecretKey::from_slice(&[21u8; 32]).unwrap());
+    let inputs = vec![InputData {
+        pubkey: PublicKey::from_secret_key(&secp, &SecretKey::from_slice(&[22u8; 32]).unwrap()),
+        is_taproot: true,
+        outpoint: None,
+    }];
+    
+    // A labeled payment, so the tweak carries the label too
+    let shared_secret = scan_key.compute_shared_secret(&inputs).unwrap();
+    let output_pubkey = scan_key.derive_output_pubkey(&shared_secret, &spend_pubkey, Some(3)).unwrap();
+    let mut script = vec![0x51, 0x20];
+    script.extend_from_slice(&output_pubkey.serialize());
+    let output_match = scan_key.check_output(&script, &spend_pubkey, &inputs, &[None, Some(3)])
+        .unwrap()
+        .expect("Should detect labeled output");
+    let result = ScanResult::from_match(&output_match, [1u8; 32], 0, 1000);
+    assert_eq!(result.spend_pubkey, Some(spend_pubkey));
+    
+    // base_spend + tweak*G == output_pubkey
+    let tweak = Scalar::from_be_bytes(result.tweak).unwrap();
+    let rederived = result.spend_pubkey.unwrap()
+        .add_exp_tweak(&secp, &tweak)
+        .unwrap()
+        .x_only_public_key().0;
+    assert_eq!(rederived, result.output_pubkey);
+    
+    // Not kept by the compact encoding
+    assert_eq!(ScanResult::from_bytes(&result.to_bytes()).unwrap().spend_pubkey, None);
+}
+
 #[test]
 fn test_scan_result_from_match() {
     let secp = Secp256k1::new();
@@ -226,10 +381,10 @@ fn test_scan_result_from_match() {
     let scan_key = ScanKey::new(scan_secret).unwrap();
     
     let spend_secret = SecretKey::from_slice(&[21u8; 32]).unwrap();
-    let spend_pubkey = PublicKey::from_secret_key(&secp, &spend_secret).x_only_public_key().0;
+    let spend_pubkey = PublicKey::from_secret_key(&secp, &spend_secret);
     
     let input_pubkey = PublicKey::from_secret_key(&secp, &SecretKey::from_slice(&[22u8; 32]).unwrap());
-    let inputs = vec![InputData { pubkey: input_pubkey, is_taproot: true }];
+    let inputs = vec![InputData { pubkey: input_pubkey, is_taproot: true, outpoint: None }];
     
     let shared_secret = scan_key.compute_shared_secret(&inputs).unwrap();
     let output_pubkey = scan_key.derive_output_pubkey(&shared_secret, &spend_pubkey, None).unwrap();
@@ -251,4 +406,1434 @@ fn test_scan_result_from_match() {
     assert_eq!(scan_result.amount, 50000);
     assert_eq!(scan_result.label, None);
     assert_eq!(scan_result.output_pubkey, output_pubkey);
+    
+    // The reconstructed scriptPubKey is the one that was scanned
+    let output_script = scan_result.output_script();
+    assert!(output_script.is_p2tr());
+    assert_eq!(output_script.as_bytes(), &script[..]);
+    assert!(scan_key.check_output(output_script.as_bytes(), &spend_pubkey, &inputs, &labels)
+        .unwrap()
+        .is_some());
+}
+
+#[test]
+fn test_scan_result_outpoint_and_txout() {
+    let secp = Secp256k1::new();
+    let output_pubkey = PublicKey::from_secret_key(&secp, &SecretKey::from_slice(&[23u8; 32]).unwrap())
+        .x_only_public_key().0;
+    
+    // Internal byte order 00 01 .. 1f displays as 1f 1e .. 00
+    let mut txid = [0u8; 32];
+    for (i, byte) in txid.iter_mut().enumerate() {
+        *byte = i as u8;
+    }
+    let scan_result = ScanResult {
+        txid,
+        vout: 3,
+        amount: 75_000,
+        label: None,
+        tweak: [1u8; 32],
+        output_pubkey,
+        block_height: None,
+        spend_pubkey: None,
+        account: 0,
+    };
+    
+    let outpoint = scan_result.outpoint();
+    assert_eq!(
+        outpoint.to_string(),
+        "1f1e1d1c1b1a191817161514131211100f0e0d0c0b0a09080706050403020100:3"
+    );
+    
+    let txout = scan_result.txout();
+    assert_eq!(txout.value, bitcoin::Amount::from_sat(75_000));
+    assert!(txout.script_pubkey.is_p2tr());
+    assert_eq!(&txout.script_pubkey.as_bytes()[2..], &output_pubkey.serialize()[..]);
+}
+
+#[test]
+fn test_dedup_results_keeps_highest_block() {
+    let secp = Secp256k1::new();
+    let output_pubkey = PublicKey::from_secret_key(&secp, &SecretKey::from_slice(&[37u8; 32]).unwrap())
+        .x_only_public_key().0;
+    let result = |txid: u8, vout: u32, block_height: u32| ScanResult {
+        txid: [txid; 32],
+        vout,
+        amount: 1000,
+        label: None,
+        tweak: [0u8; 32],
+        output_pubkey,
+        block_height: Some(block_height),
+        spend_pubkey: None,
+        account: 0,
+    };
+    
+    // Range 100..=200, then a rescan of 150..=250 after a reorg moved tx 2
+    let first_scan = vec![result(1, 0, 120), result(2, 1, 160)];
+    let rescan = vec![result(2, 1, 170), result(3, 0, 240)];
+    
+    assert_eq!(result(2, 1, 160), result(2, 1, 170));
+    assert_ne!(result(2, 1, 160), result(2, 0, 160));
+    
+    let merged = dedup_results(first_scan.into_iter().chain(rescan).collect());
+    let outpoints: Vec<([u8; 32], u32, Option<u32>)> = merged
+        .iter()
+        .map(|r| (r.txid, r.vout, r.block_height))
+        .collect();
+    assert_eq!(outpoints, vec![
+        ([1; 32], 0, Some(120)),
+        ([2; 32], 1, Some(170)),
+        ([3; 32], 0, Some(240)),
+    ]);
+    
+    let unique: std::collections::HashSet<ScanResult> = merged.into_iter().collect();
+    assert_eq!(unique.len(), 3);
+}
+
+#[test]
+fn test_export_descriptors_match_output_scripts() {
+    // BIP-380 test vector
+    assert_eq!(descriptor_checksum("raw(deadbeef)").as_deref(), Some("89f8spxm"));
+    
+    let secp = Secp256k1::new();
+    let results: Vec<ScanResult> = (40..44u8)
+        .map(|i| ScanResult {
+            txid: [i; 32],
+            vout: 0,
+            amount: 1000,
+            label: None,
+            tweak: [0u8; 32],
+            output_pubkey: PublicKey::from_secret_key(&secp, &SecretKey::from_slice(&[i; 32]).unwrap())
+                .x_only_public_key().0,
+            block_height: None,
+            spend_pubkey: None,
+            account: 0,
+        })
+        .collect();
+    
+    let descriptors = export_descriptors(&results);
+    assert_eq!(descriptors.len(), results.len());
+    for (descriptor, result) in descriptors.iter().zip(&results) {
+        let (body, checksum) = descriptor.split_once('#').unwrap();
+        assert_eq!(descriptor_checksum(body).unwrap(), checksum);
+        
+        let key_hex = body.strip_prefix("rawtr(").and_then(|d| d.strip_suffix(')')).unwrap();
+        let mut script = vec![0x51, 0x20];
+        script.extend_from_slice(&hex::decode(key_hex).unwrap());
+        assert_eq!(script, result.output_script().into_bytes());
+    }
+    
+    // The same output found twice is exported once
+    let doubled: Vec<ScanResult> = results.iter().chain(&results).cloned().collect();
+    assert_eq!(export_descriptors(&doubled), descriptors);
+}
+
+#[test]
+fn test_scan_result_scripthash_matches_electrum() {
+    let secp = Secp256k1::new();
+    let mut one = [0u8; 32];
+    one[31] = 1;
+    let result = ScanResult {
+        txid: [1u8; 32],
+        vout: 0,
+        amount: 1000,
+        label: None,
+        tweak: [0u8; 32],
+        output_pubkey: SecretKey::from_slice(&one).unwrap().x_only_public_key(&secp).0,
+        block_height: None,
+        spend_pubkey: None,
+        account: 0,
+    };
+    
+    // sha256(5120 || x(G)), reversed, as Electrum servers index it
+    assert_eq!(
+        hex::encode(scan_result_scripthash(&result)),
+        "a12cf1aa7c74a6e9f54984646526173abed2a9f4a4862dc83eb94e8e8ef5220a"
+    );
+}
+
+#[test]
+fn test_shared_secret_from_tweak_matches_inputs() {
+    use bitcoin::hashes::Hash;
+    
+    let secp = Secp256k1::new();
+    let scan_key = ScanKey::from_slice(&[25u8; 32]).unwrap();
+    
+    let input_pubkey = PublicKey::from_secret_key(&secp, &SecretKey::from_slice(&[26u8; 32]).unwrap());
+    let other = PublicKey::from_secret_key(&secp, &SecretKey::from_slice(&[27u8; 32]).unwrap());
+    let outpoint = |vout| bitcoin::OutPoint { txid: bitcoin::Txid::from_byte_array([28u8; 32]), vout };
+    let inputs = vec![
+        InputData { pubkey: input_pubkey, is_taproot: true, outpoint: Some(outpoint(1)) },
+        InputData { pubkey: other, is_taproot: false, outpoint: Some(outpoint(0)) },
+    ];
+    
+    // The tweak point stands in for all the inputs
+    let tweak = InputData::tweak(&inputs).unwrap();
+    let from_inputs = scan_key.compute_shared_secret(&inputs).unwrap();
+    assert_eq!(scan_key.shared_secret_from_tweak(&tweak).unwrap(), from_inputs);
+    
+    // It is the key sum A scaled by the input hash, not A itself
+    let summed = input_pubkey.combine(&other).unwrap();
+    assert_ne!(scan_key.shared_secret_from_tweak(&summed).unwrap(), from_inputs);
+    let input_hash = input_hash(&[outpoint(1), outpoint(0)], &summed).unwrap();
+    assert_eq!(tweak, summed.mul_tweak(&secp, &bitcoin::secp256k1::Scalar::from_be_bytes(input_hash).unwrap()).unwrap());
+    
+    // Spending another outpoint changes the input hash, and the secret
+    let mut moved = inputs.clone();
+    moved[1].outpoint = Some(outpoint(2));
+    assert_ne!(scan_key.compute_shared_secret(&moved).unwrap(), from_inputs);
+    
+    // Keys summing to the point at infinity give no tweak
+    let cancelling = vec![
+        InputData { pubkey: input_pubkey, is_taproot: false, outpoint: Some(outpoint(0)) },
+        InputData { pubkey: input_pubkey.negate(&secp), is_taproot: false, outpoint: Some(outpoint(1)) },
+    ];
+    assert!(InputData::tweak(&cancelling).is_none());
+    assert!(scan_key.compute_shared_secret(&cancelling).is_err());
+    assert!(InputData::tweak(&[]).is_none());
+}
+
+#[test]
+fn test_expected_outputs_match_check_output() {
+    let secp = Secp256k1::new();
+    let scan_key = ScanKey::from_slice(&[28u8; 32]).unwrap();
+    let spend_pubkey = PublicKey::from_secret_key(&secp, &SecretKey::from_slice(&[29u8; 32]).unwrap());
+    let input_pubkey = PublicKey::from_secret_key(&secp, &SecretKey::from_slice(&[30u8; 32]).unwrap());
+    let inputs = vec![InputData { pubkey: input_pubkey, is_taproot: true, outpoint: None }];
+    
+    let max_label = 3;
+    let expected = scan_key.compute_expected_outputs(&spend_pubkey, &inputs, max_label).unwrap();
+    assert_eq!(expected.len(), max_label as usize + 1);
+    
+    let labels: Vec<Option<u32>> = std::iter::once(None).chain((1..=max_label).map(Some)).collect();
+    for (key, label) in expected.iter().zip(&labels) {
+        let mut script = vec![0x51, 0x20];
+        script.extend_from_slice(&key.serialize());
+        
+        let found = scan_key.check_output(&script, &spend_pubkey, &inputs, &labels)
+            .unwrap()
+            .expect("expected output should be detected");
+        assert_eq!(found.output_pubkey, *key);
+        assert_eq!(found.label, *label);
+    }
+    
+    // Prefixes are derived from the same keys
+    let prefixes = compute_prefixes(&scan_key, &spend_pubkey, &inputs, max_label).unwrap();
+    assert_eq!(prefixes, expected.iter().map(prefix_from_xonly).collect::<Vec<_>>());
+}
+
+#[test]
+fn test_prefix_widths() {
+    let secp = Secp256k1::new();
+    let scan_key = ScanKey::from_slice(&[31u8; 32]).unwrap();
+    let spend_pubkey = PublicKey::from_secret_key(&secp, &SecretKey::from_slice(&[32u8; 32]).unwrap());
+    let input_pubkey = PublicKey::from_secret_key(&secp, &SecretKey::from_slice(&[33u8; 32]).unwrap());
+    let inputs = vec![InputData { pubkey: input_pubkey, is_taproot: true, outpoint: None }];
+    
+    let short = compute_prefixes(&scan_key, &spend_pubkey, &inputs, 2).unwrap();
+    let short_len = compute_prefixes_with_len(&scan_key, &spend_pubkey, &inputs, 2, 4).unwrap();
+    let long = compute_prefixes_with_len(&scan_key, &spend_pubkey, &inputs, 2, 8).unwrap();
+    
+    for ((s, s_len), l) in short.iter().zip(&short_len).zip(&long) {
+        assert_eq!(*s as u64, *s_len);
+        // The 4-byte prefix is the top half of the 8-byte one
+        assert_eq!(l >> 32, *s as u64);
+    }
+    
+    let key = spend_pubkey.x_only_public_key().0;
+    assert!(prefix_from_xonly_len(&key, 0).is_err());
+    assert!(prefix_from_xonly_len(&key, 9).is_err());
+}
+
+#[test]
+fn test_prefixes_for_sparse_labels() {
+    let secp = Secp256k1::new();
+    let scan_key = ScanKey::from_slice(&[34u8; 32]).unwrap();
+    let spend_pubkey = PublicKey::from_secret_key(&secp, &SecretKey::from_slice(&[35u8; 32]).unwrap());
+    let input_pubkey = PublicKey::from_secret_key(&secp, &SecretKey::from_slice(&[36u8; 32]).unwrap());
+    let inputs = vec![InputData { pubkey: input_pubkey, is_taproot: true, outpoint: None }];
+    
+    let labels = [None, Some(3), Some(7)];
+    let prefixes = compute_prefixes_for_labels(&scan_key, &spend_pubkey, &inputs, &labels).unwrap();
+    assert_eq!(prefixes.len(), 3);
+    
+    let shared_secret = scan_key.compute_shared_secret(&inputs).unwrap();
+    for (label, prefix) in labels.iter().zip(&prefixes) {
+        let output = scan_key.derive_output_pubkey(&shared_secret, &spend_pubkey, *label).unwrap();
+        assert_eq!(*prefix, prefix_from_xonly(&output));
+    }
+    
+    // Same values as the dense range at those positions
+    let dense = compute_prefixes(&scan_key, &spend_pubkey, &inputs, 7).unwrap();
+    assert_eq!(prefixes, vec![dense[0], dense[3], dense[7]]);
+}
+
+#[test]
+fn test_labels_above_255_follow_bip352() {
+    use bitcoin::secp256k1::Scalar;
+    
+    let secp = Secp256k1::new();
+    let scan_key = ScanKey::from_slice(&[14u8; 32]).unwrap();
+    let spend_secret = SecretKey::from_slice(&[15u8; 32]).unwrap();
+    let spend_pubkey = PublicKey::from_secret_key(&secp, &spend_secret);
+    let inputs = vec![InputData {
+        pubkey: PublicKey::from_secret_key(&secp, &SecretKey::from_slice(&[16u8; 32]).unwrap()),
+        is_taproot: true,
+        outpoint: None,
+    }];
+    let shared_secret = scan_key.compute_shared_secret(&inputs).unwrap();
+    let t = Scalar::from_be_bytes(TaggedHash::output(&shared_secret, 0)).unwrap();
+    
+    let labels: Vec<Option<u32>> = vec![None, Some(1), Some(256), Some(300)];
+    let table = scan_key.label_lookup_table(300);
+    for &label in &labels[1..] {
+        let m = label.unwrap();
+        
+        // P = B_m + t*G with B_m = B + TaggedHash("BIP0352/Label", b_scan || ser32(m))*G
+        let expected = scan_key.labeled_spend_pubkey(&spend_pubkey, m).unwrap()
+            .add_exp_tweak(&secp, &t).unwrap()
+            .x_only_public_key().0;
+        let output = scan_key.derive_output_pubkey(&shared_secret, &spend_pubkey, label).unwrap();
+        assert_eq!(output, expected, "label {}", m);
+        
+        let mut script = vec![0x51, 0x20];
+        script.extend_from_slice(&output.serialize());
+        let found = scan_key.check_output(&script, &spend_pubkey, &inputs, &labels).unwrap().unwrap();
+        assert_eq!(found.label, label);
+        
+        // The spend tweak controls the output key
+        let tweak = Scalar::from_be_bytes(found.tweak).unwrap();
+        let expected_key = spend_secret.add_tweak(&tweak).unwrap();
+        assert_eq!(expected_key.x_only_public_key(&secp).0, output, "label {}", m);
+        let result = ScanResult::from_match(&found, [0u8; 32], 0, 1_000);
+        assert_eq!(result.spending_key(&spend_secret).unwrap(), expected_key, "label {}", m);
+        
+        // The table lookup agrees
+        let (_, via_table) = scan_key.check_outputs_with_labels(&[&script[..]], &spend_pubkey, &inputs, &table)
+            .unwrap()
+            .remove(0);
+        assert_eq!(via_table.label, label);
+        assert_eq!(via_table.tweak, found.tweak);
+    }
+    
+    // Distinct labels differing only above the low byte give distinct outputs
+    assert_ne!(
+        scan_key.derive_output_pubkey(&shared_secret, &spend_pubkey, Some(1)).unwrap(),
+        scan_key.derive_output_pubkey(&shared_secret, &spend_pubkey, Some(257)).unwrap(),
+    );
+}
+
+#[test]
+fn test_address_base_fingerprint_ignores_label() {
+    let secp = Secp256k1::new();
+    let key = |b: u8| PublicKey::from_secret_key(&secp, &SecretKey::from_slice(&[b; 32]).unwrap());
+    let scan_key = ScanKey::from_slice(&[35u8; 32]).unwrap();
+    
+    let base = scan_key.to_address(&key(34), bitcoin::Network::Bitcoin);
+    let labeled = |label: u32| scan_key.to_labeled_address(&key(34), label, bitcoin::Network::Bitcoin).unwrap();
+    let (first, second) = (labeled(1), labeled(2));
+    
+    assert_ne!(first, second);
+    assert_ne!(first.spend_pubkey, second.spend_pubkey);
+    assert_eq!(scan_key.base_spend_key(&first).unwrap(), key(34));
+    assert_eq!(scan_key.base_spend_key(&second).unwrap(), key(34));
+    assert_eq!(scan_key.base_fingerprint(&first).unwrap(), scan_key.base_fingerprint(&base).unwrap());
+    assert_eq!(scan_key.base_fingerprint(&first).unwrap(), scan_key.base_fingerprint(&second).unwrap());
+    
+    // A different spend key is a different base
+    let other = scan_key.to_labeled_address(&key(36), 1, bitcoin::Network::Bitcoin).unwrap();
+    assert_ne!(scan_key.base_fingerprint(&other).unwrap(), scan_key.base_fingerprint(&first).unwrap());
+    
+    // Only the address's own scan key can remove the label, and only with
+    // the label number
+    let stranger = ScanKey::from_slice(&[37u8; 32]).unwrap();
+    assert!(stranger.base_spend_key(&first).is_err());
+    assert!(scan_key.base_spend_key(&SilentPaymentAddress { label: None, ..first }).is_err());
+}
+
+#[test]
+fn test_keypair_addresses_rotate_through_labels() {
+    use bitcoin::secp256k1::Parity;
+    
+    let secp = Secp256k1::new();
+    let spend_secret = SecretKey::from_slice(&[82u8; 32]).unwrap();
+    let keypair = SilentPaymentKeypair::from_secrets(SecretKey::from_slice(&[81u8; 32]).unwrap(), spend_secret).unwrap();
+    
+    let first: Vec<SilentPaymentAddress> = keypair.addresses(bitcoin::Network::Bitcoin).take(3).collect();
+    assert_eq!(first.iter().map(|a| a.label).collect::<Vec<_>>(), vec![None, Some(1), Some(2)]);
+    
+    assert_eq!(first[0], keypair.address(bitcoin::Network::Bitcoin));
+    let base = keypair.spend.public;
+    for address in &first {
+        // Every address holds the key a sender pays, so its string round-trips
+        let decoded: SilentPaymentAddress = address.to_string().parse().unwrap();
+        assert_eq!(decoded.scan_pubkey, keypair.scan.public);
+        let expected = match address.label {
+            Some(m) => keypair.scan.labeled_spend_pubkey(&base, m).unwrap(),
+            None => keypair.spend.public,
+        };
+        assert_eq!(decoded.spend_pubkey, expected);
+        assert_eq!(address.spend_pubkey, expected);
+        assert_eq!(keypair.scan.base_spend_key(address).unwrap(), base);
+    }
+    assert_ne!(first[1].to_string(), first[0].to_string());
+    assert_ne!(first[2].to_string(), first[1].to_string());
+    
+    // A sender paying each decoded address is found by one scan over the
+    // labels handed out, whichever Y its labeled spend key has
+    let addresses: Vec<SilentPaymentAddress> = keypair.addresses(bitcoin::Network::Bitcoin).take(9).collect();
+    let labels: Vec<Option<u32>> = addresses.iter().map(|a| a.label).collect();
+    let parities: Vec<Parity> = (1..9u32)
+        .map(|m| keypair.scan.labeled_spend_pubkey(&base, m).unwrap().x_only_public_key().1)
+        .collect();
+    assert!(parities.contains(&Parity::Odd) && parities.contains(&Parity::Even));
+    
+    let sender_secret = SecretKey::from_slice(&[83u8; 32]).unwrap();
+    let inputs = vec![InputData { pubkey: PublicKey::from_secret_key(&secp, &sender_secret), is_taproot: false, outpoint: None }];
+    for address in &addresses {
+        let decoded: SilentPaymentAddress = address.to_string().parse().unwrap();
+        let (_, output) = derive_send_outputs(&[sender_secret], &[bitcoin::OutPoint::null()], &[decoded]).unwrap().remove(0);
+        let mut script = vec![0x51, 0x20];
+        script.extend_from_slice(&output.serialize());
+        
+        let found = keypair.scan.check_output(&script, &keypair.spend.public, &inputs, &labels).unwrap()
+            .unwrap_or_else(|| panic!("payment to label {:?} not found", address.label));
+        assert_eq!(found.label, address.label);
+        let result = ScanResult::from_match(&found, [0u8; 32], 0, 1_000);
+        assert_eq!(result.spending_key(&spend_secret).unwrap().x_only_public_key(&secp).0, output);
+    }
+}
+
+#[test]
+fn test_address_encode_decode_roundtrip() {
+    let secp = Secp256k1::new();
+    let scan_key = ScanKey::from_slice(&[1u8; 32]).unwrap();
+    let spend_pubkey = PublicKey::from_secret_key(&secp, &SecretKey::from_slice(&[2u8; 32]).unwrap());
+    
+    let address = scan_key.to_address(&spend_pubkey, bitcoin::Network::Bitcoin);
+    let encoded = address.to_string();
+    // Cross-checked against an independent bech32m implementation
+    assert_eq!(
+        encoded,
+        "sp1qqvdcf32k0vfxgsyet5ldt246q4jaw8scx3sysx0lnstlt6w4m5rc7qjdfdkdzdssxt9fh54wh8vsp2jdghv74kq2e9prxaxy2xnj2ng8vct7nplx"
+    );
+    
+    let decoded: SilentPaymentAddress = encoded.parse().unwrap();
+    assert_eq!(decoded, address);
+    assert_eq!(decoded.scan_pubkey, scan_key.public);
+    assert_eq!(decoded.spend_pubkey, spend_pubkey);
+    
+    // Test networks use the tsp prefix; uppercase is accepted
+    let testnet = scan_key.to_address(&spend_pubkey, bitcoin::Network::Testnet).to_string();
+    assert!(testnet.starts_with("tsp1q"));
+    let decoded = SilentPaymentAddress::decode(&testnet.to_uppercase()).unwrap();
+    assert_eq!(decoded.network, bitcoin::Network::Testnet);
+    
+    // Corrupted checksum and foreign prefixes are rejected
+    let mut corrupted = encoded.clone().into_bytes();
+    let last = corrupted.len() - 1;
+    corrupted[last] = if corrupted[last] == b'q' { b'p' } else { b'q' };
+    assert!(SilentPaymentAddress::decode(std::str::from_utf8(&corrupted).unwrap()).is_err());
+    assert!(SilentPaymentAddress::decode("bc1pxyz").is_err());
+}
+
+#[test]
+fn test_address_hrp_for_each_network() {
+    use bitcoin::Network;
+    
+    let secp = Secp256k1::new();
+    let scan_key = ScanKey::from_slice(&[1u8; 32]).unwrap();
+    let spend_pubkey = PublicKey::from_secret_key(&secp, &SecretKey::from_slice(&[2u8; 32]).unwrap());
+    
+    let encoded = |network| scan_key.to_address(&spend_pubkey, network).to_string();
+    assert!(encoded(Network::Bitcoin).starts_with("sp1q"));
+    for network in [Network::Testnet, Network::Signet, Network::Regtest] {
+        let address = encoded(network);
+        assert!(address.starts_with("tsp1q"), "{}: {}", network, address);
+        
+        // Test networks share one encoding, so they all decode as testnet
+        assert_eq!(address, encoded(Network::Testnet));
+        let decoded = SilentPaymentAddress::decode(&address).unwrap();
+        assert_eq!(decoded.network, Network::Testnet);
+        assert_eq!(decoded.scan_pubkey, scan_key.public);
+        assert_eq!(decoded.spend_pubkey, spend_pubkey);
+        assert!(decoded.matches_network(network));
+        assert!(!decoded.matches_network(Network::Bitcoin));
+    }
+    
+    let mainnet = SilentPaymentAddress::decode(&encoded(Network::Bitcoin)).unwrap();
+    assert_eq!(mainnet.network, Network::Bitcoin);
+    assert!(mainnet.matches_network(Network::Bitcoin));
+    assert!(!mainnet.matches_network(Network::Signet));
+}
+
+#[test]
+fn test_parsers_reject_off_by_one_lengths() {
+    let secp = Secp256k1::new();
+    let scan_key = ScanKey::from_slice(&[1u8; 32]).unwrap();
+    let spend_pubkey = PublicKey::from_secret_key(&secp, &SecretKey::from_slice(&[2u8; 32]).unwrap());
+    let inputs = vec![InputData {
+        pubkey: PublicKey::from_secret_key(&secp, &SecretKey::from_slice(&[3u8; 32]).unwrap()),
+        is_taproot: true,
+        outpoint: None,
+    }];
+    let table = scan_key.label_lookup_table(1);
+    
+    // A real output with one byte dropped or appended is simply not a match
+    let output = scan_key.compute_expected_outputs(&spend_pubkey, &inputs, 0).unwrap()[0];
+    let mut script = vec![0x51, 0x20];
+    script.extend_from_slice(&output.serialize());
+    assert!(scan_key.check_output(&script, &spend_pubkey, &inputs, &[None]).unwrap().is_some());
+    
+    let mut long = script.clone();
+    long.push(0x00);
+    for candidate in [&script[..33], &long[..], &[0x51][..], &[][..]] {
+        assert!(scan_key.check_output(candidate, &spend_pubkey, &inputs, &[None]).unwrap().is_none());
+        assert!(scan_key.check_outputs_with_labels(&[candidate], &spend_pubkey, &inputs, &table).unwrap().is_empty());
+    }
+    
+    for len in [0, 31, 33] {
+        assert!(matches!(ScanKey::from_slice(&vec![1u8; len]), Err(CoreError::InvalidInput(_))));
+    }
+    for len in [79, 81] {
+        assert!(matches!(verify_merkle_proof(&[0u8; 32], &[], 0, &vec![0u8; len]), Err(CoreError::InvalidInput(_))));
+    }
+    let err = ScanResult::from_bytes(&[0u8; ScanResult::ENCODED_LEN + 1]).unwrap_err();
+    assert!(err.to_string().contains("must be 117 bytes"), "{}", err);
+}
+
+#[test]
+fn test_scan_result_bytes_roundtrip() {
+    let secp = Secp256k1::new();
+    let output_pubkey = PublicKey::from_secret_key(&secp, &SecretKey::from_slice(&[23u8; 32]).unwrap())
+        .x_only_public_key().0;
+    
+    for (label, block_height) in [(None, None), (Some(0), Some(0)), (Some(7), Some(840_000)), (Some(70_000), None)] {
+        let result = ScanResult {
+            txid: [0xABu8; 32],
+            vout: 3,
+            amount: 2_100_000_000_000_000,
+            label,
+            tweak: [0xCDu8; 32],
+            output_pubkey,
+            block_height,
+            spend_pubkey: None,
+            account: 0,
+        };
+        
+        let bytes = result.to_bytes();
+        assert_eq!(bytes.len(), ScanResult::ENCODED_LEN);
+        
+        let decoded = ScanResult::from_bytes(&bytes).unwrap();
+        assert_eq!(decoded.txid, result.txid);
+        assert_eq!(decoded.vout, result.vout);
+        assert_eq!(decoded.amount, result.amount);
+        assert_eq!(decoded.label, result.label);
+        assert_eq!(decoded.tweak, result.tweak);
+        assert_eq!(decoded.output_pubkey, result.output_pubkey);
+        assert_eq!(decoded.block_height, result.block_height);
+    }
+}
+
+#[test]
+fn test_scan_result_from_bytes_rejects_truncated() {
+    let secp = Secp256k1::new();
+    let output_pubkey = PublicKey::from_secret_key(&secp, &SecretKey::from_slice(&[24u8; 32]).unwrap())
+        .x_only_public_key().0;
+    let result = ScanResult {
+        txid: [1u8; 32],
+        vout: 0,
+        amount: 1000,
+        label: Some(1),
+        tweak: [2u8; 32],
+        output_pubkey,
+        block_height: None,
+        spend_pubkey: None,
+        account: 0,
+    };
+    let bytes = result.to_bytes();
+    
+    assert!(ScanResult::from_bytes(&bytes[..ScanResult::ENCODED_LEN - 1]).is_err());
+    assert!(ScanResult::from_bytes(&[]).is_err());
+    
+    // Invalid label flag
+    let mut corrupted = bytes;
+    corrupted[44] = 0x02;
+    assert!(ScanResult::from_bytes(&corrupted).is_err());
+}
+
+#[test]
+fn test_merkle_branch_roundtrip() {
+    use bitcoin::hashes::Hash;
+    
+    // Odd leaf count exercises the duplicate-last-node rule
+    let txids: Vec<[u8; 32]> = (1..=7u8).map(|i| [i; 32]).collect();
+    let expected_root = bitcoin::merkle_tree::calculate_root(
+        txids.iter().map(|t| bitcoin::Txid::from_byte_array(*t))
+    ).unwrap();
+    
+    for index in 0..txids.len() {
+        let branch = merkle_branch(&txids, index);
+        let root = merkle_root_from_branch(&txids[index], &branch, index as u32);
+        assert_eq!(root, expected_root.to_byte_array(), "Root mismatch for leaf {}", index);
+    }
+    
+    // Single-transaction block: the root is the txid itself
+    assert!(merkle_branch(&txids[..1], 0).is_empty());
+}
+
+#[test]
+fn test_merkle_proof_against_header() {
+    use bitcoin::hashes::Hash;
+    
+    let txids: Vec<[u8; 32]> = (1..=4u8).map(|i| [i; 32]).collect();
+    let root = merkle_root_from_branch(&txids[2], &merkle_branch(&txids, 2), 2);
+    
+    let header = bitcoin::block::Header {
+        version: bitcoin::block::Version::TWO,
+        prev_blockhash: bitcoin::BlockHash::all_zeros(),
+        merkle_root: bitcoin::TxMerkleNode::from_byte_array(root),
+        time: 0,
+        bits: bitcoin::CompactTarget::from_consensus(0x207fffff),
+        nonce: 0,
+    };
+    let header_bytes = bitcoin::consensus::serialize(&header);
+    assert_eq!(block_hash_from_header(&header_bytes), header.block_hash().to_byte_array());
+    
+    // Valid proof
+    let branch = merkle_branch(&txids, 2);
+    assert!(verify_merkle_proof(&txids[2], &branch, 2, &header_bytes).unwrap());
+    
+    // Tampered sibling hash
+    let mut tampered = branch.clone();
+    tampered[0][0] ^= 0x01;
+    assert!(!verify_merkle_proof(&txids[2], &tampered, 2, &header_bytes).unwrap());
+    
+    // Wrong position
+    assert!(!verify_merkle_proof(&txids[2], &branch, 3, &header_bytes).unwrap());
+    
+    // Truncated header
+    assert!(verify_merkle_proof(&txids[2], &branch, 2, &header_bytes[..79]).is_err());
+}
+
+#[test]
+fn test_input_data_from_transaction() {
+    use bitcoin::hashes::{hash160, Hash};
+    use bitcoin::{Amount, OutPoint, ScriptBuf, Sequence, Transaction, TxIn, TxOut, Txid, Witness};
+    
+    let secp = Secp256k1::new();
+    
+    // P2TR input: prevout commits to the x-only key
+    let taproot_key = PublicKey::from_secret_key(&secp, &SecretKey::from_slice(&[40u8; 32]).unwrap());
+    let taproot_xonly = taproot_key.x_only_public_key().0;
+    let mut p2tr_script = vec![0x51, 0x20];
+    p2tr_script.extend_from_slice(&taproot_xonly.serialize());
+    
+    // P2WPKH input: pubkey revealed in the witness
+    let wpkh_key = PublicKey::from_secret_key(&secp, &SecretKey::from_slice(&[41u8; 32]).unwrap());
+    let mut p2wpkh_script = vec![0x00, 0x14];
+    p2wpkh_script.extend_from_slice(hash160::Hash::hash(&wpkh_key.serialize()).as_byte_array());
+    
+    // P2WSH input: not eligible
+    let mut p2wsh_script = vec![0x00, 0x20];
+    p2wsh_script.extend_from_slice(&[0x33u8; 32]);
+    
+    let outpoint = |n: u8| OutPoint { txid: Txid::from_byte_array([n; 32]), vout: n as u32 };
+    let txin = |n: u8, witness: Witness| TxIn {
+        previous_output: outpoint(n),
+        script_sig: ScriptBuf::new(),
+        sequence: Sequence::MAX,
+        witness,
+    };
+    let prevout = |script: Vec<u8>| TxOut {
+        value: Amount::from_sat(10_000),
+        script_pubkey: ScriptBuf::from_bytes(script),
+    };
+    
+    let tx = Transaction {
+        version: bitcoin::transaction::Version::TWO,
+        lock_time: bitcoin::absolute::LockTime::ZERO,
+        input: vec![
+            txin(1, Witness::from_slice(&[vec![0u8; 64]])),
+            txin(2, Witness::from_slice(&[vec![0u8; 71], wpkh_key.serialize().to_vec()])),
+            txin(3, Witness::from_slice(&[vec![0u8; 71], vec![0u8; 40]])),
+        ],
+        output: vec![],
+    };
+    let prevouts = vec![prevout(p2tr_script), prevout(p2wpkh_script), prevout(p2wsh_script)];
+    
+    let inputs = InputData::from_transaction(&tx, &prevouts);
+    
+    assert_eq!(inputs.len(), 2, "P2WSH input must be skipped");
+    
+    assert!(inputs[0].is_taproot);
+    assert_eq!(inputs[0].pubkey.x_only_public_key().0, taproot_xonly);
+    assert_eq!(inputs[0].outpoint, Some(outpoint(1)));
+    
+    assert!(!inputs[1].is_taproot);
+    assert_eq!(inputs[1].pubkey, wpkh_key);
+    assert_eq!(inputs[1].outpoint, Some(outpoint(2)));
+    
+    // No eligible inputs (P2WSH and bare multisig): not a silent payment
+    let mut bare_multisig = vec![0x51, 0x21];
+    bare_multisig.extend_from_slice(&wpkh_key.serialize());
+    bare_multisig.extend_from_slice(&[0x51, 0xae]);
+    let ineligible = Transaction {
+        input: vec![tx.input[2].clone(), txin(4, Witness::new())],
+        ..tx.clone()
+    };
+    let ineligible_prevouts = vec![prevouts[2].clone(), prevout(bare_multisig)];
+    assert!(InputData::from_transaction(&ineligible, &ineligible_prevouts).is_empty());
+    let scan_key = ScanKey::new(SecretKey::from_slice(&[42u8; 32]).unwrap()).unwrap();
+    assert_eq!(
+        scan_key.scan_transaction(&ineligible, &ineligible_prevouts, &taproot_key, &[None]).unwrap(),
+        Vec::new(),
+    );
+}
+
+#[test]
+fn test_scan_transaction_finds_payment() {
+    use bitcoin::hashes::{hash160, Hash};
+    use bitcoin::{Amount, ScriptBuf, Transaction, TxIn, TxOut, Witness};
+    
+    let secp = Secp256k1::new();
+    let scan_key = ScanKey::from_slice(&[1u8; 32]).unwrap();
+    let spend_pubkey = PublicKey::from_secret_key(&secp, &SecretKey::from_slice(&[2u8; 32]).unwrap());
+    let address = SilentPaymentAddress {
+        spend_pubkey,
+        scan_pubkey: scan_key.public,
+        is_labeled: false,
+        label: None,
+        network: bitcoin::Network::Bitcoin,
+    };
+    
+    // The sender spends one P2WPKH input
+    let sender_secret = SecretKey::from_slice(&[41u8; 32]).unwrap();
+    let sender_key = PublicKey::from_secret_key(&secp, &sender_secret);
+    let mut p2wpkh_script = vec![0x00, 0x14];
+    p2wpkh_script.extend_from_slice(hash160::Hash::hash(&sender_key.serialize()).as_byte_array());
+    let prevouts = vec![TxOut { value: Amount::from_sat(100_000), script_pubkey: ScriptBuf::from_bytes(p2wpkh_script) }];
+    
+    // The input spends the default (null) outpoint
+    let (_, ours) = derive_send_outputs(&[sender_secret], &[bitcoin::OutPoint::null()], &[address]).unwrap().remove(0);
+    let p2tr = |key: XOnlyPublicKey| {
+        let mut script = vec![0x51, 0x20];
+        script.extend_from_slice(&key.serialize());
+        ScriptBuf::from_bytes(script)
+    };
+    let stranger = PublicKey::from_secret_key(&secp, &SecretKey::from_slice(&[9u8; 32]).unwrap())
+        .x_only_public_key().0;
+    
+    let tx = Transaction {
+        version: bitcoin::transaction::Version::TWO,
+        lock_time: bitcoin::absolute::LockTime::ZERO,
+        input: vec![TxIn {
+            witness: Witness::from_slice(&[vec![0u8; 71], sender_key.serialize().to_vec()]),
+            ..Default::default()
+        }],
+        output: vec![
+            TxOut { value: Amount::from_sat(20_000), script_pubkey: prevouts[0].script_pubkey.clone() },
+            TxOut { value: Amount::from_sat(30_000), script_pubkey: p2tr(stranger) },
+            TxOut { value: Amount::from_sat(40_000), script_pubkey: p2tr(ours) },
+        ],
+    };
+    
+    let results = scan_key.scan_transaction(&tx, &prevouts, &spend_pubkey, &[None, Some(1)]).unwrap();
+    
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0].outpoint(), bitcoin::OutPoint { txid: tx.compute_txid(), vout: 2 });
+    assert_eq!(results[0].amount, 40_000);
+    assert_eq!(results[0].label, None);
+    assert_eq!(results[0].output_pubkey, ours);
+    
+    // Prevouts must line up with the inputs
+    assert!(matches!(
+        scan_key.scan_transaction(&tx, &[], &spend_pubkey, &[None]),
+        Err(CoreError::InvalidInput(_))
+    ));
+}
+
+#[test]
+fn test_scan_transaction_stops_at_first_missing_k() {
+    use bitcoin::hashes::{hash160, Hash};
+    use bitcoin::{Amount, ScriptBuf, Transaction, TxIn, TxOut, Witness};
+    
+    let secp = Secp256k1::new();
+    let scan_key = ScanKey::from_slice(&[1u8; 32]).unwrap();
+    let spend_pubkey = PublicKey::from_secret_key(&secp, &SecretKey::from_slice(&[2u8; 32]).unwrap());
+    let address = SilentPaymentAddress {
+        spend_pubkey,
+        scan_pubkey: scan_key.public,
+        is_labeled: false,
+        label: None,
+        network: bitcoin::Network::Bitcoin,
+    };
+    
+    let sender_secret = SecretKey::from_slice(&[41u8; 32]).unwrap();
+    let sender_key = PublicKey::from_secret_key(&secp, &sender_secret);
+    let mut p2wpkh_script = vec![0x00, 0x14];
+    p2wpkh_script.extend_from_slice(hash160::Hash::hash(&sender_key.serialize()).as_byte_array());
+    let prevouts = vec![TxOut { value: Amount::from_sat(100_000), script_pubkey: ScriptBuf::from_bytes(p2wpkh_script) }];
+    
+    // Three payments to the same address, which the sender numbers k = 0, 1, 2
+    let keys: Vec<XOnlyPublicKey> = derive_send_outputs(&[sender_secret], &[bitcoin::OutPoint::null()], &[address.clone(), address.clone(), address])
+        .unwrap()
+        .into_iter()
+        .map(|(_, key)| key)
+        .collect();
+    let paying = |ks: &[usize]| Transaction {
+        version: bitcoin::transaction::Version::TWO,
+        lock_time: bitcoin::absolute::LockTime::ZERO,
+        input: vec![TxIn {
+            witness: Witness::from_slice(&[vec![0u8; 71], sender_key.serialize().to_vec()]),
+            ..Default::default()
+        }],
+        // Highest k first, so output order doesn't give the k order away
+        output: ks.iter().rev().map(|&k| {
+            let mut script = vec![0x51, 0x20];
+            script.extend_from_slice(&keys[k].serialize());
+            TxOut { value: Amount::from_sat(10_000 * (k as u64 + 1)), script_pubkey: ScriptBuf::from_bytes(script) }
+        }).collect(),
+    };
+    
+    // The label table lookup walks k the same way
+    let inputs = InputData::from_transaction(&paying(&[]), &prevouts);
+    let table = scan_key.label_lookup_table(1);
+    let via_table = |tx: &Transaction| -> Vec<usize> {
+        let scripts: Vec<&[u8]> = tx.output.iter().map(|output| output.script_pubkey.as_bytes()).collect();
+        scan_key.check_outputs_with_labels(&scripts, &spend_pubkey, &inputs, &table)
+            .unwrap()
+            .iter()
+            .map(|(i, _)| *i)
+            .collect()
+    };
+    
+    // Contiguous from 0: all found, in output order
+    let tx = paying(&[0, 1, 2]);
+    let results = scan_key.scan_transaction(&tx, &prevouts, &spend_pubkey, &[None]).unwrap();
+    let found: Vec<(u32, XOnlyPublicKey)> = results.iter().map(|r| (r.vout, r.output_pubkey)).collect();
+    assert_eq!(found, vec![(0, keys[2]), (1, keys[1]), (2, keys[0])]);
+    assert_eq!(via_table(&tx), vec![0, 1, 2]);
+    
+    // k = 1 missing: the scan stops there, so only k = 0 is found
+    let tx = paying(&[0, 2]);
+    let results = scan_key.scan_transaction(&tx, &prevouts, &spend_pubkey, &[None]).unwrap();
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0].output_pubkey, keys[0]);
+    assert_eq!(results[0].vout, 1);
+    assert_eq!(via_table(&tx), vec![1]);
+    
+    // Without k = 0 nothing is found
+    let tx = paying(&[1, 2]);
+    assert!(scan_key.scan_transaction(&tx, &prevouts, &spend_pubkey, &[None]).unwrap().is_empty());
+    assert!(via_table(&tx).is_empty());
+}
+
+#[test]
+fn test_generated_payment_is_found_by_scan_transaction() {
+    let secp = Secp256k1::new();
+    let scan_key = ScanKey::from_slice(&[1u8; 32]).unwrap();
+    let spend_pubkey = PublicKey::from_secret_key(&secp, &SecretKey::from_slice(&[2u8; 32]).unwrap());
+    
+    let payment = generate_test_payment(&scan_key, &spend_pubkey, Some(3), 0, 7).unwrap();
+    let results = scan_key
+        .scan_transaction(&payment.transaction, &payment.prevouts, &spend_pubkey, &[None, Some(3)])
+        .unwrap();
+    
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0], payment.expected);
+    assert_eq!(results[0].label, Some(3));
+    assert_eq!(results[0].amount, payment.expected.amount);
+    assert_eq!(results[0].tweak, payment.expected.tweak);
+    
+    // Same seed, same payment; another seed, another sender and output
+    let again = generate_test_payment(&scan_key, &spend_pubkey, Some(3), 0, 7).unwrap();
+    assert_eq!(again.transaction, payment.transaction);
+    let other = generate_test_payment(&scan_key, &spend_pubkey, Some(3), 0, 8).unwrap();
+    assert_ne!(other.expected.output_pubkey, payment.expected.output_pubkey);
+    
+    // The k-th payment comes after outputs 0..k to the same address, all found
+    let third = generate_test_payment(&scan_key, &spend_pubkey, Some(3), 2, 7).unwrap();
+    assert_eq!(third.transaction.output.len(), 3);
+    assert_eq!(third.expected.vout, 2);
+    assert_eq!(third.transaction.output[0], payment.transaction.output[0]);
+    let results = scan_key
+        .scan_transaction(&third.transaction, &third.prevouts, &spend_pubkey, &[Some(3)])
+        .unwrap();
+    assert_eq!(results.len(), 3);
+    assert_eq!(results[2], third.expected);
+    assert_ne!(third.expected.output_pubkey, payment.expected.output_pubkey);
+}
+
+#[test]
+fn test_p2sh_p2wpkh_input() {
+    use bitcoin::script::PushBytesBuf;
+    use bitcoin::{Amount, OutPoint, ScriptBuf, Sequence, TxIn, TxOut, Witness};
+    
+    // P2SH-P2WPKH spend from the BIP-143 test vectors
+    let pubkey_bytes = hex::decode("03ad1d8e89212f0b92c74d23bb710c00662ad1470198ac48c43f7d6f93a2a26873").unwrap();
+    let redeem_script = hex::decode("001479091972186c449eb1ded22b78e40d009bdf0089").unwrap();
+    let signature = hex::decode(
+        "3044022047ac8e878352d3ebbde1c94ce3a10d057c24175747116f8288e5d794d12d482f\
+         0220217f36a485cae903c713331d877c1f64677e3622ad4010726870540656fe9dcb01"
+    ).unwrap();
+    let p2sh_script = ScriptBuf::from_bytes(
+        hex::decode("a9144733f37cf4db86fbc2efed2500b4f4e49f31202387").unwrap()
+    );
+    
+    let script_sig = |push: &[u8]| {
+        ScriptBuf::builder()
+            .push_slice(PushBytesBuf::try_from(push.to_vec()).unwrap())
+            .into_script()
+    };
+    let txin = |script_sig: ScriptBuf| TxIn {
+        previous_output: OutPoint::null(),
+        script_sig,
+        sequence: Sequence::MAX,
+        witness: Witness::from_slice(&[signature.clone(), pubkey_bytes.clone()]),
+    };
+    let prevout = TxOut { value: Amount::from_sat(1_000_000_000), script_pubkey: p2sh_script };
+    
+    let input = extract_eligible_input(&txin(script_sig(&redeem_script)), &prevout)
+        .expect("P2SH-P2WPKH input should be eligible");
+    assert!(!input.is_taproot);
+    assert_eq!(input.pubkey.serialize().to_vec(), pubkey_bytes);
+    
+    // redeemScript that doesn't hash to the P2SH commitment
+    let mut other_program = redeem_script.clone();
+    other_program[2] ^= 0xff;
+    assert!(extract_eligible_input(&txin(script_sig(&other_program)), &prevout).is_none());
+    
+    // redeemScript that isn't a P2WPKH program
+    assert!(extract_eligible_input(&txin(script_sig(&[0x51])), &prevout).is_none());
+    
+    // Extra pushes in the scriptSig
+    let mut extra = script_sig(&redeem_script).into_bytes();
+    extra.splice(0..0, [0x01, 0x00]);
+    assert!(extract_eligible_input(&txin(ScriptBuf::from_bytes(extra)), &prevout).is_none());
+}
+
+#[test]
+fn test_taproot_script_path_with_annex() {
+    use bitcoin::secp256k1::Parity;
+    use bitcoin::{Amount, OutPoint, ScriptBuf, Sequence, TxIn, TxOut, Witness};
+    
+    let secp = Secp256k1::new();
+    let output_key = PublicKey::from_secret_key(&secp, &SecretKey::from_slice(&[42u8; 32]).unwrap())
+        .x_only_public_key().0;
+    let mut script = vec![0x51, 0x20];
+    script.extend_from_slice(&output_key.serialize());
+    let prevout = TxOut { value: Amount::from_sat(10_000), script_pubkey: ScriptBuf::from_bytes(script) };
+    
+    let control_block = |internal_key: &[u8]| {
+        let mut cb = vec![0xc0];
+        cb.extend_from_slice(internal_key);
+        cb
+    };
+    let annex = vec![0x50, 0x01, 0x02];
+    let leaf_script = vec![0x51];
+    let txin = |items: Vec<Vec<u8>>| TxIn {
+        previous_output: OutPoint::null(),
+        script_sig: ScriptBuf::new(),
+        sequence: Sequence::MAX,
+        witness: Witness::from_slice(&items),
+    };
+    
+    // Script-path spend with an annex: eligible, contributes the output key
+    let internal_key = PublicKey::from_secret_key(&secp, &SecretKey::from_slice(&[43u8; 32]).unwrap())
+        .x_only_public_key().0;
+    let spend = txin(vec![vec![0u8; 64], leaf_script.clone(), control_block(&internal_key.serialize()), annex.clone()]);
+    let input = extract_eligible_input(&spend, &prevout).expect("script-path spend should be eligible");
+    assert!(input.is_taproot);
+    assert_eq!(input.pubkey, PublicKey::from_x_only_public_key(output_key, Parity::Even));
+    
+    // Key-path spend with an annex
+    let spend = txin(vec![vec![0u8; 64], annex.clone()]);
+    assert!(extract_eligible_input(&spend, &prevout).is_some());
+    
+    // NUMS internal key is skipped, with or without an annex
+    let nums = hex::decode("50929b74c1a04954b78b4b6035e97a5e078a5a0f28ec96d547bfee9ace803ac0").unwrap();
+    let spend = txin(vec![vec![0u8; 64], leaf_script.clone(), control_block(&nums), annex]);
+    assert!(extract_eligible_input(&spend, &prevout).is_none());
+    let spend = txin(vec![vec![0u8; 64], leaf_script, control_block(&nums)]);
+    assert!(extract_eligible_input(&spend, &prevout).is_none());
+}
+
+#[test]
+fn test_label_lookup_table_matches_linear_search() {
+    use bitcoin::secp256k1::Scalar;
+    
+    let secp = Secp256k1::new();
+    let scan_key = ScanKey::new(SecretKey::from_slice(&[50u8; 32]).unwrap()).unwrap();
+    let spend_secret = SecretKey::from_slice(&[51u8; 32]).unwrap();
+    let spend_pubkey = PublicKey::from_secret_key(&secp, &spend_secret);
+    
+    let input_pubkey = PublicKey::from_secret_key(&secp, &SecretKey::from_slice(&[52u8; 32]).unwrap());
+    let inputs = vec![InputData { pubkey: input_pubkey, is_taproot: true, outpoint: None }];
+    let shared_secret = scan_key.compute_shared_secret(&inputs).unwrap();
+    let t = Scalar::from_be_bytes(TaggedHash::output(&shared_secret, 0)).unwrap();
+    
+    // Sender pays to the address for label 737: P = B_m + t*G
+    let paid_label = 737u32;
+    let labeled_spend = scan_key.labeled_spend_pubkey(&spend_pubkey, paid_label).unwrap();
+    let output = labeled_spend.add_exp_tweak(&secp, &t).unwrap().x_only_public_key().0;
+    let mut script = vec![0x51, 0x20];
+    script.extend_from_slice(&output.serialize());
+    
+    let max_label = 1000u32;
+    
+    // Linear search: rederive every labeled output
+    let linear = (1..=max_label).find(|&m| {
+        let b_m = scan_key.labeled_spend_pubkey(&spend_pubkey, m).unwrap();
+        b_m.add_exp_tweak(&secp, &t).unwrap().x_only_public_key().0 == output
+    });
+    
+    // Table lookup
+    let table = scan_key.label_lookup_table(max_label);
+    assert_eq!(table.len(), max_label as usize);
+    let (_, found) = scan_key.check_outputs_with_labels(&[&script[..]], &spend_pubkey, &inputs, &table)
+        .unwrap()
+        .pop()
+        .expect("labeled output must be found via table");
+    
+    assert_eq!(linear, Some(paid_label));
+    assert_eq!(found.label, Some(paid_label));
+    assert_eq!(found.output_pubkey, output);
+    
+    // The returned tweak spends the output: (b + label_m + t)*G == P
+    let output_secret = spend_secret.add_tweak(&Scalar::from_be_bytes(found.tweak).unwrap()).unwrap();
+    assert_eq!(PublicKey::from_secret_key(&secp, &output_secret).x_only_public_key().0, output);
+    
+    // Unlabeled payment is reported without a label
+    let base = spend_pubkey.add_exp_tweak(&secp, &t).unwrap().x_only_public_key().0;
+    let mut base_script = vec![0x51, 0x20];
+    base_script.extend_from_slice(&base.serialize());
+    let (_, unlabeled) = scan_key.check_outputs_with_labels(&[&base_script[..]], &spend_pubkey, &inputs, &table)
+        .unwrap()
+        .pop()
+        .expect("unlabeled output must be found");
+    assert_eq!(unlabeled.label, None);
+}
+
+#[test]
+fn test_scan_only_keypair_detects_without_spend_secret() {
+    let secp = Secp256k1::new();
+    let keypair = SilentPaymentKeypair::from_secrets(
+        SecretKey::from_slice(&[81u8; 32]).unwrap(),
+        SecretKey::from_slice(&[82u8; 32]).unwrap(),
+    ).unwrap();
+    
+    // Converting drops the spend secret but keeps the address
+    let scan_only = ScanOnlyKeypair::from(keypair.clone());
+    assert_eq!(scan_only.address(bitcoin::Network::Bitcoin), keypair.address(bitcoin::Network::Bitcoin));
+    let rebuilt = ScanOnlyKeypair::new(keypair.scan.secret, keypair.spend.public).unwrap();
+    assert_eq!(rebuilt.spend_pubkey, scan_only.spend_pubkey);
+    
+    // Scanning needs only the public spend key
+    let inputs = vec![InputData {
+        pubkey: PublicKey::from_secret_key(&secp, &SecretKey::from_slice(&[83u8; 32]).unwrap()),
+        is_taproot: false,
+        outpoint: None,
+    }];
+    let expected = scan_only.scan.compute_expected_outputs(&scan_only.spend_pubkey, &inputs, 0).unwrap();
+    let mut script = vec![0x51, 0x20];
+    script.extend_from_slice(&expected[0].serialize());
+    let found = scan_only.scan.check_output(&script, &scan_only.spend_pubkey, &inputs, &[None])
+        .unwrap()
+        .expect("scan-only keys must detect the payment");
+    let result = ScanResult::from_match(&found, [1u8; 32], 0, 1000);
+    
+    // Spending takes the secret the scan-only keys never held
+    assert!(result.spending_key(&keypair.spend.secret.unwrap()).is_ok());
+    assert!(result.spending_key(&scan_only.scan.secret).is_err());
+}
+
+#[test]
+fn test_derive_keys_from_seed_bip352_paths() {
+    use bitcoin::Network;
+    
+    // BIP-32 test vector 1 seed; expected keys derived independently along
+    // m/352'/coin'/account'/1'/0 (scan) and m/352'/coin'/account'/0'/0 (spend)
+    let seed = hex::decode("000102030405060708090a0b0c0d0e0f").unwrap();
+    let cases = [
+        (Network::Bitcoin, 0, "18778f6ba4b363113417af64262408b7c28ac02fb443aeefc285269e8186419b",
+            "320cb82a9e88ac7c562119f44e049bd0b2e6554a1b9682b0630c4105a7982075"),
+        (Network::Testnet, 0, "8d33048cb2f37d5977262ab67e5b1a9a5ef79dacc1d3065e12a9e98f35cc4fcc",
+            "7a8610556cf2e4d679871e63ab782430327f6ac3dc2caa3b6620cb67c95f887c"),
+        (Network::Bitcoin, 1, "348d73e1f6f3f2e955dca7a9584eb90c83fdccdabddb97354a1082ca766ddf99",
+            "c32783a739e73b82488e8249627aa93b9f9a1eb2a8aa2a3815e8db8519a96a4a"),
+    ];
+    
+    for (network, account, scan, spend) in cases {
+        let keypair = derive_keys_from_seed(&seed, network, account).unwrap();
+        assert_eq!(hex::encode(keypair.scan.secret.secret_bytes()), scan);
+        assert_eq!(hex::encode(keypair.spend.secret.unwrap().secret_bytes()), spend);
+        
+        // Restoring from the same seed gives the same address
+        let restored = derive_keys_from_seed(&seed, network, account).unwrap();
+        assert_eq!(restored.address(network), keypair.address(network));
+    }
+    
+    // Hardened indices only go up to 2^31 - 1
+    assert!(derive_keys_from_seed(&seed, Network::Bitcoin, 1 << 31).is_err());
+}
+
+#[test]
+fn test_keypair_flags_use_on_wrong_network() {
+    use bitcoin::Network;
+    
+    let seed = hex::decode("000102030405060708090a0b0c0d0e0f").unwrap();
+    
+    // Testnet keys (coin type 1') fit every test network but not mainnet
+    let testnet = derive_keys_from_seed(&seed, Network::Testnet, 0).unwrap();
+    for network in [Network::Testnet, Network::Signet, Network::Regtest] {
+        assert!(testnet.validate_for_network(network).is_ok());
+    }
+    let err = testnet.validate_for_network(Network::Bitcoin).unwrap_err();
+    assert!(matches!(err, CoreError::NetworkMismatch(_)));
+    assert!(err.to_string().contains("coin type 1'"), "{}", err);
+    
+    let mainnet = derive_keys_from_seed(&seed, Network::Bitcoin, 0).unwrap();
+    assert!(mainnet.validate_for_network(Network::Bitcoin).is_ok());
+    assert!(matches!(mainnet.validate_for_network(Network::Signet), Err(CoreError::NetworkMismatch(_))));
+    
+    let testnet_path: bitcoin::bip32::DerivationPath = "m/352'/1'/0'".parse().unwrap();
+    assert_eq!(testnet.account_path(), Some(&testnet_path));
+    
+    // Keys with no BIP-352 derivation path can't be checked
+    let imported = SilentPaymentKeypair::from_secrets(testnet.scan.secret, testnet.spend.secret.unwrap()).unwrap();
+    assert!(imported.account_path().is_none());
+    assert!(imported.validate_for_network(Network::Bitcoin).is_ok());
+    let master = bitcoin::bip32::Xpriv::new_master(Network::Testnet, &seed).unwrap();
+    let other_path: bitcoin::bip32::DerivationPath = "m/84'/1'/0'".parse().unwrap();
+    let other = SilentPaymentKeypair::from_bip32(&master, &other_path).unwrap();
+    assert!(other.validate_for_network(Network::Bitcoin).is_ok());
+}
+
+#[test]
+fn test_verify_candidates_offline() {
+    let secp = Secp256k1::new();
+    let scan_key = ScanKey::new(SecretKey::from_slice(&[60u8; 32]).unwrap()).unwrap();
+    let spend_pubkey = PublicKey::from_secret_key(&secp, &SecretKey::from_slice(&[61u8; 32]).unwrap());
+    let input_pubkey = PublicKey::from_secret_key(&secp, &SecretKey::from_slice(&[62u8; 32]).unwrap());
+    let inputs = vec![InputData { pubkey: input_pubkey, is_taproot: true, outpoint: None }];
+    
+    let expected = scan_key.compute_expected_outputs(&spend_pubkey, &inputs, 2).unwrap();
+    let p2tr = |key: &XOnlyPublicKey| {
+        let mut script = vec![0x51, 0x20];
+        script.extend_from_slice(&key.serialize());
+        script
+    };
+    let unrelated = PublicKey::from_secret_key(&secp, &SecretKey::from_slice(&[63u8; 32]).unwrap())
+        .x_only_public_key().0;
+    
+    let scripts = [
+        p2tr(&unrelated),
+        p2tr(&expected[0]),
+        vec![0x00, 0x14, 0xAA, 0xBB], // not taproot
+        p2tr(&expected[2]),           // label 2
+    ];
+    let candidates: Vec<CandidateOutput> = scripts
+        .into_iter()
+        .enumerate()
+        .map(|(i, script_pubkey)| CandidateOutput {
+            script_pubkey,
+            txid: [i as u8; 32],
+            vout: i as u32,
+            amount: 1000 * (i as u64 + 1),
+            block_height: Some(700 + i as u32),
+        })
+        .collect();
+    
+    let results = verify_candidates(&scan_key, &spend_pubkey, &inputs, 2, &candidates).unwrap();
+    let found: Vec<(u32, Option<u32>, u64, Option<u32>)> = results
+        .iter()
+        .map(|r| (r.vout, r.label, r.amount, r.block_height))
+        .collect();
+    assert_eq!(found, vec![(1, None, 2000, Some(701)), (3, Some(2), 4000, Some(703))]);
+    assert_eq!(results[1].output_pubkey, expected[2]);
+    
+    // Labels above max_label are not searched
+    let results = verify_candidates(&scan_key, &spend_pubkey, &inputs, 1, &candidates).unwrap();
+    assert_eq!(results.len(), 1);
+}
+
+/// A wallet that received two outputs (unlabeled and label 1) in one
+/// transaction, with an unsigned PSBT sweeping both: `(keypair, received
+/// transaction, results, psbt)`.
+fn sweep_fixture() -> (SilentPaymentKeypair, bitcoin::Transaction, Vec<ScanResult>, bitcoin::Psbt) {
+    use bitcoin::hashes::Hash;
+    
+    let secp = Secp256k1::new();
+    let keypair = SilentPaymentKeypair::from_secrets(
+        SecretKey::from_slice(&[71u8; 32]).unwrap(),
+        SecretKey::from_slice(&[72u8; 32]).unwrap(),
+    ).unwrap();
+    let inputs = vec![InputData {
+        pubkey: PublicKey::from_secret_key(&secp, &SecretKey::from_slice(&[73u8; 32]).unwrap()),
+        is_taproot: false,
+        outpoint: None,
+    }];
+    
+    // The sender pays the unlabeled and label-1 outputs in one transaction
+    let expected = keypair.scan.compute_expected_outputs(&keypair.spend.public, &inputs, 1).unwrap();
+    let received = bitcoin::Transaction {
+        version: bitcoin::transaction::Version::TWO,
+        lock_time: bitcoin::absolute::LockTime::ZERO,
+        input: vec![bitcoin::TxIn::default()],
+        output: expected
+            .iter()
+            .map(|key| bitcoin::TxOut {
+                value: bitcoin::Amount::from_sat(40_000),
+                script_pubkey: bitcoin::ScriptBuf::new_p2tr_tweaked(
+                    bitcoin::key::TweakedPublicKey::dangerous_assume_tweaked(*key),
+                ),
+            })
+            .collect(),
+    };
+    let txid = received.compute_txid().to_byte_array();
+    let candidates: Vec<CandidateOutput> = received.output
+        .iter()
+        .enumerate()
+        .map(|(vout, out)| CandidateOutput {
+            script_pubkey: out.script_pubkey.to_bytes(),
+            txid,
+            vout: vout as u32,
+            amount: out.value.to_sat(),
+            block_height: Some(101),
+        })
+        .collect();
+    let results = verify_candidates(&keypair.scan, &keypair.spend.public, &inputs, 1, &candidates).unwrap();
+    assert_eq!(results.len(), 2);
+    
+    // Sweep both to a fresh address
+    let sweep = bitcoin::Transaction {
+        version: bitcoin::transaction::Version::TWO,
+        lock_time: bitcoin::absolute::LockTime::ZERO,
+        input: results
+            .iter()
+            .map(|r| bitcoin::TxIn {
+                previous_output: bitcoin::OutPoint { txid: received.compute_txid(), vout: r.vout },
+                ..Default::default()
+            })
+            .collect(),
+        output: vec![bitcoin::TxOut {
+            value: bitcoin::Amount::from_sat(79_000),
+            script_pubkey: received.output[0].script_pubkey.clone(),
+        }],
+    };
+    let mut psbt = bitcoin::Psbt::from_unsigned_tx(sweep).unwrap();
+    for (input, result) in psbt.inputs.iter_mut().zip(&results) {
+        input.witness_utxo = Some(received.output[result.vout as usize].clone());
+    }
+    
+    (keypair, received, results, psbt)
+}
+
+/// Runs every input of `tx` through libbitcoinconsensus with the taproot
+/// flags, `prevouts` being the outputs it spends in input order
+#[cfg(feature = "bitcoinconsensus")]
+fn consensus_verify(tx: &bitcoin::Transaction, prevouts: &[bitcoin::TxOut]) -> std::result::Result<(), bitcoinconsensus::Error> {
+    let tx_bytes = bitcoin::consensus::serialize(tx);
+    let utxos: Vec<bitcoinconsensus::Utxo> = prevouts
+        .iter()
+        .map(|txout| bitcoinconsensus::Utxo {
+            script_pubkey: txout.script_pubkey.as_bytes().as_ptr(),
+            script_pubkey_len: txout.script_pubkey.len() as u32,
+            value: txout.value.to_sat() as i64,
+        })
+        .collect();
+    for (index, prevout) in prevouts.iter().enumerate() {
+        bitcoinconsensus::verify(
+            prevout.script_pubkey.as_bytes(),
+            prevout.value.to_sat(),
+            &tx_bytes,
+            Some(&utxos),
+            index,
+        )?;
+    }
+    Ok(())
+}
+
+#[test]
+fn test_received_output_can_be_swept() {
+    use bitcoin::hashes::Hash;
+    use bitcoin::secp256k1::{schnorr, Message};
+    use bitcoin::sighash::{Prevouts, SighashCache, TapSighashType};
+    
+    let secp = Secp256k1::new();
+    let (keypair, received, results, psbt) = sweep_fixture();
+    let signed = sign_sweep(psbt.clone(), &keypair, &results, None).unwrap();
+    
+    // Every key-path signature verifies against its output key
+    let mut cache = SighashCache::new(&signed);
+    for (index, result) in results.iter().enumerate() {
+        let witness = &signed.input[index].witness;
+        assert_eq!(witness.len(), 1);
+        let signature = schnorr::Signature::from_slice(witness.nth(0).unwrap()).unwrap();
+        let sighash = cache
+            .taproot_key_spend_signature_hash(index, &Prevouts::All(&received.output), TapSighashType::Default)
+            .unwrap();
+        secp.verify_schnorr(&signature, &Message::from_digest(sighash.to_byte_array()), &result.output_pubkey)
+            .unwrap();
+    }
+    
+    // Consensus accepts the sweep, and rejects it once a signature is altered
+    #[cfg(feature = "bitcoinconsensus")]
+    {
+        let prevouts: Vec<_> = results.iter().map(|r| received.output[r.vout as usize].clone()).collect();
+        consensus_verify(&signed, &prevouts).unwrap();
+        
+        let mut tampered = signed.clone();
+        let mut signature = tampered.input[0].witness.nth(0).unwrap().to_vec();
+        signature[0] ^= 1;
+        tampered.input[0].witness = bitcoin::Witness::from_slice(&[signature]);
+        assert!(consensus_verify(&tampered, &prevouts).is_err());
+    }
+    
+    // Another wallet's spend secret can't sign for these outputs
+    let other = SilentPaymentKeypair::from_secrets(
+        SecretKey::from_slice(&[71u8; 32]).unwrap(),
+        SecretKey::from_slice(&[74u8; 32]).unwrap(),
+    ).unwrap();
+    assert!(matches!(sign_sweep(psbt, &other, &results, None), Err(CoreError::InvalidKey(_))));
+}
+
+#[test]
+fn test_sweep_with_annex_signs_over_it() {
+    use bitcoin::hashes::Hash;
+    use bitcoin::secp256k1::{schnorr, Message};
+    use bitcoin::sighash::{Annex, Prevouts, SighashCache, TapSighashType};
+    
+    let secp = Secp256k1::new();
+    let (keypair, received, results, psbt) = sweep_fixture();
+    let annex_bytes = [0x50, 0xde, 0xad, 0xbe, 0xef];
+    let annex = Annex::new(&annex_bytes).unwrap();
+    
+    let signed = sign_sweep(psbt.clone(), &keypair, &results, Some(annex.clone())).unwrap();
+    let unannexed = sign_sweep(psbt, &keypair, &results, None).unwrap();
+    
+    let mut cache = SighashCache::new(&signed);
+    for (index, result) in results.iter().enumerate() {
+        let witness = &signed.input[index].witness;
+        assert_eq!(witness.len(), 2);
+        assert_eq!(witness.taproot_annex(), Some(&annex_bytes[..]));
+        
+        // The signature commits to the annex: it verifies only against the
+        // annex sighash, and differs from the signature without one
+        let signature = schnorr::Signature::from_slice(witness.nth(0).unwrap()).unwrap();
+        let mut sighash = |annex| cache
+            .taproot_signature_hash(index, &Prevouts::All(&received.output), annex, None, TapSighashType::Default)
+            .unwrap();
+        let with_annex = Message::from_digest(sighash(Some(annex.clone())).to_byte_array());
+        let without_annex = Message::from_digest(sighash(None).to_byte_array());
+        secp.verify_schnorr(&signature, &with_annex, &result.output_pubkey).unwrap();
+        assert!(secp.verify_schnorr(&signature, &without_annex, &result.output_pubkey).is_err());
+        assert_ne!(witness.nth(0), unannexed.input[index].witness.nth(0));
+    }
+    
+    #[cfg(feature = "bitcoinconsensus")]
+    {
+        let prevouts: Vec<_> = results.iter().map(|r| received.output[r.vout as usize].clone()).collect();
+        consensus_verify(&signed, &prevouts).unwrap();
+    }
+    
+    // The annex type enforces the BIP-341 0x50 prefix
+    assert!(Annex::new(&[0x51, 0x00]).is_err());
+}
+
+#[test]
+fn test_key_path_spend_under_script_tree() {
+    use bitcoin::hashes::Hash;
+    use bitcoin::secp256k1::{schnorr, Message};
+    use bitcoin::taproot::{LeafVersion, TaprootBuilder};
+    
+    let secp = Secp256k1::new();
+    let keypair = SilentPaymentKeypair::from_secrets(
+        SecretKey::from_slice(&[91u8; 32]).unwrap(),
+        SecretKey::from_slice(&[92u8; 32]).unwrap(),
+    ).unwrap();
+    let spend_secret = keypair.spend.secret.unwrap();
+    let inputs = vec![InputData {
+        pubkey: PublicKey::from_secret_key(&secp, &SecretKey::from_slice(&[93u8; 32]).unwrap()),
+        is_taproot: false,
+        outpoint: None,
+    }];
+    let output = keypair.scan.compute_expected_outputs(&keypair.spend.public, &inputs, 0).unwrap()[0];
+    let mut script = vec![0x51, 0x20];
+    script.extend_from_slice(&output.serialize());
+    let found = keypair.scan.check_output(&script, &keypair.spend.public, &inputs, &[None])
+        .unwrap()
+        .unwrap();
+    let result = ScanResult::from_match(&found, [1u8; 32], 0, 1000);
+    
+    // Recovery path: <recovery key> OP_CHECKSIG
+    let recovery = PublicKey::from_secret_key(&secp, &SecretKey::from_slice(&[94u8; 32]).unwrap())
+        .x_only_public_key().0;
+    let recovery_script = bitcoin::ScriptBuf::builder()
+        .push_x_only_key(&recovery)
+        .push_opcode(bitcoin::opcodes::all::OP_CHECKSIG)
+        .into_script();
+    let tree = TaprootBuilder::new().add_leaf(0, recovery_script.clone()).unwrap();
+    let info = result.taproot_spend_info(tree).unwrap();
+    assert_eq!(info.internal_key(), result.output_pubkey);
+    assert!(info.merkle_root().is_some());
+    
+    // The script path commits to the tree under the new output key
+    let control_block = info.control_block(&(recovery_script.clone(), LeafVersion::TapScript)).unwrap();
+    assert!(control_block.verify_taproot_commitment(&secp, info.output_key().to_x_only_public_key(), &recovery_script));
+    
+    // The derived key still signs by key path for the tweaked output
+    let tweaked = result.tweaked_spending_key(&spend_secret, info.merkle_root()).unwrap();
+    assert_eq!(tweaked.to_keypair().x_only_public_key().0, info.output_key().to_x_only_public_key());
+    let message = Message::from_digest(bitcoin::hashes::sha256::Hash::hash(b"sighash").to_byte_array());
+    let signature: schnorr::Signature = secp.sign_schnorr(&message, &tweaked.to_keypair());
+    secp.verify_schnorr(&signature, &message, &info.output_key().to_x_only_public_key()).unwrap();
+    
+    // Without the merkle root the key doesn't match
+    let untweaked = result.tweaked_spending_key(&spend_secret, None).unwrap();
+    assert_ne!(untweaked.to_keypair().x_only_public_key().0, info.output_key().to_x_only_public_key());
+    
+    // Incomplete trees are rejected
+    let partial = TaprootBuilder::new().add_leaf(1, recovery_script).unwrap();
+    assert!(result.taproot_spend_info(partial).is_err());
+}
+
+#[test]
+fn test_assert_spendable_rejects_near_matches() {
+    let secp = Secp256k1::new();
+    let keypair = SilentPaymentKeypair::from_secrets(
+        SecretKey::from_slice(&[81u8; 32]).unwrap(),
+        SecretKey::from_slice(&[82u8; 32]).unwrap(),
+    ).unwrap();
+    let spend_secret = keypair.spend.secret.unwrap();
+    let inputs = vec![InputData {
+        pubkey: PublicKey::from_secret_key(&secp, &SecretKey::from_slice(&[83u8; 32]).unwrap()),
+        is_taproot: false,
+        outpoint: None,
+    }];
+    
+    let expected = keypair.scan.compute_expected_outputs(&keypair.spend.public, &inputs, 0).unwrap();
+    let mut script = vec![0x51, 0x20];
+    script.extend_from_slice(&expected[0].serialize());
+    let output_match = keypair.scan
+        .check_output(&script, &keypair.spend.public, &inputs, &[None])
+        .unwrap()
+        .unwrap();
+    let genuine = ScanResult::from_match(&output_match, [1u8; 32], 0, 10_000);
+    assert!(genuine.assert_spendable(&spend_secret).is_ok());
+    
+    // Same output key, tweak off by one
+    let mut wrong_tweak = genuine.clone();
+    wrong_tweak.tweak[31] ^= 0x01;
+    assert!(matches!(wrong_tweak.assert_spendable(&spend_secret), Err(CoreError::InvalidKey(_))));
+    
+    // Same tweak, output key of a neighbouring secret
+    let mut wrong_output = genuine.clone();
+    wrong_output.output_pubkey = SecretKey::from_slice(&[84u8; 32]).unwrap().x_only_public_key(&secp).0;
+    assert!(matches!(wrong_output.assert_spendable(&spend_secret), Err(CoreError::InvalidKey(_))));
+    
+    // Right result, wrong wallet
+    let other_secret = SecretKey::from_slice(&[85u8; 32]).unwrap();
+    assert!(genuine.assert_spendable(&other_secret).is_err());
 }
@@ -0,0 +1,16 @@
+-- Unconfirmed taproot outputs seen via ZMQ rawtx
+
+CREATE TABLE mempool_outputs (
+    id BIGSERIAL PRIMARY KEY,
+    txid BYTEA NOT NULL CHECK (length(txid) = 32),
+    vout INTEGER NOT NULL,
+    script_pubkey BYTEA NOT NULL CHECK (length(script_pubkey) = 34),
+    amount BIGINT NOT NULL,
+    x_only_pubkey BYTEA NOT NULL CHECK (length(x_only_pubkey) = 32),
+    sp_prefix INTEGER NOT NULL,
+    first_seen TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    UNIQUE(txid, vout)
+);
+
+CREATE INDEX idx_mempool_prefix ON mempool_outputs(sp_prefix);
+CREATE INDEX idx_mempool_first_seen ON mempool_outputs(first_seen);
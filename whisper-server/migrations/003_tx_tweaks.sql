@@ -0,0 +1,10 @@
+-- Per-transaction tweak data for server-side full scans: the concatenated
+-- 33-byte eligible input pubkeys, stored when every prevout was resolvable
+
+CREATE TABLE tx_tweaks (
+    txid BYTEA PRIMARY KEY REFERENCES transactions(txid) ON DELETE CASCADE,
+    block_height INTEGER NOT NULL REFERENCES blocks(height) ON DELETE CASCADE,
+    input_pubkeys BYTEA NOT NULL CHECK (length(input_pubkeys) > 0 AND length(input_pubkeys) % 33 = 0)
+);
+
+CREATE INDEX idx_tx_tweaks_height ON tx_tweaks(block_height);
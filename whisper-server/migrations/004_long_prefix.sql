@@ -0,0 +1,12 @@
+-- 8-byte prefixes (PREFIX_LENGTH=8), derived from the stored x-only key.
+-- Stored as the signed interpretation of the big-endian bytes, like sp_prefix.
+
+ALTER TABLE taproot_outputs ADD COLUMN sp_prefix_long BIGINT NOT NULL
+    GENERATED ALWAYS AS (('x' || encode(substring(x_only_pubkey FROM 1 FOR 8), 'hex'))::bit(64)::bigint) STORED;
+
+CREATE INDEX idx_outputs_prefix_long_height ON taproot_outputs(sp_prefix_long, block_height);
+
+ALTER TABLE mempool_outputs ADD COLUMN sp_prefix_long BIGINT NOT NULL
+    GENERATED ALWAYS AS (('x' || encode(substring(x_only_pubkey FROM 1 FOR 8), 'hex'))::bit(64)::bigint) STORED;
+
+CREATE INDEX idx_mempool_prefix_long ON mempool_outputs(sp_prefix_long);
@@ -0,0 +1,5 @@
+-- Bloom filter over the 4-byte prefixes of each block's taproot outputs,
+-- so scans can skip blocks that cannot match. NULL for blocks indexed
+-- before this column existed; those are always queried.
+
+ALTER TABLE blocks ADD COLUMN prefix_bloom BYTEA;
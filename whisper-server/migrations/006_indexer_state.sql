@@ -0,0 +1,10 @@
+-- Last block the indexer fully processed, written in the same transaction
+-- as the block itself. On startup the stored hash is checked against the
+-- node to detect reorgs that happened while the indexer was down.
+
+CREATE TABLE indexer_state (
+    id SMALLINT PRIMARY KEY DEFAULT 1 CHECK (id = 1),
+    block_hash BYTEA NOT NULL CHECK (length(block_hash) = 32),
+    height INTEGER NOT NULL,
+    updated_at TIMESTAMPTZ DEFAULT NOW()
+);
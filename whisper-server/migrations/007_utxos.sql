@@ -0,0 +1,11 @@
+-- Unspent outputs of indexed transactions, so the indexer can resolve the
+-- prevouts of later inputs without fetching them from the node. Rows are
+-- added as outputs are indexed and deleted when spent.
+
+CREATE TABLE utxos (
+    txid BYTEA NOT NULL REFERENCES transactions(txid) ON DELETE CASCADE,
+    vout INTEGER NOT NULL,
+    script_pubkey BYTEA NOT NULL,
+    amount BIGINT NOT NULL,
+    PRIMARY KEY (txid, vout)
+);
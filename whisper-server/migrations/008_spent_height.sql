@@ -0,0 +1,4 @@
+-- Height of the block whose transaction spent each taproot output, NULL
+-- while unspent, so scans can leave out outputs that were already swept.
+
+ALTER TABLE taproot_outputs ADD COLUMN spent_height INTEGER;
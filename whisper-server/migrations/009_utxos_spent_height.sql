@@ -0,0 +1,7 @@
+-- Height of the block that spent each UTXO row, NULL while unspent. Spent
+-- rows are kept (and cleared again on rollback) until they are deeper than
+-- any reorg the indexer rolls back.
+
+ALTER TABLE utxos ADD COLUMN spent_height INTEGER;
+
+CREATE INDEX idx_utxos_spent_height ON utxos(spent_height) WHERE spent_height IS NOT NULL;
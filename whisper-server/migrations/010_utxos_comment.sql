@@ -0,0 +1,8 @@
+-- 007 described utxos rows as deleted when spent; since 009 they are marked
+-- with spent_height instead. Record the current semantics on the table.
+
+COMMENT ON TABLE utxos IS
+    'Outputs of indexed transactions, for resolving the prevouts of later inputs. '
+    'spent_height is the height of the spending block, NULL while unspent; spent '
+    'rows are kept until deeper than any reorg the indexer rolls back, and are '
+    'unspent again when that block is rolled back.';
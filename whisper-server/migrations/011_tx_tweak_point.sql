@@ -0,0 +1,11 @@
+-- Tweak data becomes the BIP-352 tweak point input_hash * A, 33 bytes per
+-- transaction, instead of the eligible input keys. The input hash commits to
+-- the smallest outpoint spent, which the old rows don't record, so they
+-- can't be converted: they are dropped, and blocks indexed before this
+-- migration have no tweak data for full scans and sync until reindexed.
+
+DELETE FROM tx_tweaks;
+
+ALTER TABLE tx_tweaks DROP CONSTRAINT tx_tweaks_input_pubkeys_check;
+ALTER TABLE tx_tweaks RENAME COLUMN input_pubkeys TO tweak;
+ALTER TABLE tx_tweaks ADD CONSTRAINT tx_tweaks_tweak_check CHECK (length(tweak) = 33);
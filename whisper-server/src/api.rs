@@ -1,11 +1,17 @@
 use axum::{
-    extract::{State, Json},
+    extract::{Path, Query, State, Json},
     response::IntoResponse,
     http::StatusCode,
 };
 use serde::{Deserialize, Serialize};
-use crate::AppState;
+use crate::{AppState, ServerConfig};
+use crate::bloom::{BloomCache, PrefixBloom};
+use crate::indexer::{db_prefix, db_prefix_long, prefix_from_db};
+use crate::scan_cache::{CachedScan, ScanCacheKey};
 use thiserror::Error;
+use std::collections::{hash_map::Entry, HashMap, HashSet};
+use std::sync::Arc;
+use bitcoincore_rpc::RpcApi;
 
 #[derive(Error, Debug)]
 pub enum ApiError {
@@ -13,42 +19,109 @@ pub enum ApiError {
     Validation(String),
     #[error("Database error: {0}")]
     Database(String),
+    #[error("Not found: {0}")]
+    NotFound(String),
+    #[error("Forbidden: {0}")]
+    Forbidden(String),
+}
+
+/// JSON body of every error response, e.g.
+/// `{ "error": "validation", "message": "...", "code": 400 }`.
+#[derive(Debug, Serialize)]
+pub struct ErrorBody {
+    /// Machine-readable kind: `validation`, `database`, `not_found` or `forbidden`
+    pub error: &'static str,
+    pub message: String,
+    /// HTTP status code
+    pub code: u16,
 }
 
 impl IntoResponse for ApiError {
     fn into_response(self) -> axum::response::Response {
-        let (status, message) = match self {
-            ApiError::Validation(msg) => (StatusCode::BAD_REQUEST, msg),
-            ApiError::Database(msg) => (StatusCode::INTERNAL_SERVER_ERROR, msg),
+        let (status, kind, message) = match self {
+            ApiError::Validation(msg) => (StatusCode::BAD_REQUEST, "validation", msg),
+            ApiError::Database(msg) => (StatusCode::INTERNAL_SERVER_ERROR, "database", msg),
+            ApiError::NotFound(msg) => (StatusCode::NOT_FOUND, "not_found", msg),
+            ApiError::Forbidden(msg) => (StatusCode::FORBIDDEN, "forbidden", msg),
         };
         
-        let body = serde_json::json!({
-            "error": message,
-            "status": status.as_u16(),
-        });
+        let body = ErrorBody {
+            error: kind,
+            message,
+            code: status.as_u16(),
+        };
         
         (status, Json(body)).into_response()
     }
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 pub struct ScanRequest {
     pub scan_pubkey: String,
     pub start_height: i32,
     pub end_height: i32,
     pub prefixes: Vec<String>,
     pub include_proofs: Option<bool>,
+    /// Also return matching unconfirmed outputs (with `block_height: null`)
+    pub include_mempool: Option<bool>,
+    /// Only return outputs worth at least this many sats
+    pub min_amount: Option<i64>,
+    /// Only return outputs worth at most this many sats
+    pub max_amount: Option<i64>,
+    /// Leave out outputs spent in an indexed block
+    pub unspent_only: Option<bool>,
 }
 
-#[derive(Debug, Serialize)]
+impl ScanRequest {
+    /// Inclusive amount range, with unset bounds left open.
+    fn amount_range(&self) -> (i64, i64) {
+        (self.min_amount.unwrap_or(0), self.max_amount.unwrap_or(i64::MAX))
+    }
+}
+
+/// Hex txid in display byte order, as block explorers show it.
+///
+/// Txids are stored in internal byte order (`Txid::as_byte_array`) and
+/// reversed at the API boundary, so every `txid` the API returns or accepts
+/// is the conventional one; merkle proof hashes stay in internal order.
+pub(crate) fn txid_to_hex(internal: &[u8]) -> String {
+    let mut bytes = internal.to_vec();
+    bytes.reverse();
+    hex::encode(bytes)
+}
+
+/// Internal-order bytes of a display-order hex txid, if it is 32 bytes of hex.
+pub(crate) fn txid_from_hex(display: &str) -> Option<[u8; 32]> {
+    let mut bytes: [u8; 32] = hex::decode(display).ok()?.try_into().ok()?;
+    bytes.reverse();
+    Some(bytes)
+}
+
+#[derive(Debug, Clone, Serialize)]
 pub struct OutputCandidate {
+    /// In display byte order; see `txid_to_hex`
     pub txid: String,
     pub vout: i32,
-    pub amount: i64,
+    /// Omitted when the server hides amounts (`reveal_amounts`)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub amount: Option<i64>,
     pub script_pubkey: String,
-    pub block_height: i32,
-    pub block_hash: String,
+    /// `None` for unconfirmed (mempool) outputs
+    pub block_height: Option<i32>,
+    pub block_hash: Option<String>,
+    /// Block time, or first-seen time for mempool outputs
     pub timestamp: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub proof: Option<MerkleProof>,
+}
+
+/// Inclusion proof for a candidate's transaction, returned when
+/// `include_proofs` is set. All hashes are hex in internal byte order.
+#[derive(Debug, Clone, Serialize)]
+pub struct MerkleProof {
+    pub block_header: String,
+    pub tx_index: u32,
+    pub branch: Vec<String>,
 }
 
 #[derive(Debug, Serialize)]
@@ -56,73 +129,363 @@ pub struct ScanResponse {
     pub candidates: Vec<OutputCandidate>,
     pub scanned_blocks: Vec<i32>,
     pub server_time_ms: u64,
+    /// Number of requested prefixes that matched at least one output in range
+    pub matched_prefixes: usize,
+    /// Rows matched by prefix and height before orphaned blocks were dropped
+    pub candidates_before_filter: usize,
+    /// Blocks in range not queried because their prefix bloom filter
+    /// ruled out every requested prefix
+    pub skipped_blocks: usize,
 }
 
-pub async fn scan_handler(
-    State(state): State<AppState>,
-    Json(req): Json<ScanRequest>,
-) -> Result<impl IntoResponse, ApiError> {
-    let start = std::time::Instant::now();
-    
+/// Validate a requested height range against the server limits.
+fn validate_height_range(
+    start_height: i32,
+    end_height: i32,
+    config: &ServerConfig,
+) -> Result<(), ApiError> {
     // FIX Bug 3: Validate start_height <= end_height
-    if req.start_height > req.end_height {
+    if start_height > end_height {
         return Err(ApiError::Validation(
             "start_height must be <= end_height".into()
         ));
     }
     
-    if req.start_height < 0 {
+    if start_height < 0 {
         return Err(ApiError::Validation(
             "start_height must be non-negative".into()
         ));
     }
     
     // Validate block range
-    if req.end_height - req.start_height > state.config.max_block_range {
+    if end_height - start_height > config.max_block_range {
         return Err(ApiError::Validation(format!(
             "Block range too large (max: {})",
-            state.config.max_block_range
+            config.max_block_range
         )));
     }
     
-    if req.prefixes.is_empty() {
+    Ok(())
+}
+
+/// Prefixes from a scan request. With `PREFIX_LENGTH=8`, `long` holds the
+/// 8-byte prefixes and `short` their top 4 bytes (so queries still narrow by
+/// the 4-byte index); with 4-byte prefixes `long` is empty.
+#[derive(Debug, Clone, Default)]
+struct ParsedPrefixes {
+    short: Vec<i32>,
+    long: Vec<i64>,
+}
+
+impl ParsedPrefixes {
+    fn is_long(&self) -> bool {
+        !self.long.is_empty()
+    }
+}
+
+/// Validate a scan request against the server limits and parse its prefixes.
+fn parse_scan_request(req: &ScanRequest, config: &ServerConfig) -> Result<ParsedPrefixes, ApiError> {
+    validate_height_range(req.start_height, req.end_height, config)?;
+    
+    if req.min_amount.is_some_and(|a| a < 0) || req.max_amount.is_some_and(|a| a < 0) {
+        return Err(ApiError::Validation(
+            "min_amount and max_amount must be non-negative".into()
+        ));
+    }
+    
+    let (min_amount, max_amount) = req.amount_range();
+    if min_amount > max_amount {
+        return Err(ApiError::Validation(
+            "min_amount must be <= max_amount".into()
+        ));
+    }
+    
+    // Filtering by value would let clients recover the hidden amounts
+    if !config.reveal_amounts && (req.min_amount.is_some() || req.max_amount.is_some()) {
+        return Err(ApiError::Validation(
+            "min_amount and max_amount are disabled on this server".into()
+        ));
+    }
+    
+    parse_prefixes(&req.scan_pubkey, &req.prefixes, config)
+}
+
+/// Check a request's scan pubkey and prefix count, and parse its prefixes
+/// at the configured width.
+fn parse_prefixes(
+    scan_pubkey: &str,
+    prefixes: &[String],
+    config: &ServerConfig,
+) -> Result<ParsedPrefixes, ApiError> {
+    if prefixes.is_empty() {
         return Err(ApiError::Validation(
             "At least one prefix is required".into()
         ));
     }
     
-    if req.prefixes.len() > state.config.max_prefixes {
+    if prefixes.len() > config.max_prefixes {
         return Err(ApiError::Validation(format!(
             "Too many prefixes (max: {})",
-            state.config.max_prefixes
+            config.max_prefixes
         )));
     }
     
     // Validate scan_pubkey is valid hex
-    if req.scan_pubkey.len() != 64 {
+    if scan_pubkey.len() != 64 {
         return Err(ApiError::Validation(
             "scan_pubkey must be 32 bytes (64 hex chars)".into()
         ));
     }
     
-    // FIX Bug 4: Parse prefixes as u32 first, then cast to i32 with wrapping.
-    // Pubkey prefix bytes can exceed i32::MAX (0x80000000+).
-    let prefix_ints: Result<Vec<i32>, _> = req.prefixes
+    let values = prefixes
         .iter()
         .map(|p| {
-            let hex = p.trim_start_matches("0x");
-            u32::from_str_radix(hex, 16).map(|v| v as i32)
+            parse_hex_prefix(p, config.prefix_len).ok_or_else(|| ApiError::Validation(format!(
+                "Invalid prefix {:?} — expected {}-char hex", p, config.prefix_len * 2
+            )))
         })
-        .collect();
+        .collect::<Result<Vec<u64>, _>>()?;
     
-    let prefix_ints = prefix_ints
-        .map_err(|_| ApiError::Validation("Invalid prefix format — expected 8-char hex".into()))?;
+    // Same signed bit patterns the indexer stores
+    if config.prefix_len == 8 {
+        let short = values.iter().map(|&v| db_prefix((v >> 32) as u32)).collect();
+        let long = values.iter().map(|&v| db_prefix_long(v)).collect();
+        return Ok(ParsedPrefixes { short, long });
+    }
+    
+    let short = values.iter().map(|&v| db_prefix(v as u32)).collect();
+    Ok(ParsedPrefixes { short, long: Vec::new() })
+}
+
+/// Parse a `len`-byte prefix written as exactly `2 * len` hex digits,
+/// optionally `0x`-prefixed. Signs, odd or wrong lengths and non-hex
+/// characters are rejected rather than leniently parsed.
+fn parse_hex_prefix(prefix: &str, len: usize) -> Option<u64> {
+    let hex = prefix.strip_prefix("0x").unwrap_or(prefix);
+    if hex.len() != len * 2 || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    u64::from_str_radix(hex, 16).ok()
+}
+
+/// Drop candidate amounts unless the server is configured to reveal them.
+/// Prefix collisions are mostly other people's outputs, so hiding values
+/// keeps them from leaking; clients read the amount from the transaction
+/// of an output they have matched.
+fn redact_amounts(candidates: &mut [OutputCandidate], config: &ServerConfig) {
+    if !config.reveal_amounts {
+        for candidate in candidates {
+            candidate.amount = None;
+        }
+    }
+}
+
+/// Add the requested height range to the current request span.
+fn record_scan_range(start_height: i32, end_height: i32) {
+    tracing::Span::current().record(
+        "scan_range",
+        tracing::field::display(format_args!("{}..={}", start_height, end_height)),
+    );
+}
+
+pub async fn scan_handler(
+    State(state): State<AppState>,
+    Json(req): Json<ScanRequest>,
+) -> Result<impl IntoResponse, ApiError> {
+    let start = std::time::Instant::now();
+    record_scan_range(req.start_height, req.end_height);
+    
+    let prefixes = parse_scan_request(&req, &state.config)?;
+    let (min_amount, max_amount) = req.amount_range();
+    
+    let cache_key = ScanCacheKey::new(
+        req.start_height,
+        req.end_height,
+        &prefixes.short,
+        &prefixes.long,
+        (min_amount, max_amount),
+        req.include_proofs.unwrap_or(false),
+        req.unspent_only.unwrap_or(false),
+    );
+    let confirmed = match state.scan_cache.get(&cache_key) {
+        Some(cached) => cached,
+        None => {
+            let generation = state.scan_cache.generation();
+            let scan = scan_confirmed(&state, &req, &prefixes).await?;
+            state.scan_cache.insert(cache_key, scan.clone(), generation);
+            scan
+        }
+    };
+    let mut candidates = confirmed.candidates;
+    
+    if req.include_mempool.unwrap_or(false) {
+        candidates.extend(
+            fetch_mempool_candidates(&state.db, &prefixes, min_amount, max_amount).await?
+        );
+    }
+    redact_amounts(&mut candidates, &state.config);
+    
+    let response = ScanResponse {
+        candidates,
+        scanned_blocks: (req.start_height..=req.end_height).collect(),
+        server_time_ms: start.elapsed().as_millis() as u64,
+        matched_prefixes: confirmed.matched_prefixes,
+        candidates_before_filter: confirmed.candidates_before_filter,
+        skipped_blocks: confirmed.skipped_blocks,
+    };
+    
+    Ok((StatusCode::OK, Json(response)))
+}
+
+/// Confirmed candidates for a validated scan request, with proofs if asked.
+/// Mempool outputs are not included, so the result can be cached.
+async fn scan_confirmed(
+    state: &AppState,
+    req: &ScanRequest,
+    prefixes: &ParsedPrefixes,
+) -> Result<CachedScan, ApiError> {
+    let db = &state.db;
+    let (min_amount, max_amount) = req.amount_range();
+    let (heights, skipped_blocks) = heights_to_query(
+        db,
+        &state.bloom_cache,
+        req.start_height,
+        req.end_height,
+        &prefixes.short,
+    ).await?;
     
     // Query database
+    let rows = if heights.is_empty() {
+        Vec::new()
+    } else {
+        sqlx::query!(
+            r#"
+            SELECT 
+                o.txid as "txid!",
+                o.vout as "vout!",
+                o.amount as "amount!",
+                encode(o.script_pubkey, 'hex') as "script_pubkey!",
+                o.block_height as "block_height!",
+                encode(b.hash, 'hex') as "block_hash!",
+                EXTRACT(EPOCH FROM b.created_at)::bigint as "timestamp!",
+                o.sp_prefix as "sp_prefix!",
+                o.sp_prefix_long as "sp_prefix_long!",
+                b.is_orphaned as "is_orphaned!"
+            FROM taproot_outputs o
+            JOIN blocks b ON b.height = o.block_height
+            WHERE o.block_height BETWEEN $1 AND $2
+            AND o.block_height = ANY($7::int[])
+            AND o.sp_prefix = ANY($3::int[])
+            AND (cardinality($6::bigint[]) = 0 OR o.sp_prefix_long = ANY($6::bigint[]))
+            AND o.amount BETWEEN $4 AND $5
+            AND (NOT $8 OR o.spent_height IS NULL)
+            ORDER BY o.block_height, o.txid, o.vout
+            "#,
+            req.start_height,
+            req.end_height,
+            &prefixes.short,
+            min_amount,
+            max_amount,
+            &prefixes.long,
+            &heights,
+            req.unspent_only.unwrap_or(false)
+        )
+        .fetch_all(db)
+        .await
+        .map_err(|e| ApiError::Database(e.to_string()))?
+    };
+    
+    let candidates_before_filter = rows.len();
+    let matched_prefixes = rows
+        .iter()
+        .map(|r| if prefixes.is_long() { r.sp_prefix_long } else { r.sp_prefix as i64 })
+        .collect::<HashSet<_>>()
+        .len();
+    
+    let mut candidates: Vec<OutputCandidate> = rows
+        .into_iter()
+        .filter(|r| !r.is_orphaned)
+        .map(|r| OutputCandidate {
+            txid: txid_to_hex(&r.txid),
+            vout: r.vout,
+            amount: Some(r.amount),
+            script_pubkey: r.script_pubkey,
+            block_height: Some(r.block_height),
+            block_hash: Some(r.block_hash),
+            timestamp: r.timestamp,
+            proof: None,
+        })
+        .collect();
+    
+    if req.include_proofs.unwrap_or(false) {
+        attach_proofs(db, &mut candidates).await?;
+    }
+    
+    Ok(CachedScan {
+        candidates,
+        matched_prefixes,
+        candidates_before_filter,
+        skipped_blocks,
+    })
+}
+
+/// Most txids accepted by one `POST /api/v1/scan/txids` request.
+const MAX_SCAN_TXIDS: usize = 1000;
+
+/// Scan of specific transactions, e.g. ones a counterparty said paid the
+/// wallet, instead of a height range.
+#[derive(Debug, Clone, Deserialize)]
+pub struct TxidScanRequest {
+    pub scan_pubkey: String,
+    pub prefixes: Vec<String>,
+    /// In display byte order; see `txid_to_hex`
+    pub txids: Vec<String>,
+    /// Attach a merkle inclusion proof to each candidate
+    pub include_proofs: Option<bool>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TxidScanResponse {
+    pub candidates: Vec<OutputCandidate>,
+    pub server_time_ms: u64,
+}
+
+/// Candidates among the taproot outputs of the given transactions. Only
+/// transactions in non-orphaned indexed blocks are covered; unknown txids
+/// simply contribute no candidates.
+pub async fn scan_txids_handler(
+    State(state): State<AppState>,
+    Json(req): Json<TxidScanRequest>,
+) -> Result<impl IntoResponse, ApiError> {
+    let start = std::time::Instant::now();
+    
+    if req.txids.is_empty() {
+        return Err(ApiError::Validation(
+            "At least one txid is required".into()
+        ));
+    }
+    
+    if req.txids.len() > MAX_SCAN_TXIDS {
+        return Err(ApiError::Validation(format!(
+            "Too many txids (max: {})",
+            MAX_SCAN_TXIDS
+        )));
+    }
+    
+    let prefixes = parse_prefixes(&req.scan_pubkey, &req.prefixes, &state.config)?;
+    let txids = req.txids
+        .iter()
+        .map(|txid| {
+            txid_from_hex(txid)
+                .map(|bytes| bytes.to_vec())
+                .ok_or_else(|| ApiError::Validation(format!("Invalid txid {:?}", txid)))
+        })
+        .collect::<Result<Vec<Vec<u8>>, _>>()?;
+    
     let rows = sqlx::query!(
         r#"
         SELECT 
-            encode(o.txid, 'hex') as "txid!",
+            o.txid as "txid!",
             o.vout as "vout!",
             o.amount as "amount!",
             encode(o.script_pubkey, 'hex') as "script_pubkey!",
@@ -131,41 +494,969 @@ pub async fn scan_handler(
             EXTRACT(EPOCH FROM b.created_at)::bigint as "timestamp!"
         FROM taproot_outputs o
         JOIN blocks b ON b.height = o.block_height
-        WHERE o.block_height BETWEEN $1 AND $2
-        AND o.sp_prefix = ANY($3::int[])
+        WHERE o.txid = ANY($1::bytea[])
+        AND o.sp_prefix = ANY($2::int[])
+        AND (cardinality($3::bigint[]) = 0 OR o.sp_prefix_long = ANY($3::bigint[]))
         AND b.is_orphaned = FALSE
         ORDER BY o.block_height, o.txid, o.vout
         "#,
-        req.start_height,
-        req.end_height,
-        &prefix_ints
+        &txids,
+        &prefixes.short,
+        &prefixes.long
     )
     .fetch_all(&state.db)
     .await
     .map_err(|e| ApiError::Database(e.to_string()))?;
     
-    let candidates: Vec<OutputCandidate> = rows
+    let mut candidates: Vec<OutputCandidate> = rows
         .into_iter()
         .map(|r| OutputCandidate {
-            txid: r.txid,
+            txid: txid_to_hex(&r.txid),
             vout: r.vout,
-            amount: r.amount,
+            amount: Some(r.amount),
             script_pubkey: r.script_pubkey,
-            block_height: r.block_height,
-            block_hash: r.block_hash,
+            block_height: Some(r.block_height),
+            block_hash: Some(r.block_hash),
             timestamp: r.timestamp,
+            proof: None,
         })
         .collect();
     
-    let response = ScanResponse {
+    if req.include_proofs.unwrap_or(false) {
+        attach_proofs(&state.db, &mut candidates).await?;
+    }
+    redact_amounts(&mut candidates, &state.config);
+    
+    Ok((StatusCode::OK, Json(TxidScanResponse {
         candidates,
+        server_time_ms: start.elapsed().as_millis() as u64,
+    })))
+}
+
+/// Heights in `start..=end` whose prefix bloom filter may contain one of
+/// `prefixes`, plus the number of stored blocks ruled out. Blocks without a
+/// filter are always kept, so a block is only skipped when it provably has
+/// no matching output. Filters come from `bloom_cache` where possible; only
+/// the ones it lacks are read from the database.
+async fn heights_to_query(
+    db: &sqlx::PgPool,
+    bloom_cache: &BloomCache,
+    start_height: i32,
+    end_height: i32,
+    prefixes: &[i32],
+) -> Result<(Vec<i32>, usize), ApiError> {
+    let generation = bloom_cache.generation();
+    let stored: Vec<i32> = sqlx::query_scalar(
+        "SELECT height FROM blocks WHERE height BETWEEN $1 AND $2 ORDER BY height"
+    )
+    .bind(start_height)
+    .bind(end_height)
+    .fetch_all(db)
+    .await
+    .map_err(|e| ApiError::Database(e.to_string()))?;
+    
+    let (mut blocks, missing) = bloom_cache.get_many(&stored);
+    if !missing.is_empty() {
+        let loaded: Vec<(i32, Option<Vec<u8>>)> = sqlx::query_as(
+            "SELECT height, prefix_bloom FROM blocks WHERE height = ANY($1)"
+        )
+        .bind(&missing)
+        .fetch_all(db)
+        .await
+        .map_err(|e| ApiError::Database(e.to_string()))?;
+        for (height, bloom) in loaded {
+            let bloom = bloom.and_then(PrefixBloom::from_bytes);
+            bloom_cache.insert(height, bloom.clone(), generation);
+            blocks.push((height, bloom));
+        }
+    }
+    blocks.sort_unstable_by_key(|(height, _)| *height);
+    
+    let total = blocks.len();
+    let heights: Vec<i32> = blocks
+        .into_iter()
+        .filter_map(|(height, bloom)| {
+            let may_match = match bloom {
+                Some(bloom) => prefixes.iter().any(|&p| bloom.may_contain(prefix_from_db(p))),
+                None => true,
+            };
+            may_match.then_some(height)
+        })
+        .collect();
+    
+    let skipped = total - heights.len();
+    Ok((heights, skipped))
+}
+
+/// Batch scan: answers several scan requests (e.g. one per wallet account)
+/// with a single database round-trip. Every (request, prefix) pair is
+/// flattened into parallel arrays and joined against the outputs table, so
+/// each returned row is tagged with the index of the request it satisfies.
+/// Responses are returned in the same order as the requests.
+pub async fn scan_batch_handler(
+    State(state): State<AppState>,
+    Json(reqs): Json<Vec<ScanRequest>>,
+) -> Result<impl IntoResponse, ApiError> {
+    let start = std::time::Instant::now();
+    
+    if reqs.is_empty() {
+        return Err(ApiError::Validation(
+            "At least one scan request is required".into()
+        ));
+    }
+    
+    if reqs.len() > state.config.max_batch_size {
+        return Err(ApiError::Validation(format!(
+            "Too many scan requests in batch (max: {})",
+            state.config.max_batch_size
+        )));
+    }
+    
+    let mut parsed_prefixes = Vec::with_capacity(reqs.len());
+    let mut request_indices = Vec::new();
+    let mut start_heights = Vec::new();
+    let mut end_heights = Vec::new();
+    let mut prefixes = Vec::new();
+    let mut long_prefixes = Vec::new();
+    let mut min_amounts = Vec::new();
+    let mut max_amounts = Vec::new();
+    let mut unspent_only = Vec::new();
+    // Heights each request queries after its bloom filters
+    let mut height_requests = Vec::new();
+    let mut query_heights = Vec::new();
+    let mut skipped_blocks = Vec::with_capacity(reqs.len());
+    
+    for (i, req) in reqs.iter().enumerate() {
+        let mut parsed = parse_scan_request(req, &state.config)
+            .map_err(|e| match e {
+                ApiError::Validation(msg) => ApiError::Validation(format!("Request {}: {}", i, msg)),
+                other => other,
+            })?;
+        // Duplicate prefixes would otherwise produce duplicate rows
+        parsed.short.sort_unstable();
+        parsed.short.dedup();
+        parsed.long.sort_unstable();
+        parsed.long.dedup();
+        
+        // One row per prefix at the configured width (long prefixes carry
+        // their top 4 bytes for the index; short ones a placeholder)
+        let pairs: Vec<(i32, i64)> = if parsed.is_long() {
+            parsed.long.iter().map(|&l| ((l >> 32) as i32, l)).collect()
+        } else {
+            parsed.short.iter().map(|&s| (s, 0)).collect()
+        };
+        
+        let (heights, skipped) = heights_to_query(
+            &state.db,
+            &state.bloom_cache,
+            req.start_height,
+            req.end_height,
+            &parsed.short,
+        ).await?;
+        height_requests.extend(heights.iter().map(|_| i as i32));
+        query_heights.extend(heights);
+        skipped_blocks.push(skipped);
+        
+        let (min_amount, max_amount) = req.amount_range();
+        for (prefix, long_prefix) in pairs {
+            request_indices.push(i as i32);
+            start_heights.push(req.start_height);
+            end_heights.push(req.end_height);
+            prefixes.push(prefix);
+            long_prefixes.push(long_prefix);
+            min_amounts.push(min_amount);
+            max_amounts.push(max_amount);
+            unspent_only.push(req.unspent_only.unwrap_or(false));
+        }
+        parsed_prefixes.push(parsed);
+    }
+    
+    let rows = sqlx::query!(
+        r#"
+        SELECT 
+            r.request_index as "request_index!",
+            r.sp_prefix as "sp_prefix!",
+            o.sp_prefix_long as "sp_prefix_long!",
+            o.txid as "txid!",
+            o.vout as "vout!",
+            o.amount as "amount!",
+            encode(o.script_pubkey, 'hex') as "script_pubkey!",
+            o.block_height as "block_height!",
+            encode(b.hash, 'hex') as "block_hash!",
+            EXTRACT(EPOCH FROM b.created_at)::bigint as "timestamp!",
+            b.is_orphaned as "is_orphaned!"
+        FROM unnest($1::int[], $2::int[], $3::int[], $4::int[], $5::bigint[], $6::bigint[], $7::bigint[], $9::bool[])
+            AS r(request_index, start_height, end_height, sp_prefix, min_amount, max_amount, sp_prefix_long, unspent_only)
+        JOIN taproot_outputs o
+            ON o.sp_prefix = r.sp_prefix
+            AND (NOT $8 OR o.sp_prefix_long = r.sp_prefix_long)
+            AND o.block_height BETWEEN r.start_height AND r.end_height
+            AND o.amount BETWEEN r.min_amount AND r.max_amount
+            AND (NOT r.unspent_only OR o.spent_height IS NULL)
+        JOIN unnest($10::int[], $11::int[]) AS h(request_index, height)
+            ON h.request_index = r.request_index
+            AND h.height = o.block_height
+        JOIN blocks b ON b.height = o.block_height
+        ORDER BY r.request_index, o.block_height, o.txid, o.vout
+        "#,
+        &request_indices,
+        &start_heights,
+        &end_heights,
+        &prefixes,
+        &min_amounts,
+        &max_amounts,
+        &long_prefixes,
+        state.config.prefix_len == 8,
+        &unspent_only,
+        &height_requests,
+        &query_heights
+    )
+    .fetch_all(&state.db)
+    .await
+    .map_err(|e| ApiError::Database(e.to_string()))?;
+    
+    let mut candidates_per_request: Vec<Vec<OutputCandidate>> =
+        reqs.iter().map(|_| Vec::new()).collect();
+    let mut stats: Vec<(HashSet<i64>, usize)> =
+        reqs.iter().map(|_| (HashSet::new(), 0)).collect();
+    
+    for r in rows {
+        let i = r.request_index as usize;
+        let matched = if state.config.prefix_len == 8 { r.sp_prefix_long } else { r.sp_prefix as i64 };
+        stats[i].0.insert(matched);
+        stats[i].1 += 1;
+        if r.is_orphaned {
+            continue;
+        }
+        
+        candidates_per_request[i].push(OutputCandidate {
+            txid: txid_to_hex(&r.txid),
+            vout: r.vout,
+            amount: Some(r.amount),
+            script_pubkey: r.script_pubkey,
+            block_height: Some(r.block_height),
+            block_hash: Some(r.block_hash),
+            timestamp: r.timestamp,
+            proof: None,
+        });
+    }
+    
+    for ((req, candidates), parsed) in reqs
+        .iter()
+        .zip(candidates_per_request.iter_mut())
+        .zip(&parsed_prefixes)
+    {
+        if req.include_proofs.unwrap_or(false) {
+            attach_proofs(&state.db, candidates).await?;
+        }
+        if req.include_mempool.unwrap_or(false) {
+            let (min_amount, max_amount) = req.amount_range();
+            candidates.extend(
+                fetch_mempool_candidates(&state.db, parsed, min_amount, max_amount).await?
+            );
+        }
+        redact_amounts(candidates, &state.config);
+    }
+    
+    let server_time_ms = start.elapsed().as_millis() as u64;
+    let responses: Vec<ScanResponse> = reqs
+        .iter()
+        .zip(candidates_per_request)
+        .zip(stats)
+        .zip(skipped_blocks)
+        .map(|(((req, candidates), (matched, before_filter)), skipped_blocks)| ScanResponse {
+            candidates,
+            scanned_blocks: (req.start_height..=req.end_height).collect(),
+            server_time_ms,
+            matched_prefixes: matched.len(),
+            candidates_before_filter: before_filter,
+            skipped_blocks,
+        })
+        .collect();
+    
+    Ok((StatusCode::OK, Json(responses)))
+}
+
+/// Full-scan request: the server derives outputs itself, so the client
+/// must hand over its scan secret. Only served when `allow_full_scan` is set.
+#[derive(Debug, Clone, Deserialize)]
+pub struct FullScanRequest {
+    /// Hex-encoded 32-byte scan secret key
+    pub scan_secret: String,
+    /// Hex-encoded 33-byte compressed spend public key
+    pub spend_pubkey: String,
+    pub start_height: i32,
+    pub end_height: i32,
+    /// Labels 1..=max_label are checked in addition to the unlabeled output;
+    /// at most the server's `max_full_scan_label`
+    pub max_label: Option<u32>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FullScanMatch {
+    #[serde(flatten)]
+    pub output: OutputCandidate,
+    pub label: Option<u32>,
+    /// Hex tweak to add to the spend secret to spend this output
+    pub tweak: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FullScanResponse {
+    pub matches: Vec<FullScanMatch>,
+    pub scanned_blocks: Vec<i32>,
+    pub server_time_ms: u64,
+    /// Transactions in range with tweak data that were checked
+    pub transactions_checked: usize,
+}
+
+/// Server-side scan: derives the expected outputs for every indexed
+/// transaction with tweak data in range and returns only real matches,
+/// rather than prefix candidates. Transactions whose prevouts weren't
+/// indexed have no tweak data and are not covered.
+pub async fn full_scan_handler(
+    State(state): State<AppState>,
+    Json(req): Json<FullScanRequest>,
+) -> Result<impl IntoResponse, ApiError> {
+    let start = std::time::Instant::now();
+    record_scan_range(req.start_height, req.end_height);
+    
+    if !state.config.allow_full_scan {
+        return Err(ApiError::Forbidden(
+            "Full scan is disabled on this server".into()
+        ));
+    }
+    
+    validate_height_range(req.start_height, req.end_height, &state.config)?;
+    
+    let scan_key = hex::decode(&req.scan_secret)
+        .ok()
+        .and_then(|bytes| whisper_core::ScanKey::from_slice(&bytes).ok())
+        .ok_or_else(|| ApiError::Validation(
+            "scan_secret must be a 32-byte hex secret key".into()
+        ))?;
+    let spend_pubkey = hex::decode(&req.spend_pubkey)
+        .ok()
+        .and_then(|bytes| bitcoin::secp256k1::PublicKey::from_slice(&bytes).ok())
+        .ok_or_else(|| ApiError::Validation(
+            "spend_pubkey must be a 33-byte hex compressed public key".into()
+        ))?;
+    let max_label = req.max_label.unwrap_or(0);
+    if max_label > state.config.max_full_scan_label {
+        return Err(ApiError::Validation(format!(
+            "max_label must be at most {}",
+            state.config.max_full_scan_label
+        )));
+    }
+    let labels: Vec<Option<u32>> = std::iter::once(None)
+        .chain((1..=max_label).map(Some))
+        .collect();
+    
+    let rows = sqlx::query!(
+        r#"
+        SELECT 
+            o.txid as "txid!",
+            o.vout as "vout!",
+            o.amount as "amount!",
+            o.script_pubkey as "script_pubkey!",
+            o.x_only_pubkey as "x_only_pubkey!",
+            o.block_height as "block_height!",
+            encode(b.hash, 'hex') as "block_hash!",
+            EXTRACT(EPOCH FROM b.created_at)::bigint as "timestamp!",
+            t.tweak as "tweak!"
+        FROM tx_tweaks t
+        JOIN taproot_outputs o ON o.txid = t.txid
+        JOIN blocks b ON b.height = o.block_height
+        WHERE t.block_height BETWEEN $1 AND $2
+        AND b.is_orphaned = FALSE
+        ORDER BY o.block_height, o.txid, o.vout
+        "#,
+        req.start_height,
+        req.end_height
+    )
+    .fetch_all(&state.db)
+    .await
+    .map_err(|e| ApiError::Database(e.to_string()))?;
+    
+    let mut matches = Vec::new();
+    let mut transactions_checked = 0;
+    
+    // Rows are ordered by txid within a block, so each tx's outputs are
+    // adjacent: its shared secret is computed once, then its outputs are
+    // matched for k = 0, 1, ... as a sender numbers them
+    for tx_rows in rows.chunk_by(|a, b| a.txid == b.txid) {
+        transactions_checked += 1;
+        let shared_secret = bitcoin::secp256k1::PublicKey::from_slice(&tx_rows[0].tweak)
+            .map_err(|e| e.to_string())
+            .and_then(|tweak| scan_key.shared_secret_from_tweak(&tweak).map_err(|e| e.to_string()))
+            .map_err(|e| ApiError::Database(format!("Invalid tweak data: {}", e)))?;
+        
+        // Outputs whose key isn't a valid point can't be ours
+        let outputs: Vec<(&_, bitcoin::secp256k1::XOnlyPublicKey)> = tx_rows
+            .iter()
+            .filter_map(|r| Some((r, bitcoin::secp256k1::XOnlyPublicKey::from_slice(&r.x_only_pubkey).ok()?)))
+            .collect();
+        let keys: Vec<_> = outputs.iter().map(|&(_, key)| key).collect();
+        let found = scan_key.scan_output_keys(&shared_secret, &keys, &spend_pubkey, &labels)
+            .map_err(|e| ApiError::Database(format!("Invalid tweak data: {}", e)))?;
+        
+        for (i, m) in found {
+            let r = outputs[i].0;
+            matches.push(FullScanMatch {
+                output: OutputCandidate {
+                    txid: txid_to_hex(&r.txid),
+                    vout: r.vout,
+                    amount: Some(r.amount),
+                    script_pubkey: hex::encode(&r.script_pubkey),
+                    block_height: Some(r.block_height),
+                    block_hash: Some(r.block_hash.clone()),
+                    timestamp: r.timestamp,
+                    proof: None,
+                },
+                label: m.label,
+                tweak: hex::encode(m.tweak),
+            });
+        }
+    }
+    
+    let response = FullScanResponse {
+        matches,
         scanned_blocks: (req.start_height..=req.end_height).collect(),
         server_time_ms: start.elapsed().as_millis() as u64,
+        transactions_checked,
+    };
+    
+    Ok((StatusCode::OK, Json(response)))
+}
+
+
+#[derive(Debug, Deserialize)]
+pub struct SyncQuery {
+    /// First block height to return (inclusive)
+    pub since_height: i32,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SyncOutput {
+    pub vout: i32,
+    /// Omitted when the server hides amounts (`reveal_amounts`)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub amount: Option<i64>,
+    /// Hex x-only output key
+    pub pubkey: String,
+}
+
+/// Tweak data of one transaction: its tweak point and the taproot outputs
+/// a wallet should check against it.
+#[derive(Debug, Serialize)]
+pub struct SyncTransaction {
+    pub txid: String,
+    pub block_height: i32,
+    pub block_hash: String,
+    /// Hex 33-byte compressed tweak point input_hash * A, as passed to
+    /// `ScanKey::shared_secret_from_tweak`
+    pub tweak: String,
+    pub outputs: Vec<SyncOutput>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SyncResponse {
+    pub transactions: Vec<SyncTransaction>,
+    /// Heights covered by this page
+    pub start_height: i32,
+    pub end_height: i32,
+    /// `since_height` for the next page, `None` once the indexed tip is reached
+    pub next_since_height: Option<i32>,
+    pub server_time_ms: u64,
+}
+
+/// Tweak data for every indexed transaction from `since_height` on, in
+/// height order, so a wallet can scan locally without sending prefixes or
+/// keys. Pages cover at most `max_block_range + 1` blocks; follow
+/// `next_since_height` until it is `None`. As with full scans, transactions
+/// whose prevouts weren't indexed have no tweak data and are left out.
+/// Output amounts are omitted unless the server reveals them, as for scan
+/// candidates.
+pub async fn sync_handler(
+    State(state): State<AppState>,
+    Query(query): Query<SyncQuery>,
+) -> Result<impl IntoResponse, ApiError> {
+    let start = std::time::Instant::now();
+    
+    if query.since_height < 0 {
+        return Err(ApiError::Validation("since_height must be non-negative".into()));
+    }
+    
+    let tip: Option<i32> = sqlx::query_scalar(
+        "SELECT MAX(height) FROM blocks WHERE is_orphaned = FALSE"
+    )
+    .fetch_one(&state.db)
+    .await
+    .map_err(|e| ApiError::Database(e.to_string()))?;
+    
+    let start_height = query.since_height;
+    let end_height = start_height
+        .saturating_add(state.config.max_block_range)
+        .min(tip.unwrap_or(-1).max(start_height));
+    record_scan_range(start_height, end_height);
+    
+    let rows = sqlx::query!(
+        r#"
+        SELECT 
+            t.txid as "txid!",
+            t.block_height,
+            encode(b.hash, 'hex') as "block_hash!",
+            encode(t.tweak, 'hex') as "tweak!",
+            o.vout,
+            o.amount,
+            encode(o.x_only_pubkey, 'hex') as "pubkey!"
+        FROM tx_tweaks t
+        JOIN taproot_outputs o ON o.txid = t.txid
+        JOIN blocks b ON b.height = t.block_height
+        WHERE t.block_height BETWEEN $1 AND $2
+        AND b.is_orphaned = FALSE
+        ORDER BY t.block_height, t.txid, o.vout
+        "#,
+        start_height,
+        end_height
+    )
+    .fetch_all(&state.db)
+    .await
+    .map_err(|e| ApiError::Database(e.to_string()))?;
+    
+    // Rows are ordered by txid within a block, so each tx's outputs are
+    // adjacent
+    let mut transactions: Vec<SyncTransaction> = Vec::new();
+    for r in rows {
+        let output = SyncOutput {
+            vout: r.vout,
+            amount: state.config.reveal_amounts.then_some(r.amount),
+            pubkey: r.pubkey,
+        };
+        let txid = txid_to_hex(&r.txid);
+        match transactions.last_mut() {
+            Some(tx) if tx.txid == txid => tx.outputs.push(output),
+            _ => transactions.push(SyncTransaction {
+                txid,
+                block_height: r.block_height,
+                block_hash: r.block_hash,
+                tweak: r.tweak,
+                outputs: vec![output],
+            }),
+        }
+    }
+    
+    let response = SyncResponse {
+        transactions,
+        start_height,
+        end_height,
+        next_since_height: tip.filter(|&tip| tip > end_height).map(|_| end_height + 1),
+        server_time_ms: start.elapsed().as_millis() as u64,
     };
     
     Ok((StatusCode::OK, Json(response)))
 }
 
+/// Unconfirmed outputs matching any of the prefixes within the amount range.
+async fn fetch_mempool_candidates(
+    db: &sqlx::PgPool,
+    prefixes: &ParsedPrefixes,
+    min_amount: i64,
+    max_amount: i64,
+) -> Result<Vec<OutputCandidate>, ApiError> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT 
+            txid as "txid!",
+            vout,
+            amount,
+            encode(script_pubkey, 'hex') as "script_pubkey!",
+            EXTRACT(EPOCH FROM first_seen)::bigint as "timestamp!"
+        FROM mempool_outputs
+        WHERE sp_prefix = ANY($1::int[])
+        AND (cardinality($4::bigint[]) = 0 OR sp_prefix_long = ANY($4::bigint[]))
+        AND amount BETWEEN $2 AND $3
+        ORDER BY first_seen, txid, vout
+        "#,
+        &prefixes.short,
+        min_amount,
+        max_amount,
+        &prefixes.long
+    )
+    .fetch_all(db)
+    .await
+    .map_err(|e| ApiError::Database(e.to_string()))?;
+    
+    Ok(rows
+        .into_iter()
+        .map(|r| OutputCandidate {
+            txid: txid_to_hex(&r.txid),
+            vout: r.vout,
+            amount: Some(r.amount),
+            script_pubkey: r.script_pubkey,
+            block_height: None,
+            block_hash: None,
+            timestamp: r.timestamp,
+            proof: None,
+        })
+        .collect())
+}
+
+/// Fill in a merkle inclusion proof for every candidate. The block's txids
+/// are loaded once per distinct height.
+async fn attach_proofs(
+    db: &sqlx::PgPool,
+    candidates: &mut [OutputCandidate],
+) -> Result<(), ApiError> {
+    let mut blocks: HashMap<i32, (Vec<u8>, Vec<[u8; 32]>)> = HashMap::new();
+    
+    for candidate in candidates.iter_mut() {
+        // Unconfirmed outputs have no block to prove inclusion in
+        let Some(height) = candidate.block_height else {
+            continue;
+        };
+        
+        if let Entry::Vacant(entry) = blocks.entry(height) {
+            let header: (Vec<u8>,) = sqlx::query_as(
+                "SELECT header FROM blocks WHERE height = $1"
+            )
+            .bind(height)
+            .fetch_one(db)
+            .await
+            .map_err(|e| ApiError::Database(e.to_string()))?;
+            
+            let txids: Vec<(Vec<u8>,)> = sqlx::query_as(
+                "SELECT txid FROM transactions WHERE block_height = $1 ORDER BY block_index"
+            )
+            .bind(height)
+            .fetch_all(db)
+            .await
+            .map_err(|e| ApiError::Database(e.to_string()))?;
+            
+            let txids = txids
+                .into_iter()
+                .filter_map(|(t,)| <[u8; 32]>::try_from(t.as_slice()).ok())
+                .collect();
+            entry.insert((header.0, txids));
+        }
+        
+        let (header, txids) = &blocks[&height];
+        let txid = txid_from_hex(&candidate.txid)
+            .ok_or_else(|| ApiError::Database(format!("Invalid txid {}", candidate.txid)))?;
+        let Some(tx_index) = txids.iter().position(|t| *t == txid) else {
+            return Err(ApiError::Database(format!(
+                "Transaction {} missing from block {}",
+                candidate.txid, height
+            )));
+        };
+        
+        candidate.proof = Some(MerkleProof {
+            block_header: hex::encode(header),
+            tx_index: tx_index as u32,
+            branch: whisper_core::merkle_branch(txids, tx_index)
+                .iter()
+                .map(hex::encode)
+                .collect(),
+        });
+    }
+    
+    Ok(())
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct BlockQuery {
+    pub include_orphans: Option<bool>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BlockHeaderResponse {
+    pub height: i32,
+    pub hash: String,
+    pub header: String,
+    pub timestamp: i64,
+    pub is_orphaned: bool,
+}
+
+/// Stored header for a block height. Orphaned blocks are only returned
+/// with `?include_orphans=true`.
+pub async fn block_handler(
+    State(state): State<AppState>,
+    Path(height): Path<i32>,
+    Query(query): Query<BlockQuery>,
+) -> Result<impl IntoResponse, ApiError> {
+    let row = sqlx::query!(
+        r#"
+        SELECT 
+            height,
+            encode(hash, 'hex') as "hash!",
+            encode(header, 'hex') as "header!",
+            EXTRACT(EPOCH FROM created_at)::bigint as "timestamp!",
+            COALESCE(is_orphaned, FALSE) as "is_orphaned!"
+        FROM blocks
+        WHERE height = $1
+        "#,
+        height
+    )
+    .fetch_optional(&state.db)
+    .await
+    .map_err(|e| ApiError::Database(e.to_string()))?;
+    
+    let block = match row {
+        Some(r) if !r.is_orphaned || query.include_orphans.unwrap_or(false) => r,
+        _ => return Err(ApiError::NotFound(format!("No block at height {}", height))),
+    };
+    
+    Ok(Json(BlockHeaderResponse {
+        height: block.height,
+        hash: block.hash,
+        header: block.header,
+        timestamp: block.timestamp,
+        is_orphaned: block.is_orphaned,
+    }))
+}
+
+/// Most blocks `/api/v1/blocks` returns at once.
+pub const MAX_BLOCKS_LIMIT: i64 = 100;
+
+#[derive(Debug, Default, Deserialize)]
+pub struct BlocksQuery {
+    /// Number of blocks to return (default 10, at most `MAX_BLOCKS_LIMIT`)
+    pub limit: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BlockSummary {
+    pub height: i32,
+    pub hash: String,
+    pub timestamp: i64,
+    /// Taproot outputs indexed in the block
+    pub taproot_outputs: i64,
+}
+
+/// The most recently indexed non-orphaned blocks, highest first, so clients
+/// can spot new blocks and show sync progress cheaply.
+pub async fn blocks_handler(
+    State(state): State<AppState>,
+    Query(query): Query<BlocksQuery>,
+) -> Result<impl IntoResponse, ApiError> {
+    let limit = query.limit.unwrap_or(10);
+    if !(1..=MAX_BLOCKS_LIMIT).contains(&limit) {
+        return Err(ApiError::Validation(format!(
+            "limit must be between 1 and {}", MAX_BLOCKS_LIMIT
+        )));
+    }
+    
+    let blocks = sqlx::query!(
+        r#"
+        SELECT 
+            b.height,
+            encode(b.hash, 'hex') as "hash!",
+            EXTRACT(EPOCH FROM b.created_at)::bigint as "timestamp!",
+            (SELECT COUNT(*) FROM taproot_outputs o WHERE o.block_height = b.height) as "taproot_outputs!"
+        FROM blocks b
+        WHERE b.is_orphaned = FALSE
+        ORDER BY b.height DESC
+        LIMIT $1
+        "#,
+        limit
+    )
+    .fetch_all(&state.db)
+    .await
+    .map_err(|e| ApiError::Database(e.to_string()))?
+    .into_iter()
+    .map(|r| BlockSummary {
+        height: r.height,
+        hash: r.hash,
+        timestamp: r.timestamp,
+        taproot_outputs: r.taproot_outputs,
+    })
+    .collect::<Vec<_>>();
+    
+    Ok(Json(blocks))
+}
+
+#[derive(Debug, Serialize)]
+pub struct TransactionResponse {
+    pub txid: String,
+    pub raw_tx: String,
+    pub block_height: i32,
+    /// Hash of the block containing the transaction
+    pub block_hash: String,
+    /// Position of the transaction within its block
+    pub block_index: i32,
+    pub is_orphaned: bool,
+}
+
+/// Stored raw transaction, so clients can check what the index reported
+/// against the transaction itself. `txid` is hex in display byte order,
+/// as in scan candidates. Like `block_handler`, transactions in orphaned
+/// blocks are hidden unless `?include_orphans=true` is given.
+pub async fn tx_handler(
+    State(state): State<AppState>,
+    Path(txid): Path<String>,
+    Query(query): Query<BlockQuery>,
+) -> Result<impl IntoResponse, ApiError> {
+    let txid_bytes = txid_from_hex(&txid)
+        .ok_or_else(|| ApiError::Validation("txid must be 32 bytes of hex".into()))?;
+    
+    let row = sqlx::query!(
+        r#"
+        SELECT 
+            encode(t.raw_tx, 'hex') as "raw_tx!",
+            t.block_height,
+            encode(b.hash, 'hex') as "block_hash!",
+            t.block_index,
+            COALESCE(b.is_orphaned, FALSE) as "is_orphaned!"
+        FROM transactions t
+        JOIN blocks b ON b.height = t.block_height
+        WHERE t.txid = $1
+        "#,
+        &txid_bytes[..]
+    )
+    .fetch_optional(&state.db)
+    .await
+    .map_err(|e| ApiError::Database(e.to_string()))?;
+    
+    let tx = match row {
+        Some(r) if !r.is_orphaned || query.include_orphans.unwrap_or(false) => r,
+        _ => return Err(ApiError::NotFound(format!("Unknown transaction {}", txid))),
+    };
+    
+    Ok(Json(TransactionResponse {
+        txid: txid.to_ascii_lowercase(),
+        raw_tx: tx.raw_tx,
+        block_height: tx.block_height,
+        block_hash: tx.block_hash,
+        block_index: tx.block_index,
+        is_orphaned: tx.is_orphaned,
+    }))
+}
+
+/// How long a `/api/v1/stats` result is reused before querying again.
+const STATS_CACHE_TTL: std::time::Duration = std::time::Duration::from_secs(10);
+
+/// Index size counters for capacity planning and debugging.
+#[derive(Debug, Clone, Serialize)]
+pub struct IndexStats {
+    /// Highest non-orphaned block, `None` while the index is empty
+    pub indexed_height: Option<i32>,
+    pub total_blocks: i64,
+    pub total_transactions: i64,
+    pub total_outputs: i64,
+    /// Transactions with eligible-input tweak data (usable by full scans)
+    pub eligible_input_transactions: i64,
+}
+
+pub async fn stats_handler(State(state): State<AppState>) -> Result<impl IntoResponse, ApiError> {
+    // Held across the query so concurrent requests share one refresh
+    let mut cache = state.stats_cache.lock().await;
+    if let Some((computed_at, stats)) = cache.as_ref() {
+        if computed_at.elapsed() < STATS_CACHE_TTL {
+            return Ok(Json(stats.clone()));
+        }
+    }
+    
+    let (indexed_height, total_blocks, total_transactions, total_outputs, eligible_input_transactions):
+        (Option<i32>, i64, i64, i64, i64) = sqlx::query_as(
+        "SELECT
+            (SELECT MAX(height) FROM blocks WHERE is_orphaned = FALSE),
+            (SELECT COUNT(*) FROM blocks WHERE is_orphaned = FALSE),
+            (SELECT COUNT(*) FROM transactions),
+            (SELECT COUNT(*) FROM taproot_outputs),
+            (SELECT COUNT(*) FROM tx_tweaks)"
+    )
+    .fetch_one(&state.db)
+    .await
+    .map_err(|e| ApiError::Database(e.to_string()))?;
+    
+    let stats = IndexStats {
+        indexed_height,
+        total_blocks,
+        total_transactions,
+        total_outputs,
+        eligible_input_transactions,
+    };
+    *cache = Some((std::time::Instant::now(), stats.clone()));
+    
+    Ok(Json(stats))
+}
+
+/// Liveness probe: answers as long as the process is serving requests.
+pub async fn healthz_handler() -> impl IntoResponse {
+    (StatusCode::OK, Json(serde_json::json!({ "status": "ok" })))
+}
+
+/// Readiness probe: 200 only when the database answers and the indexer is
+/// within `READY_MAX_LAG_BLOCKS` of the node tip, otherwise 503. The tip is
+/// asked of the node when the indexer hasn't seen one yet; while it stays
+/// unknown the server is not ready, since the lag can't be judged.
+pub async fn readyz_handler(State(state): State<AppState>) -> impl IntoResponse {
+    let db_ok = sqlx::query("SELECT 1").execute(&state.db).await.is_ok();
+    
+    let progress = &state.indexer_progress;
+    if progress.node_tip().is_none() {
+        // Records the tip in `progress` when the node answers
+        node_tip(&state).await;
+    }
+    let lag = progress.lag();
+    let synced = matches!(lag, Some(lag) if lag <= state.config.ready_max_lag_blocks);
+    let ready = db_ok && synced;
+    
+    let code = if ready { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE };
+    (code, Json(serde_json::json!({
+        "status": if ready { "ready" } else { "not_ready" },
+        "database": db_ok,
+        "indexed_height": progress.indexed_height(),
+        "node_tip": progress.node_tip(),
+        "lag_blocks": lag,
+    })))
+}
+
+/// How long a block count fetched from the node is reused by `/api/v1/status`.
+const NODE_TIP_CACHE_TTL: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// The node's block height: `getblockcount` over RPC, cached for
+/// `NODE_TIP_CACHE_TTL`, or the highest tip the indexer has seen when the
+/// node can't be asked. Failed calls are cached for the TTL too, so an
+/// unreachable node isn't asked on every request.
+async fn node_tip(state: &AppState) -> Option<i64> {
+    if let Some(rpc) = &state.node_rpc {
+        {
+            let mut cache = state.node_tip_cache.lock().unwrap();
+            match *cache {
+                Some((fetched_at, tip)) if fetched_at.elapsed() < NODE_TIP_CACHE_TTL => {
+                    if let Some(tip) = tip {
+                        state.indexer_progress.record_tip(tip as i32);
+                    }
+                    return tip.or_else(|| state.indexer_progress.node_tip());
+                }
+                // Claim the refresh: until it finishes, concurrent requests
+                // see the previous value as fresh instead of calling too
+                previous => *cache = Some((std::time::Instant::now(), previous.and_then(|(_, tip)| tip))),
+            }
+        }
+        
+        let client = Arc::clone(rpc);
+        let tip = match tokio::task::spawn_blocking(move || client.get_block_count()).await {
+            Ok(Ok(count)) => Some(count as i64),
+            Ok(Err(e)) => {
+                tracing::warn!("getblockcount failed: {}", e);
+                None
+            }
+            Err(e) => {
+                tracing::warn!("getblockcount task failed: {}", e);
+                None
+            }
+        };
+        *state.node_tip_cache.lock().unwrap() = Some((std::time::Instant::now(), tip));
+        if let Some(tip) = tip {
+            state.indexer_progress.record_tip(tip as i32);
+            return Some(tip);
+        }
+    }
+    state.indexer_progress.node_tip()
+}
+
 /// Enhanced status endpoint with richer diagnostics
 pub async fn status_handler(State(state): State<AppState>) -> impl IntoResponse {
     let tip: Option<(Option<i32>,)> = sqlx::query_as(
@@ -200,13 +1491,22 @@ pub async fn status_handler(State(state): State<AppState>) -> impl IntoResponse
     
     let uptime_secs = state.started_at.elapsed().as_secs();
     
+    // Distinguishes a current index from a stalled one; `tip_height` alone can't
+    let node_tip = node_tip(&state).await;
+    let blocks_behind = node_tip.map(|tip| (tip - tip_height as i64).max(0));
+    
     Json(serde_json::json!({
         "status": "ok",
         "version": env!("CARGO_PKG_VERSION"),
         "tip_height": tip_height,
+        "node_tip": node_tip,
+        "blocks_behind": blocks_behind,
         "total_outputs": output_count,
         "total_blocks": block_count,
-        "network": state.config.network,
+        "network": state.config.network.to_string(),
         "uptime_seconds": uptime_secs,
+        "max_block_range": state.config.max_block_range,
+        "max_prefixes": state.config.max_prefixes,
+        "prefix_len": state.config.prefix_len,
     }))
 }
@@ -0,0 +1,238 @@
+use crate::indexer::IndexerError;
+#[cfg(feature = "zmq")]
+use crate::indexer::process_mempool_tx;
+use bitcoin::{Block, BlockHash};
+use bitcoincore_rpc::RpcApi;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// What one `BlockSource::next_block` call produced.
+#[derive(Debug)]
+pub(crate) enum SourceEvent {
+    /// A newly announced block, normally the node's tip
+    Block(Block),
+    /// Nothing new within the source's wait (receive timeout, poll
+    /// interval), or only mempool traffic; the indexer checks for shutdown
+    /// and asks again
+    Idle,
+    /// Block notifications were dropped; the indexer catches up to the
+    /// node's tip over RPC before asking again
+    #[cfg_attr(not(feature = "zmq"), allow(dead_code))]
+    MissedBlocks,
+    /// The source has nothing more to deliver
+    #[cfg_attr(not(feature = "zmq"), allow(dead_code))]
+    Closed,
+}
+
+/// Future returned by `BlockSource::next_block`, boxed so sources can be
+/// used as trait objects.
+pub(crate) type NextBlock<'a> = Pin<Box<dyn Future<Output = Result<SourceEvent, IndexerError>> + Send + 'a>>;
+
+/// Where the indexer learns about new blocks. The indexer only needs the
+/// latest block: `handle_block` fetches any ancestors it hasn't indexed
+/// over RPC and rolls back blocks a reorg replaced, so a source may skip
+/// blocks or switch branches.
+pub(crate) trait BlockSource: Send {
+    fn next_block(&mut self) -> NextBlock<'_>;
+}
+
+/// The node RPC calls `RpcPollingSource` makes, abstracted so polling can
+/// be exercised without a node.
+pub(crate) trait TipRpc: Send + Sync {
+    fn best_block_hash(&self) -> impl Future<Output = Result<BlockHash, IndexerError>> + Send;
+    fn block(&self, hash: BlockHash) -> impl Future<Output = Result<Block, IndexerError>> + Send;
+}
+
+impl TipRpc for Arc<bitcoincore_rpc::Client> {
+    async fn best_block_hash(&self) -> Result<BlockHash, IndexerError> {
+        let client = Arc::clone(self);
+        Ok(tokio::task::spawn_blocking(move || client.get_best_block_hash()).await??)
+    }
+    
+    async fn block(&self, hash: BlockHash) -> Result<Block, IndexerError> {
+        let client = Arc::clone(self);
+        Ok(tokio::task::spawn_blocking(move || client.get_block(&hash)).await??)
+    }
+}
+
+/// Pure-Rust block source for builds without libzmq: asks the node for its
+/// best block hash every `interval` and delivers the tip whenever it
+/// changes. Mempool transactions aren't seen this way.
+pub(crate) struct RpcPollingSource<R> {
+    rpc: R,
+    interval: Duration,
+    last_tip: Option<BlockHash>,
+}
+
+impl<R: TipRpc> RpcPollingSource<R> {
+    pub(crate) fn new(rpc: R, interval: Duration) -> Self {
+        Self { rpc, interval, last_tip: None }
+    }
+}
+
+impl<R: TipRpc> BlockSource for RpcPollingSource<R> {
+    fn next_block(&mut self) -> NextBlock<'_> {
+        Box::pin(async move {
+            let tip = self.rpc.best_block_hash().await?;
+            if self.last_tip == Some(tip) {
+                tokio::time::sleep(self.interval).await;
+                return Ok(SourceEvent::Idle);
+            }
+            
+            let block = self.rpc.block(tip).await?;
+            self.last_tip = Some(tip);
+            Ok(SourceEvent::Block(block))
+        })
+    }
+}
+
+/// Source of raw ZMQ notifications, abstracted so the reconnect logic can
+/// be exercised without a node.
+#[cfg(feature = "zmq")]
+pub(crate) trait NotificationSource {
+    /// Next multipart message, or `None` once the source is closed.
+    fn recv(&mut self) -> Result<Option<Vec<Vec<u8>>>, zmq::Error>;
+}
+
+#[cfg(feature = "zmq")]
+pub(crate) struct ZmqSource {
+    _ctx: zmq::Context,
+    socket: zmq::Socket,
+}
+
+#[cfg(feature = "zmq")]
+impl ZmqSource {
+    pub(crate) fn connect(endpoint: &str, rcvhwm: i32, recv_timeout_ms: i32) -> Result<Self, IndexerError> {
+        let ctx = zmq::Context::new();
+        let socket = ctx.socket(zmq::SUB)?;
+        // Options must be set before connecting to apply to the connection
+        socket.set_rcvhwm(rcvhwm)?;
+        // Receives time out so the loop can check for shutdown
+        socket.set_rcvtimeo(recv_timeout_ms)?;
+        socket.connect(endpoint)?;
+        socket.set_subscribe(b"rawblock")?;
+        socket.set_subscribe(b"rawtx")?;
+        
+        tracing::info!("Connected to ZMQ: {} (rcvhwm {}, timeout {}ms)", endpoint, rcvhwm, recv_timeout_ms);
+        Ok(Self { _ctx: ctx, socket })
+    }
+}
+
+#[cfg(feature = "zmq")]
+impl NotificationSource for ZmqSource {
+    fn recv(&mut self) -> Result<Option<Vec<Vec<u8>>>, zmq::Error> {
+        self.socket.recv_multipart(0).map(Some)
+    }
+}
+
+/// Last ZMQ sequence number seen per topic. bitcoind numbers each topic's
+/// notifications (third message frame, little-endian u32), so a jump means
+/// messages were dropped, e.g. at the receive high-water mark.
+#[cfg(feature = "zmq")]
+#[derive(Debug, Default)]
+pub(crate) struct SequenceTracker(std::collections::HashMap<Vec<u8>, u32>);
+
+#[cfg(feature = "zmq")]
+impl SequenceTracker {
+    /// Record `msg`'s sequence number and return how many notifications on
+    /// its topic were missed since the previous one. A lower number (node
+    /// restart) or a message without one counts as no gap.
+    pub(crate) fn missed(&mut self, msg: &[Vec<u8>]) -> u32 {
+        let Some(seq) = msg.get(2)
+            .and_then(|frame| <[u8; 4]>::try_from(frame.as_slice()).ok())
+            .map(u32::from_le_bytes)
+        else {
+            return 0;
+        };
+        
+        match self.0.insert(msg[0].clone(), seq) {
+            Some(prev) if seq > prev => seq - prev - 1,
+            _ => 0,
+        }
+    }
+}
+
+/// Blocks from bitcoind's `rawblock` notifications. Each `rawtx`
+/// notification on the same socket is indexed into the mempool tables and
+/// reported as `Idle`, so a busy mempool doesn't hold off shutdown. A gap
+/// in `rawblock` sequence numbers is reported as `MissedBlocks` before the
+/// block that revealed it is delivered.
+#[cfg(feature = "zmq")]
+pub(crate) struct ZmqBlockSource<S> {
+    notifications: S,
+    db: sqlx::PgPool,
+    sequences: SequenceTracker,
+    /// Block held back while `MissedBlocks` is handled
+    held: Option<Block>,
+}
+
+#[cfg(feature = "zmq")]
+impl<S: NotificationSource> ZmqBlockSource<S> {
+    pub(crate) fn new(notifications: S, db: sqlx::PgPool) -> Self {
+        Self { notifications, db, sequences: SequenceTracker::default(), held: None }
+    }
+}
+
+#[cfg(feature = "zmq")]
+impl<S: NotificationSource + Send> BlockSource for ZmqBlockSource<S> {
+    fn next_block(&mut self) -> NextBlock<'_> {
+        Box::pin(async move {
+            use bitcoin::consensus::Decodable;
+            
+            if let Some(block) = self.held.take() {
+                return Ok(SourceEvent::Block(block));
+            }
+            
+            loop {
+                let msg = match self.notifications.recv() {
+                    Ok(Some(msg)) => msg,
+                    Ok(None) => return Ok(SourceEvent::Closed),
+                    // Receive timeout: nothing announced recently, which is normal
+                    Err(zmq::Error::EAGAIN) => return Ok(SourceEvent::Idle),
+                    Err(e) => {
+                        tracing::error!("ZMQ receive error: {}", e);
+                        return Err(IndexerError::Zmq(e));
+                    }
+                };
+                
+                if msg.len() < 2 {
+                    continue;
+                }
+                
+                let topic = String::from_utf8_lossy(&msg[0]);
+                let missed = self.sequences.missed(&msg);
+                if missed > 0 {
+                    // Dropped blocks are refetched over RPC; dropped mempool
+                    // transactions are picked up once they confirm
+                    tracing::warn!("Missed {} ZMQ {} notification(s); consider raising ZMQ_RCVHWM", missed, topic);
+                }
+                
+                if topic == "rawblock" {
+                    match Block::consensus_decode(&mut std::io::Cursor::new(&msg[1])) {
+                        Ok(block) if missed > 0 => {
+                            self.held = Some(block);
+                            return Ok(SourceEvent::MissedBlocks);
+                        }
+                        Ok(block) => return Ok(SourceEvent::Block(block)),
+                        Err(e) => tracing::error!("Failed to decode block: {}", e),
+                    }
+                    if missed > 0 {
+                        return Ok(SourceEvent::MissedBlocks);
+                    }
+                } else if topic == "rawtx" {
+                    match bitcoin::Transaction::consensus_decode(&mut std::io::Cursor::new(&msg[1])) {
+                        Ok(tx) => {
+                            if let Err(e) = process_mempool_tx(&self.db, &tx).await {
+                                tracing::error!("Failed to process mempool tx: {}", e);
+                            }
+                        }
+                        Err(e) => tracing::error!("Failed to decode transaction: {}", e),
+                    }
+                    return Ok(SourceEvent::Idle);
+                }
+            }
+        })
+    }
+}
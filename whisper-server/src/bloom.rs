@@ -0,0 +1,147 @@
+//! Per-block bloom filter over the 4-byte prefixes of its taproot outputs.
+//!
+//! Filters are stored in `blocks.prefix_bloom`, so the bit layout and hash
+//! positions below must not change once blocks have been indexed.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+
+/// Filter bits per inserted prefix (~1% false positives with 7 hashes).
+const BITS_PER_PREFIX: usize = 10;
+/// Number of bit positions set per prefix.
+const HASHES: u64 = 7;
+/// Smallest filter, used for blocks with few or no taproot outputs.
+const MIN_BYTES: usize = 8;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PrefixBloom {
+    bits: Vec<u8>,
+}
+
+impl PrefixBloom {
+    /// Build a filter sized for `prefixes`.
+    pub fn from_prefixes(prefixes: &[u32]) -> Self {
+        let len = (prefixes.len() * BITS_PER_PREFIX).div_ceil(8).max(MIN_BYTES);
+        let mut bloom = Self { bits: vec![0u8; len] };
+        for &prefix in prefixes {
+            for bit in bloom.positions(prefix) {
+                bloom.bits[bit / 8] |= 1 << (bit % 8);
+            }
+        }
+        bloom
+    }
+    
+    /// Filter as stored in the database. Returns `None` for an empty
+    /// (corrupt) filter, which callers treat like a missing one.
+    pub fn from_bytes(bits: Vec<u8>) -> Option<Self> {
+        (!bits.is_empty()).then_some(Self { bits })
+    }
+    
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bits
+    }
+    
+    /// `false` means no output in the block has this prefix; `true` means
+    /// it may (false positives only cost a normal query).
+    pub fn may_contain(&self, prefix: u32) -> bool {
+        self.positions(prefix).all(|bit| self.bits[bit / 8] & (1 << (bit % 8)) != 0)
+    }
+    
+    /// Double hashing (Kirsch–Mitzenmacher) over two splitmix64 outputs.
+    fn positions(&self, prefix: u32) -> impl Iterator<Item = usize> {
+        let h1 = splitmix64(prefix as u64);
+        let h2 = splitmix64(h1) | 1;
+        let m = (self.bits.len() * 8) as u64;
+        (0..HASHES).map(move |i| (h1.wrapping_add(i.wrapping_mul(h2)) % m) as usize)
+    }
+}
+
+fn splitmix64(x: u64) -> u64 {
+    let mut z = x.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+/// Decoded filters by block height, so scans don't read them from the
+/// database every time. The indexer forgets a height whenever it stores,
+/// replaces or rolls back the block there; a filter read before such a
+/// change is not cached. `None` entries are blocks without a filter.
+pub struct BloomCache {
+    capacity: usize,
+    inner: Mutex<BloomCacheInner>,
+}
+
+#[derive(Default)]
+struct BloomCacheInner {
+    entries: HashMap<i32, Option<PrefixBloom>>,
+    /// Insertion order for eviction; may still hold forgotten heights
+    order: VecDeque<i32>,
+    /// Bumped by every `remove`
+    generation: u64,
+}
+
+impl BloomCache {
+    /// A cache holding the filters of at most `capacity` blocks. A
+    /// capacity of 0 disables it.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            inner: Mutex::default(),
+        }
+    }
+    
+    /// Cached filters among `heights`, and the heights not cached.
+    pub fn get_many(&self, heights: &[i32]) -> (Vec<(i32, Option<PrefixBloom>)>, Vec<i32>) {
+        let inner = self.inner.lock().unwrap();
+        let mut found = Vec::new();
+        let mut missing = Vec::new();
+        for &height in heights {
+            match inner.entries.get(&height) {
+                Some(bloom) => found.push((height, bloom.clone())),
+                None => missing.push(height),
+            }
+        }
+        (found, missing)
+    }
+    
+    /// Current generation; take it before reading filters to `insert`.
+    pub fn generation(&self) -> u64 {
+        self.inner.lock().unwrap().generation
+    }
+    
+    /// Cache the filter read at `height`, unless a block changed since
+    /// `generation` was taken.
+    pub fn insert(&self, height: i32, bloom: Option<PrefixBloom>, generation: u64) {
+        if self.capacity == 0 {
+            return;
+        }
+        
+        let mut inner = self.inner.lock().unwrap();
+        if inner.generation != generation {
+            return;
+        }
+        while inner.entries.len() >= self.capacity {
+            let Some(oldest) = inner.order.pop_front() else {
+                break;
+            };
+            inner.entries.remove(&oldest);
+        }
+        
+        if inner.order.len() >= self.capacity * 2 {
+            let BloomCacheInner { entries, order, .. } = &mut *inner;
+            order.retain(|height| entries.contains_key(height));
+        }
+        
+        if inner.entries.insert(height, bloom).is_none() {
+            inner.order.push_back(height);
+        }
+    }
+    
+    /// Forget the filter at `height` after the block there changed.
+    pub fn remove(&self, height: i32) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.generation += 1;
+        inner.entries.remove(&height);
+    }
+}
@@ -1,3 +1,4 @@
+use bitcoin::Network;
 use serde::Deserialize;
 use thiserror::Error;
 
@@ -12,22 +13,97 @@ pub enum ConfigError {
 #[derive(Clone, Debug, Deserialize)]
 pub struct ServerConfig {
     pub database_url: String,
+    pub db_max_connections: u32,
+    pub db_min_connections: u32,
+    pub db_acquire_timeout_secs: u64,
+    /// Postgres `statement_timeout` set on every pooled connection; longer
+    /// queries are cancelled by the server (0 disables it)
+    pub db_statement_timeout_ms: u64,
+    /// Size of the indexer's own pool, kept apart from the API pool so
+    /// catch-up writes can't starve scan requests of connections
+    pub indexer_db_max_connections: u32,
     pub bitcoin_rpc_url: String,
     pub bitcoin_rpc_user: String,
     pub bitcoin_rpc_pass: String,
     pub zmq_socket: String,
-    pub network: String,
+    /// ZMQ receive high-water mark: notifications queued before libzmq
+    /// starts dropping new ones
+    pub zmq_rcvhwm: i32,
+    /// How long one ZMQ receive waits before the indexer checks for
+    /// shutdown and retries
+    pub zmq_recv_timeout_ms: i32,
+    /// How often the node is asked for its tip when built without the
+    /// `zmq` feature
+    pub block_poll_interval_ms: u64,
+    /// Chain the node and index are on; also selects the address HRP
+    /// (`sp` on mainnet, `tsp` otherwise)
+    #[serde(with = "whisper_core::network_serde")]
+    pub network: Network,
     pub host: String,
     pub port: u16,
     pub max_block_range: i32,
     pub max_prefixes: usize,
+    /// Prefix width in bytes expected in scan requests (4 or 8)
+    pub prefix_len: usize,
+    pub max_batch_size: usize,
+    pub mempool_ttl_secs: i64,
+    /// Enables `POST /api/v1/scan/full`, where clients send their scan
+    /// secret and the server does the matching
+    pub allow_full_scan: bool,
+    /// Highest `max_label` a full scan may ask for; each label is another
+    /// output derivation per transaction in range
+    pub max_full_scan_label: u32,
+    /// Include output amounts in scan candidates. When false, clients
+    /// learn amounts only from the transactions of outputs they match
+    pub reveal_amounts: bool,
+    /// `/readyz` fails once the index is more than this many blocks
+    /// behind the node
+    pub ready_max_lag_blocks: i64,
+    /// Most scan results kept in the response cache (0 disables it)
+    pub scan_cache_size: usize,
+    pub scan_cache_ttl_secs: u64,
+    /// Most outputs kept in memory for resolving prevouts (0 disables it)
+    pub utxo_cache_size: usize,
+    /// Most blocks whose bloom filters are kept in memory (0 disables it)
+    pub bloom_cache_size: usize,
     pub cors_origin: String,
 }
 
 impl ServerConfig {
     pub fn from_env() -> Result<Self, ConfigError> {
+        let prefix_len: usize = std::env::var("PREFIX_LENGTH")
+            .unwrap_or_else(|_| "4".into())
+            .parse()
+            .map_err(|e| ConfigError::Parse(format!("Invalid prefix_len: {}", e)))?;
+        if !whisper_core::PREFIX_LENGTHS.contains(&prefix_len) {
+            return Err(ConfigError::Parse(format!(
+                "Invalid prefix_len: {} (expected 4 or 8)",
+                prefix_len
+            )));
+        }
+        
         Ok(Self {
             database_url: std::env::var("DATABASE_URL")?,
+            db_max_connections: std::env::var("DB_MAX_CONNECTIONS")
+                .unwrap_or_else(|_| "10".into())
+                .parse()
+                .map_err(|e| ConfigError::Parse(format!("Invalid db_max_connections: {}", e)))?,
+            db_min_connections: std::env::var("DB_MIN_CONNECTIONS")
+                .unwrap_or_else(|_| "0".into())
+                .parse()
+                .map_err(|e| ConfigError::Parse(format!("Invalid db_min_connections: {}", e)))?,
+            db_acquire_timeout_secs: std::env::var("DB_ACQUIRE_TIMEOUT_SECS")
+                .unwrap_or_else(|_| "30".into())
+                .parse()
+                .map_err(|e| ConfigError::Parse(format!("Invalid db_acquire_timeout_secs: {}", e)))?,
+            db_statement_timeout_ms: std::env::var("DB_STATEMENT_TIMEOUT_MS")
+                .unwrap_or_else(|_| "30000".into())
+                .parse()
+                .map_err(|e| ConfigError::Parse(format!("Invalid db_statement_timeout_ms: {}", e)))?,
+            indexer_db_max_connections: std::env::var("INDEXER_DB_MAX_CONNECTIONS")
+                .unwrap_or_else(|_| "2".into())
+                .parse()
+                .map_err(|e| ConfigError::Parse(format!("Invalid indexer_db_max_connections: {}", e)))?,
             bitcoin_rpc_url: std::env::var("BITCOIN_RPC_URL")
                 .unwrap_or_else(|_| "http://localhost:8332".into()),
             bitcoin_rpc_user: std::env::var("BITCOIN_RPC_USER")
@@ -36,8 +112,19 @@ impl ServerConfig {
                 .unwrap_or_else(|_| "password".into()),
             zmq_socket: std::env::var("ZMQ_BLOCK_SOCKET")
                 .unwrap_or_else(|_| "tcp://127.0.0.1:28332".into()),
-            network: std::env::var("NETWORK")
-                .unwrap_or_else(|_| "regtest".into()),
+            zmq_rcvhwm: std::env::var("ZMQ_RCVHWM")
+                .unwrap_or_else(|_| "10000".into())
+                .parse()
+                .map_err(|e| ConfigError::Parse(format!("Invalid zmq_rcvhwm: {}", e)))?,
+            zmq_recv_timeout_ms: std::env::var("ZMQ_RECV_TIMEOUT_MS")
+                .unwrap_or_else(|_| "30000".into())
+                .parse()
+                .map_err(|e| ConfigError::Parse(format!("Invalid zmq_recv_timeout_ms: {}", e)))?,
+            block_poll_interval_ms: std::env::var("BLOCK_POLL_INTERVAL_MS")
+                .unwrap_or_else(|_| "2000".into())
+                .parse()
+                .map_err(|e| ConfigError::Parse(format!("Invalid block_poll_interval_ms: {}", e)))?,
+            network: parse_network(&std::env::var("NETWORK").unwrap_or_else(|_| "regtest".into()))?,
             host: std::env::var("HOST")
                 .unwrap_or_else(|_| "0.0.0.0".into()),
             port: std::env::var("PORT")
@@ -52,8 +139,64 @@ impl ServerConfig {
                 .unwrap_or_else(|_| "1000".into())
                 .parse()
                 .map_err(|e| ConfigError::Parse(format!("Invalid max_prefixes: {}", e)))?,
+            prefix_len,
+            max_batch_size: std::env::var("MAX_BATCH_SIZE")
+                .unwrap_or_else(|_| "20".into())
+                .parse()
+                .map_err(|e| ConfigError::Parse(format!("Invalid max_batch_size: {}", e)))?,
+            mempool_ttl_secs: std::env::var("MEMPOOL_TTL_SECS")
+                .unwrap_or_else(|_| "1209600".into())
+                .parse()
+                .map_err(|e| ConfigError::Parse(format!("Invalid mempool_ttl_secs: {}", e)))?,
+            allow_full_scan: std::env::var("ALLOW_FULL_SCAN")
+                .unwrap_or_else(|_| "false".into())
+                .parse()
+                .map_err(|e| ConfigError::Parse(format!("Invalid allow_full_scan: {}", e)))?,
+            max_full_scan_label: std::env::var("MAX_FULL_SCAN_LABEL")
+                .unwrap_or_else(|_| "100".into())
+                .parse()
+                .map_err(|e| ConfigError::Parse(format!("Invalid max_full_scan_label: {}", e)))?,
+            reveal_amounts: std::env::var("REVEAL_AMOUNTS")
+                .unwrap_or_else(|_| "true".into())
+                .parse()
+                .map_err(|e| ConfigError::Parse(format!("Invalid reveal_amounts: {}", e)))?,
+            ready_max_lag_blocks: std::env::var("READY_MAX_LAG_BLOCKS")
+                .unwrap_or_else(|_| "2".into())
+                .parse()
+                .map_err(|e| ConfigError::Parse(format!("Invalid ready_max_lag_blocks: {}", e)))?,
+            scan_cache_size: std::env::var("SCAN_CACHE_SIZE")
+                .unwrap_or_else(|_| "1024".into())
+                .parse()
+                .map_err(|e| ConfigError::Parse(format!("Invalid scan_cache_size: {}", e)))?,
+            scan_cache_ttl_secs: std::env::var("SCAN_CACHE_TTL_SECS")
+                .unwrap_or_else(|_| "30".into())
+                .parse()
+                .map_err(|e| ConfigError::Parse(format!("Invalid scan_cache_ttl_secs: {}", e)))?,
+            utxo_cache_size: std::env::var("UTXO_CACHE_SIZE")
+                .unwrap_or_else(|_| "100000".into())
+                .parse()
+                .map_err(|e| ConfigError::Parse(format!("Invalid utxo_cache_size: {}", e)))?,
+            bloom_cache_size: std::env::var("BLOOM_CACHE_SIZE")
+                .unwrap_or_else(|_| "10000".into())
+                .parse()
+                .map_err(|e| ConfigError::Parse(format!("Invalid bloom_cache_size: {}", e)))?,
             cors_origin: std::env::var("CORS_ORIGIN")
                 .unwrap_or_else(|_| "*".into()),
         })
     }
 }
+
+/// Parse `NETWORK`, accepting Bitcoin Core's names as aliases
+/// (`main`, `test`) and `bitcoin` for mainnet.
+fn parse_network(name: &str) -> Result<Network, ConfigError> {
+    match name.to_ascii_lowercase().as_str() {
+        "mainnet" | "main" | "bitcoin" => Ok(Network::Bitcoin),
+        "testnet" | "testnet3" | "test" => Ok(Network::Testnet),
+        "signet" => Ok(Network::Signet),
+        "regtest" => Ok(Network::Regtest),
+        _ => Err(ConfigError::Parse(format!(
+            "Invalid network: {} (expected mainnet, testnet, signet or regtest)",
+            name
+        ))),
+    }
+}
@@ -1,11 +1,18 @@
-use crate::AppState;
-use bitcoin::{Block, Transaction, consensus::Decodable};
+use crate::{AppState, ServerConfig};
+use crate::block_source::*;
+use crate::bloom::PrefixBloom;
+use crate::utxo_cache::{UtxoCache, UtxoChanges};
+use bitcoin::{Block, BlockHash, Transaction, hashes::Hash};
+use bitcoincore_rpc::RpcApi;
 use sqlx::PgPool;
 use thiserror::Error;
-use std::io::Cursor;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicI64, Ordering};
 
 #[derive(Error, Debug)]
 pub enum IndexerError {
+    #[cfg(feature = "zmq")]
     #[error("ZMQ error: {0}")]
     Zmq(#[from] zmq::Error),
     #[error("Database error: {0}")]
@@ -14,20 +21,218 @@ pub enum IndexerError {
     Bitcoin(#[from] bitcoin::consensus::encode::Error),
     #[error("RPC error: {0}")]
     Rpc(#[from] bitcoincore_rpc::Error),
+    #[error("Blocking task failed: {0}")]
+    Join(#[from] tokio::task::JoinError),
+    #[error("Block gap too large: more than {0} missing ancestors")]
+    GapTooLarge(usize),
+    #[error("No common ancestor with the node within {0} blocks of the checkpoint")]
+    NoCommonAncestor(usize),
+    #[error("No block source: ZMQ is unavailable or unset and so is the RPC client")]
+    NoBlockSource,
 }
 
 /// Maximum number of consecutive reconnection attempts before backing off.
 const MAX_RECONNECT_ATTEMPTS: u32 = 10;
 /// Base delay between reconnection attempts (doubles each time).
 const BASE_RECONNECT_DELAY_MS: u64 = 1000;
+/// Blocks waiting for their parent are dropped beyond this many.
+const MAX_PENDING_BLOCKS: usize = 100;
+/// Maximum number of missing ancestors fetched over RPC for one block.
+const MAX_GAP_BLOCKS: usize = 2016;
 
-pub async fn run_indexer(state: AppState) -> Result<(), IndexerError> {
+/// Heights the indexer has reached, shared with the API for readiness checks.
+/// A value of -1 means nothing has been seen yet.
+#[derive(Debug)]
+pub struct IndexerProgress {
+    indexed_height: AtomicI64,
+    node_tip: AtomicI64,
+    /// Set on shutdown; the receive loop exits at its next timeout
+    stop: AtomicBool,
+}
+
+impl Default for IndexerProgress {
+    fn default() -> Self {
+        Self {
+            indexed_height: AtomicI64::new(-1),
+            node_tip: AtomicI64::new(-1),
+            stop: AtomicBool::new(false),
+        }
+    }
+}
+
+impl IndexerProgress {
+    /// Height of the last block committed to the database.
+    pub fn indexed_height(&self) -> Option<i64> {
+        Some(self.indexed_height.load(Ordering::Relaxed)).filter(|h| *h >= 0)
+    }
+    
+    /// Highest block height announced by the node (ZMQ or RPC).
+    pub fn node_tip(&self) -> Option<i64> {
+        Some(self.node_tip.load(Ordering::Relaxed)).filter(|h| *h >= 0)
+    }
+    
+    /// Blocks the index is behind the node, if the node tip is known.
+    /// Counts the whole tip as missing when nothing has been indexed yet.
+    pub fn lag(&self) -> Option<i64> {
+        let tip = self.node_tip()?;
+        Some((tip - self.indexed_height().unwrap_or(-1)).max(0))
+    }
+    
+    pub(crate) fn record_indexed(&self, height: i32) {
+        self.indexed_height.fetch_max(height as i64, Ordering::Relaxed);
+        self.record_tip(height);
+    }
+    
+    pub(crate) fn record_tip(&self, height: i32) {
+        self.node_tip.fetch_max(height as i64, Ordering::Relaxed);
+    }
+    
+    /// Ask the indexer to stop; it exits within one ZMQ receive timeout.
+    pub fn request_stop(&self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+    
+    pub(crate) fn stop_requested(&self) -> bool {
+        self.stop.load(Ordering::Relaxed)
+    }
+}
+
+/// Blocks received before their parent was indexed, keyed by parent hash.
+#[derive(Default)]
+pub(crate) struct PendingBlocks {
+    by_parent: HashMap<BlockHash, Block>,
+}
+
+impl PendingBlocks {
+    fn insert(&mut self, block: Block) {
+        if self.by_parent.len() >= MAX_PENDING_BLOCKS {
+            tracing::warn!("Pending block queue full, dropping queued blocks");
+            self.by_parent.clear();
+        }
+        self.by_parent.insert(block.header.prev_blockhash, block);
+    }
+    
+    fn take_child(&mut self, parent: &BlockHash) -> Option<Block> {
+        self.by_parent.remove(parent)
+    }
+    
+    #[cfg(test)]
+    pub(crate) fn len(&self) -> usize {
+        self.by_parent.len()
+    }
+}
+
+/// The node's view of the active chain, abstracted so checkpoint
+/// validation can be exercised without a node.
+pub(crate) trait ChainView {
+    /// Hash of the active chain's block at `height`, or `None` when the
+    /// node's chain is shorter than that.
+    async fn hash_at(&self, height: i32) -> Result<Option<BlockHash>, IndexerError>;
+}
+
+impl ChainView for Arc<bitcoincore_rpc::Client> {
+    async fn hash_at(&self, height: i32) -> Result<Option<BlockHash>, IndexerError> {
+        let client = Arc::clone(self);
+        let hash = tokio::task::spawn_blocking(move || {
+            if height < 0 || client.get_block_count()? < height as u64 {
+                return Ok(None);
+            }
+            client.get_block_hash(height as u64).map(Some)
+        })
+        .await??;
+        Ok(hash)
+    }
+}
+
+/// RPC client for the node configured in `config`. No request is made
+/// until the client is first used.
+pub(crate) fn rpc_client(config: &ServerConfig) -> Result<bitcoincore_rpc::Client, bitcoincore_rpc::Error> {
+    bitcoincore_rpc::Client::new(
+        &config.bitcoin_rpc_url,
+        bitcoincore_rpc::Auth::UserPass(config.bitcoin_rpc_user.clone(), config.bitcoin_rpc_pass.clone()),
+    )
+}
+
+/// Index blocks until shutdown is requested. All indexer queries go
+/// through `db`, its own pool, rather than the API pool in `state`.
+pub async fn run_indexer(state: AppState, db: PgPool) -> Result<(), IndexerError> {
     tracing::info!("Starting block indexer...");
+    let state = AppState { db, ..state };
+    
+    let rpc = match rpc_client(&state.config) {
+        Ok(client) => Some(Arc::new(client)),
+        Err(e) => {
+            tracing::warn!("RPC client unavailable, block gaps will not be filled: {}", e);
+            None
+        }
+    };
+    
+    if let Some(rpc) = &rpc {
+        match resync_from_checkpoint(&state, rpc).await {
+            Ok(Some(height)) => tracing::info!("Resuming from checkpoint at height {}", height),
+            Ok(None) => tracing::info!("No checkpoint stored, starting a fresh index"),
+            Err(e) => tracing::error!("Failed to validate indexer checkpoint: {}", e),
+        }
+    }
     
+    run_with_reconnect(&state, rpc.as_ref(), BASE_RECONNECT_DELAY_MS, || {
+        connect_block_source(&state, rpc.as_ref())
+    })
+    .await
+}
+
+/// bitcoind's ZMQ `rawblock`/`rawtx` notifications when built with the
+/// `zmq` feature and `ZMQ_BLOCK_SOCKET` is set; otherwise polling the
+/// node's tip over RPC.
+fn connect_block_source(
+    state: &AppState,
+    rpc: Option<&Arc<bitcoincore_rpc::Client>>,
+) -> Result<Box<dyn BlockSource>, IndexerError> {
+    let config = &state.config;
+    #[cfg(feature = "zmq")]
+    if !config.zmq_socket.is_empty() {
+        let notifications = ZmqSource::connect(&config.zmq_socket, config.zmq_rcvhwm, config.zmq_recv_timeout_ms)?;
+        return Ok(Box::new(ZmqBlockSource::new(notifications, state.db.clone())));
+    }
+    
+    let rpc = rpc.ok_or(IndexerError::NoBlockSource)?;
+    let interval = std::time::Duration::from_millis(config.block_poll_interval_ms);
+    tracing::info!("Polling the node for new blocks every {:?}", interval);
+    Ok(Box::new(RpcPollingSource::new(Arc::clone(rpc), interval)))
+}
+
+/// Index blocks from the source `connect()` returns, reconnecting with
+/// exponential backoff whenever it fails, until the source closes or
+/// shutdown is requested. On every connect, blocks mined since the last
+/// indexed one (while stopped or disconnected) are backfilled from RPC.
+/// Gap filling and reorg rollback happen in `handle_block`, whatever the
+/// source.
+pub(crate) async fn run_with_reconnect<F>(
+    state: &AppState,
+    rpc: Option<&Arc<bitcoincore_rpc::Client>>,
+    base_delay_ms: u64,
+    mut connect: F,
+) -> Result<(), IndexerError>
+where
+    F: FnMut() -> Result<Box<dyn BlockSource>, IndexerError>,
+{
     let mut attempt = 0u32;
+    let mut pending = PendingBlocks::default();
     
     loop {
-        match run_zmq_loop(&state).await {
+        let result = match connect() {
+            Ok(mut source) => {
+                if let Some(rpc) = rpc {
+                    if let Err(e) = backfill_missed_blocks(state, rpc, &mut pending).await {
+                        tracing::error!("Failed to backfill missed blocks: {}", e);
+                    }
+                }
+                receive_loop(state, source.as_mut(), rpc, &mut pending).await
+            }
+            Err(e) => Err(e),
+        };
+        
+        match result {
             Ok(()) => {
                 // Clean exit (shouldn't happen normally)
                 tracing::info!("Indexer loop exited cleanly");
@@ -36,7 +241,7 @@ pub async fn run_indexer(state: AppState) -> Result<(), IndexerError> {
             Err(e) => {
                 attempt += 1;
                 let delay = std::cmp::min(
-                    BASE_RECONNECT_DELAY_MS * 2u64.pow(attempt.min(MAX_RECONNECT_ATTEMPTS)),
+                    base_delay_ms * 2u64.pow(attempt.min(MAX_RECONNECT_ATTEMPTS)),
                     60_000, // Cap at 60 seconds
                 );
                 
@@ -50,60 +255,439 @@ pub async fn run_indexer(state: AppState) -> Result<(), IndexerError> {
                 // Reset attempt counter after successful longer run
                 if attempt > MAX_RECONNECT_ATTEMPTS {
                     tracing::warn!("Max reconnection attempts reached, resetting counter");
-                    attempt = 0;
+                    attempt = 1;
                 }
             }
         }
     }
 }
 
-async fn run_zmq_loop(state: &AppState) -> Result<(), IndexerError> {
-    let ctx = zmq::Context::new();
-    let socket = ctx.socket(zmq::SUB)?;
-    socket.connect(&state.config.zmq_socket)?;
-    socket.set_subscribe(b"rawblock")?;
-    // Set receive timeout to detect dead connections
-    socket.set_rcvtimeo(30_000)?;
-    
-    tracing::info!("Connected to ZMQ: {}", state.config.zmq_socket);
+/// Index the node's current tip; `handle_block` fetches any missing
+/// ancestors between it and the last indexed block.
+async fn backfill_missed_blocks(
+    state: &AppState,
+    rpc: &Arc<bitcoincore_rpc::Client>,
+    pending: &mut PendingBlocks,
+) -> Result<(), IndexerError> {
+    let client = Arc::clone(rpc);
+    let tip = tokio::task::spawn_blocking(move || {
+        let hash = client.get_best_block_hash()?;
+        client.get_block(&hash)
+    })
+    .await??;
     
+    handle_block(state, Some(rpc), pending, tip).await
+}
+
+async fn receive_loop(
+    state: &AppState,
+    source: &mut dyn BlockSource,
+    rpc: Option<&Arc<bitcoincore_rpc::Client>>,
+    pending: &mut PendingBlocks,
+) -> Result<(), IndexerError> {
     loop {
-        let msg = match socket.recv_multipart(0) {
-            Ok(msg) => msg,
-            Err(zmq::Error::EAGAIN) => {
-                // Timeout — no block received in 30s, that's normal. Continue.
+        if state.indexer_progress.stop_requested() {
+            tracing::info!("Indexer stopping");
+            return Ok(());
+        }
+        
+        let block = match source.next_block().await? {
+            SourceEvent::Block(block) => block,
+            // Loop to check for shutdown, then wait again
+            SourceEvent::Idle => continue,
+            SourceEvent::MissedBlocks => {
+                if let Some(rpc) = rpc {
+                    if let Err(e) = backfill_missed_blocks(state, rpc, pending).await {
+                        tracing::error!("Failed to backfill missed blocks: {}", e);
+                    }
+                }
                 continue;
             }
-            Err(e) => {
-                tracing::error!("ZMQ receive error: {}", e);
-                return Err(IndexerError::Zmq(e));
-            }
+            SourceEvent::Closed => return Ok(()),
         };
         
-        if msg.len() < 2 {
-            continue;
+        if let Err(e) = handle_block(state, rpc, pending, block).await {
+            tracing::error!("Failed to process block: {}", e);
+        }
+        if let Err(e) = evict_stale_mempool(&state.db, state.config.mempool_ttl_secs).await {
+            tracing::error!("Failed to evict stale mempool outputs: {}", e);
+        }
+    }
+}
+
+/// Index a block from the block source, tolerating redelivery, gaps and
+/// reorgs.
+///
+/// - A block whose hash is already stored is skipped (blocks are committed
+///   atomically with their transactions, so a stored hash means fully indexed).
+/// - With RPC, a block that isn't the node's block at its height (e.g. one
+///   redelivered after a reorg) is dropped.
+/// - A block whose parent isn't indexed yet gets its missing ancestors
+///   fetched over RPC first; without RPC it is queued until the parent arrives.
+/// - A block at a height that already holds another block replaces it and
+///   everything above it (a reorg), in the transaction that indexes it; see
+///   `process_block`.
+/// - After indexing, any queued children are indexed in order.
+pub(crate) async fn handle_block(
+    state: &AppState,
+    rpc: Option<&Arc<bitcoincore_rpc::Client>>,
+    pending: &mut PendingBlocks,
+    block: Block,
+) -> Result<(), IndexerError> {
+    let db = &state.db;
+    let block_hash = block.block_hash();
+    
+    // Every announced block is (at least) the node's tip at that moment
+    let height = extract_height_from_coinbase(&block.txdata[0]);
+    if let Some(height) = height {
+        state.indexer_progress.record_tip(height);
+    }
+    
+    if is_block_indexed(db, &block_hash).await? {
+        tracing::info!("Block {} already indexed, skipping", block_hash);
+        return Ok(());
+    }
+    
+    // Don't fetch ancestors for, or queue, a block the node moved away from
+    if let (Some(rpc), Some(height)) = (rpc, height) {
+        match is_on_active_chain(Some(rpc), height, &block_hash).await {
+            Ok(true) => {}
+            Ok(false) => {
+                tracing::warn!("Ignoring block {} at height {}: not on the node's active chain", block_hash, height);
+                return Ok(());
+            }
+            Err(e) => tracing::warn!("Could not check block {} against the node: {}", block_hash, e),
         }
+    }
+    
+    let mut ancestors = Vec::new();
+    if !is_parent_indexed(db, &block).await? {
+        let fetched = match rpc {
+            Some(rpc) => fetch_missing_ancestors(db, rpc, &block).await,
+            None => Ok(Vec::new()),
+        };
         
-        let topic = String::from_utf8_lossy(&msg[0]);
-        if topic == "rawblock" {
-            let block_data = &msg[1];
-            let mut cursor = Cursor::new(block_data);
-            
-            match Block::consensus_decode(&mut cursor) {
-                Ok(block) => {
-                    if let Err(e) = process_block(&state.db, &block).await {
-                        tracing::error!("Failed to process block: {}", e);
-                    }
-                }
-                Err(e) => {
-                    tracing::error!("Failed to decode block: {}", e);
+        match fetched {
+            Ok(fetched) if !fetched.is_empty() => {
+                tracing::warn!(
+                    "Block {} arrived before {} ancestor(s), filling gap via RPC",
+                    block_hash, fetched.len()
+                );
+                ancestors = fetched;
+            }
+            result => {
+                if let Err(e) = result {
+                    tracing::warn!("Could not fetch ancestors of {}: {}", block_hash, e);
                 }
+                tracing::warn!(
+                    "Parent {} of block {} not indexed yet, queuing",
+                    block.header.prev_blockhash, block_hash
+                );
+                pending.insert(block);
+                return Ok(());
             }
         }
     }
+    
+    for ancestor in &ancestors {
+        process_block(state, rpc, ancestor).await?;
+    }
+    process_block(state, rpc, &block).await?;
+    
+    // Index children that were waiting on this block
+    let mut parent = block_hash;
+    while let Some(child) = pending.take_child(&parent) {
+        parent = child.block_hash();
+        if !is_block_indexed(db, &parent).await? {
+            process_block(state, rpc, &child).await?;
+        }
+    }
+    
+    Ok(())
+}
+
+/// Last fully processed block as `(hash, height)`, if any.
+pub(crate) async fn load_checkpoint(db: &PgPool) -> Result<Option<(BlockHash, i32)>, IndexerError> {
+    let row: Option<(Vec<u8>, i32)> = sqlx::query_as(
+        "SELECT block_hash, height FROM indexer_state WHERE id = 1"
+    )
+    .fetch_optional(db)
+    .await?;
+    
+    Ok(row.and_then(|(hash, height)| {
+        BlockHash::from_slice(&hash).ok().map(|hash| (hash, height))
+    }))
+}
+
+async fn store_checkpoint<'e, E>(executor: E, hash: &BlockHash, height: i32) -> Result<(), IndexerError>
+where
+    E: sqlx::PgExecutor<'e>,
+{
+    sqlx::query!(
+        "INSERT INTO indexer_state (id, block_hash, height) VALUES (1, $1, $2)
+         ON CONFLICT (id) DO UPDATE
+         SET block_hash = EXCLUDED.block_hash, height = EXCLUDED.height, updated_at = NOW()",
+        hash.as_byte_array().as_slice(),
+        height
+    )
+    .execute(executor)
+    .await?;
+    Ok(())
+}
+
+/// Check the stored checkpoint against the node's chain before resuming.
+///
+/// If the checkpoint block is no longer in the active chain (a reorg
+/// happened while the indexer was down), stored blocks are walked back to
+/// the last one the node still agrees with. Everything above that ancestor
+/// is deleted (transactions and outputs cascade) and the checkpoint moves
+/// back to it, so the next backfill re-indexes the node's branch from there.
+///
+/// Returns the height to resume from, or `None` when nothing is stored yet.
+pub(crate) async fn resync_from_checkpoint<C: ChainView>(
+    state: &AppState,
+    chain: &C,
+) -> Result<Option<i32>, IndexerError> {
+    let db = &state.db;
+    let Some((hash, height)) = load_checkpoint(db).await? else {
+        return Ok(None);
+    };
+    
+    if chain.hash_at(height).await? == Some(hash) {
+        state.indexer_progress.record_indexed(height);
+        return Ok(Some(height));
+    }
+    
+    tracing::warn!(
+        "Checkpoint {} at height {} is not in the node's chain, looking for the fork point",
+        hash, height
+    );
+    
+    let stored: Vec<(i32, Vec<u8>)> = sqlx::query_as(
+        "SELECT height, hash FROM blocks
+         WHERE height < $1 AND is_orphaned = FALSE
+         ORDER BY height DESC
+         LIMIT $2"
+    )
+    .bind(height)
+    .bind(MAX_GAP_BLOCKS as i64)
+    .fetch_all(db)
+    .await?;
+    
+    let mut ancestor = None;
+    for (stored_height, stored_hash) in stored {
+        let Ok(stored_hash) = BlockHash::from_slice(&stored_hash) else {
+            continue;
+        };
+        if chain.hash_at(stored_height).await? == Some(stored_hash) {
+            ancestor = Some((stored_hash, stored_height));
+            break;
+        }
+    }
+    let Some((ancestor_hash, ancestor_height)) = ancestor else {
+        return Err(IndexerError::NoCommonAncestor(MAX_GAP_BLOCKS));
+    };
+    
+    roll_back_to(state, &ancestor_hash, ancestor_height).await?;
+    Ok(Some(ancestor_height))
+}
+
+/// Drop cached scan results and the cached bloom filter at `height` once
+/// the block stored there has changed.
+fn invalidate_height(state: &AppState, height: i32) {
+    state.scan_cache.invalidate_height(height);
+    state.bloom_cache.remove(height);
+}
+
+/// Drop everything cached about blocks a committed rollback removed. The
+/// UTXO cache is cleared outright: it may hold outputs those blocks
+/// created, and lack outputs they spent.
+fn invalidate_removed(state: &AppState, removed: &[i32]) {
+    if removed.is_empty() {
+        return;
+    }
+    for height in removed {
+        invalidate_height(state, *height);
+    }
+    state.utxo_cache.clear();
+}
+
+/// Delete every stored block above `ancestor_height` (transactions and
+/// outputs cascade, spends in them are undone) and move the checkpoint
+/// back to the ancestor, in one transaction.
+async fn roll_back_to(
+    state: &AppState,
+    ancestor_hash: &BlockHash,
+    ancestor_height: i32,
+) -> Result<(), IndexerError> {
+    let mut tx = state.db.begin().await?;
+    let removed = delete_blocks_above(&mut tx, ancestor_height).await?;
+    store_checkpoint(&mut *tx, ancestor_hash, ancestor_height).await?;
+    tx.commit().await?;
+    
+    invalidate_removed(state, &removed);
+    tracing::warn!(
+        "Rolled back {} block(s) above common ancestor {} at height {}",
+        removed.len(), ancestor_hash, ancestor_height
+    );
+    
+    state.indexer_progress.record_indexed(ancestor_height);
+    Ok(())
+}
+
+/// Delete the blocks above `height` within `tx` and undo the spends they
+/// recorded (taproot outputs and `utxos` rows alike), returning the
+/// removed heights.
+async fn delete_blocks_above(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    height: i32,
+) -> Result<Vec<i32>, IndexerError> {
+    let removed: Vec<(i32,)> = sqlx::query_as(
+        "DELETE FROM blocks WHERE height > $1 RETURNING height"
+    )
+    .bind(height)
+    .fetch_all(&mut **tx)
+    .await?;
+    sqlx::query("UPDATE taproot_outputs SET spent_height = NULL WHERE spent_height > $1")
+        .bind(height)
+        .execute(&mut **tx)
+        .await?;
+    sqlx::query("UPDATE utxos SET spent_height = NULL WHERE spent_height > $1")
+        .bind(height)
+        .execute(&mut **tx)
+        .await?;
+    Ok(removed.into_iter().map(|(height,)| height).collect())
+}
+
+/// Whether `hash` is the node's block at `height`. Without RPC there is no
+/// node to ask, and the latest block delivered for a height wins.
+async fn is_on_active_chain(
+    rpc: Option<&Arc<bitcoincore_rpc::Client>>,
+    height: i32,
+    hash: &BlockHash,
+) -> Result<bool, IndexerError> {
+    match rpc {
+        Some(rpc) => Ok(rpc.hash_at(height).await? == Some(*hash)),
+        None => Ok(true),
+    }
+}
+
+async fn is_block_indexed(db: &PgPool, hash: &BlockHash) -> Result<bool, IndexerError> {
+    let exists: (bool,) = sqlx::query_as("SELECT EXISTS(SELECT 1 FROM blocks WHERE hash = $1)")
+        .bind(hash.as_byte_array().as_slice())
+        .fetch_one(db)
+        .await?;
+    Ok(exists.0)
+}
+
+/// The parent is considered indexed when it is stored, or when nothing is
+/// stored yet (the first block seen starts the index).
+async fn is_parent_indexed(db: &PgPool, block: &Block) -> Result<bool, IndexerError> {
+    let (parent_known, any_blocks): (bool, bool) = sqlx::query_as(
+        "SELECT EXISTS(SELECT 1 FROM blocks WHERE hash = $1), EXISTS(SELECT 1 FROM blocks)"
+    )
+    .bind(block.header.prev_blockhash.as_byte_array().as_slice())
+    .fetch_one(db)
+    .await?;
+    Ok(parent_known || !any_blocks)
+}
+
+/// Walk back from `block` over RPC until an indexed ancestor is found and
+/// return the missing ancestors oldest first.
+async fn fetch_missing_ancestors(
+    db: &PgPool,
+    rpc: &Arc<bitcoincore_rpc::Client>,
+    block: &Block,
+) -> Result<Vec<Block>, IndexerError> {
+    let mut missing = Vec::new();
+    let mut prev = block.header.prev_blockhash;
+    
+    while !is_block_indexed(db, &prev).await? {
+        if missing.len() >= MAX_GAP_BLOCKS {
+            return Err(IndexerError::GapTooLarge(MAX_GAP_BLOCKS));
+        }
+        
+        let client = Arc::clone(rpc);
+        let ancestor = tokio::task::spawn_blocking(move || client.get_block(&prev)).await??;
+        prev = ancestor.header.prev_blockhash;
+        missing.push(ancestor);
+    }
+    
+    missing.reverse();
+    Ok(missing)
 }
 
-async fn process_block(db: &PgPool, block: &Block) -> Result<(), IndexerError> {
+/// Store the taproot outputs of an unconfirmed transaction. Core also
+/// publishes `rawtx` for transactions in connected blocks, so anything
+/// already confirmed is ignored.
+#[cfg_attr(not(feature = "zmq"), allow(dead_code))]
+pub(crate) async fn process_mempool_tx(db: &PgPool, tx: &Transaction) -> Result<(), IndexerError> {
+    let txid = tx.compute_txid();
+    
+    let confirmed: Option<(i32,)> = sqlx::query_as(
+        "SELECT block_height FROM transactions WHERE txid = $1"
+    )
+    .bind(txid.as_byte_array().as_slice())
+    .fetch_optional(db)
+    .await?;
+    
+    if confirmed.is_some() {
+        return Ok(());
+    }
+    
+    for (vout, output) in tx.output.iter().enumerate() {
+        let script = output.script_pubkey.as_bytes();
+        let Some((x_only_bytes, prefix)) = taproot_output_key(script) else {
+            continue;
+        };
+        
+        sqlx::query!(
+            "INSERT INTO mempool_outputs
+             (txid, vout, script_pubkey, amount, x_only_pubkey, sp_prefix)
+             VALUES ($1, $2, $3, $4, $5, $6)
+             ON CONFLICT (txid, vout) DO NOTHING",
+            txid.as_byte_array().as_slice(),
+            vout as i32,
+            script,
+            output.value.to_sat() as i64,
+            x_only_bytes,
+            db_prefix(prefix)
+        )
+        .execute(db)
+        .await?;
+    }
+    
+    Ok(())
+}
+
+/// Drop mempool outputs that have been unconfirmed for longer than the TTL
+/// (evicted or replaced transactions never confirm).
+async fn evict_stale_mempool(db: &PgPool, ttl_secs: i64) -> Result<(), IndexerError> {
+    let evicted = sqlx::query!(
+        "DELETE FROM mempool_outputs WHERE first_seen < NOW() - make_interval(secs => $1)",
+        ttl_secs as f64
+    )
+    .execute(db)
+    .await?
+    .rows_affected();
+    
+    if evicted > 0 {
+        tracing::info!("Evicted {} stale mempool outputs", evicted);
+    }
+    
+    Ok(())
+}
+
+/// Index `block` in one DB transaction. Prevouts missing from the UTXO
+/// cache and table are fetched from `rpc`, if given. A different block
+/// stored at the same height is replaced, together with the blocks above
+/// it, once `rpc` confirms `block` is on the node's active chain; otherwise
+/// `block` is stale and nothing changes. Without RPC the newer block wins.
+pub(crate) async fn process_block(
+    state: &AppState,
+    rpc: Option<&Arc<bitcoincore_rpc::Client>>,
+    block: &Block,
+) -> Result<(), IndexerError> {
     let block_hash = block.block_hash();
     let header_bytes = bitcoin::consensus::serialize(&block.header);
     
@@ -113,37 +697,104 @@ async fn process_block(db: &PgPool, block: &Block) -> Result<(), IndexerError> {
     
     tracing::info!("Processing block {} at height {}", block_hash, height);
     
-    let mut tx = db.begin().await?;
+    let prefixes: Vec<u32> = block.txdata
+        .iter()
+        .flat_map(|t| &t.output)
+        .filter_map(|o| taproot_output_key(o.script_pubkey.as_bytes()))
+        .map(|(_, prefix)| prefix)
+        .collect();
+    let bloom = PrefixBloom::from_prefixes(&prefixes);
+    
+    let mut tx = state.db.begin().await?;
+    
+    // A different block at this height lost a reorg: it and everything
+    // built on it go in this transaction, so readers never see the height
+    // empty or held by both blocks (`height` is the primary key)
+    let stored: Option<(Vec<u8>,)> = sqlx::query_as(
+        "SELECT hash FROM blocks WHERE height = $1 FOR UPDATE"
+    )
+    .bind(height)
+    .fetch_optional(&mut *tx)
+    .await?;
+    let mut replaced = Vec::new();
+    if stored.is_some_and(|(hash,)| hash != block_hash.as_byte_array()) {
+        if !is_on_active_chain(rpc, height, &block_hash).await? {
+            tracing::warn!("Keeping the stored block at height {}: {} is not on the node's active chain", height, block_hash);
+            return Ok(());
+        }
+        replaced = delete_blocks_above(&mut tx, height - 1).await?;
+        tracing::warn!("Reorg: block {} replaces {} block(s) from height {}", block_hash, replaced.len(), height);
+    }
     
     // Insert block
     sqlx::query!(
-        "INSERT INTO blocks (height, hash, header, is_orphaned) 
-         VALUES ($1, $2, $3, FALSE)
+        "INSERT INTO blocks (height, hash, header, is_orphaned, prefix_bloom) 
+         VALUES ($1, $2, $3, FALSE, $4)
          ON CONFLICT (hash) DO NOTHING",
         height,
         block_hash.as_byte_array().as_slice(),
-        &header_bytes
+        &header_bytes,
+        bloom.as_bytes()
     )
     .execute(&mut *tx)
     .await?;
     
     // Process transactions
+    let mut eligible_txs = 0;
+    let mut utxo_changes = UtxoChanges::default();
     for (tx_index, transaction) in block.txdata.iter().enumerate() {
-        process_transaction(&mut tx, transaction, height, tx_index as i32).await?;
+        let indexed = process_transaction(
+            &mut tx,
+            &state.utxo_cache,
+            &mut utxo_changes,
+            rpc,
+            transaction,
+            height,
+            tx_index as i32,
+        )
+        .await?;
+        if indexed {
+            eligible_txs += 1;
+        }
     }
     
+    // Spent rows are kept while a rollback could still need them
+    sqlx::query("DELETE FROM utxos WHERE spent_height < $1")
+        .bind(height - MAX_GAP_BLOCKS as i32)
+        .execute(&mut *tx)
+        .await?;
+    
+    store_checkpoint(&mut *tx, &block_hash, height).await?;
     tx.commit().await?;
-    tracing::info!("Block {} indexed successfully ({} txs)", height, block.txdata.len());
+    state.indexer_progress.record_indexed(height);
+    invalidate_height(state, height);
+    invalidate_removed(state, &replaced);
+    state.utxo_cache.apply(utxo_changes);
+    
+    let taproot_outputs = block.txdata
+        .iter()
+        .flat_map(|t| &t.output)
+        .filter(|o| o.script_pubkey.is_p2tr())
+        .count();
+    tracing::info!(
+        "Block {} indexed successfully ({} txs, {} taproot outputs, {} txs with eligible inputs)",
+        height, block.txdata.len(), taproot_outputs, eligible_txs
+    );
     
     Ok(())
 }
 
+/// Index one transaction, recording its cache changes in `changes`;
+/// returns whether tweak data was stored for it.
 async fn process_transaction(
     db_tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    utxos: &UtxoCache,
+    changes: &mut UtxoChanges,
+    rpc: Option<&Arc<bitcoincore_rpc::Client>>,
     tx: &Transaction,
     block_height: i32,
     block_index: i32,
-) -> Result<(), IndexerError> {
+) -> Result<bool, IndexerError> {
     let txid = tx.txid();
     let is_coinbase = tx.is_coinbase();
     let raw_tx = bitcoin::consensus::serialize(tx);
@@ -161,14 +812,191 @@ async fn process_transaction(
     .execute(&mut **db_tx)
     .await?;
     
+    // The transaction confirmed: it is no longer a mempool candidate
+    sqlx::query!(
+        "DELETE FROM mempool_outputs WHERE txid = $1",
+        txid.as_byte_array().as_slice()
+    )
+    .execute(&mut **db_tx)
+    .await?;
+    
     // Process outputs
     for (vout, output) in tx.output.iter().enumerate() {
         process_output(db_tx, &txid, vout as i32, output, block_height).await?;
     }
     
+    let stored = if !is_coinbase && tx.output.iter().any(|o| o.script_pubkey.is_p2tr()) {
+        store_tweak_data(db_tx, utxos, rpc, tx, &txid, block_height).await?
+    } else {
+        false
+    };
+    
+    if !is_coinbase {
+        spend_utxos(db_tx, changes, tx, block_height).await?;
+    }
+    store_utxos(db_tx, changes, tx, &txid).await?;
+    
+    Ok(stored)
+}
+
+/// Add `tx`'s spendable outputs to the `utxos` table, and to the UTXO
+/// cache once the block commits.
+async fn store_utxos(
+    db_tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    changes: &mut UtxoChanges,
+    tx: &Transaction,
+    txid: &bitcoin::Txid,
+) -> Result<(), IndexerError> {
+    let outputs: Vec<(u32, &bitcoin::TxOut)> = tx.output
+        .iter()
+        .enumerate()
+        .filter(|(_, output)| !output.script_pubkey.is_op_return())
+        .map(|(vout, output)| (vout as u32, output))
+        .collect();
+    if outputs.is_empty() {
+        return Ok(());
+    }
+    
+    sqlx::query(
+        "INSERT INTO utxos (txid, vout, script_pubkey, amount)
+         SELECT $1, * FROM UNNEST($2::INTEGER[], $3::BYTEA[], $4::BIGINT[])
+         ON CONFLICT (txid, vout) DO NOTHING"
+    )
+    .bind(txid.as_byte_array().as_slice())
+    .bind(outputs.iter().map(|(vout, _)| *vout as i32).collect::<Vec<_>>())
+    .bind(outputs.iter().map(|(_, o)| o.script_pubkey.to_bytes()).collect::<Vec<_>>())
+    .bind(outputs.iter().map(|(_, o)| o.value.to_sat() as i64).collect::<Vec<_>>())
+    .execute(&mut **db_tx)
+    .await?;
+    
+    for (vout, output) in outputs {
+        changes.create(bitcoin::OutPoint { txid: *txid, vout }, output.clone());
+    }
+    Ok(())
+}
+
+/// Mark the outputs spent by `tx` with `block_height`, in the `utxos`
+/// table (so a rollback can restore them) and among taproot outputs, and
+/// drop them from the UTXO cache once the block commits.
+async fn spend_utxos(
+    db_tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    changes: &mut UtxoChanges,
+    tx: &Transaction,
+    block_height: i32,
+) -> Result<(), IndexerError> {
+    let txids: Vec<Vec<u8>> = tx.input
+        .iter()
+        .map(|i| i.previous_output.txid.as_byte_array().to_vec())
+        .collect();
+    let vouts: Vec<i32> = tx.input.iter().map(|i| i.previous_output.vout as i32).collect();
+    
+    sqlx::query(
+        "UPDATE utxos SET spent_height = $3
+         WHERE (txid, vout) IN (SELECT * FROM UNNEST($1::BYTEA[], $2::INTEGER[]))"
+    )
+    .bind(&txids)
+    .bind(&vouts)
+    .bind(block_height)
+    .execute(&mut **db_tx)
+    .await?;
+    
+    sqlx::query(
+        "UPDATE taproot_outputs SET spent_height = $3
+         WHERE (txid, vout) IN (SELECT * FROM UNNEST($1::BYTEA[], $2::INTEGER[]))"
+    )
+    .bind(&txids)
+    .bind(&vouts)
+    .bind(block_height)
+    .execute(&mut **db_tx)
+    .await?;
+    
+    for txin in &tx.input {
+        changes.spend(txin.previous_output);
+    }
     Ok(())
 }
 
+/// The output spent by `outpoint`: from the UTXO cache, else the `utxos`
+/// table, else the node (`getrawtransaction`, which needs `txindex=1` for
+/// outputs it no longer holds). `None` if none of them has it.
+async fn resolve_prevout(
+    db_tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    utxos: &UtxoCache,
+    rpc: Option<&Arc<bitcoincore_rpc::Client>>,
+    outpoint: &bitcoin::OutPoint,
+) -> Result<Option<bitcoin::TxOut>, IndexerError> {
+    if let Some(output) = utxos.get(outpoint) {
+        return Ok(Some(output));
+    }
+    
+    let row: Option<(Vec<u8>, i64)> = sqlx::query_as(
+        "SELECT script_pubkey, amount FROM utxos WHERE txid = $1 AND vout = $2"
+    )
+    .bind(outpoint.txid.as_byte_array().as_slice())
+    .bind(outpoint.vout as i32)
+    .fetch_optional(&mut **db_tx)
+    .await?;
+    if let Some((script, amount)) = row {
+        return Ok(Some(bitcoin::TxOut {
+            value: bitcoin::Amount::from_sat(amount as u64),
+            script_pubkey: bitcoin::ScriptBuf::from_bytes(script),
+        }));
+    }
+    
+    let Some(rpc) = rpc else {
+        return Ok(None);
+    };
+    let client = Arc::clone(rpc);
+    let txid = outpoint.txid;
+    match tokio::task::spawn_blocking(move || client.get_raw_transaction(&txid, None)).await? {
+        Ok(prev_tx) => Ok(prev_tx.output.get(outpoint.vout as usize).cloned()),
+        Err(e) => {
+            tracing::debug!("Prevout {} not available from RPC: {}", outpoint, e);
+            Ok(None)
+        }
+    }
+}
+
+/// Store the tweak point of `tx` (`InputData::transaction_tweak`) for
+/// server-side full scans and sync. If any prevout can't be resolved the
+/// tweak can't be computed, so nothing is stored; neither is it for a
+/// transaction without eligible inputs or whose input keys cancel out.
+/// Returns whether tweak data was stored.
+async fn store_tweak_data(
+    db_tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    utxos: &UtxoCache,
+    rpc: Option<&Arc<bitcoincore_rpc::Client>>,
+    tx: &Transaction,
+    txid: &bitcoin::Txid,
+    block_height: i32,
+) -> Result<bool, IndexerError> {
+    let mut prevouts = Vec::with_capacity(tx.input.len());
+    for txin in &tx.input {
+        let Some(prevout) = resolve_prevout(db_tx, utxos, rpc, &txin.previous_output).await? else {
+            return Ok(false);
+        };
+        prevouts.push(prevout);
+    }
+    
+    let Some(tweak) = whisper_core::InputData::transaction_tweak(tx, &prevouts) else {
+        return Ok(false);
+    };
+    let tweak = tweak.serialize();
+    
+    sqlx::query!(
+        "INSERT INTO tx_tweaks (txid, block_height, tweak)
+         VALUES ($1, $2, $3)
+         ON CONFLICT (txid) DO NOTHING",
+        txid.as_byte_array().as_slice(),
+        block_height,
+        tweak.as_slice()
+    )
+    .execute(&mut **db_tx)
+    .await?;
+    
+    Ok(true)
+}
+
 async fn process_output(
     db_tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
     txid: &bitcoin::Txid,
@@ -178,18 +1006,7 @@ async fn process_output(
 ) -> Result<(), IndexerError> {
     let script = output.script_pubkey.as_bytes();
     
-    // Check if Taproot: 0x51 0x20 + 32 bytes
-    if script.len() == 34 && script[0] == 0x51 && script[1] == 0x20 {
-        let x_only_bytes = &script[2..34];
-        
-        // Compute 4-byte prefix (same wrapping semantics as API parsing)
-        let prefix = i32::from_be_bytes([
-            x_only_bytes[0],
-            x_only_bytes[1],
-            x_only_bytes[2],
-            x_only_bytes[3],
-        ]);
-        
+    if let Some((x_only_bytes, prefix)) = taproot_output_key(script) {
         sqlx::query!(
             "INSERT INTO taproot_outputs 
              (txid, vout, block_height, script_pubkey, amount, x_only_pubkey, sp_prefix)
@@ -201,7 +1018,7 @@ async fn process_output(
             script,
             output.value.to_sat() as i64,
             x_only_bytes,
-            prefix
+            db_prefix(prefix)
         )
         .execute(&mut **db_tx)
         .await?;
@@ -210,6 +1027,43 @@ async fn process_output(
     Ok(())
 }
 
+/// For a Taproot script (0x51 0x20 + 32 bytes), return the x-only key and
+/// its 4-byte prefix.
+fn taproot_output_key(script: &[u8]) -> Option<(&[u8], u32)> {
+    if script.len() != 34 || script[0] != 0x51 || script[1] != 0x20 {
+        return None;
+    }
+    
+    let x_only_bytes = &script[2..34];
+    let prefix = u32::from_be_bytes([
+        x_only_bytes[0],
+        x_only_bytes[1],
+        x_only_bytes[2],
+        x_only_bytes[3],
+    ]);
+    
+    Some((x_only_bytes, prefix))
+}
+
+/// `sp_prefix` column value for a 4-byte prefix. Prefixes are unsigned,
+/// the column is a signed INTEGER holding the same bit pattern, so
+/// prefixes from 0x80000000 up are stored as negative numbers. The indexer
+/// and the API must both go through this (and `prefix_from_db`).
+pub(crate) fn db_prefix(prefix: u32) -> i32 {
+    prefix as i32
+}
+
+/// Inverse of `db_prefix`.
+pub(crate) fn prefix_from_db(value: i32) -> u32 {
+    value as u32
+}
+
+/// `sp_prefix_long` column value for an 8-byte prefix (the column is
+/// generated in SQL from the key bytes with the same signed bit pattern).
+pub(crate) fn db_prefix_long(prefix: u64) -> i64 {
+    prefix as i64
+}
+
 fn extract_height_from_coinbase(tx: &Transaction) -> Option<i32> {
     if !tx.is_coinbase() || tx.input.is_empty() {
         return None;
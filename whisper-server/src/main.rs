@@ -1,12 +1,25 @@
 mod api;
+mod block_source;
+mod bloom;
 mod indexer;
 mod config;
+mod rpc;
+mod scan_cache;
+mod utxo_cache;
+
+#[cfg(test)]
+mod tests;
 
 use axum::{Router, routing::{get, post}};
+use axum::extract::{ConnectInfo, Request};
 use sqlx::postgres::PgPoolOptions;
+use std::net::SocketAddr;
 use std::sync::Arc;
+use tower_http::compression::CompressionLayer;
 use tower_http::cors::{CorsLayer, Any};
+use tower_http::request_id::{MakeRequestUuid, PropagateRequestIdLayer, SetRequestIdLayer};
 use tower_http::set_header::SetResponseHeaderLayer;
+use tower_http::trace::TraceLayer;
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 use axum::http::HeaderValue;
 
@@ -14,11 +27,30 @@ pub use api::*;
 pub use indexer::*;
 pub use config::*;
 
+/// When the node was last asked for its block count, and the answer
+/// (`None` if the call failed).
+pub type NodeTipCache = std::sync::Mutex<Option<(std::time::Instant, Option<i64>)>>;
+
 #[derive(Clone)]
 pub struct AppState {
     pub db: sqlx::PgPool,
     pub config: ServerConfig,
     pub started_at: std::time::Instant,
+    /// Last `/api/v1/stats` result and when it was computed
+    pub stats_cache: Arc<tokio::sync::Mutex<Option<(std::time::Instant, IndexStats)>>>,
+    /// Indexed height and node tip, updated by the indexer
+    pub indexer_progress: Arc<IndexerProgress>,
+    /// Node RPC asked for its block count by `/api/v1/status`; `None` falls
+    /// back to the tip the indexer has seen
+    pub node_rpc: Option<Arc<bitcoincore_rpc::Client>>,
+    /// Last block count reported by `node_rpc` and when it was fetched
+    pub node_tip_cache: Arc<NodeTipCache>,
+    /// Recent confirmed scan results; the indexer invalidates covered ranges
+    pub scan_cache: Arc<scan_cache::ScanCache>,
+    /// Recently created outputs, for resolving prevouts while indexing
+    pub utxo_cache: Arc<utxo_cache::UtxoCache>,
+    /// Decoded prefix bloom filters by height, for skipping blocks in scans
+    pub bloom_cache: Arc<bloom::BloomCache>,
 }
 
 #[tokio::main]
@@ -33,36 +65,113 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let config = ServerConfig::from_env()?;
     
     tracing::info!("Connecting to database...");
-    let db = PgPoolOptions::new()
-        .max_connections(10)
+    let db = pool_options(&config)
         .connect(&config.database_url)
         .await?;
     
     tracing::info!("Running migrations...");
     sqlx::migrate!("./migrations").run(&db).await?;
     
+    let indexer_db = indexer_pool_options(&config)
+        .connect(&config.database_url)
+        .await?;
+    
     let state = AppState {
         db: db.clone(),
         config: config.clone(),
         started_at: std::time::Instant::now(),
+        stats_cache: Arc::default(),
+        indexer_progress: Arc::default(),
+        node_rpc: match indexer::rpc_client(&config) {
+            Ok(client) => Some(Arc::new(client)),
+            Err(e) => {
+                tracing::warn!("RPC client unavailable, status will not query the node tip: {}", e);
+                None
+            }
+        },
+        node_tip_cache: Arc::default(),
+        scan_cache: Arc::new(scan_cache::ScanCache::new(
+            config.scan_cache_size,
+            std::time::Duration::from_secs(config.scan_cache_ttl_secs),
+        )),
+        utxo_cache: Arc::new(utxo_cache::UtxoCache::new(config.utxo_cache_size)),
+        bloom_cache: Arc::new(bloom::BloomCache::new(config.bloom_cache_size)),
     };
     
     // Start indexer in background
     let indexer_state = state.clone();
-    tokio::spawn(async move {
-        if let Err(e) = run_indexer(indexer_state).await {
+    let indexer_progress = state.indexer_progress.clone();
+    let indexer = tokio::spawn(async move {
+        if let Err(e) = run_indexer(indexer_state, indexer_db).await {
             tracing::error!("Indexer fatal error: {}", e);
         }
     });
     
+    let addr = format!("{}:{}", config.host, config.port);
+    tracing::info!("Whisper server v{} listening on {}", env!("CARGO_PKG_VERSION"), addr);
+    tracing::info!("Dashboard: http://{}", addr);
+    tracing::info!("API: http://{}/api/v1/status", addr);
+    
+    let listener = tokio::net::TcpListener::bind(&addr).await?;
+    
+    // Graceful shutdown on Ctrl+C
+    axum::serve(listener, app(state).into_make_service_with_connect_info::<SocketAddr>())
+        .with_graceful_shutdown(shutdown_signal())
+        .await?;
+    
+    // The indexer notices at its next ZMQ receive timeout or poll
+    indexer_progress.request_stop();
+    let idle_ms = (config.zmq_recv_timeout_ms.max(0) as u64).max(config.block_poll_interval_ms);
+    let wait = std::time::Duration::from_millis(idle_ms + 5_000);
+    if tokio::time::timeout(wait, indexer).await.is_err() {
+        tracing::warn!("Indexer did not stop within {:?}", wait);
+    }
+    
+    tracing::info!("Server shut down gracefully");
+    Ok(())
+}
+
+/// Connection pool settings from `config`. Every new connection gets the
+/// configured `statement_timeout`, so a runaway query is cancelled by
+/// Postgres instead of holding its connection indefinitely.
+pub(crate) fn pool_options(config: &ServerConfig) -> PgPoolOptions {
+    let statement_timeout_ms = config.db_statement_timeout_ms;
+    PgPoolOptions::new()
+        .max_connections(config.db_max_connections)
+        .min_connections(config.db_min_connections)
+        .acquire_timeout(std::time::Duration::from_secs(config.db_acquire_timeout_secs))
+        .after_connect(move |conn, _meta| {
+            Box::pin(async move {
+                if statement_timeout_ms > 0 {
+                    sqlx::query(&format!("SET statement_timeout = {}", statement_timeout_ms))
+                        .execute(conn)
+                        .await?;
+                }
+                Ok(())
+            })
+        })
+}
+
+/// Options for the indexer's dedicated pool: the same per-connection
+/// settings as `pool_options`, capped at `indexer_db_max_connections`.
+pub(crate) fn indexer_pool_options(config: &ServerConfig) -> PgPoolOptions {
+    pool_options(config)
+        .max_connections(config.indexer_db_max_connections)
+        .min_connections(config.db_min_connections.min(config.indexer_db_max_connections))
+}
+
+/// API routes, dashboard and middleware. Responses are gzip/brotli
+/// compressed when the client sends `Accept-Encoding`, and API responses
+/// carry the `x-request-id` of their tracing span.
+pub(crate) fn app(state: AppState) -> Router {
     // Build CORS layer — configurable via CORS_ORIGIN env var
-    let cors = if config.cors_origin == "*" {
+    let cors = if state.config.cors_origin == "*" {
         CorsLayer::new()
             .allow_origin(Any)
             .allow_methods(Any)
             .allow_headers(Any)
     } else {
-        let origin: HeaderValue = config.cors_origin.parse()
+        let origin: HeaderValue = state.config.cors_origin.parse()
             .expect("Invalid CORS_ORIGIN value");
         CorsLayer::new()
             .allow_origin(origin)
@@ -74,10 +183,22 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let static_service = tower_http::services::ServeDir::new("static")
         .fallback(tower_http::services::ServeFile::new("static/index.html"));
 
-    let app = Router::new()
+    Router::new()
+        .route("/healthz", get(healthz_handler))
+        .route("/readyz", get(readyz_handler))
         .route("/api/v1/scan", post(scan_handler))
+        .route("/api/v1/scan/batch", post(scan_batch_handler))
+        .route("/api/v1/scan/full", post(full_scan_handler))
+        .route("/api/v1/scan/txids", post(scan_txids_handler))
+        .route("/api/v1/sync", get(sync_handler))
+        .route("/api/v1/blocks", get(blocks_handler))
+        .route("/api/v1/block/:height", get(block_handler))
+        .route("/api/v1/tx/:txid", get(tx_handler))
         .route("/api/v1/status", get(status_handler))
+        .route("/api/v1/stats", get(stats_handler))
+        .route("/rpc", post(rpc::rpc_handler))
         .layer(cors)
+        .layer(CompressionLayer::new())
         .layer(SetResponseHeaderLayer::overriding(
             axum::http::header::X_CONTENT_TYPE_OPTIONS,
             HeaderValue::from_static("nosniff"),
@@ -86,23 +207,35 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             axum::http::header::X_FRAME_OPTIONS,
             HeaderValue::from_static("DENY"),
         ))
+        // Layers wrap outwards: the ID is set first, then the span is
+        // opened with it, then it is copied onto the response.
+        .layer(PropagateRequestIdLayer::x_request_id())
+        .layer(TraceLayer::new_for_http().make_span_with(request_span))
+        .layer(SetRequestIdLayer::x_request_id(MakeRequestUuid))
         .fallback_service(static_service)
-        .with_state(state);
-    
-    let addr = format!("{}:{}", config.host, config.port);
-    tracing::info!("Whisper server v{} listening on {}", env!("CARGO_PKG_VERSION"), addr);
-    tracing::info!("Dashboard: http://{}", addr);
-    tracing::info!("API: http://{}/api/v1/status", addr);
-    
-    let listener = tokio::net::TcpListener::bind(&addr).await?;
-    
-    // Graceful shutdown on Ctrl+C
-    axum::serve(listener, app)
-        .with_graceful_shutdown(shutdown_signal())
-        .await?;
-    
-    tracing::info!("Server shut down gracefully");
-    Ok(())
+        .with_state(state)
+}
+
+/// Span for one API request, tagged with its request ID (the caller's
+/// `x-request-id`, or a generated UUID) and the client IP. Handlers fill in
+/// `scan_range` once the request body is parsed.
+fn request_span(request: &Request) -> tracing::Span {
+    let request_id = request.headers()
+        .get("x-request-id")
+        .and_then(|id| id.to_str().ok())
+        .unwrap_or("-");
+    let client_ip = request.extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| addr.ip().to_string())
+        .unwrap_or_else(|| "-".into());
+    tracing::info_span!(
+        "request",
+        request_id,
+        client_ip,
+        method = %request.method(),
+        uri = %request.uri(),
+        scan_range = tracing::field::Empty,
+    )
 }
 
 async fn shutdown_signal() {
@@ -0,0 +1,154 @@
+use axum::{
+    body::Bytes,
+    extract::{Path, Query, State, Json},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use crate::AppState;
+use crate::api::{
+    scan_handler, status_handler, sync_handler, tx_handler, ApiError, BlockQuery, ScanRequest,
+    SyncQuery,
+};
+
+/// JSON-RPC 2.0 error codes.
+pub const PARSE_ERROR: i32 = -32700;
+pub const INVALID_REQUEST: i32 = -32600;
+pub const METHOD_NOT_FOUND: i32 = -32601;
+pub const INVALID_PARAMS: i32 = -32602;
+pub const INTERNAL_ERROR: i32 = -32603;
+/// Server-defined codes for `ApiError::NotFound` and `ApiError::Forbidden`.
+pub const NOT_FOUND: i32 = -32001;
+pub const FORBIDDEN: i32 = -32002;
+
+#[derive(Debug, Deserialize)]
+pub struct RpcRequest {
+    pub jsonrpc: String,
+    pub method: String,
+    #[serde(default)]
+    pub params: Value,
+    /// `None` when the member is absent, which makes the request a
+    /// notification; an explicit `null` id is `Some(Value::Null)`
+    #[serde(default, deserialize_with = "present")]
+    pub id: Option<Value>,
+}
+
+/// Deserialize a field that is present, even as `null`, to `Some`.
+fn present<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<Option<Value>, D::Error> {
+    Value::deserialize(deserializer).map(Some)
+}
+
+#[derive(Debug, Serialize)]
+pub struct RpcError {
+    pub code: i32,
+    pub message: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RpcResponse {
+    pub jsonrpc: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<RpcError>,
+    pub id: Value,
+}
+
+impl RpcError {
+    fn new(code: i32, message: impl Into<String>) -> Self {
+        Self { code, message: message.into() }
+    }
+}
+
+impl From<ApiError> for RpcError {
+    fn from(error: ApiError) -> Self {
+        let code = match &error {
+            ApiError::Validation(_) => INVALID_PARAMS,
+            ApiError::Database(_) => INTERNAL_ERROR,
+            ApiError::NotFound(_) => NOT_FOUND,
+            ApiError::Forbidden(_) => FORBIDDEN,
+        };
+        Self::new(code, error.to_string())
+    }
+}
+
+/// Params of the `tx` method: the `/api/v1/tx/{txid}` path plus its query.
+#[derive(Debug, Deserialize)]
+struct TxParams {
+    txid: String,
+    #[serde(flatten)]
+    query: BlockQuery,
+}
+
+/// `POST /rpc`: JSON-RPC 2.0 over the REST handlers. Methods and their
+/// `params` objects mirror the REST endpoints:
+///
+/// - `scan`: the `POST /api/v1/scan` body
+/// - `status`: no params
+/// - `tx`: `{ "txid": ..., "include_orphans": ... }`
+/// - `tweaks`: `{ "since_height": ... }`, as `GET /api/v1/sync`
+///
+/// Responses are HTTP 200, with the outcome in `result` or `error`.
+/// Notifications (requests without an `id`) are run but get no reply, only
+/// an empty `204`. Batch requests are not supported.
+pub async fn rpc_handler(State(state): State<AppState>, body: Bytes) -> Response {
+    let request: RpcRequest = match serde_json::from_slice::<Value>(&body) {
+        Err(e) => return Json(reply(Value::Null, Err(RpcError::new(PARSE_ERROR, e.to_string())))).into_response(),
+        Ok(value) => match serde_json::from_value(value) {
+            Ok(request) => request,
+            Err(e) => {
+                return Json(reply(Value::Null, Err(RpcError::new(INVALID_REQUEST, e.to_string())))).into_response()
+            }
+        },
+    };
+    let id = request.id.clone().unwrap_or(Value::Null);
+    if request.jsonrpc != "2.0" {
+        return Json(reply(id, Err(RpcError::new(INVALID_REQUEST, "jsonrpc must be \"2.0\"")))).into_response();
+    }
+    
+    let result = dispatch(state, &request.method, request.params).await;
+    if request.id.is_none() {
+        return StatusCode::NO_CONTENT.into_response();
+    }
+    Json(reply(id, result)).into_response()
+}
+
+fn reply(id: Value, result: Result<Value, RpcError>) -> RpcResponse {
+    match result {
+        Ok(result) => RpcResponse { jsonrpc: "2.0", result: Some(result), error: None, id },
+        Err(error) => RpcResponse { jsonrpc: "2.0", result: None, error: Some(error), id },
+    }
+}
+
+async fn dispatch(state: AppState, method: &str, params: Value) -> Result<Value, RpcError> {
+    match method {
+        "scan" => {
+            let request: ScanRequest = params_as(params)?;
+            into_value(scan_handler(State(state), Json(request)).await?).await
+        }
+        "status" => into_value(status_handler(State(state)).await).await,
+        "tx" => {
+            let TxParams { txid, query } = params_as(params)?;
+            into_value(tx_handler(State(state), Path(txid), Query(query)).await?).await
+        }
+        "tweaks" => {
+            let query: SyncQuery = params_as(params)?;
+            into_value(sync_handler(State(state), Query(query)).await?).await
+        }
+        _ => Err(RpcError::new(METHOD_NOT_FOUND, format!("Method not found: {}", method))),
+    }
+}
+
+fn params_as<T: serde::de::DeserializeOwned>(params: Value) -> Result<T, RpcError> {
+    serde_json::from_value(params).map_err(|e| RpcError::new(INVALID_PARAMS, e.to_string()))
+}
+
+/// JSON body of a REST handler's response.
+async fn into_value(response: impl IntoResponse) -> Result<Value, RpcError> {
+    let body = response.into_response().into_body();
+    let bytes = axum::body::to_bytes(body, usize::MAX)
+        .await
+        .map_err(|e| RpcError::new(INTERNAL_ERROR, e.to_string()))?;
+    serde_json::from_slice(&bytes).map_err(|e| RpcError::new(INTERNAL_ERROR, e.to_string()))
+}
@@ -0,0 +1,172 @@
+//! Short-lived cache of confirmed scan results, so identical requests from
+//! many clients (or one client polling) don't repeat the same DB queries.
+//!
+//! Entries expire after a TTL, the least recently used entry is evicted when
+//! full, and the indexer drops every entry whose range covers a block it
+//! has just indexed (and every unspent-only entry, since the block may
+//! spend outputs anywhere below it). A scan that was querying while an
+//! invalidation happened is not cached, as it may predate the new block.
+
+use crate::OutputCandidate;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Everything that determines the confirmed part of a scan response.
+/// Prefixes are sorted and deduplicated, so their order doesn't matter.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ScanCacheKey {
+    pub start_height: i32,
+    pub end_height: i32,
+    pub prefixes: Vec<i32>,
+    pub long_prefixes: Vec<i64>,
+    pub min_amount: i64,
+    pub max_amount: i64,
+    pub include_proofs: bool,
+    pub unspent_only: bool,
+}
+
+impl ScanCacheKey {
+    pub fn new(
+        start_height: i32,
+        end_height: i32,
+        prefixes: &[i32],
+        long_prefixes: &[i64],
+        (min_amount, max_amount): (i64, i64),
+        include_proofs: bool,
+        unspent_only: bool,
+    ) -> Self {
+        let mut prefixes = prefixes.to_vec();
+        prefixes.sort_unstable();
+        prefixes.dedup();
+        let mut long_prefixes = long_prefixes.to_vec();
+        long_prefixes.sort_unstable();
+        long_prefixes.dedup();
+        
+        Self {
+            start_height,
+            end_height,
+            prefixes,
+            long_prefixes,
+            min_amount,
+            max_amount,
+            include_proofs,
+            unspent_only,
+        }
+    }
+}
+
+/// Confirmed candidates and counters of a scan, without mempool outputs.
+#[derive(Debug, Clone)]
+pub struct CachedScan {
+    pub candidates: Vec<OutputCandidate>,
+    pub matched_prefixes: usize,
+    pub candidates_before_filter: usize,
+    pub skipped_blocks: usize,
+}
+
+struct CacheEntry {
+    inserted_at: Instant,
+    last_used: u64,
+    scan: CachedScan,
+}
+
+#[derive(Default)]
+struct CacheInner {
+    entries: HashMap<ScanCacheKey, CacheEntry>,
+    /// Logical clock for LRU ordering
+    tick: u64,
+    /// Bumped by every invalidation
+    generation: u64,
+}
+
+pub struct ScanCache {
+    capacity: usize,
+    ttl: Duration,
+    inner: Mutex<CacheInner>,
+    hits: AtomicU64,
+}
+
+impl ScanCache {
+    /// A cache holding at most `capacity` entries for `ttl` each.
+    /// A capacity of 0 disables caching.
+    pub fn new(capacity: usize, ttl: Duration) -> Self {
+        Self {
+            capacity,
+            ttl,
+            inner: Mutex::default(),
+            hits: AtomicU64::new(0),
+        }
+    }
+    
+    pub fn get(&self, key: &ScanCacheKey) -> Option<CachedScan> {
+        let mut inner = self.inner.lock().unwrap();
+        inner.tick += 1;
+        let tick = inner.tick;
+        
+        let entry = inner.entries.get_mut(key)?;
+        if entry.inserted_at.elapsed() > self.ttl {
+            inner.entries.remove(key);
+            return None;
+        }
+        
+        entry.last_used = tick;
+        self.hits.fetch_add(1, Ordering::Relaxed);
+        Some(entry.scan.clone())
+    }
+    
+    /// Current invalidation generation; take it before running the query
+    /// whose result is passed to `insert`.
+    pub fn generation(&self) -> u64 {
+        self.inner.lock().unwrap().generation
+    }
+    
+    /// Cache `scan`, unless an invalidation happened since `generation` was
+    /// taken, in which case the result may be stale and is dropped.
+    pub fn insert(&self, key: ScanCacheKey, scan: CachedScan, generation: u64) {
+        if self.capacity == 0 {
+            return;
+        }
+        
+        let mut inner = self.inner.lock().unwrap();
+        if inner.generation != generation {
+            return;
+        }
+        inner.tick += 1;
+        let tick = inner.tick;
+        
+        let ttl = self.ttl;
+        inner.entries.retain(|_, e| e.inserted_at.elapsed() <= ttl);
+        if inner.entries.len() >= self.capacity && !inner.entries.contains_key(&key) {
+            let oldest = inner.entries
+                .iter()
+                .min_by_key(|(_, e)| e.last_used)
+                .map(|(k, _)| k.clone());
+            if let Some(oldest) = oldest {
+                inner.entries.remove(&oldest);
+            }
+        }
+        
+        inner.entries.insert(key, CacheEntry {
+            inserted_at: Instant::now(),
+            last_used: tick,
+            scan,
+        });
+    }
+    
+    /// Drop every entry whose height range contains `height`, and every
+    /// unspent-only entry.
+    pub fn invalidate_height(&self, height: i32) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.generation += 1;
+        inner
+            .entries
+            .retain(|key, _| !key.unspent_only && !(key.start_height..=key.end_height).contains(&height));
+    }
+    
+    /// Number of requests answered from the cache since startup.
+    pub fn hits(&self) -> u64 {
+        self.hits.load(Ordering::Relaxed)
+    }
+}
@@ -0,0 +1,2405 @@
+use crate::*;
+use axum::extract::{Json, Query, State};
+use axum::response::IntoResponse;
+use sqlx::PgPool;
+
+fn test_config() -> ServerConfig {
+    ServerConfig {
+        database_url: String::new(),
+        db_max_connections: 10,
+        db_min_connections: 0,
+        db_acquire_timeout_secs: 30,
+        db_statement_timeout_ms: 0,
+        indexer_db_max_connections: 2,
+        bitcoin_rpc_url: "http://localhost:18443".into(),
+        bitcoin_rpc_user: "bitcoin".into(),
+        bitcoin_rpc_pass: "password".into(),
+        zmq_socket: "tcp://127.0.0.1:28332".into(),
+        zmq_rcvhwm: 10_000,
+        zmq_recv_timeout_ms: 30_000,
+        block_poll_interval_ms: 2_000,
+        network: bitcoin::Network::Regtest,
+        host: "127.0.0.1".into(),
+        port: 3000,
+        max_block_range: 1000,
+        max_prefixes: 1000,
+        prefix_len: 4,
+        max_batch_size: 20,
+        mempool_ttl_secs: 1_209_600,
+        allow_full_scan: false,
+        max_full_scan_label: 100,
+        reveal_amounts: true,
+        ready_max_lag_blocks: 2,
+        scan_cache_size: 16,
+        scan_cache_ttl_secs: 30,
+        utxo_cache_size: 1000,
+        bloom_cache_size: 1000,
+        cors_origin: "*".into(),
+    }
+}
+
+/// Serializes tests that mutate process environment variables.
+static ENV_LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
+fn test_state(db: PgPool) -> AppState {
+    AppState {
+        db,
+        config: test_config(),
+        started_at: std::time::Instant::now(),
+        stats_cache: std::sync::Arc::default(),
+        indexer_progress: std::sync::Arc::default(),
+        node_rpc: None,
+        node_tip_cache: std::sync::Arc::default(),
+        scan_cache: std::sync::Arc::new(crate::scan_cache::ScanCache::new(16, std::time::Duration::from_secs(30))),
+        utxo_cache: std::sync::Arc::new(crate::utxo_cache::UtxoCache::new(1000)),
+        bloom_cache: std::sync::Arc::new(crate::bloom::BloomCache::new(1000)),
+    }
+}
+
+async fn insert_block(db: &PgPool, height: i32) {
+    let mut hash = [0u8; 32];
+    hash[..4].copy_from_slice(&height.to_be_bytes());
+    sqlx::query("INSERT INTO blocks (height, hash, header) VALUES ($1, $2, $3)")
+        .bind(height)
+        .bind(&hash[..])
+        .bind(&[0u8; 80][..])
+        .execute(db)
+        .await
+        .unwrap();
+}
+
+/// Insert a taproot output whose x-only key starts with `prefix`.
+async fn insert_output(db: &PgPool, height: i32, txid_byte: u8, vout: i32, prefix: u32) {
+    let mut x_only = [0x11u8; 32];
+    x_only[..4].copy_from_slice(&prefix.to_be_bytes());
+    insert_output_key(db, height, txid_byte, vout, x_only).await;
+}
+
+/// Insert a taproot output paying to `x_only`.
+async fn insert_output_key(db: &PgPool, height: i32, txid_byte: u8, vout: i32, x_only: [u8; 32]) {
+    let prefix = u32::from_be_bytes([x_only[0], x_only[1], x_only[2], x_only[3]]);
+    let txid = [txid_byte; 32];
+    sqlx::query(
+        "INSERT INTO transactions (txid, block_height, block_index, raw_tx)
+         VALUES ($1, $2, 0, $3) ON CONFLICT (txid) DO NOTHING",
+    )
+    .bind(&txid[..])
+    .bind(height)
+    .bind(&[0u8; 1][..])
+    .execute(db)
+    .await
+    .unwrap();
+
+    let mut script = vec![0x51, 0x20];
+    script.extend_from_slice(&x_only);
+
+    sqlx::query(
+        "INSERT INTO taproot_outputs
+         (txid, vout, block_height, script_pubkey, amount, x_only_pubkey, sp_prefix)
+         VALUES ($1, $2, $3, $4, $5, $6, $7)",
+    )
+    .bind(&txid[..])
+    .bind(vout)
+    .bind(height)
+    .bind(&script)
+    .bind(10_000i64)
+    .bind(&x_only[..])
+    .bind(prefix as i32)
+    .execute(db)
+    .await
+    .unwrap();
+}
+
+fn scan_request(start_height: i32, end_height: i32, prefixes: &[u32]) -> ScanRequest {
+    ScanRequest {
+        scan_pubkey: "ab".repeat(32),
+        start_height,
+        end_height,
+        prefixes: prefixes.iter().map(|p| format!("{:08x}", p)).collect(),
+        include_proofs: None,
+        include_mempool: None,
+        unspent_only: None,
+        min_amount: None,
+        max_amount: None,
+    }
+}
+
+/// A transaction paying to a taproot output whose key starts with `prefix`.
+fn taproot_payment(prefix: u32, seed: u8) -> bitcoin::Transaction {
+    use bitcoin::hashes::Hash;
+    
+    let mut script = vec![0x51, 0x20];
+    script.extend_from_slice(&prefix.to_be_bytes());
+    script.extend_from_slice(&[0x22u8; 28]);
+    
+    bitcoin::Transaction {
+        version: bitcoin::transaction::Version::TWO,
+        lock_time: bitcoin::absolute::LockTime::ZERO,
+        input: vec![bitcoin::TxIn {
+            previous_output: bitcoin::OutPoint {
+                txid: bitcoin::Txid::from_byte_array([seed; 32]),
+                vout: 0,
+            },
+            ..Default::default()
+        }],
+        output: vec![bitcoin::TxOut {
+            value: bitcoin::Amount::from_sat(50_000),
+            script_pubkey: bitcoin::ScriptBuf::from_bytes(script),
+        }],
+    }
+}
+
+/// A block at `height` (BIP34 coinbase, height < 128) containing `txs`.
+fn block_at(height: u8, prev: bitcoin::BlockHash, txs: Vec<bitcoin::Transaction>) -> bitcoin::Block {
+    let coinbase = bitcoin::Transaction {
+        version: bitcoin::transaction::Version::TWO,
+        lock_time: bitcoin::absolute::LockTime::ZERO,
+        input: vec![bitcoin::TxIn {
+            previous_output: bitcoin::OutPoint::null(),
+            script_sig: bitcoin::ScriptBuf::from_bytes(vec![0x01, height]),
+            ..Default::default()
+        }],
+        output: vec![bitcoin::TxOut {
+            value: bitcoin::Amount::from_sat(5_000_000_000),
+            script_pubkey: bitcoin::ScriptBuf::new(),
+        }],
+    };
+    
+    let mut txdata = vec![coinbase];
+    txdata.extend(txs);
+    let mut block = bitcoin::Block {
+        header: bitcoin::block::Header {
+            version: bitcoin::block::Version::TWO,
+            prev_blockhash: prev,
+            merkle_root: bitcoin::TxMerkleNode::from_raw_hash(bitcoin::hashes::Hash::all_zeros()),
+            time: 1_700_000_000 + height as u32,
+            bits: bitcoin::CompactTarget::from_consensus(0x207fffff),
+            nonce: 0,
+        },
+        txdata,
+    };
+    block.header.merkle_root = block.compute_merkle_root().unwrap();
+    block
+}
+
+async fn response_json(response: impl IntoResponse) -> serde_json::Value {
+    let body = response.into_response().into_body();
+    let bytes = axum::body::to_bytes(body, usize::MAX).await.unwrap();
+    serde_json::from_slice(&bytes).unwrap()
+}
+
+#[sqlx::test]
+async fn test_batch_scan_matches_individual_scans(db: PgPool) {
+    for height in 100..=102 {
+        insert_block(&db, height).await;
+    }
+    insert_output(&db, 100, 0x01, 0, 0xdeadbeef).await;
+    insert_output(&db, 101, 0x02, 1, 0x0000abcd).await;
+    insert_output(&db, 102, 0x03, 0, 0xdeadbeef).await;
+    insert_output(&db, 102, 0x03, 1, 0x12345678).await;
+
+    let requests = [
+        scan_request(100, 102, &[0xdeadbeef]),
+        scan_request(101, 102, &[0x0000abcd, 0x12345678, 0x12345678]),
+        scan_request(100, 100, &[0x99999999]),
+    ];
+
+    let mut individual = Vec::new();
+    for req in &requests {
+        let response = scan_handler(State(test_state(db.clone())), Json(req.clone()))
+            .await
+            .unwrap();
+        individual.push(response_json(response).await);
+    }
+
+    let response = scan_batch_handler(State(test_state(db.clone())), Json(requests.to_vec()))
+        .await
+        .unwrap();
+    let batch = response_json(response).await;
+    let batch = batch.as_array().unwrap();
+
+    assert_eq!(batch.len(), requests.len());
+    for (single, batched) in individual.iter().zip(batch) {
+        assert_eq!(single["candidates"], batched["candidates"]);
+        assert_eq!(single["scanned_blocks"], batched["scanned_blocks"]);
+    }
+    assert_eq!(batch[0]["candidates"].as_array().unwrap().len(), 2);
+    assert_eq!(batch[1]["candidates"].as_array().unwrap().len(), 2);
+    assert!(batch[2]["candidates"].as_array().unwrap().is_empty());
+}
+
+#[sqlx::test]
+async fn test_batch_scan_rejects_oversized_batch(db: PgPool) {
+    let requests = vec![scan_request(0, 1, &[1]); test_config().max_batch_size + 1];
+    let result = scan_batch_handler(State(test_state(db)), Json(requests)).await;
+    assert!(matches!(result, Err(ApiError::Validation(_))));
+}
+
+#[sqlx::test]
+async fn test_scan_includes_verifiable_proofs(db: PgPool) {
+    use bitcoin::hashes::Hash;
+    
+    insert_block(&db, 100).await;
+    insert_output(&db, 100, 0x01, 0, 0x01020304).await;
+    insert_output(&db, 100, 0x02, 0, 0x05060708).await;
+    insert_output(&db, 100, 0x03, 0, 0x01020304).await;
+    
+    // Give the block a header committing to its three transactions
+    let txids = [[0x01u8; 32], [0x02u8; 32], [0x03u8; 32]];
+    let root = whisper_core::merkle_root_from_branch(&txids[0], &whisper_core::merkle_branch(&txids, 0), 0);
+    let mut header = [0u8; 80];
+    header[36..68].copy_from_slice(&root);
+    sqlx::query("UPDATE blocks SET header = $1 WHERE height = 100")
+        .bind(&header[..])
+        .execute(&db)
+        .await
+        .unwrap();
+    sqlx::query("UPDATE transactions SET block_index = get_byte(txid, 0)")
+        .execute(&db)
+        .await
+        .unwrap();
+    
+    let mut req = scan_request(100, 100, &[0x01020304]);
+    req.include_proofs = Some(true);
+    let response = scan_handler(State(test_state(db)), Json(req)).await.unwrap();
+    let json = response_json(response).await;
+    let candidates = json["candidates"].as_array().unwrap();
+    assert_eq!(candidates.len(), 2);
+    
+    for candidate in candidates {
+        let proof = &candidate["proof"];
+        let txid = candidate["txid"].as_str().unwrap().parse::<bitcoin::Txid>().unwrap().to_byte_array();
+        let branch: Vec<[u8; 32]> = proof["branch"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|h| hex::decode(h.as_str().unwrap()).unwrap().try_into().unwrap())
+            .collect();
+        let header = hex::decode(proof["block_header"].as_str().unwrap()).unwrap();
+        let index = proof["tx_index"].as_u64().unwrap() as u32;
+        assert!(whisper_core::verify_merkle_proof(&txid, &branch, index, &header).unwrap());
+    }
+}
+
+#[sqlx::test]
+async fn test_scan_reports_prefix_and_filter_counts(db: PgPool) {
+    insert_block(&db, 100).await;
+    insert_block(&db, 101).await;
+    insert_output(&db, 100, 0x01, 0, 0xaaaa0001).await;
+    insert_output(&db, 101, 0x02, 0, 0xaaaa0001).await;
+    insert_output(&db, 101, 0x02, 1, 0xbbbb0002).await;
+    sqlx::query("UPDATE blocks SET is_orphaned = TRUE WHERE height = 101")
+        .execute(&db)
+        .await
+        .unwrap();
+    
+    let req = scan_request(100, 101, &[0xaaaa0001, 0xcccc0003]);
+    let response = scan_handler(State(test_state(db)), Json(req)).await.unwrap();
+    let json = response_json(response).await;
+    
+    assert_eq!(json["matched_prefixes"], 1);
+    assert_eq!(json["candidates_before_filter"], 2);
+    assert_eq!(json["candidates"].as_array().unwrap().len(), 1);
+}
+
+async fn rpc_call(db: &PgPool, request: serde_json::Value) -> serde_json::Value {
+    let body = axum::body::Bytes::from(request.to_string());
+    response_json(crate::rpc::rpc_handler(State(test_state(db.clone())), body).await).await
+}
+
+#[sqlx::test]
+async fn test_rpc_scan_matches_rest(db: PgPool) {
+    insert_block(&db, 100).await;
+    insert_output(&db, 100, 0x01, 0, 0xaaaa0001).await;
+    insert_output(&db, 100, 0x01, 1, 0xbbbb0002).await;
+    
+    let req = scan_request(100, 100, &[0xaaaa0001]);
+    let params = serde_json::json!({
+        "scan_pubkey": req.scan_pubkey,
+        "start_height": 100,
+        "end_height": 100,
+        "prefixes": req.prefixes,
+    });
+    let reply = rpc_call(&db, serde_json::json!({
+        "jsonrpc": "2.0",
+        "method": "scan",
+        "params": params,
+        "id": 7,
+    }))
+    .await;
+    
+    assert_eq!(reply["jsonrpc"], "2.0");
+    assert_eq!(reply["id"], 7);
+    assert!(reply.get("error").is_none());
+    let candidates = reply["result"]["candidates"].as_array().unwrap();
+    assert_eq!(candidates.len(), 1);
+    assert_eq!(candidates[0]["vout"], 0);
+    
+    // Validation failures keep their message under an RPC error code
+    let reply = rpc_call(&db, serde_json::json!({
+        "jsonrpc": "2.0",
+        "method": "scan",
+        "params": { "scan_pubkey": req.scan_pubkey, "start_height": 100, "end_height": 100, "prefixes": [] },
+        "id": 8,
+    }))
+    .await;
+    assert_eq!(reply["error"]["code"], crate::rpc::INVALID_PARAMS);
+    assert!(reply.get("result").is_none());
+}
+
+#[sqlx::test]
+async fn test_rpc_unknown_method_and_bad_envelope(db: PgPool) {
+    let reply = rpc_call(&db, serde_json::json!({
+        "jsonrpc": "2.0",
+        "method": "getblockcount",
+        "id": "a",
+    }))
+    .await;
+    assert_eq!(reply["error"]["code"], -32601);
+    assert_eq!(reply["id"], "a");
+    
+    let reply = rpc_call(&db, serde_json::json!({ "jsonrpc": "1.0", "method": "status", "id": 1 })).await;
+    assert_eq!(reply["error"]["code"], -32600);
+    
+    let body = axum::body::Bytes::from_static(b"{not json");
+    let reply = response_json(crate::rpc::rpc_handler(State(test_state(db.clone())), body).await).await;
+    assert_eq!(reply["error"]["code"], -32700);
+    assert!(reply["id"].is_null());
+    
+    // Params that don't fit the method
+    let reply = rpc_call(&db, serde_json::json!({
+        "jsonrpc": "2.0",
+        "method": "tweaks",
+        "params": { "since_height": "soon" },
+        "id": 2,
+    }))
+    .await;
+    assert_eq!(reply["error"]["code"], -32602);
+}
+
+#[sqlx::test]
+async fn test_rpc_notification_gets_no_reply(db: PgPool) {
+    let body = axum::body::Bytes::from(serde_json::json!({ "jsonrpc": "2.0", "method": "status" }).to_string());
+    let response = crate::rpc::rpc_handler(State(test_state(db.clone())), body).await;
+    assert_eq!(response.status(), axum::http::StatusCode::NO_CONTENT);
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    assert!(body.is_empty());
+    
+    // An explicit null id is a request, not a notification
+    let reply = rpc_call(&db, serde_json::json!({ "jsonrpc": "2.0", "method": "status", "id": null })).await;
+    assert!(reply["id"].is_null());
+    assert!(reply.get("result").is_some());
+}
+
+#[sqlx::test]
+async fn test_mempool_output_moves_to_confirmed(db: PgPool) {
+    use bitcoin::hashes::Hash;
+    
+    let tx = taproot_payment(0x0badf00d, 7);
+    crate::indexer::process_mempool_tx(&db, &tx).await.unwrap();
+    
+    let mut req = scan_request(0, 200, &[0x0badf00d]);
+    req.include_mempool = Some(true);
+    
+    // Unconfirmed: returned with no block
+    let response = scan_handler(State(test_state(db.clone())), Json(req.clone())).await.unwrap();
+    let json = response_json(response).await;
+    let candidates = json["candidates"].as_array().unwrap();
+    assert_eq!(candidates.len(), 1);
+    assert!(candidates[0]["block_height"].is_null());
+    assert!(candidates[0]["block_hash"].is_null());
+    
+    // Not returned unless asked for
+    let plain = scan_request(0, 200, &[0x0badf00d]);
+    let response = scan_handler(State(test_state(db.clone())), Json(plain)).await.unwrap();
+    assert!(response_json(response).await["candidates"].as_array().unwrap().is_empty());
+    
+    // Confirmed: the mempool row is gone and the output carries its block
+    let block = block_at(100, bitcoin::BlockHash::all_zeros(), vec![tx]);
+    crate::indexer::process_block(&test_state(db.clone()), None, &block).await.unwrap();
+    
+    let response = scan_handler(State(test_state(db.clone())), Json(req)).await.unwrap();
+    let json = response_json(response).await;
+    let candidates = json["candidates"].as_array().unwrap();
+    assert_eq!(candidates.len(), 1);
+    assert_eq!(candidates[0]["block_height"], 100);
+    
+    let (mempool_rows,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM mempool_outputs")
+        .fetch_one(&db)
+        .await
+        .unwrap();
+    assert_eq!(mempool_rows, 0);
+}
+
+#[test]
+fn test_config_parses_db_pool_settings() {
+    let _guard = ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    // sqlx tests read DATABASE_URL too, so only set it when missing
+    if std::env::var("DATABASE_URL").is_err() {
+        std::env::set_var("DATABASE_URL", "postgres://localhost/whisper");
+    }
+    std::env::set_var("DB_MAX_CONNECTIONS", "42");
+    std::env::set_var("DB_MIN_CONNECTIONS", "2");
+    std::env::set_var("DB_ACQUIRE_TIMEOUT_SECS", "5");
+    
+    let config = ServerConfig::from_env().unwrap();
+    assert_eq!(config.db_max_connections, 42);
+    assert_eq!(config.db_min_connections, 2);
+    assert_eq!(config.db_acquire_timeout_secs, 5);
+    
+    std::env::set_var("DB_MAX_CONNECTIONS", "lots");
+    let result = ServerConfig::from_env();
+    assert!(matches!(result, Err(ConfigError::Parse(msg)) if msg.contains("db_max_connections")));
+    
+    std::env::remove_var("DB_MAX_CONNECTIONS");
+    std::env::remove_var("DB_MIN_CONNECTIONS");
+    std::env::remove_var("DB_ACQUIRE_TIMEOUT_SECS");
+    
+    let config = ServerConfig::from_env().unwrap();
+    assert_eq!(config.db_max_connections, 10);
+    assert_eq!(config.db_min_connections, 0);
+    assert_eq!(config.db_acquire_timeout_secs, 30);
+}
+
+#[test]
+fn test_config_parses_network() {
+    let _guard = ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    if std::env::var("DATABASE_URL").is_err() {
+        std::env::set_var("DATABASE_URL", "postgres://localhost/whisper");
+    }
+    
+    let cases = [
+        ("mainnet", bitcoin::Network::Bitcoin),
+        ("bitcoin", bitcoin::Network::Bitcoin),
+        ("testnet3", bitcoin::Network::Testnet),
+        ("Testnet", bitcoin::Network::Testnet),
+        ("signet", bitcoin::Network::Signet),
+        ("regtest", bitcoin::Network::Regtest),
+    ];
+    for (name, expected) in cases {
+        std::env::set_var("NETWORK", name);
+        assert_eq!(ServerConfig::from_env().unwrap().network, expected, "{}", name);
+    }
+    
+    // The configured network picks the address prefix
+    let scan_key = whisper_core::ScanKey::from_slice(&[1u8; 32]).unwrap();
+    std::env::set_var("NETWORK", "mainnet");
+    let network = ServerConfig::from_env().unwrap().network;
+    assert!(scan_key.to_address(&scan_key.public, network).to_string().starts_with("sp1"));
+    
+    std::env::set_var("NETWORK", "tesnet");
+    let result = ServerConfig::from_env();
+    assert!(matches!(result, Err(ConfigError::Parse(msg)) if msg.contains("tesnet")));
+    
+    std::env::remove_var("NETWORK");
+    assert_eq!(ServerConfig::from_env().unwrap().network, bitcoin::Network::Regtest);
+}
+
+async fn get_block(db: &PgPool, height: i32, include_orphans: Option<bool>) -> Result<serde_json::Value, ApiError> {
+    let response = block_handler(
+        State(test_state(db.clone())),
+        axum::extract::Path(height),
+        Query(BlockQuery { include_orphans }),
+    )
+    .await?;
+    Ok(response_json(response).await)
+}
+
+#[sqlx::test]
+async fn test_block_header_endpoint(db: PgPool) {
+    insert_block(&db, 100).await;
+    insert_block(&db, 101).await;
+    sqlx::query("UPDATE blocks SET is_orphaned = TRUE WHERE height = 101")
+        .execute(&db)
+        .await
+        .unwrap();
+    
+    // Known height
+    let block = get_block(&db, 100, None).await.unwrap();
+    assert_eq!(block["height"], 100);
+    assert_eq!(block["header"].as_str().unwrap().len(), 160);
+    assert_eq!(block["hash"].as_str().unwrap().len(), 64);
+    assert_eq!(block["is_orphaned"], false);
+    
+    // Unknown height
+    assert!(matches!(get_block(&db, 500, None).await, Err(ApiError::NotFound(_))));
+    assert!(matches!(get_block(&db, 500, Some(true)).await, Err(ApiError::NotFound(_))));
+    
+    // Orphaned block is hidden unless requested
+    assert!(matches!(get_block(&db, 101, None).await, Err(ApiError::NotFound(_))));
+    let orphan = get_block(&db, 101, Some(true)).await.unwrap();
+    assert_eq!(orphan["is_orphaned"], true);
+    
+    let status = ApiError::NotFound(String::new()).into_response().status();
+    assert_eq!(status, axum::http::StatusCode::NOT_FOUND);
+}
+
+#[sqlx::test]
+async fn test_blocks_endpoint_lists_recent_blocks(db: PgPool) {
+    for height in 100..=105 {
+        insert_block(&db, height).await;
+    }
+    sqlx::query("UPDATE blocks SET is_orphaned = TRUE WHERE height = 105")
+        .execute(&db)
+        .await
+        .unwrap();
+    insert_output(&db, 104, 1, 0, 0xaabbccdd).await;
+    insert_output(&db, 104, 1, 1, 0x11223344).await;
+    
+    let get_blocks = |limit| {
+        let state = test_state(db.clone());
+        async move {
+            let response = blocks_handler(State(state), Query(BlocksQuery { limit })).await?;
+            Ok::<_, ApiError>(response_json(response).await)
+        }
+    };
+    
+    // Highest first, orphans skipped, limited
+    let blocks = get_blocks(Some(3)).await.unwrap();
+    let heights: Vec<i64> = blocks.as_array().unwrap().iter()
+        .map(|b| b["height"].as_i64().unwrap())
+        .collect();
+    assert_eq!(heights, vec![104, 103, 102]);
+    assert_eq!(blocks[0]["taproot_outputs"], 2);
+    assert_eq!(blocks[1]["taproot_outputs"], 0);
+    assert_eq!(blocks[0]["hash"].as_str().unwrap().len(), 64);
+    
+    // Default limit covers every indexed block here
+    assert_eq!(get_blocks(None).await.unwrap().as_array().unwrap().len(), 5);
+    
+    assert!(matches!(get_blocks(Some(0)).await, Err(ApiError::Validation(_))));
+    assert!(matches!(get_blocks(Some(MAX_BLOCKS_LIMIT + 1)).await, Err(ApiError::Validation(_))));
+}
+
+async fn get_tx(db: &PgPool, txid: String, include_orphans: Option<bool>) -> Result<serde_json::Value, ApiError> {
+    let response = tx_handler(
+        State(test_state(db.clone())),
+        axum::extract::Path(txid),
+        Query(BlockQuery { include_orphans }),
+    )
+    .await?;
+    Ok(response_json(response).await)
+}
+
+#[sqlx::test]
+async fn test_tx_endpoint(db: PgPool) {
+    insert_block(&db, 100).await;
+    insert_block(&db, 101).await;
+    insert_output(&db, 100, 0x01, 0, 0x01020304).await;
+    insert_output(&db, 101, 0x02, 0, 0x01020304).await;
+    sqlx::query("UPDATE blocks SET is_orphaned = TRUE WHERE height = 101")
+        .execute(&db)
+        .await
+        .unwrap();
+    
+    // Known txid
+    let tx = get_tx(&db, "01".repeat(32), None).await.unwrap();
+    assert_eq!(tx["txid"], "01".repeat(32));
+    assert_eq!(tx["raw_tx"], "00");
+    assert_eq!(tx["block_height"], 100);
+    assert_eq!(tx["block_index"], 0);
+    assert_eq!(tx["block_hash"].as_str().unwrap().len(), 64);
+    assert_eq!(tx["is_orphaned"], false);
+    
+    // Unknown and malformed txids
+    assert!(matches!(get_tx(&db, "ff".repeat(32), None).await, Err(ApiError::NotFound(_))));
+    assert!(matches!(get_tx(&db, "abcd".into(), None).await, Err(ApiError::Validation(_))));
+    
+    // Transaction in an orphaned block is hidden unless requested
+    assert!(matches!(get_tx(&db, "02".repeat(32), None).await, Err(ApiError::NotFound(_))));
+    let orphan = get_tx(&db, "02".repeat(32), Some(true)).await.unwrap();
+    assert_eq!(orphan["block_height"], 101);
+    assert_eq!(orphan["is_orphaned"], true);
+}
+
+async fn indexed_heights(db: &PgPool) -> Vec<i32> {
+    let rows: Vec<(i32,)> = sqlx::query_as("SELECT height FROM blocks ORDER BY height")
+        .fetch_all(db)
+        .await
+        .unwrap();
+    rows.into_iter().map(|r| r.0).collect()
+}
+
+#[sqlx::test]
+async fn test_indexer_skips_redelivered_block(db: PgPool) {
+    use bitcoin::hashes::Hash;
+    
+    let mut pending = crate::indexer::PendingBlocks::default();
+    let state = test_state(db.clone());
+    let block = block_at(100, bitcoin::BlockHash::all_zeros(), vec![taproot_payment(0x01020304, 1)]);
+    
+    crate::indexer::handle_block(&state, None, &mut pending, block.clone()).await.unwrap();
+    crate::indexer::handle_block(&state, None, &mut pending, block).await.unwrap();
+    
+    assert_eq!(indexed_heights(&db).await, vec![100]);
+    let (outputs,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM taproot_outputs")
+        .fetch_one(&db)
+        .await
+        .unwrap();
+    assert_eq!(outputs, 1);
+}
+
+#[sqlx::test]
+async fn test_indexer_queues_out_of_order_block(db: PgPool) {
+    use bitcoin::hashes::Hash;
+    
+    let mut pending = crate::indexer::PendingBlocks::default();
+    let state = test_state(db.clone());
+    let b99 = block_at(99, bitcoin::BlockHash::all_zeros(), vec![]);
+    let b100 = block_at(100, b99.block_hash(), vec![taproot_payment(0x0a0b0c0d, 2)]);
+    let b101 = block_at(101, b100.block_hash(), vec![taproot_payment(0x0a0b0c0d, 3)]);
+    
+    crate::indexer::handle_block(&state, None, &mut pending, b99).await.unwrap();
+    
+    // Child arrives before its parent: queued, not indexed
+    crate::indexer::handle_block(&state, None, &mut pending, b101).await.unwrap();
+    assert_eq!(indexed_heights(&db).await, vec![99]);
+    assert_eq!(pending.len(), 1);
+    
+    // Parent arrives: both get indexed in order
+    crate::indexer::handle_block(&state, None, &mut pending, b100).await.unwrap();
+    assert_eq!(indexed_heights(&db).await, vec![99, 100, 101]);
+    assert_eq!(pending.len(), 0);
+}
+
+/// Notification source that replays a fixed script, then closes.
+#[cfg(feature = "zmq")]
+struct ScriptedSource(std::collections::VecDeque<Result<Vec<Vec<u8>>, zmq::Error>>);
+
+#[cfg(feature = "zmq")]
+impl crate::block_source::NotificationSource for ScriptedSource {
+    fn recv(&mut self) -> Result<Option<Vec<Vec<u8>>>, zmq::Error> {
+        self.0.pop_front().transpose()
+    }
+}
+
+#[cfg(feature = "zmq")]
+#[sqlx::test]
+async fn test_indexer_reconnects_after_recv_error(db: PgPool) {
+    use bitcoin::hashes::Hash;
+    
+    let state = test_state(db.clone());
+    let block = block_at(100, bitcoin::BlockHash::all_zeros(), vec![taproot_payment(0x01020304, 1)]);
+    let rawblock = vec![b"rawblock".to_vec(), bitcoin::consensus::serialize(&block)];
+    
+    // First connection fails mid-stream, the second delivers a block
+    let mut connections = std::collections::VecDeque::from(vec![
+        ScriptedSource(vec![Err(zmq::Error::ECONNREFUSED)].into()),
+        ScriptedSource(vec![Ok(rawblock)].into()),
+    ]);
+    let mut connects = 0;
+    
+    crate::indexer::run_with_reconnect(&state, None, 1, || {
+        connects += 1;
+        let source = connections.pop_front().expect("unexpected reconnect");
+        Ok(Box::new(crate::block_source::ZmqBlockSource::new(source, db.clone())))
+    })
+    .await
+    .unwrap();
+    
+    assert_eq!(connects, 2);
+    assert_eq!(indexed_heights(&db).await, vec![100]);
+}
+
+/// Replays a script, then times out (EAGAIN) on every receive and asks the
+/// indexer to stop, like an idle ZMQ socket during shutdown.
+#[cfg(feature = "zmq")]
+struct IdleSource {
+    script: std::collections::VecDeque<Result<Vec<Vec<u8>>, zmq::Error>>,
+    progress: std::sync::Arc<crate::IndexerProgress>,
+}
+
+#[cfg(feature = "zmq")]
+impl crate::block_source::NotificationSource for IdleSource {
+    fn recv(&mut self) -> Result<Option<Vec<Vec<u8>>>, zmq::Error> {
+        if let Some(next) = self.script.pop_front() {
+            return next.map(Some);
+        }
+        self.progress.request_stop();
+        Err(zmq::Error::EAGAIN)
+    }
+}
+
+#[cfg(feature = "zmq")]
+#[sqlx::test]
+async fn test_receive_timeout_keeps_loop_running(db: PgPool) {
+    use bitcoin::hashes::Hash;
+    
+    let state = test_state(db.clone());
+    let block = block_at(100, bitcoin::BlockHash::all_zeros(), vec![taproot_payment(0x01020304, 1)]);
+    let rawblock = vec![b"rawblock".to_vec(), bitcoin::consensus::serialize(&block), 0u32.to_le_bytes().to_vec()];
+    
+    // Timeouts before the block don't end the loop or force a reconnect;
+    // one after the stop request does
+    let mut source = Some(IdleSource {
+        script: vec![Err(zmq::Error::EAGAIN), Err(zmq::Error::EAGAIN), Ok(rawblock)].into(),
+        progress: state.indexer_progress.clone(),
+    });
+    let mut connects = 0;
+    crate::indexer::run_with_reconnect(&state, None, 1, || {
+        connects += 1;
+        let source = source.take().expect("unexpected reconnect");
+        Ok(Box::new(crate::block_source::ZmqBlockSource::new(source, db.clone())))
+    })
+    .await
+    .unwrap();
+    
+    assert_eq!(connects, 1);
+    assert_eq!(indexed_heights(&db).await, vec![100]);
+}
+
+#[cfg(feature = "zmq")]
+#[sqlx::test]
+async fn test_zmq_source_yields_after_rawtx_and_reports_block_gaps(db: PgPool) {
+    use crate::block_source::{BlockSource, SourceEvent};
+    use bitcoin::hashes::Hash;
+    
+    let b100 = block_at(100, bitcoin::BlockHash::all_zeros(), vec![taproot_payment(0x01020304, 1)]);
+    let b103 = block_at(103, bitcoin::BlockHash::all_zeros(), vec![taproot_payment(0x05060708, 2)]);
+    let msg = |topic: &str, data: Vec<u8>, seq: u32| vec![topic.as_bytes().to_vec(), data, seq.to_le_bytes().to_vec()];
+    let rawtx = |seq: u32| msg("rawtx", bitcoin::consensus::serialize(&taproot_payment(0x0a0b0c00 + seq, 3)), seq);
+    
+    // Two mempool transactions, block 100, then block 103 after two
+    // dropped block notifications
+    let script = vec![
+        Ok(rawtx(0)),
+        Ok(rawtx(1)),
+        Ok(msg("rawblock", bitcoin::consensus::serialize(&b100), 0)),
+        Ok(msg("rawblock", bitcoin::consensus::serialize(&b103), 3)),
+    ];
+    let mut source = crate::block_source::ZmqBlockSource::new(ScriptedSource(script.into()), db.clone());
+    
+    let mut events = Vec::new();
+    loop {
+        match source.next_block().await.unwrap() {
+            SourceEvent::Block(block) if block.block_hash() == b100.block_hash() => events.push("block 100"),
+            SourceEvent::Block(block) if block.block_hash() == b103.block_hash() => events.push("block 103"),
+            SourceEvent::Block(_) => events.push("other block"),
+            SourceEvent::Idle => events.push("idle"),
+            SourceEvent::MissedBlocks => events.push("missed"),
+            SourceEvent::Closed => break,
+        }
+    }
+    
+    assert_eq!(events, vec!["idle", "idle", "block 100", "missed", "block 103"]);
+    let mempool: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM mempool_outputs")
+        .fetch_one(&db)
+        .await
+        .unwrap();
+    assert_eq!(mempool, 2);
+}
+
+/// Delivers mempool transactions forever, asking the indexer to stop on
+/// the first one, like a busy node during shutdown.
+#[cfg(feature = "zmq")]
+struct FloodSource(std::sync::Arc<crate::IndexerProgress>);
+
+#[cfg(feature = "zmq")]
+impl crate::block_source::NotificationSource for FloodSource {
+    fn recv(&mut self) -> Result<Option<Vec<Vec<u8>>>, zmq::Error> {
+        self.0.request_stop();
+        Ok(Some(vec![b"rawtx".to_vec(), bitcoin::consensus::serialize(&taproot_payment(0x01020304, 1))]))
+    }
+}
+
+#[cfg(feature = "zmq")]
+#[sqlx::test]
+async fn test_mempool_traffic_does_not_block_shutdown(db: PgPool) {
+    let state = test_state(db.clone());
+    let mut source = Some(FloodSource(state.indexer_progress.clone()));
+    let run = crate::indexer::run_with_reconnect(&state, None, 1, || {
+        let source = source.take().expect("unexpected reconnect");
+        Ok(Box::new(crate::block_source::ZmqBlockSource::new(source, db.clone())))
+    });
+    
+    tokio::time::timeout(std::time::Duration::from_secs(10), run)
+        .await
+        .expect("indexer kept consuming rawtx after stop")
+        .unwrap();
+}
+
+#[cfg(feature = "zmq")]
+#[test]
+fn test_zmq_sequence_gaps_are_detected() {
+    let msg = |topic: &str, seq: u32| vec![topic.as_bytes().to_vec(), vec![0u8], seq.to_le_bytes().to_vec()];
+    let mut sequences = crate::block_source::SequenceTracker::default();
+    
+    assert_eq!(sequences.missed(&msg("rawblock", 7)), 0);
+    assert_eq!(sequences.missed(&msg("rawblock", 8)), 0);
+    // Topics are numbered independently
+    assert_eq!(sequences.missed(&msg("rawtx", 100)), 0);
+    assert_eq!(sequences.missed(&msg("rawblock", 11)), 2);
+    assert_eq!(sequences.missed(&msg("rawtx", 101)), 0);
+    // Node restart and messages without a sequence frame
+    assert_eq!(sequences.missed(&msg("rawblock", 0)), 0);
+    assert_eq!(sequences.missed(&[b"rawblock".to_vec(), vec![0u8]]), 0);
+}
+
+/// Node whose tip moves through a fixed list of blocks, one poll each,
+/// then stays at the last.
+struct ScriptedTips {
+    tips: std::sync::Mutex<std::collections::VecDeque<bitcoin::Block>>,
+    blocks: std::collections::HashMap<bitcoin::BlockHash, bitcoin::Block>,
+}
+
+impl ScriptedTips {
+    fn new(tips: Vec<bitcoin::Block>) -> Self {
+        let blocks = tips.iter().map(|b| (b.block_hash(), b.clone())).collect();
+        Self { tips: std::sync::Mutex::new(tips.into()), blocks }
+    }
+}
+
+impl crate::block_source::TipRpc for ScriptedTips {
+    async fn best_block_hash(&self) -> Result<bitcoin::BlockHash, crate::IndexerError> {
+        let mut tips = self.tips.lock().unwrap();
+        let tip = if tips.len() > 1 { tips.pop_front() } else { tips.front().cloned() };
+        Ok(tip.expect("at least one tip").block_hash())
+    }
+    
+    async fn block(&self, hash: bitcoin::BlockHash) -> Result<bitcoin::Block, crate::IndexerError> {
+        Ok(self.blocks[&hash].clone())
+    }
+}
+
+#[sqlx::test]
+async fn test_rpc_polling_source_delivers_new_tips(db: PgPool) {
+    use crate::block_source::{BlockSource, RpcPollingSource, SourceEvent};
+    use bitcoin::hashes::Hash;
+    
+    let state = test_state(db.clone());
+    let b100 = block_at(100, bitcoin::BlockHash::all_zeros(), vec![taproot_payment(0x01020304, 1)]);
+    let b101 = block_at(101, b100.block_hash(), vec![taproot_payment(0x05060708, 2)]);
+    
+    // The node reports 100 twice, then 101 from then on
+    let rpc = ScriptedTips::new(vec![b100.clone(), b100.clone(), b101.clone()]);
+    let mut source = RpcPollingSource::new(rpc, std::time::Duration::from_millis(1));
+    let mut pending = crate::indexer::PendingBlocks::default();
+    
+    let mut delivered = Vec::new();
+    for _ in 0..4 {
+        match source.next_block().await.unwrap() {
+            SourceEvent::Block(block) => {
+                delivered.push(block.block_hash());
+                crate::indexer::handle_block(&state, None, &mut pending, block).await.unwrap();
+            }
+            SourceEvent::Idle => delivered.push(bitcoin::BlockHash::all_zeros()),
+            SourceEvent::MissedBlocks | SourceEvent::Closed => panic!("polling neither drops blocks nor closes"),
+        }
+    }
+    
+    // Each tip once, idle polls while it doesn't change
+    assert_eq!(delivered, vec![
+        b100.block_hash(),
+        bitcoin::BlockHash::all_zeros(),
+        b101.block_hash(),
+        bitcoin::BlockHash::all_zeros(),
+    ]);
+    assert_eq!(indexed_heights(&db).await, vec![100, 101]);
+}
+
+/// Block source that delivers a fixed list of blocks, then closes.
+struct VecBlockSource(std::collections::VecDeque<bitcoin::Block>);
+
+impl crate::block_source::BlockSource for VecBlockSource {
+    fn next_block(&mut self) -> crate::block_source::NextBlock<'_> {
+        use crate::block_source::SourceEvent;
+        
+        let event = self.0.pop_front().map_or(SourceEvent::Closed, SourceEvent::Block);
+        Box::pin(async move { Ok(event) })
+    }
+}
+
+#[sqlx::test]
+async fn test_indexer_loop_follows_reorg(db: PgPool) {
+    use bitcoin::hashes::Hash;
+    
+    let state = test_state(db.clone());
+    let b100 = block_at(100, bitcoin::BlockHash::all_zeros(), vec![taproot_payment(0x01020304, 1)]);
+    let b101a = block_at(101, b100.block_hash(), vec![taproot_payment(0x0a0a0a0a, 2)]);
+    let b102a = block_at(102, b101a.block_hash(), vec![taproot_payment(0x0b0b0b0b, 3)]);
+    let b101b = block_at(101, b100.block_hash(), vec![taproot_payment(0x0c0c0c0c, 4)]);
+    let b102b = block_at(102, b101b.block_hash(), vec![taproot_payment(0x0d0d0d0d, 5)]);
+    let b103b = block_at(103, b102b.block_hash(), vec![taproot_payment(0x0e0e0e0e, 6)]);
+    
+    // Branch a to 102, then branch b from 100 overtakes it; 102a is
+    // redelivered late and must not come back
+    let blocks = vec![b100.clone(), b101a, b102a.clone(), b101b.clone(), b102b.clone(), b102a, b103b.clone()];
+    let mut source = Some(VecBlockSource(blocks.into()));
+    crate::indexer::run_with_reconnect(&state, None, 1, || {
+        Ok(Box::new(source.take().expect("unexpected reconnect")))
+    })
+    .await
+    .unwrap();
+    
+    let stored: Vec<(i32, Vec<u8>)> = sqlx::query_as("SELECT height, hash FROM blocks ORDER BY height")
+        .fetch_all(&db)
+        .await
+        .unwrap();
+    let expected: Vec<(i32, Vec<u8>)> = [(100, &b100), (101, &b101b), (102, &b102b), (103, &b103b)]
+        .iter()
+        .map(|(height, block)| (*height, block.block_hash().to_byte_array().to_vec()))
+        .collect();
+    assert_eq!(stored, expected);
+    
+    // Only the winning branch's outputs remain, and the checkpoint is its tip
+    let (outputs,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM taproot_outputs")
+        .fetch_one(&db)
+        .await
+        .unwrap();
+    assert_eq!(outputs, 4);
+    assert_eq!(
+        crate::indexer::load_checkpoint(&db).await.unwrap(),
+        Some((b103b.block_hash(), 103))
+    );
+}
+
+/// Block source that moves a `FakeNode`'s active chain before announcing
+/// each block, then closes.
+struct NodeBlockSource {
+    node: FakeNode,
+    steps: std::collections::VecDeque<(Option<Vec<bitcoin::Block>>, bitcoin::Block)>,
+}
+
+impl crate::block_source::BlockSource for NodeBlockSource {
+    fn next_block(&mut self) -> crate::block_source::NextBlock<'_> {
+        use crate::block_source::SourceEvent;
+        
+        let event = match self.steps.pop_front() {
+            Some((chain, block)) => {
+                if let Some(chain) = chain {
+                    self.node.set_chain(chain);
+                }
+                SourceEvent::Block(block)
+            }
+            None => SourceEvent::Closed,
+        };
+        Box::pin(async move { Ok(event) })
+    }
+}
+
+#[sqlx::test]
+async fn test_indexer_loop_follows_reorg_with_rpc(db: PgPool) {
+    use bitcoin::hashes::Hash;
+    
+    let state = test_state(db.clone());
+    let b100 = block_at(100, bitcoin::BlockHash::all_zeros(), vec![taproot_payment(0x01020304, 1)]);
+    let b101a = block_at(101, b100.block_hash(), vec![taproot_payment(0x0a0a0a0a, 2)]);
+    let b102a = block_at(102, b101a.block_hash(), vec![taproot_payment(0x0b0b0b0b, 3)]);
+    let b101b = block_at(101, b100.block_hash(), vec![taproot_payment(0x0c0c0c0c, 4)]);
+    let b102b = block_at(102, b101b.block_hash(), vec![taproot_payment(0x0d0d0d0d, 5)]);
+    let b103b = block_at(103, b102b.block_hash(), vec![taproot_payment(0x0e0e0e0e, 6)]);
+    let node = FakeNode::new(100, vec![b100.clone()]);
+    let rpc = node.serve().await;
+    
+    // Branch a grows to 102, then the node switches to branch b at 103 and
+    // only 103b is announced (101b and 102b come over RPC). Stale branch a
+    // blocks are redelivered afterwards and must not come back.
+    let branch_a = vec![b100.clone(), b101a.clone(), b102a.clone()];
+    let branch_b = vec![b100.clone(), b101b.clone(), b102b.clone(), b103b.clone()];
+    let steps = vec![
+        (Some(branch_a[..2].to_vec()), b101a.clone()),
+        (Some(branch_a.clone()), b102a.clone()),
+        (Some(branch_b), b103b.clone()),
+        (None, b102a.clone()),
+        (None, b101a),
+    ];
+    let mut source = Some(NodeBlockSource { node, steps: steps.into() });
+    crate::indexer::run_with_reconnect(&state, Some(&rpc), 1, || {
+        Ok(Box::new(source.take().expect("unexpected reconnect")))
+    })
+    .await
+    .unwrap();
+    
+    let stored: Vec<(i32, Vec<u8>)> = sqlx::query_as("SELECT height, hash FROM blocks ORDER BY height")
+        .fetch_all(&db)
+        .await
+        .unwrap();
+    let expected: Vec<(i32, Vec<u8>)> = [(100, &b100), (101, &b101b), (102, &b102b), (103, &b103b)]
+        .iter()
+        .map(|(height, block)| (*height, block.block_hash().to_byte_array().to_vec()))
+        .collect();
+    assert_eq!(stored, expected);
+    let (outputs,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM taproot_outputs")
+        .fetch_one(&db)
+        .await
+        .unwrap();
+    assert_eq!(outputs, 4);
+    assert_eq!(
+        crate::indexer::load_checkpoint(&db).await.unwrap(),
+        Some((b103b.block_hash(), 103))
+    );
+    
+    // With the node to ask, a stale block is dropped rather than queued
+    let mut pending = crate::indexer::PendingBlocks::default();
+    crate::indexer::handle_block(&state, Some(&rpc), &mut pending, b102a).await.unwrap();
+    assert_eq!(pending.len(), 0);
+}
+
+#[sqlx::test]
+async fn test_competing_block_replaces_stored_block_at_same_height(db: PgPool) {
+    use bitcoin::hashes::Hash;
+    
+    let state = test_state(db.clone());
+    let b100 = block_at(100, bitcoin::BlockHash::all_zeros(), vec![taproot_payment(0x01020304, 1)]);
+    let b101a = block_at(101, b100.block_hash(), vec![taproot_payment(0x0a0a0a0a, 2)]);
+    let b102a = block_at(102, b101a.block_hash(), vec![taproot_payment(0x0b0b0b0b, 3)]);
+    let b101b = block_at(101, b100.block_hash(), vec![taproot_payment(0x0c0c0c0c, 4)]);
+    for block in [&b100, &b101a, &b102a, &b101b] {
+        crate::indexer::process_block(&state, None, block).await.unwrap();
+    }
+    
+    // Exactly one active block at 101, the winner, and nothing built on the loser
+    let at_101: Vec<(Vec<u8>, bool)> = sqlx::query_as("SELECT hash, is_orphaned FROM blocks WHERE height = 101")
+        .fetch_all(&db)
+        .await
+        .unwrap();
+    assert_eq!(at_101, vec![(b101b.block_hash().to_byte_array().to_vec(), false)]);
+    assert_eq!(indexed_heights(&db).await, vec![100, 101]);
+    
+    let req = scan_request(100, 102, &[0x0a0a0a0a, 0x0b0b0b0b, 0x0c0c0c0c]);
+    let body = response_json(scan_handler(State(state), Json(req)).await.unwrap()).await;
+    let candidates = body["candidates"].as_array().unwrap();
+    assert_eq!(candidates.len(), 1);
+    assert_eq!(candidates[0]["txid"], b101b.txdata[1].compute_txid().to_string());
+}
+
+/// Minimal bitcoind JSON-RPC endpoint: serves every block it has been
+/// given, reports `chain` (starting at height `base`) as its active chain
+/// and knows no transactions.
+#[derive(Clone)]
+struct FakeNode {
+    base: i32,
+    chain: std::sync::Arc<std::sync::Mutex<Vec<bitcoin::Block>>>,
+    blocks: std::sync::Arc<std::sync::Mutex<std::collections::HashMap<bitcoin::BlockHash, bitcoin::Block>>>,
+}
+
+impl FakeNode {
+    fn new(base: i32, chain: Vec<bitcoin::Block>) -> Self {
+        let node = Self { base, chain: Default::default(), blocks: Default::default() };
+        node.set_chain(chain);
+        node
+    }
+    
+    /// Switch the active chain, remembering the blocks of the old one.
+    fn set_chain(&self, chain: Vec<bitcoin::Block>) {
+        self.blocks.lock().unwrap().extend(chain.iter().map(|b| (b.block_hash(), b.clone())));
+        *self.chain.lock().unwrap() = chain;
+    }
+    
+    fn handle(&self, method: &str, params: &serde_json::Value) -> Result<serde_json::Value, String> {
+        let chain = self.chain.lock().unwrap();
+        match method {
+            "getblockcount" => Ok(serde_json::json!(self.base as usize + chain.len() - 1)),
+            "getbestblockhash" => Ok(serde_json::json!(chain.last().unwrap().block_hash().to_string())),
+            "getblockhash" => params[0]
+                .as_i64()
+                .and_then(|height| chain.get(usize::try_from(height - self.base as i64).ok()?))
+                .map(|block| serde_json::json!(block.block_hash().to_string()))
+                .ok_or_else(|| "Block height out of range".into()),
+            "getblock" => params[0]
+                .as_str()
+                .and_then(|hash| hash.parse().ok())
+                .and_then(|hash: bitcoin::BlockHash| self.blocks.lock().unwrap().get(&hash).cloned())
+                .map(|block| serde_json::json!(hex::encode(bitcoin::consensus::serialize(&block))))
+                .ok_or_else(|| "Block not found".into()),
+            _ => Err(format!("{} not supported", method)),
+        }
+    }
+    
+    /// Serve the node on a local port and return a client for it.
+    async fn serve(&self) -> std::sync::Arc<bitcoincore_rpc::Client> {
+        let url = self.listen().await;
+        std::sync::Arc::new(bitcoincore_rpc::Client::new(&url, bitcoincore_rpc::Auth::None).unwrap())
+    }
+    
+    /// Serve the node on a local port and return its URL.
+    async fn listen(&self) -> String {
+        async fn rpc(State(node): State<FakeNode>, Json(request): Json<serde_json::Value>) -> Json<serde_json::Value> {
+            let method = request["method"].as_str().unwrap_or_default();
+            let (result, error) = match node.handle(method, &request["params"]) {
+                Ok(result) => (result, serde_json::Value::Null),
+                Err(message) => (serde_json::Value::Null, serde_json::json!({ "code": -5, "message": message })),
+            };
+            Json(serde_json::json!({ "result": result, "error": error, "id": request["id"] }))
+        }
+        
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let app = axum::Router::new().route("/", axum::routing::post(rpc)).with_state(self.clone());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        url
+    }
+}
+
+#[sqlx::test]
+async fn test_stale_block_does_not_replace_active_block(db: PgPool) {
+    use bitcoin::hashes::Hash;
+    
+    let state = test_state(db.clone());
+    let b100 = block_at(100, bitcoin::BlockHash::all_zeros(), vec![taproot_payment(0x01020304, 1)]);
+    let b101a = block_at(101, b100.block_hash(), vec![taproot_payment(0x0a0a0a0a, 2)]);
+    let b101b = block_at(101, b100.block_hash(), vec![taproot_payment(0x0c0c0c0c, 4)]);
+    let node = FakeNode::new(100, vec![b100.clone(), b101a.clone()]);
+    node.set_chain(vec![b100.clone(), b101b.clone()]);
+    let rpc = node.serve().await;
+    let stored_at_101 = || async {
+        sqlx::query_as::<_, (Vec<u8>,)>("SELECT hash FROM blocks WHERE height = 101")
+            .fetch_one(&db)
+            .await
+            .unwrap()
+            .0
+    };
+    
+    for block in [&b100, &b101b] {
+        crate::indexer::process_block(&state, Some(&rpc), block).await.unwrap();
+    }
+    
+    // 101a is redelivered after the node switched to 101b: it is ignored
+    crate::indexer::process_block(&state, Some(&rpc), &b101a).await.unwrap();
+    assert_eq!(stored_at_101().await, b101b.block_hash().to_byte_array().to_vec());
+    
+    // Once the node's chain holds 101a again, it replaces 101b
+    node.set_chain(vec![b100.clone(), b101a.clone()]);
+    crate::indexer::process_block(&state, Some(&rpc), &b101a).await.unwrap();
+    assert_eq!(stored_at_101().await, b101a.block_hash().to_byte_array().to_vec());
+}
+
+/// Node chain given as a list of block hashes starting at `base`.
+struct FixedChain {
+    base: i32,
+    hashes: Vec<bitcoin::BlockHash>,
+}
+
+impl crate::indexer::ChainView for FixedChain {
+    async fn hash_at(&self, height: i32) -> Result<Option<bitcoin::BlockHash>, crate::indexer::IndexerError> {
+        Ok(usize::try_from(height - self.base).ok().and_then(|i| self.hashes.get(i).copied()))
+    }
+}
+
+#[sqlx::test]
+async fn test_restart_after_reorg_resyncs_from_ancestor(db: PgPool) {
+    use bitcoin::hashes::Hash;
+    
+    let mut pending = crate::indexer::PendingBlocks::default();
+    let state = test_state(db.clone());
+    let b100 = block_at(100, bitcoin::BlockHash::all_zeros(), vec![]);
+    let b101 = block_at(101, b100.block_hash(), vec![taproot_payment(0x01020304, 1)]);
+    let a102 = block_at(102, b101.block_hash(), vec![taproot_payment(0x0a0b0c0d, 2)]);
+    let a103 = block_at(103, a102.block_hash(), vec![taproot_payment(0x0a0b0c0d, 3)]);
+    for block in [&b100, &b101, &a102, &a103] {
+        crate::indexer::handle_block(&state, None, &mut pending, block.clone()).await.unwrap();
+    }
+    assert_eq!(
+        crate::indexer::load_checkpoint(&db).await.unwrap(),
+        Some((a103.block_hash(), 103))
+    );
+    
+    // While the indexer was down the node switched to a branch forking after 101
+    let b102 = block_at(102, b101.block_hash(), vec![taproot_payment(0x05060708, 4)]);
+    let b103 = block_at(103, b102.block_hash(), vec![]);
+    let b104 = block_at(104, b103.block_hash(), vec![]);
+    let chain = FixedChain {
+        base: 100,
+        hashes: [&b100, &b101, &b102, &b103, &b104].iter().map(|b| b.block_hash()).collect(),
+    };
+    
+    // Restart: a fresh state validates the checkpoint and rolls back to 101
+    let state = test_state(db.clone());
+    let resumed = crate::indexer::resync_from_checkpoint(&state, &chain).await.unwrap();
+    assert_eq!(resumed, Some(101));
+    assert_eq!(indexed_heights(&db).await, vec![100, 101]);
+    assert_eq!(
+        crate::indexer::load_checkpoint(&db).await.unwrap(),
+        Some((b101.block_hash(), 101))
+    );
+    let (stale,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM taproot_outputs WHERE block_height > 101")
+        .fetch_one(&db)
+        .await
+        .unwrap();
+    assert_eq!(stale, 0);
+    
+    // The node's branch now connects to the stored ancestor
+    for block in [&b102, &b103, &b104] {
+        crate::indexer::handle_block(&state, None, &mut pending, block.clone()).await.unwrap();
+    }
+    assert_eq!(indexed_heights(&db).await, vec![100, 101, 102, 103, 104]);
+    let stored: (Vec<u8>,) = sqlx::query_as("SELECT hash FROM blocks WHERE height = 102")
+        .fetch_one(&db)
+        .await
+        .unwrap();
+    assert_eq!(stored.0, b102.block_hash().as_byte_array().to_vec());
+    assert_eq!(
+        crate::indexer::load_checkpoint(&db).await.unwrap(),
+        Some((b104.block_hash(), 104))
+    );
+    
+    // A checkpoint the node agrees with is resumed as is
+    assert_eq!(crate::indexer::resync_from_checkpoint(&state, &chain).await.unwrap(), Some(104));
+}
+
+#[sqlx::test]
+async fn test_scan_amount_range(db: PgPool) {
+    insert_block(&db, 100).await;
+    insert_output(&db, 100, 1, 0, 0xdeadbeef).await;
+    sqlx::query("UPDATE taproot_outputs SET amount = 1000")
+        .execute(&db)
+        .await
+        .unwrap();
+    
+    let scan = |min_amount, max_amount| {
+        let mut req = scan_request(100, 100, &[0xdeadbeef]);
+        req.min_amount = min_amount;
+        req.max_amount = max_amount;
+        scan_handler(State(test_state(db.clone())), Json(req))
+    };
+    
+    let body = response_json(scan(Some(2000), None).await.unwrap()).await;
+    assert_eq!(body["candidates"].as_array().unwrap().len(), 0);
+    
+    let body = response_json(scan(None, None).await.unwrap()).await;
+    assert_eq!(body["candidates"].as_array().unwrap().len(), 1);
+    
+    let body = response_json(scan(Some(500), Some(1000)).await.unwrap()).await;
+    assert_eq!(body["candidates"].as_array().unwrap().len(), 1);
+    
+    assert!(matches!(scan(Some(-1), None).await, Err(ApiError::Validation(_))));
+    assert!(matches!(scan(Some(2000), Some(1000)).await, Err(ApiError::Validation(_))));
+}
+
+#[sqlx::test]
+async fn test_generated_payment_is_indexed_and_scannable(db: PgPool) {
+    use bitcoin::hashes::Hash;
+    use bitcoin::secp256k1::{PublicKey, Secp256k1, SecretKey};
+    
+    let secp = Secp256k1::new();
+    let scan_key = whisper_core::ScanKey::from_slice(&[1u8; 32]).unwrap();
+    let spend_pubkey = PublicKey::from_secret_key(&secp, &SecretKey::from_slice(&[2u8; 32]).unwrap());
+    let payment = whisper_core::generate_test_payment(&scan_key, &spend_pubkey, None, 0, 1).unwrap();
+    
+    let state = test_state(db);
+    let block = block_at(100, bitcoin::BlockHash::all_zeros(), vec![payment.transaction.clone()]);
+    crate::indexer::process_block(&state, None, &block).await.unwrap();
+    
+    let prefix = whisper_core::prefix_from_xonly(&payment.expected.output_pubkey);
+    let body = response_json(scan_handler(State(state.clone()), Json(scan_request(100, 100, &[prefix]))).await.unwrap()).await;
+    
+    assert_eq!(body["candidates"].as_array().unwrap().len(), 1);
+    let candidate = &body["candidates"][0];
+    assert_eq!(candidate["txid"], payment.expected.outpoint().txid.to_string());
+    assert_eq!(candidate["vout"], payment.expected.vout);
+    assert_eq!(candidate["amount"], payment.expected.amount);
+    
+    // The client, talking to the server over HTTP, verifies the proof and
+    // finds the payment from the transaction's inputs alone
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let app = crate::app(state);
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    
+    let client = whisper_client::SilentPaymentClient::new(format!("http://{}", addr), scan_key, spend_pubkey, 0);
+    let inputs = whisper_core::InputData::from_transaction(&payment.transaction, &payment.prevouts);
+    let outcome = client.scan_range_detailed(100, 100, &inputs).await.unwrap();
+    assert_eq!(outcome.false_positives, 0);
+    assert_eq!(outcome.results.len(), 1);
+    assert_eq!(outcome.results[0], payment.expected);
+    assert_eq!(outcome.results[0].block_height, Some(100));
+}
+
+#[sqlx::test]
+async fn test_client_prefix_scan_stops_at_first_missing_k(db: PgPool) {
+    use bitcoin::hashes::{hash160, Hash};
+    use bitcoin::secp256k1::{PublicKey, Secp256k1, SecretKey};
+    
+    let secp = Secp256k1::new();
+    let scan_key = whisper_core::ScanKey::from_slice(&[1u8; 32]).unwrap();
+    let spend_pubkey = PublicKey::from_secret_key(&secp, &SecretKey::from_slice(&[2u8; 32]).unwrap());
+    let address = scan_key.to_address(&spend_pubkey, bitcoin::Network::Regtest);
+    
+    // Block 99 funds two P2WPKH outputs of one sender key
+    let sender_secret = SecretKey::from_slice(&[7u8; 32]).unwrap();
+    let sender_key = PublicKey::from_secret_key(&secp, &sender_secret);
+    let mut p2wpkh = vec![0x00, 0x14];
+    p2wpkh.extend_from_slice(hash160::Hash::hash(&sender_key.serialize()).as_byte_array());
+    let mut funding = taproot_payment(0x01020304, 1);
+    funding.output = vec![
+        bitcoin::TxOut { value: bitcoin::Amount::from_sat(100_000), script_pubkey: bitcoin::ScriptBuf::from_bytes(p2wpkh) };
+        2
+    ];
+    
+    // Each spends one of them, paying the address three times (k = 0, 1, 2)
+    // but only putting the outputs for `ks` on chain, highest k first
+    let paying = |vout: u32, ks: &[usize]| {
+        let outpoint = bitcoin::OutPoint { txid: funding.compute_txid(), vout };
+        let keys = whisper_core::derive_send_outputs(&[sender_secret], &[outpoint], &vec![address.clone(); 3]).unwrap();
+        bitcoin::Transaction {
+            version: bitcoin::transaction::Version::TWO,
+            lock_time: bitcoin::absolute::LockTime::ZERO,
+            input: vec![bitcoin::TxIn {
+                previous_output: outpoint,
+                witness: bitcoin::Witness::from_slice(&[vec![0u8; 71], sender_key.serialize().to_vec()]),
+                ..Default::default()
+            }],
+            output: ks.iter().rev().map(|&k| {
+                let mut script = vec![0x51, 0x20];
+                script.extend_from_slice(&keys[k].1.serialize());
+                bitcoin::TxOut { value: bitcoin::Amount::from_sat(10_000), script_pubkey: bitcoin::ScriptBuf::from_bytes(script) }
+            }).collect(),
+        }
+    };
+    let contiguous = paying(0, &[0, 1, 2]);
+    let gap = paying(1, &[0, 2]);
+    
+    let state = test_state(db);
+    let b99 = block_at(99, bitcoin::BlockHash::all_zeros(), vec![funding.clone()]);
+    let b100 = block_at(100, b99.block_hash(), vec![contiguous.clone(), gap.clone()]);
+    crate::indexer::process_block(&state, None, &b99).await.unwrap();
+    crate::indexer::process_block(&state, None, &b100).await.unwrap();
+    
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let app = crate::app(state);
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    let client = whisper_client::SilentPaymentClient::new(format!("http://{}", addr), scan_key.clone(), spend_pubkey, 0);
+    
+    // Contiguous from 0: the client asks for k = 0, 1, 2 (and 3) in turn
+    // and finds all three, in output order, as scan_transaction does
+    let inputs = whisper_core::InputData::from_transaction(&contiguous, &funding.output[..1]);
+    let found = client.scan_range(99, 100, &inputs).await.unwrap();
+    let expected = scan_key.scan_transaction(&contiguous, &funding.output[..1], &spend_pubkey, &[None]).unwrap();
+    assert_eq!(expected.len(), 3);
+    assert_eq!(found.iter().map(|r| r.vout).collect::<Vec<_>>(), vec![0, 1, 2]);
+    assert_eq!(
+        found.iter().map(|r| r.output_pubkey).collect::<Vec<_>>(),
+        expected.iter().map(|r| r.output_pubkey).collect::<Vec<_>>(),
+    );
+    
+    // k = 1 missing: the k = 1 request finds nothing, so the k = 2 output
+    // is never looked for and only k = 0 is found
+    let inputs = whisper_core::InputData::from_transaction(&gap, &funding.output[1..]);
+    let found = client.scan_range(99, 100, &inputs).await.unwrap();
+    assert_eq!(found.len(), 1);
+    assert_eq!(found[0].outpoint(), bitcoin::OutPoint { txid: gap.compute_txid(), vout: 1 });
+    assert_eq!(found, scan_key.scan_transaction(&gap, &funding.output[1..], &spend_pubkey, &[None]).unwrap()
+        .into_iter()
+        .map(|result| whisper_core::ScanResult { block_height: Some(100), ..result })
+        .collect::<Vec<_>>());
+}
+
+#[sqlx::test]
+async fn test_hidden_amounts_are_omitted_from_candidates(db: PgPool) {
+    insert_block(&db, 100).await;
+    insert_output(&db, 100, 1, 0, 0xdeadbeef).await;
+    let mut state = test_state(db);
+    state.config.reveal_amounts = false;
+    
+    let req = scan_request(100, 100, &[0xdeadbeef]);
+    let body = response_json(scan_handler(State(state.clone()), Json(req)).await.unwrap()).await;
+    let candidate = &body["candidates"][0];
+    assert_eq!(candidate["vout"], 0);
+    assert!(candidate.get("amount").is_none());
+    
+    // Amount filters would reveal what the candidates leave out
+    let mut req = scan_request(100, 100, &[0xdeadbeef]);
+    req.min_amount = Some(5_000);
+    assert!(matches!(scan_handler(State(state), Json(req)).await, Err(ApiError::Validation(_))));
+}
+
+#[sqlx::test]
+async fn test_status_reports_limits(db: PgPool) {
+    let mut state = test_state(db);
+    state.config.max_block_range = 500;
+    state.config.max_prefixes = 250;
+    
+    let body = response_json(status_handler(State(state)).await).await;
+    
+    assert_eq!(body["max_block_range"], 500);
+    assert_eq!(body["max_prefixes"], 250);
+}
+
+#[sqlx::test]
+async fn test_status_reports_blocks_behind_node(db: PgPool) {
+    insert_block(&db, 100).await;
+    let state = test_state(db);
+    
+    // Node tip unknown: no lag to report
+    let body = response_json(status_handler(State(state.clone())).await).await;
+    assert_eq!(body["tip_height"], 100);
+    assert!(body["node_tip"].is_null());
+    assert!(body["blocks_behind"].is_null());
+    
+    state.indexer_progress.record_tip(105);
+    let body = response_json(status_handler(State(state)).await).await;
+    assert_eq!(body["node_tip"], 105);
+    assert_eq!(body["blocks_behind"], 5);
+}
+
+#[sqlx::test]
+async fn test_status_caches_node_tip_failures(db: PgPool) {
+    insert_block(&db, 100).await;
+    let mut state = test_state(db);
+    // Nothing listens on port 1: getblockcount fails straight away
+    let mut config = test_config();
+    config.bitcoin_rpc_url = "http://127.0.0.1:1".into();
+    state.node_rpc = Some(std::sync::Arc::new(crate::indexer::rpc_client(&config).unwrap()));
+    
+    // The failure is cached and the indexer's tip used instead
+    state.indexer_progress.record_tip(103);
+    let body = response_json(status_handler(State(state.clone())).await).await;
+    assert_eq!(body["node_tip"], 103);
+    assert!(matches!(*state.node_tip_cache.lock().unwrap(), Some((_, None))));
+    
+    // A fresh cached count is served without asking the node
+    *state.node_tip_cache.lock().unwrap() = Some((std::time::Instant::now(), Some(110)));
+    let body = response_json(status_handler(State(state)).await).await;
+    assert_eq!(body["node_tip"], 110);
+    assert_eq!(body["blocks_behind"], 10);
+}
+
+#[sqlx::test]
+async fn test_scan_empty_prefixes_is_bad_request(db: PgPool) {
+    insert_block(&db, 100).await;
+    insert_output(&db, 100, 1, 0, 0xdeadbeef).await;
+    
+    let req = scan_request(100, 100, &[]);
+    let response = scan_handler(State(test_state(db)), Json(req)).await.into_response();
+    
+    assert_eq!(response.status(), axum::http::StatusCode::BAD_REQUEST);
+    let body = response_json(response).await;
+    assert_eq!(body, serde_json::json!({
+        "error": "validation",
+        "message": "At least one prefix is required",
+        "code": 400,
+    }));
+}
+
+#[sqlx::test]
+async fn test_scan_txids_returns_outputs_of_known_transactions(db: PgPool) {
+    use bitcoin::hashes::Hash;
+    
+    let paid = taproot_payment(0xdeadbeef, 1);
+    let other = taproot_payment(0xdeadbeef, 2);
+    let block = block_at(100, bitcoin::BlockHash::all_zeros(), vec![paid.clone(), other]);
+    let state = test_state(db);
+    crate::indexer::process_block(&state, None, &block).await.unwrap();
+    
+    let txid = paid.compute_txid();
+    let request = |txid: String| TxidScanRequest {
+        scan_pubkey: "00".repeat(32),
+        prefixes: vec!["deadbeef".into()],
+        txids: vec![txid],
+        include_proofs: Some(true),
+    };
+    
+    // Txids are taken in display order and candidates carry a proof
+    let body = response_json(scan_txids_handler(State(state.clone()), Json(request(txid.to_string()))).await.unwrap()).await;
+    let candidates = body["candidates"].as_array().unwrap();
+    assert_eq!(candidates.len(), 1);
+    assert_eq!(candidates[0]["txid"], txid.to_string());
+    assert_eq!(candidates[0]["vout"], 0);
+    assert_eq!(candidates[0]["block_height"], 100);
+    let proof = &candidates[0]["proof"];
+    let branch: Vec<[u8; 32]> = proof["branch"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|h| hex::decode(h.as_str().unwrap()).unwrap().try_into().unwrap())
+        .collect();
+    let header = hex::decode(proof["block_header"].as_str().unwrap()).unwrap();
+    let index = proof["tx_index"].as_u64().unwrap() as u32;
+    assert!(whisper_core::verify_merkle_proof(&txid.to_byte_array(), &branch, index, &header).unwrap());
+    
+    // The same bytes in internal order name a different transaction
+    let body = response_json(
+        scan_txids_handler(State(state.clone()), Json(request(hex::encode(txid.to_byte_array())))).await.unwrap()
+    ).await;
+    assert!(body["candidates"].as_array().unwrap().is_empty());
+    
+    let req = request("not a txid".into());
+    assert!(matches!(scan_txids_handler(State(state.clone()), Json(req)).await, Err(ApiError::Validation(_))));
+    
+    // Prefix limits are the ones `/api/v1/scan` applies
+    let req = TxidScanRequest { prefixes: Vec::new(), ..request(txid.to_string()) };
+    assert!(matches!(scan_txids_handler(State(state), Json(req)).await, Err(ApiError::Validation(_))));
+}
+
+#[sqlx::test]
+async fn test_scan_high_bit_prefix_and_malformed_prefixes(db: PgPool) {
+    insert_block(&db, 100).await;
+    insert_output(&db, 100, 1, 0, 0xfedcba98).await;
+    insert_output(&db, 100, 2, 0, 0x80000000).await;
+    insert_output(&db, 100, 3, 0, 0x7fffffff).await;
+    let state = test_state(db);
+    
+    // Prefixes above i32::MAX match their stored bit pattern
+    let req = scan_request(100, 100, &[0xfedcba98, 0x80000000]);
+    let body = response_json(scan_handler(State(state.clone()), Json(req)).await.unwrap()).await;
+    let mut txids: Vec<&str> = body["candidates"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|c| c["txid"].as_str().unwrap())
+        .collect();
+    txids.sort();
+    assert_eq!(txids, vec![hex::encode([1u8; 32]), hex::encode([2u8; 32])]);
+    
+    for bad in ["fedcba9", "fedcba981", "+edcba98", "fedcbaxx", "-1234567", ""] {
+        let mut req = scan_request(100, 100, &[]);
+        req.prefixes = vec![bad.into()];
+        let response = scan_handler(State(state.clone()), Json(req)).await.into_response();
+        assert_eq!(response.status(), axum::http::StatusCode::BAD_REQUEST, "{:?}", bad);
+    }
+    
+    // An optional 0x prefix is still accepted
+    let mut req = scan_request(100, 100, &[]);
+    req.prefixes = vec!["0x7fffffff".into()];
+    let body = response_json(scan_handler(State(state), Json(req)).await.unwrap()).await;
+    assert_eq!(body["candidates"].as_array().unwrap().len(), 1);
+}
+
+#[sqlx::test]
+async fn test_high_bit_output_indexed_and_queryable(db: PgPool) {
+    use bitcoin::hashes::Hash;
+    
+    let mut state = test_state(db.clone());
+    let block = block_at(100, bitcoin::BlockHash::all_zeros(), vec![
+        taproot_payment(0x9a0b0c0d, 1),
+        taproot_payment(0x1a0b0c0d, 2),
+    ]);
+    crate::indexer::process_block(&state, None, &block).await.unwrap();
+    
+    // Stored as the signed bit pattern
+    let (stored,): (i32,) = sqlx::query_as("SELECT sp_prefix FROM taproot_outputs WHERE sp_prefix < 0")
+        .fetch_one(&db)
+        .await
+        .unwrap();
+    assert_eq!(stored, 0x9a0b0c0du32 as i32);
+    
+    // Txids come back in display order, as block explorers show them
+    let high_txid = block.txdata[1].compute_txid().to_string();
+    let req = scan_request(100, 100, &[0x9a0b0c0d]);
+    let body = response_json(scan_handler(State(state.clone()), Json(req)).await.unwrap()).await;
+    assert_eq!(body["candidates"].as_array().unwrap().len(), 1);
+    assert_eq!(body["candidates"][0]["txid"], high_txid);
+    
+    // Same with 8-byte prefixes (the generated column uses the same encoding)
+    state.config.prefix_len = 8;
+    let mut req = scan_request(100, 100, &[]);
+    req.prefixes = vec!["9a0b0c0d22222222".into()];
+    let body = response_json(scan_handler(State(state), Json(req)).await.unwrap()).await;
+    assert_eq!(body["candidates"].as_array().unwrap().len(), 1);
+    assert_eq!(body["candidates"][0]["txid"], high_txid);
+}
+
+#[sqlx::test]
+async fn test_scan_query_uses_prefix_height_index(db: PgPool) {
+    // 1000 blocks of 50 outputs each, with pseudo-random prefixes
+    sqlx::query(
+        "INSERT INTO blocks (height, hash, header)
+         SELECT h, decode(lpad(to_hex(h), 64, '0'), 'hex'), decode(repeat('00', 80), 'hex')
+         FROM generate_series(1, 1000) h"
+    )
+    .execute(&db)
+    .await
+    .unwrap();
+    sqlx::query(
+        "INSERT INTO transactions (txid, block_height, block_index, raw_tx)
+         SELECT decode(lpad(to_hex(h), 64, '0'), 'hex'), h, 0, '\\x00'
+         FROM generate_series(1, 1000) h"
+    )
+    .execute(&db)
+    .await
+    .unwrap();
+    sqlx::query(
+        "INSERT INTO taproot_outputs
+         (txid, vout, block_height, script_pubkey, amount, x_only_pubkey, sp_prefix)
+         SELECT decode(lpad(to_hex(h), 64, '0'), 'hex'), v, h,
+                decode('5120' || md5(h || ':' || v) || md5(v || ':' || h), 'hex'), 10000,
+                decode(md5(h || ':' || v) || md5(v || ':' || h), 'hex'),
+                ('x' || substr(md5(h || ':' || v), 1, 8))::bit(32)::int
+         FROM generate_series(1, 1000) h, generate_series(0, 49) v"
+    )
+    .execute(&db)
+    .await
+    .unwrap();
+    sqlx::query("ANALYZE").execute(&db).await.unwrap();
+    
+    // Same predicates as the confirmed-output query in scan_confirmed
+    let heights: Vec<i32> = (100..=900).collect();
+    let plan: Vec<(String,)> = sqlx::query_as(
+        "EXPLAIN
+         SELECT o.txid, o.vout
+         FROM taproot_outputs o
+         JOIN blocks b ON b.height = o.block_height
+         WHERE o.block_height BETWEEN $1 AND $2
+         AND o.block_height = ANY($7::int[])
+         AND o.sp_prefix = ANY($3::int[])
+         AND (cardinality($6::bigint[]) = 0 OR o.sp_prefix_long = ANY($6::bigint[]))
+         AND o.amount BETWEEN $4 AND $5
+         ORDER BY o.block_height, o.txid, o.vout"
+    )
+    .bind(100)
+    .bind(900)
+    .bind(vec![0x12345678u32 as i32, 0xdeadbeefu32 as i32])
+    .bind(0i64)
+    .bind(i64::MAX)
+    .bind(Vec::<i64>::new())
+    .bind(&heights)
+    .fetch_all(&db)
+    .await
+    .unwrap();
+    let plan: String = plan.into_iter().map(|(line,)| line + "\n").collect();
+    
+    assert!(plan.contains("idx_outputs_prefix_height"), "{}", plan);
+    assert!(!plan.contains("Seq Scan on taproot_outputs"), "{}", plan);
+}
+
+#[test]
+fn test_error_kinds_in_json_body() {
+    let cases = [
+        (ApiError::Database("down".into()), "database", 500),
+        (ApiError::NotFound("gone".into()), "not_found", 404),
+        (ApiError::Forbidden("no".into()), "forbidden", 403),
+    ];
+    
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    for (error, kind, code) in cases {
+        let response = error.into_response();
+        assert_eq!(response.status().as_u16(), code);
+        let body = runtime.block_on(response_json(response));
+        assert_eq!(body["error"], kind);
+        assert_eq!(body["code"], code);
+    }
+}
+
+#[sqlx::test]
+async fn test_full_scan_matches_client_scan_transaction(db: PgPool) {
+    use bitcoin::hashes::{hash160, Hash};
+    use bitcoin::secp256k1::{PublicKey, Secp256k1, SecretKey};
+    use whisper_core::{InputData, ScanKey};
+    
+    let secp = Secp256k1::new();
+    let scan_secret = SecretKey::from_slice(&[1u8; 32]).unwrap();
+    let scan_key = ScanKey::new(scan_secret).unwrap();
+    let spend_pubkey = PublicKey::from_secret_key(&secp, &SecretKey::from_slice(&[2u8; 32]).unwrap());
+    let taproot_script = |key: bitcoin::secp256k1::XOnlyPublicKey| {
+        let mut script = vec![0x51, 0x20];
+        script.extend_from_slice(&key.serialize());
+        bitcoin::ScriptBuf::from_bytes(script)
+    };
+    
+    // Block 99 funds a P2WPKH output that block 100 spends
+    let input_key = PublicKey::from_secret_key(&secp, &SecretKey::from_slice(&[7u8; 32]).unwrap());
+    let mut p2wpkh = vec![0x00, 0x14];
+    p2wpkh.extend_from_slice(hash160::Hash::hash(&input_key.serialize()).as_byte_array());
+    let mut funding = taproot_payment(0x01020304, 1);
+    funding.output[0].script_pubkey = bitcoin::ScriptBuf::from_bytes(p2wpkh);
+    
+    let mut spend = bitcoin::Transaction {
+        version: bitcoin::transaction::Version::TWO,
+        lock_time: bitcoin::absolute::LockTime::ZERO,
+        input: vec![bitcoin::TxIn {
+            previous_output: bitcoin::OutPoint { txid: funding.compute_txid(), vout: 0 },
+            witness: bitcoin::Witness::from_slice(&[vec![0u8; 71], input_key.serialize().to_vec()]),
+            ..Default::default()
+        }],
+        output: vec![],
+    };
+    let funding_outputs = funding.output.clone();
+    let inputs = InputData::from_transaction(&spend, &funding_outputs);
+    assert_eq!(inputs.len(), 1);
+    
+    // An unrelated taproot output, then an unlabeled payment (k = 0) and a
+    // label 1 payment (k = 1) as one sender pays both
+    let shared_secret = scan_key.compute_shared_secret(&inputs).unwrap();
+    let decoy = PublicKey::from_secret_key(&secp, &SecretKey::from_slice(&[9u8; 32]).unwrap())
+        .x_only_public_key().0;
+    let label_1 = scan_key.labeled_spend_pubkey(&spend_pubkey, 1).unwrap();
+    for key in [
+        decoy,
+        whisper_core::sender_output_pubkey(&shared_secret, &spend_pubkey, 0).unwrap(),
+        whisper_core::sender_output_pubkey(&shared_secret, &label_1, 1).unwrap(),
+    ] {
+        spend.output.push(bitcoin::TxOut {
+            value: bitcoin::Amount::from_sat(10_000),
+            script_pubkey: taproot_script(key),
+        });
+    }
+    
+    let b99 = block_at(99, bitcoin::BlockHash::all_zeros(), vec![funding]);
+    let b100 = block_at(100, b99.block_hash(), vec![spend.clone()]);
+    crate::indexer::process_block(&test_state(db.clone()), None, &b99).await.unwrap();
+    crate::indexer::process_block(&test_state(db.clone()), None, &b100).await.unwrap();
+    
+    let request = FullScanRequest {
+        scan_secret: hex::encode(scan_secret.secret_bytes()),
+        spend_pubkey: hex::encode(spend_pubkey.serialize()),
+        start_height: 99,
+        end_height: 100,
+        max_label: Some(1),
+    };
+    
+    // Disabled by default
+    let result = full_scan_handler(State(test_state(db.clone())), Json(request.clone())).await;
+    assert!(matches!(result, Err(ApiError::Forbidden(_))));
+    
+    let mut state = test_state(db.clone());
+    state.config.allow_full_scan = true;
+    let body = response_json(full_scan_handler(State(state), Json(request.clone())).await.unwrap()).await;
+    
+    // Same outputs the client finds locally
+    let labels = [None, Some(1)];
+    let expected: Vec<(i64, Option<u32>)> = scan_key
+        .scan_transaction(&spend, &funding_outputs, &spend_pubkey, &labels)
+        .unwrap()
+        .iter()
+        .map(|result| (result.vout as i64, result.label))
+        .collect();
+    let found: Vec<(i64, Option<u32>)> = body["matches"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|m| (m["vout"].as_i64().unwrap(), m["label"].as_u64().map(|l| l as u32)))
+        .collect();
+    
+    assert_eq!(expected, vec![(1, None), (2, Some(1))]);
+    assert_eq!(found, expected);
+    assert_eq!(body["transactions_checked"], 1);
+    
+    // Labels beyond the configured cap are rejected
+    let mut state = test_state(db);
+    state.config.allow_full_scan = true;
+    state.config.max_full_scan_label = 10;
+    let request = FullScanRequest { max_label: Some(11), ..request };
+    let result = full_scan_handler(State(state), Json(request)).await;
+    assert!(matches!(result, Err(ApiError::Validation(_))));
+}
+
+async fn sync_page(state: &AppState, since_height: i32) -> serde_json::Value {
+    response_json(sync_handler(State(state.clone()), Query(SyncQuery { since_height })).await.unwrap()).await
+}
+
+#[sqlx::test]
+async fn test_sync_returns_tweak_data_in_height_order(db: PgPool) {
+    use bitcoin::hashes::{hash160, Hash};
+    use bitcoin::secp256k1::{PublicKey, Secp256k1, SecretKey};
+    
+    // Block 1 funds P2WPKH outputs; blocks 2-4 spend them into taproot
+    // outputs (eligible), alongside payments with unknown prevouts
+    let secp = Secp256k1::new();
+    let keys: Vec<PublicKey> = (10..13u8)
+        .map(|i| PublicKey::from_secret_key(&secp, &SecretKey::from_slice(&[i; 32]).unwrap()))
+        .collect();
+    let mut funding = taproot_payment(0x01020304, 1);
+    funding.output = keys.iter()
+        .map(|key| {
+            let mut p2wpkh = vec![0x00, 0x14];
+            p2wpkh.extend_from_slice(hash160::Hash::hash(&key.serialize()).as_byte_array());
+            bitcoin::TxOut {
+                value: bitcoin::Amount::from_sat(20_000),
+                script_pubkey: bitcoin::ScriptBuf::from_bytes(p2wpkh),
+            }
+        })
+        .collect();
+    
+    let state = test_state(db);
+    let mut prev = block_at(1, bitcoin::BlockHash::all_zeros(), vec![funding.clone()]);
+    crate::indexer::process_block(&state, None, &prev).await.unwrap();
+    
+    let mut expected = Vec::new();
+    for (i, key) in keys.iter().enumerate() {
+        let mut spend = taproot_payment(0x0a0b0c00 + i as u32, 0);
+        spend.input[0] = bitcoin::TxIn {
+            previous_output: bitcoin::OutPoint { txid: funding.compute_txid(), vout: i as u32 },
+            witness: bitcoin::Witness::from_slice(&[vec![0u8; 71], key.serialize().to_vec()]),
+            ..Default::default()
+        };
+        let height = 2 + i as u8;
+        let block = block_at(height, prev.block_hash(), vec![spend.clone(), taproot_payment(0x05060708, 50 + i as u8)]);
+        crate::indexer::process_block(&state, None, &block).await.unwrap();
+        let tweak = whisper_core::InputData::transaction_tweak(&spend, &funding.output[i..=i]).unwrap();
+        expected.push((height as i64, spend.compute_txid(), hex::encode(tweak.serialize())));
+        prev = block;
+    }
+    
+    let check = |transactions: &[serde_json::Value]| {
+        let found: Vec<(i64, String, String)> = transactions
+            .iter()
+            .map(|tx| {
+                assert_eq!(tx["outputs"].as_array().unwrap().len(), 1);
+                (
+                    tx["block_height"].as_i64().unwrap(),
+                    tx["txid"].as_str().unwrap().to_string(),
+                    tx["tweak"].as_str().unwrap().to_string(),
+                )
+            })
+            .collect();
+        let expected: Vec<(i64, String, String)> = expected
+            .iter()
+            .map(|(height, txid, tweak)| (*height, txid.to_string(), tweak.clone()))
+            .collect();
+        assert_eq!(found, expected);
+    };
+    
+    // One page when the range allows it
+    let body = sync_page(&state, 0).await;
+    check(body["transactions"].as_array().unwrap());
+    assert_eq!(body["end_height"], 4);
+    assert!(body["next_since_height"].is_null());
+    
+    // Small pages chain together to the same result
+    let mut state = state;
+    state.config.max_block_range = 1;
+    let mut transactions = Vec::new();
+    let mut since = Some(0);
+    while let Some(height) = since {
+        let body = sync_page(&state, height).await;
+        transactions.extend(body["transactions"].as_array().unwrap().iter().cloned());
+        since = body["next_since_height"].as_i64().map(|h| h as i32);
+    }
+    check(&transactions);
+    
+    let past_tip = sync_page(&state, 10).await;
+    assert!(past_tip["transactions"].as_array().unwrap().is_empty());
+    assert!(past_tip["next_since_height"].is_null());
+    
+    // Hidden amounts stay hidden in tweak data too
+    assert!(transactions[0]["outputs"][0]["amount"].is_i64());
+    state.config.reveal_amounts = false;
+    let body = sync_page(&state, 0).await;
+    assert!(body["transactions"][0]["outputs"][0].get("amount").is_none());
+}
+
+#[sqlx::test]
+async fn test_spent_prevouts_resolve_from_utxo_cache(db: PgPool) {
+    use bitcoin::hashes::{hash160, Hash};
+    use bitcoin::secp256k1::{PublicKey, Secp256k1, SecretKey};
+    
+    // Block 1 funds two P2WPKH outputs
+    let secp = Secp256k1::new();
+    let keys: Vec<PublicKey> = (20..22u8)
+        .map(|i| PublicKey::from_secret_key(&secp, &SecretKey::from_slice(&[i; 32]).unwrap()))
+        .collect();
+    let mut funding = taproot_payment(0x01020304, 1);
+    funding.output = keys.iter()
+        .map(|key| {
+            let mut p2wpkh = vec![0x00, 0x14];
+            p2wpkh.extend_from_slice(hash160::Hash::hash(&key.serialize()).as_byte_array());
+            bitcoin::TxOut {
+                value: bitcoin::Amount::from_sat(20_000),
+                script_pubkey: bitcoin::ScriptBuf::from_bytes(p2wpkh),
+            }
+        })
+        .collect();
+    let spend = |vout: u32| {
+        let mut tx = taproot_payment(0x0a0b0c00 + vout, 0);
+        tx.input[0] = bitcoin::TxIn {
+            previous_output: bitcoin::OutPoint { txid: funding.compute_txid(), vout },
+            witness: bitcoin::Witness::from_slice(&[vec![0u8; 71], keys[vout as usize].serialize().to_vec()]),
+            ..Default::default()
+        };
+        tx
+    };
+    async fn stored_tweak(db: &PgPool, txid: bitcoin::Txid) -> Option<Vec<u8>> {
+        sqlx::query_as::<_, (Vec<u8>,)>("SELECT tweak FROM tx_tweaks WHERE txid = $1")
+            .bind(txid.as_byte_array().as_slice())
+            .fetch_optional(db)
+            .await
+            .unwrap()
+            .map(|(tweak,)| tweak)
+    }
+    let tweak_of = |tx: &bitcoin::Transaction, vout: usize| {
+        whisper_core::InputData::transaction_tweak(tx, &funding.output[vout..=vout])
+            .map(|tweak| tweak.serialize().to_vec())
+    };
+    async fn unspent(db: &PgPool, txid: bitcoin::Txid) -> i64 {
+        sqlx::query_as::<_, (i64,)>("SELECT COUNT(*) FROM utxos WHERE txid = $1 AND spent_height IS NULL")
+            .bind(txid.as_byte_array().as_slice())
+            .fetch_one(db)
+            .await
+            .unwrap()
+            .0
+    }
+    
+    let state = test_state(db.clone());
+    let b1 = block_at(1, bitcoin::BlockHash::all_zeros(), vec![funding.clone()]);
+    crate::indexer::process_block(&state, None, &b1).await.unwrap();
+    assert_eq!(unspent(&db, funding.compute_txid()).await, 2);
+    
+    // Block 2 spends output 0: resolved from memory, then marked spent
+    let spend0 = spend(0);
+    let b2 = block_at(2, b1.block_hash(), vec![spend0.clone()]);
+    crate::indexer::process_block(&state, None, &b2).await.unwrap();
+    assert_eq!(state.utxo_cache.hits(), 1);
+    assert_eq!(stored_tweak(&db, spend0.compute_txid()).await, tweak_of(&spend0, 0));
+    assert_eq!(unspent(&db, funding.compute_txid()).await, 1);
+    
+    // After a restart the memory cache is empty; the table still has output 1
+    let restarted = test_state(db.clone());
+    let spend1 = spend(1);
+    let b3 = block_at(3, b2.block_hash(), vec![spend1.clone()]);
+    crate::indexer::process_block(&restarted, None, &b3).await.unwrap();
+    assert_eq!(restarted.utxo_cache.hits(), 0);
+    assert_eq!(stored_tweak(&db, spend1.compute_txid()).await, tweak_of(&spend1, 1));
+    assert_eq!(unspent(&db, funding.compute_txid()).await, 0);
+    
+    // A competing block 2 replaces blocks 2 and 3: both spends are undone,
+    // and outputs created by the replaced blocks leave the cache
+    let spend0_output = bitcoin::OutPoint { txid: spend0.compute_txid(), vout: 0 };
+    assert!(state.utxo_cache.get(&spend0_output).is_some());
+    let b2b = block_at(2, b1.block_hash(), vec![taproot_payment(0x0d0d0d0d, 7)]);
+    crate::indexer::process_block(&state, None, &b2b).await.unwrap();
+    assert_eq!(unspent(&db, funding.compute_txid()).await, 2);
+    assert!(state.utxo_cache.get(&spend0_output).is_none());
+    
+    // Output 1 resolves from the table again on the new branch
+    let b3b = block_at(3, b2b.block_hash(), vec![spend1.clone()]);
+    crate::indexer::process_block(&state, None, &b3b).await.unwrap();
+    assert_eq!(stored_tweak(&db, spend1.compute_txid()).await, tweak_of(&spend1, 1));
+    assert_eq!(unspent(&db, funding.compute_txid()).await, 1);
+}
+
+#[sqlx::test]
+async fn test_unspent_only_excludes_spent_outputs(db: PgPool) {
+    use bitcoin::hashes::Hash;
+    
+    let state = test_state(db);
+    let payment = taproot_payment(0x01020304, 1);
+    let b100 = block_at(100, bitcoin::BlockHash::all_zeros(), vec![payment.clone()]);
+    crate::indexer::process_block(&state, None, &b100).await.unwrap();
+    
+    let mut req = scan_request(100, 101, &[0x01020304]);
+    req.unspent_only = Some(true);
+    let candidates = |body: serde_json::Value| body["candidates"].as_array().unwrap().len();
+    let body = response_json(scan_handler(State(state.clone()), Json(req.clone())).await.unwrap()).await;
+    assert_eq!(candidates(body), 1);
+    
+    // Block 101 spends it; the cached unspent-only result is dropped
+    let mut sweep = taproot_payment(0x0a0b0c0d, 0);
+    sweep.input[0].previous_output = bitcoin::OutPoint { txid: payment.compute_txid(), vout: 0 };
+    let b101 = block_at(101, b100.block_hash(), vec![sweep]);
+    crate::indexer::process_block(&state, None, &b101).await.unwrap();
+    
+    let body = response_json(scan_handler(State(state.clone()), Json(req.clone())).await.unwrap()).await;
+    assert_eq!(candidates(body), 0);
+    let body = response_json(scan_batch_handler(State(state.clone()), Json(vec![req.clone()])).await.unwrap()).await;
+    assert_eq!(candidates(body[0].clone()), 0);
+    
+    // Still returned without the flag
+    req.unspent_only = None;
+    let body = response_json(scan_handler(State(state), Json(req)).await.unwrap()).await;
+    assert_eq!(candidates(body), 1);
+}
+
+#[sqlx::test]
+async fn test_statement_timeout_cancels_slow_queries(
+    _: sqlx::postgres::PgPoolOptions,
+    connect_options: sqlx::postgres::PgConnectOptions,
+) {
+    let mut config = test_config();
+    config.db_statement_timeout_ms = 200;
+    let db = crate::pool_options(&config).connect_with(connect_options).await.unwrap();
+    
+    let started = std::time::Instant::now();
+    let err = sqlx::query("SELECT pg_sleep(10)").execute(&db).await.unwrap_err();
+    assert!(started.elapsed() < std::time::Duration::from_secs(5), "took {:?}", started.elapsed());
+    // query_canceled
+    assert_eq!(err.as_database_error().and_then(|e| e.code()).as_deref(), Some("57014"));
+    
+    let response = ApiError::Database(err.to_string()).into_response();
+    assert_eq!(response.status(), axum::http::StatusCode::INTERNAL_SERVER_ERROR);
+    
+    // Fast queries are unaffected
+    sqlx::query("SELECT 1").execute(&db).await.unwrap();
+}
+
+#[sqlx::test]
+async fn test_indexer_pool_leaves_api_pool_free(
+    _: sqlx::postgres::PgPoolOptions,
+    connect_options: sqlx::postgres::PgConnectOptions,
+) {
+    use bitcoin::hashes::Hash;
+    
+    // The indexer polls a node whose tip is block 1
+    let block = block_at(1, bitcoin::BlockHash::all_zeros(), vec![taproot_payment(0x01020304, 1)]);
+    let node = FakeNode::new(1, vec![block]);
+    let mut config = test_config();
+    config.indexer_db_max_connections = 1;
+    config.bitcoin_rpc_url = node.listen().await;
+    config.zmq_socket = String::new();
+    config.block_poll_interval_ms = 10;
+    let api_db = crate::pool_options(&config).connect_with(connect_options.clone()).await.unwrap();
+    let indexer_db = crate::indexer_pool_options(&config).connect_with(connect_options).await.unwrap();
+    let state = AppState { config, ..test_state(api_db.clone()) };
+    
+    // Something else holds the indexer pool's only connection: the indexer
+    // waits for it, while API queries get their own
+    let held = indexer_db.acquire().await.unwrap();
+    let indexer = tokio::spawn(crate::indexer::run_indexer(state.clone(), indexer_db.clone()));
+    tokio::task::yield_now().await;
+    let body = response_json(status_handler(State(state.clone())).await).await;
+    assert_eq!(body["status"], "ok");
+    assert_eq!(state.indexer_progress.indexed_height(), None);
+    assert_eq!(indexer_db.size(), 1);
+    assert!(api_db.size() >= 1);
+    
+    // With the API pool closed, the indexer still indexes the node's block
+    // once its own connection is free
+    api_db.close().await;
+    drop(held);
+    for _ in 0..200 {
+        if state.indexer_progress.indexed_height() == Some(1) {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+    }
+    state.indexer_progress.request_stop();
+    indexer.await.unwrap().unwrap();
+    
+    assert_eq!(state.indexer_progress.indexed_height(), Some(1));
+    assert_eq!(indexer_db.size(), 1);
+    let (blocks,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM blocks")
+        .fetch_one(&indexer_db)
+        .await
+        .unwrap();
+    assert_eq!(blocks, 1);
+}
+
+#[sqlx::test]
+async fn test_stats_counts_indexed_block(db: PgPool) {
+    use bitcoin::hashes::Hash;
+    
+    let state = test_state(db.clone());
+    let block = block_at(
+        100,
+        bitcoin::BlockHash::all_zeros(),
+        vec![taproot_payment(0x01020304, 1), taproot_payment(0x05060708, 2)],
+    );
+    crate::indexer::process_block(&state, None, &block).await.unwrap();
+    
+    let body = response_json(stats_handler(State(state.clone())).await.unwrap()).await;
+    assert_eq!(body["indexed_height"], 100);
+    assert_eq!(body["total_blocks"], 1);
+    assert_eq!(body["total_transactions"], 3);
+    assert_eq!(body["total_outputs"], 2);
+    assert_eq!(body["eligible_input_transactions"], 0);
+    
+    // Served from cache within the TTL
+    let next = block_at(101, block.block_hash(), vec![taproot_payment(0x01020304, 3)]);
+    crate::indexer::process_block(&state, None, &next).await.unwrap();
+    let cached = response_json(stats_handler(State(state)).await.unwrap()).await;
+    assert_eq!(cached, body);
+}
+
+#[sqlx::test]
+async fn test_long_prefixes_reduce_false_positives(db: PgPool) {
+    insert_block(&db, 100).await;
+    // Three outputs sharing the 4-byte prefix, differing in byte 4
+    for (i, byte) in [0x11u8, 0x22, 0x33].into_iter().enumerate() {
+        let mut x_only = [byte; 32];
+        x_only[..4].copy_from_slice(&0xdeadbeefu32.to_be_bytes());
+        insert_output_key(&db, 100, i as u8 + 1, 0, x_only).await;
+    }
+    
+    // 4-byte prefixes: all three are candidates
+    let req = scan_request(100, 100, &[0xdeadbeef]);
+    let body = response_json(scan_handler(State(test_state(db.clone())), Json(req)).await.unwrap()).await;
+    assert_eq!(body["candidates"].as_array().unwrap().len(), 3);
+    
+    // 8-byte prefixes: only the real match
+    let mut state = test_state(db.clone());
+    state.config.prefix_len = 8;
+    let mut req = scan_request(100, 100, &[]);
+    req.prefixes = vec!["deadbeef22222222".into()];
+    
+    let body = response_json(scan_handler(State(state.clone()), Json(req.clone())).await.unwrap()).await;
+    let candidates = body["candidates"].as_array().unwrap();
+    assert_eq!(candidates.len(), 1);
+    assert_eq!(candidates[0]["script_pubkey"], format!("5120deadbeef{}", "22".repeat(28)));
+    assert_eq!(body["matched_prefixes"], 1);
+    
+    let body = response_json(scan_batch_handler(State(state.clone()), Json(vec![req])).await.unwrap()).await;
+    assert_eq!(body[0]["candidates"].as_array().unwrap().len(), 1);
+    
+    // 4-byte prefixes are rejected when the server expects 8
+    let req = scan_request(100, 100, &[0xdeadbeef]);
+    assert!(matches!(scan_handler(State(state), Json(req)).await, Err(ApiError::Validation(_))));
+}
+
+#[sqlx::test]
+async fn test_readyz_tracks_indexer_lag(db: PgPool) {
+    use bitcoin::hashes::Hash;
+    
+    let state = test_state(db.clone());
+    assert_eq!(healthz_handler().await.into_response().status(), axum::http::StatusCode::OK);
+    
+    // Node tip unknown and no RPC to ask: the lag can't be judged
+    let response = readyz_handler(State(state.clone())).await.into_response();
+    assert_eq!(response.status(), axum::http::StatusCode::SERVICE_UNAVAILABLE);
+    assert!(response_json(response).await["node_tip"].is_null());
+    
+    // With RPC, the node's block count seeds the tip
+    let mut seeded = test_state(db.clone());
+    seeded.node_rpc = Some(std::sync::Arc::new(crate::indexer::rpc_client(&test_config()).unwrap()));
+    *seeded.node_tip_cache.lock().unwrap() = Some((std::time::Instant::now(), Some(101)));
+    let body = response_json(readyz_handler(State(seeded)).await.into_response()).await;
+    assert_eq!(body["node_tip"], 101);
+    assert_eq!(body["lag_blocks"], 102);
+    
+    // Node announces 105 while nothing is indexed
+    state.indexer_progress.record_tip(105);
+    let response = readyz_handler(State(state.clone())).await.into_response();
+    assert_eq!(response.status(), axum::http::StatusCode::SERVICE_UNAVAILABLE);
+    let body = response_json(response).await;
+    assert_eq!(body["status"], "not_ready");
+    assert_eq!(body["node_tip"], 105);
+    
+    // Indexing block 103 brings the lag within READY_MAX_LAG_BLOCKS
+    let block = block_at(103, bitcoin::BlockHash::all_zeros(), vec![]);
+    crate::indexer::process_block(&state, None, &block).await.unwrap();
+    let response = readyz_handler(State(state.clone())).await.into_response();
+    assert_eq!(response.status(), axum::http::StatusCode::OK);
+    let body = response_json(response).await;
+    assert_eq!(body["indexed_height"], 103);
+    assert_eq!(body["lag_blocks"], 2);
+    
+    // Database unreachable: not ready, still alive
+    let mut state = state;
+    state.db = sqlx::postgres::PgPoolOptions::new()
+        .acquire_timeout(std::time::Duration::from_millis(200))
+        .connect_lazy("postgres://nobody@127.0.0.1:1/none")
+        .unwrap();
+    let response = readyz_handler(State(state)).await.into_response();
+    assert_eq!(response.status(), axum::http::StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(healthz_handler().await.into_response().status(), axum::http::StatusCode::OK);
+}
+
+#[test]
+fn test_prefix_bloom_has_no_false_negatives() {
+    use crate::bloom::PrefixBloom;
+    
+    let prefixes: Vec<u32> = (0..500u32).map(|i| i.wrapping_mul(0x9e37_79b9)).collect();
+    let bloom = PrefixBloom::from_prefixes(&prefixes);
+    assert!(prefixes.iter().all(|&p| bloom.may_contain(p)));
+    
+    // Roughly 1% false positives at 10 bits per prefix
+    let false_positives = (0..10_000u32)
+        .map(|i| i.wrapping_mul(0x85eb_ca6b) ^ 0x5bd1_e995)
+        .filter(|p| !prefixes.contains(p) && bloom.may_contain(*p))
+        .count();
+    assert!(false_positives < 300, "{} false positives", false_positives);
+    
+    let empty = PrefixBloom::from_prefixes(&[]);
+    assert!(!empty.may_contain(0x01020304));
+    assert_eq!(PrefixBloom::from_bytes(empty.as_bytes().to_vec()), Some(empty));
+    assert_eq!(PrefixBloom::from_bytes(Vec::new()), None);
+}
+
+#[sqlx::test]
+async fn test_scan_skips_blocks_ruled_out_by_bloom(db: PgPool) {
+    use bitcoin::hashes::Hash;
+    
+    // Block 100 pays the requested prefix, block 101 only other prefixes
+    let b100 = block_at(100, bitcoin::BlockHash::all_zeros(), vec![taproot_payment(0x01020304, 1)]);
+    let b101 = block_at(101, b100.block_hash(), vec![
+        taproot_payment(0x0a0b0c0d, 2),
+        taproot_payment(0x0e0f1011, 3),
+    ]);
+    let state = test_state(db.clone());
+    crate::indexer::process_block(&state, None, &b100).await.unwrap();
+    crate::indexer::process_block(&state, None, &b101).await.unwrap();
+    
+    let req = scan_request(100, 101, &[0x01020304]);
+    let body = response_json(scan_handler(State(test_state(db.clone())), Json(req.clone())).await.unwrap()).await;
+    assert_eq!(body["skipped_blocks"], 1);
+    let candidates = body["candidates"].as_array().unwrap().clone();
+    assert_eq!(candidates.len(), 1);
+    assert_eq!(candidates[0]["block_height"], 100);
+    
+    // Batch scans skip blocks per request
+    let batch = vec![req.clone(), scan_request(100, 101, &[0x0a0b0c0d])];
+    let body = response_json(scan_batch_handler(State(state.clone()), Json(batch)).await.unwrap()).await;
+    assert_eq!(body[0]["skipped_blocks"], 1);
+    assert_eq!(body[0]["candidates"].as_array().unwrap(), &candidates);
+    assert_eq!(body[1]["skipped_blocks"], 1);
+    assert_eq!(body[1]["candidates"][0]["block_height"], 101);
+    
+    // Same results with the filters gone (blocks indexed before blooms existed)
+    sqlx::query("UPDATE blocks SET prefix_bloom = NULL")
+        .execute(&db)
+        .await
+        .unwrap();
+    
+    // Filters already read stay cached until the block changes
+    let body = response_json(scan_handler(State(state.clone()), Json(req.clone())).await.unwrap()).await;
+    assert_eq!(body["skipped_blocks"], 1);
+    state.bloom_cache.remove(101);
+    state.scan_cache.invalidate_height(101);
+    let body = response_json(scan_handler(State(state.clone()), Json(req.clone())).await.unwrap()).await;
+    assert_eq!(body["skipped_blocks"], 0);
+    
+    let body = response_json(scan_handler(State(test_state(db.clone())), Json(req)).await.unwrap()).await;
+    assert_eq!(body["skipped_blocks"], 0);
+    assert_eq!(body["candidates"].as_array().unwrap(), &candidates);
+    
+    // Every block ruled out: no output query at all, empty result
+    sqlx::query("UPDATE blocks SET prefix_bloom = $1")
+        .bind(crate::bloom::PrefixBloom::from_prefixes(&[]).as_bytes())
+        .execute(&db)
+        .await
+        .unwrap();
+    let req = scan_request(100, 101, &[0x01020304]);
+    let body = response_json(scan_handler(State(test_state(db)), Json(req)).await.unwrap()).await;
+    assert_eq!(body["skipped_blocks"], 2);
+    assert!(body["candidates"].as_array().unwrap().is_empty());
+}
+
+/// Send a raw HTTP/1.1 POST and return the response head and body bytes
+/// exactly as they went over the wire.
+async fn raw_post(addr: std::net::SocketAddr, path: &str, body: &str, headers: &[(&str, &str)]) -> (String, Vec<u8>) {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    
+    let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+    let extra: String = headers.iter()
+        .map(|(name, value)| format!("{}: {}\r\n", name, value))
+        .collect();
+    let request = format!(
+        "POST {} HTTP/1.1\r\nhost: localhost\r\ncontent-type: application/json\r\ncontent-length: {}\r\n{}connection: close\r\n\r\n{}",
+        path, body.len(), extra, body
+    );
+    stream.write_all(request.as_bytes()).await.unwrap();
+    
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).await.unwrap();
+    let split = response.windows(4).position(|w| w == b"\r\n\r\n").unwrap();
+    let head = String::from_utf8_lossy(&response[..split]).to_ascii_lowercase();
+    (head, response[split + 4..].to_vec())
+}
+
+#[sqlx::test]
+async fn test_large_scan_response_is_compressed(db: PgPool) {
+    use bitcoin::secp256k1::{PublicKey, Secp256k1, SecretKey};
+    
+    let secp = Secp256k1::new();
+    let scan_key = whisper_core::ScanKey::from_slice(&[1u8; 32]).unwrap();
+    let spend_pubkey = scan_key.public;
+    let input_pubkey = PublicKey::from_secret_key(&secp, &SecretKey::from_slice(&[3u8; 32]).unwrap());
+    let inputs = vec![whisper_core::InputData { pubkey: input_pubkey, is_taproot: true, outpoint: None }];
+    let prefix = whisper_core::compute_prefixes(&scan_key, &spend_pubkey, &inputs, 0).unwrap()[0];
+    
+    // Hundreds of candidates sharing the client's prefix
+    insert_block(&db, 100).await;
+    for vout in 0..300 {
+        insert_output(&db, 100, 0x01, vout, prefix).await;
+    }
+    
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let app = crate::app(test_state(db));
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    
+    let body = serde_json::json!({
+        "scan_pubkey": "ab".repeat(32),
+        "start_height": 100,
+        "end_height": 100,
+        "prefixes": [format!("{:08x}", prefix)],
+    })
+    .to_string();
+    
+    let (head, plain) = raw_post(addr, "/api/v1/scan", &body, &[]).await;
+    assert!(!head.contains("content-encoding"));
+    let (head, gzipped) = raw_post(addr, "/api/v1/scan", &body, &[("accept-encoding", "gzip")]).await;
+    assert!(head.contains("content-encoding: gzip"), "{}", head);
+    assert!(gzipped.len() * 4 < plain.len(), "{} vs {} bytes", gzipped.len(), plain.len());
+    
+    // The client negotiates compression and decodes the body transparently
+    let client = whisper_client::SilentPaymentClient::new(format!("http://{}", addr), scan_key, spend_pubkey, 0);
+    let outcome = client.scan_range_detailed(100, 100, &inputs).await.unwrap();
+    assert_eq!(outcome.candidates_checked, 300);
+}
+
+#[sqlx::test]
+async fn test_responses_carry_request_id(db: PgPool) {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let app = crate::app(test_state(db)).into_make_service_with_connect_info::<std::net::SocketAddr>();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    
+    let body = serde_json::to_string(&serde_json::json!({
+        "scan_pubkey": "ab".repeat(32),
+        "start_height": 100,
+        "end_height": 110,
+        "prefixes": ["01020304"],
+    }))
+    .unwrap();
+    
+    // Generated when the caller doesn't send one
+    let (head, _) = raw_post(addr, "/api/v1/scan", &body, &[]).await;
+    let id = head.lines()
+        .find_map(|line| line.strip_prefix("x-request-id: "))
+        .unwrap_or_else(|| panic!("no x-request-id in {}", head));
+    assert_eq!(id.len(), 36, "expected a UUID, got {:?}", id);
+    
+    // Echoed back when supplied, including on error responses
+    let (head, _) = raw_post(addr, "/api/v1/scan", &body, &[("x-request-id", "wallet-42")]).await;
+    assert!(head.contains("x-request-id: wallet-42"), "{}", head);
+    let (head, _) = raw_post(addr, "/api/v1/scan", "{}", &[("x-request-id", "wallet-43")]).await;
+    assert!(head.starts_with("http/1.1 4"), "{}", head);
+    assert!(head.contains("x-request-id: wallet-43"), "{}", head);
+}
+
+#[sqlx::test]
+async fn test_scan_cache_hit_and_invalidation(db: PgPool) {
+    use bitcoin::hashes::Hash;
+    
+    let state = test_state(db.clone());
+    let b100 = block_at(100, bitcoin::BlockHash::all_zeros(), vec![taproot_payment(0x01020304, 1)]);
+    crate::indexer::process_block(&state, None, &b100).await.unwrap();
+    
+    let scan = |req: ScanRequest| {
+        let state = state.clone();
+        async move { response_json(scan_handler(State(state), Json(req)).await.unwrap()).await }
+    };
+    
+    let first = scan(scan_request(100, 105, &[0x01020304, 0x0a0b0c0d])).await;
+    assert_eq!(state.scan_cache.hits(), 0);
+    
+    // Same request with prefixes in another order is served from the cache
+    let second = scan(scan_request(100, 105, &[0x0a0b0c0d, 0x01020304])).await;
+    assert_eq!(state.scan_cache.hits(), 1);
+    assert_eq!(second["candidates"], first["candidates"]);
+    assert_eq!(first["candidates"].as_array().unwrap().len(), 1);
+    
+    // A different range is a different entry
+    scan(scan_request(100, 100, &[0x01020304])).await;
+    assert_eq!(state.scan_cache.hits(), 1);
+    
+    // Indexing block 101 invalidates the range covering it, not the other one
+    let b101 = block_at(101, b100.block_hash(), vec![taproot_payment(0x01020304, 2)]);
+    crate::indexer::process_block(&state, None, &b101).await.unwrap();
+    
+    let refreshed = scan(scan_request(100, 105, &[0x01020304, 0x0a0b0c0d])).await;
+    assert_eq!(state.scan_cache.hits(), 1);
+    assert_eq!(refreshed["candidates"].as_array().unwrap().len(), 2);
+    
+    scan(scan_request(100, 100, &[0x01020304])).await;
+    assert_eq!(state.scan_cache.hits(), 2);
+}
+
+#[test]
+fn test_scan_cache_drops_results_from_before_invalidation() {
+    use crate::scan_cache::{CachedScan, ScanCache, ScanCacheKey};
+    
+    let cache = ScanCache::new(16, std::time::Duration::from_secs(30));
+    let key = ScanCacheKey::new(100, 105, &[1], &[], (0, i64::MAX), false, false);
+    let scan = CachedScan { candidates: Vec::new(), matched_prefixes: 0, candidates_before_filter: 0, skipped_blocks: 0 };
+    
+    // Block 103 is indexed while the query runs: the result may predate
+    // it, so it is not cached
+    let generation = cache.generation();
+    cache.invalidate_height(103);
+    cache.insert(key.clone(), scan.clone(), generation);
+    assert!(cache.get(&key).is_none());
+    
+    cache.insert(key.clone(), scan, cache.generation());
+    assert!(cache.get(&key).is_some());
+}
@@ -0,0 +1,113 @@
+//! In-memory set of recently created outputs, so the indexer can resolve
+//! the prevouts of a block's inputs without a round-trip per input.
+//!
+//! Outputs are added as blocks are indexed and removed when spent, once
+//! the block's database transaction has committed; the oldest are evicted
+//! when full, and a reorg clears the cache. The `utxos` table holds the
+//! same outputs persistently, and RPC covers outputs created before
+//! indexing started.
+
+use bitcoin::{OutPoint, TxOut};
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+#[derive(Default)]
+struct CacheInner {
+    entries: HashMap<OutPoint, TxOut>,
+    /// Insertion order for eviction; may still hold spent outpoints
+    order: VecDeque<OutPoint>,
+}
+
+pub struct UtxoCache {
+    capacity: usize,
+    inner: Mutex<CacheInner>,
+    hits: AtomicU64,
+}
+
+impl UtxoCache {
+    /// A cache holding at most `capacity` outputs. A capacity of 0
+    /// disables it.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            inner: Mutex::default(),
+            hits: AtomicU64::new(0),
+        }
+    }
+    
+    pub fn get(&self, outpoint: &OutPoint) -> Option<TxOut> {
+        let output = self.inner.lock().unwrap().entries.get(outpoint).cloned()?;
+        self.hits.fetch_add(1, Ordering::Relaxed);
+        Some(output)
+    }
+    
+    pub fn insert(&self, outpoint: OutPoint, output: TxOut) {
+        if self.capacity == 0 {
+            return;
+        }
+        
+        let mut inner = self.inner.lock().unwrap();
+        while inner.entries.len() >= self.capacity {
+            let Some(oldest) = inner.order.pop_front() else {
+                break;
+            };
+            inner.entries.remove(&oldest);
+        }
+        
+        // Spent outpoints linger in `order`; drop them before it grows
+        // far past the live entries
+        if inner.order.len() >= self.capacity * 2 {
+            let CacheInner { entries, order } = &mut *inner;
+            order.retain(|outpoint| entries.contains_key(outpoint));
+        }
+        
+        if inner.entries.insert(outpoint, output).is_none() {
+            inner.order.push_back(outpoint);
+        }
+    }
+    
+    /// Forget a spent output.
+    pub fn remove(&self, outpoint: &OutPoint) {
+        self.inner.lock().unwrap().entries.remove(outpoint);
+    }
+    
+    /// Apply the changes of a committed block.
+    pub fn apply(&self, changes: UtxoChanges) {
+        for (outpoint, output) in changes.created {
+            self.insert(outpoint, output);
+        }
+        for outpoint in &changes.spent {
+            self.remove(outpoint);
+        }
+    }
+    
+    /// Forget every output, e.g. after a reorg removed the blocks that
+    /// created some of them.
+    pub fn clear(&self) {
+        *self.inner.lock().unwrap() = CacheInner::default();
+    }
+    
+    /// Number of prevouts resolved from the cache since startup.
+    pub fn hits(&self) -> u64 {
+        self.hits.load(Ordering::Relaxed)
+    }
+}
+
+/// Outputs a block creates and spends, collected while it is indexed and
+/// applied to the cache only once its transaction commits.
+#[derive(Default)]
+pub struct UtxoChanges {
+    created: Vec<(OutPoint, TxOut)>,
+    spent: Vec<OutPoint>,
+}
+
+impl UtxoChanges {
+    pub fn create(&mut self, outpoint: OutPoint, output: TxOut) {
+        self.created.push((outpoint, output));
+    }
+    
+    pub fn spend(&mut self, outpoint: OutPoint) {
+        self.spent.push(outpoint);
+    }
+}